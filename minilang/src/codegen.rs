@@ -148,23 +148,29 @@ impl CodeGenerator {
     pub fn run_on_module(&mut self, module: parser::Module) {
         println!("Parsed: {:#?}", module);
 
-        // Declare struct
+        // Declare struct. Every struct is created opaque first so that struct members can
+        // refer to any struct (including the struct itself) through pointers.
         for (name, decls) in &module.structs {
-            let decls_ = decls
-                .iter()
-                .map(|(_, ty)| ty.into_cilk_type(&self.types, &mut self.module.types))
-                .collect();
             let name2idx: HashMap<String, (usize, parser::Type)> = decls
                 .iter()
                 .enumerate()
                 .map(|(i, (name, ty))| (name.clone(), (i, ty.clone())))
                 .collect();
-            let struct_ty = self.module.types.new_struct_ty(decls_);
+            let struct_ty = self.module.types.new_named_struct_ty(name.as_str());
             self.types
                 .records
                 .insert(name.clone(), (struct_ty, name2idx));
         }
 
+        for (name, decls) in &module.structs {
+            let decls_ = decls
+                .iter()
+                .map(|(_, ty)| ty.into_cilk_type(&self.types, &mut self.module.types))
+                .collect();
+            let struct_ty = self.types.records.get(name.as_str()).unwrap().0;
+            self.module.types.set_struct_body(struct_ty, decls_);
+        }
+
         // Create function prototypes
         let mut worklist = vec![];
         for func in &module.functions {
@@ -59,6 +59,7 @@ fn main() {
 
     cilk::ir::mem2reg::Mem2Reg::new().run_on_module(&mut codegen.module);
     cilk::ir::cse::CommonSubexprElimination::new().run_on_module(&mut codegen.module);
+    cilk::ir::licm::LoopInvariantCodeMotion::new().run_on_module(&mut codegen.module);
 
     println!("{:?}", codegen.module);
 
@@ -356,7 +357,15 @@ fn ray_tracing() {
     let mut codegen = codegen::CodeGenerator::new();
     codegen.run(input);
 
+    // Once the intersection functions are inlined, the structs in 'main' can be split
+    for name in &["Env_intersect", "Sphere_intersect", "Plane_intersect"] {
+        let f = codegen.module.find_function(*name).unwrap();
+        codegen.module.function_ref_mut(f).attr.always_inline = true;
+    }
+
     cilk::ir::mem2reg::Mem2Reg::new().run_on_module(&mut codegen.module);
+    cilk::ir::inliner::Inliner::new(50).run_on_module(&mut codegen.module);
+    cilk::ir::sroa::ScalarReplacementOfAggregates::new().run_on_module(&mut codegen.module);
     cilk::ir::cse::CommonSubexprElimination::new().run_on_module(&mut codegen.module);
 
     println!("{:?}", codegen.module);
@@ -413,6 +422,9 @@ fn bubble_sort() {
 
     cilk::ir::mem2reg::Mem2Reg::new().run_on_module(&mut codegen.module);
     cilk::ir::cse::CommonSubexprElimination::new().run_on_module(&mut codegen.module);
+    cilk::ir::licm::LoopInvariantCodeMotion::new().run_on_module(&mut codegen.module);
+    cilk::ir::indvars::IndVarSimplify::new().run_on_module(&mut codegen.module);
+    cilk::ir::lsr::LoopStrengthReduction::new().run_on_module(&mut codegen.module);
 
     use cilk::codegen::x64::asm::print::MachineAsmPrinter;
     use cilk::codegen::x64::standard_conversion_into_machine_module;
@@ -507,6 +519,40 @@ fn quick_sort() {
     );
 }
 
+#[test]
+fn linked_list() {
+    let input = r#"
+    struct Node {
+      val: i32,
+      next: *struct Node
+    }
+
+    function main(): i32 {
+      var a: *struct Node;
+      var b: *struct Node;
+      var n: *struct Node;
+      a = malloc(16);
+      b = malloc(16);
+      (*a).val = 1;
+      (*a).next = b;
+      (*b).val = 2;
+      n = (*a).next;
+      return (*a).val + (*n).val;
+    }"#;
+
+    let mut codegen = codegen::CodeGenerator::new();
+    codegen.run(input);
+
+    cilk::ir::mem2reg::Mem2Reg::new().run_on_module(&mut codegen.module);
+
+    let mut jit = cilk::codegen::x64::exec::jit::JITExecutor::new(&mut codegen.module);
+    let func = jit.find_function_by_name("main").unwrap();
+    assert_eq!(
+        jit.run(func, vec![]),
+        cilk::codegen::x64::exec::jit::GenericValue::Int32(3)
+    );
+}
+
 #[test]
 fn pi() {
     //66/51
@@ -0,0 +1,355 @@
+use crate::ir::{
+    function::Function,
+    inline_asm::InlineAsms,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::{Type, TypeSize, Types},
+    value::{ArgumentValue, ImmediateValue, InlineAsmValue, InstructionValue, Value},
+};
+use rustc_hash::FxHashSet;
+
+/// A simple alias analysis. Pointers are decomposed into an underlying object (an `Alloca`, a
+/// global variable, a `noalias` argument or an unknown pointer such as another argument) and a
+/// constant byte offset when every index of the `GetElementPtr`s on the way is constant.
+///
+/// More precise analyses are chained in front of it with `with`. A query is answered by the
+/// first analysis giving a definite answer, and the simple rules are the fallback.
+pub struct AliasAnalysis<'a> {
+    types: &'a Types,
+    inline_asms: &'a InlineAsms,
+    /// Allocas whose address is stored, passed to a call or otherwise leaks out of plain loads,
+    /// stores and address computations
+    escaped: FxHashSet<InstructionId>,
+    chain: Vec<Box<dyn AliasAnalysisTrait + 'a>>,
+}
+
+/// An alias analysis that can be chained in front of `AliasAnalysis`
+pub trait AliasAnalysisTrait {
+    /// Returns `MayAlias` unless it's sure about how `a` and `b` overlap
+    fn alias(&self, func: &Function, a: &MemoryLocation, b: &MemoryLocation) -> AliasResult;
+
+    /// Returns `ModRef` unless it's sure `inst` doesn't read or write `loc`
+    fn mod_ref(&self, _func: &Function, _inst: &Instruction, _loc: &MemoryLocation) -> ModRefInfo {
+        ModRefInfo::ModRef
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasResult {
+    NoAlias,
+    MayAlias,
+    MustAlias,
+}
+
+/// Whether an instruction may read (ref) or write (mod) a location
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModRefInfo {
+    NoModRef,
+    Ref,
+    Mod,
+    ModRef,
+}
+
+/// The `size` bytes starting at `ptr`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryLocation {
+    pub ptr: Value,
+    pub size: usize,
+}
+
+impl MemoryLocation {
+    pub fn new(ptr: Value, size: usize) -> Self {
+        Self { ptr, size }
+    }
+
+    /// Returns the location accessed by a `Load` or `Store`
+    pub fn of(inst: &Instruction, types: &Types) -> Option<Self> {
+        match inst.opcode {
+            Opcode::Load => Some(Self::new(
+                *inst.operands[0].as_value(),
+                inst.ty.size_in_byte(types),
+            )),
+            Opcode::Store => Some(Self::new(
+                *inst.operands[1].as_value(),
+                inst.operands[0].as_value().get_type().size_in_byte(types),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl ModRefInfo {
+    pub fn is_mod(&self) -> bool {
+        matches!(self, Self::Mod | Self::ModRef)
+    }
+
+    pub fn is_ref(&self) -> bool {
+        matches!(self, Self::Ref | Self::ModRef)
+    }
+
+    /// Returns what both `self` and `other` allow
+    pub fn intersect(&self, other: Self) -> Self {
+        match (
+            self.is_mod() && other.is_mod(),
+            self.is_ref() && other.is_ref(),
+        ) {
+            (true, true) => Self::ModRef,
+            (true, false) => Self::Mod,
+            (false, true) => Self::Ref,
+            (false, false) => Self::NoModRef,
+        }
+    }
+}
+
+impl<'a> AliasAnalysis<'a> {
+    pub fn new(func: &Function, types: &'a Types, inline_asms: &'a InlineAsms) -> Self {
+        let mut escaped = FxHashSet::default();
+        for &bb in &func.basic_blocks.order {
+            for val in &*func.basic_blocks.arena[bb].iseq_ref() {
+                let id = val.as_instruction().id;
+                if func.inst_table[id].opcode == Opcode::Alloca && escapes(func, id) {
+                    escaped.insert(id);
+                }
+            }
+        }
+        Self {
+            types,
+            inline_asms,
+            escaped,
+            chain: vec![],
+        }
+    }
+
+    /// Consults `aa` before the analyses already chained
+    pub fn with<A: AliasAnalysisTrait + 'a>(mut self, aa: A) -> Self {
+        self.chain.insert(0, Box::new(aa));
+        self
+    }
+
+    pub fn alias(&self, func: &Function, a: &MemoryLocation, b: &MemoryLocation) -> AliasResult {
+        for aa in &self.chain {
+            match aa.alias(func, a, b) {
+                AliasResult::MayAlias => {}
+                result => return result,
+            }
+        }
+        self.simple_alias(func, a, b)
+    }
+
+    pub fn may_alias(&self, func: &Function, a: &MemoryLocation, b: &MemoryLocation) -> bool {
+        self.alias(func, a, b) != AliasResult::NoAlias
+    }
+
+    /// Returns whether `inst` may read or write `loc`
+    pub fn mod_ref(&self, func: &Function, inst: &Instruction, loc: &MemoryLocation) -> ModRefInfo {
+        self.chain
+            .iter()
+            .fold(self.simple_mod_ref(func, inst, loc), |info, aa| {
+                info.intersect(aa.mod_ref(func, inst, loc))
+            })
+    }
+
+    /// Returns true if `inst` may write to `loc`
+    pub fn may_modify(&self, func: &Function, inst: &Instruction, loc: &MemoryLocation) -> bool {
+        self.mod_ref(func, inst, loc).is_mod()
+    }
+
+    /// Returns true if `inst` may read from `loc`
+    pub fn may_read(&self, func: &Function, inst: &Instruction, loc: &MemoryLocation) -> bool {
+        self.mod_ref(func, inst, loc).is_ref()
+    }
+
+    fn simple_alias(&self, func: &Function, a: &MemoryLocation, b: &MemoryLocation) -> AliasResult {
+        if a.ptr == b.ptr {
+            return if a.size == b.size {
+                AliasResult::MustAlias
+            } else {
+                AliasResult::MayAlias
+            };
+        }
+
+        let (base_a, off_a) = self.decompose(func, a.ptr);
+        let (base_b, off_b) = self.decompose(func, b.ptr);
+
+        if base_a == base_b {
+            return match (off_a, off_b) {
+                (Some(x), Some(y)) if x == y && a.size == b.size => AliasResult::MustAlias,
+                (Some(x), Some(y)) if x + a.size as i64 <= y || y + b.size as i64 <= x => {
+                    AliasResult::NoAlias
+                }
+                _ => AliasResult::MayAlias,
+            };
+        }
+
+        // Nothing else points into what a `noalias` argument points to
+        let is_object_or_arg =
+            |base: &Value| self.is_identified(func, base) || matches!(base, Value::Argument(_));
+        if (self.is_noalias_arg(func, &base_a) && is_object_or_arg(&base_b))
+            || (self.is_noalias_arg(func, &base_b) && is_object_or_arg(&base_a))
+        {
+            return AliasResult::NoAlias;
+        }
+
+        match (
+            self.is_identified(func, &base_a),
+            self.is_identified(func, &base_b),
+        ) {
+            (true, true) => AliasResult::NoAlias,
+            // An unknown pointer can't point into an alloca whose address never leaks
+            (true, false) if self.is_local(func, &base_a) => AliasResult::NoAlias,
+            (false, true) if self.is_local(func, &base_b) => AliasResult::NoAlias,
+            _ => AliasResult::MayAlias,
+        }
+    }
+
+    fn simple_mod_ref(
+        &self,
+        func: &Function,
+        inst: &Instruction,
+        loc: &MemoryLocation,
+    ) -> ModRefInfo {
+        match inst.opcode {
+            Opcode::Load => {
+                let src = MemoryLocation::of(inst, self.types).unwrap();
+                if self.may_alias(func, &src, loc) {
+                    ModRefInfo::Ref
+                } else {
+                    ModRefInfo::NoModRef
+                }
+            }
+            Opcode::Store => {
+                let dst = MemoryLocation::of(inst, self.types).unwrap();
+                if self.may_alias(func, &dst, loc) {
+                    ModRefInfo::Mod
+                } else {
+                    ModRefInfo::NoModRef
+                }
+            }
+            // Anything a call may write to, it may read too
+            Opcode::Call => {
+                let touches = match inst.operands[0].as_value() {
+                    Value::InlineAsm(InlineAsmValue { id, .. }) => {
+                        let asm = &self.inline_asms.arena[*id];
+                        asm.side_effects || asm.clobbers_memory()
+                    }
+                    _ => {
+                        let (base, _) = self.decompose(func, loc.ptr);
+                        !self.is_local(func, &base)
+                    }
+                };
+                if touches {
+                    ModRefInfo::ModRef
+                } else {
+                    ModRefInfo::NoModRef
+                }
+            }
+            _ => ModRefInfo::NoModRef,
+        }
+    }
+
+    /// Returns true if `loc` lies within an `Alloca` or a global variable, so accessing it can't
+    /// fault
+    pub fn is_dereferenceable(&self, func: &Function, loc: &MemoryLocation) -> bool {
+        let (base, offset) = self.decompose(func, loc.ptr);
+        if !self.is_identified(func, &base) {
+            return false;
+        }
+        let object_ty = self.types.get_element_ty(base.get_type(), None).unwrap();
+        let object_size = object_ty.size_in_byte(self.types) as i64;
+        offset.map_or(false, |off| {
+            off >= 0 && off + loc.size as i64 <= object_size
+        })
+    }
+
+    /// Returns the underlying object of `ptr` and the byte offset from it, if constant
+    pub fn decompose(&self, func: &Function, mut ptr: Value) -> (Value, Option<i64>) {
+        let mut offset = Some(0i64);
+        while let Value::Instruction(InstructionValue { id, .. }) = ptr {
+            let inst = &func.inst_table[id];
+            if inst.opcode != Opcode::GetElementPtr {
+                break;
+            }
+            let base = *inst.operands[0].as_value();
+            offset = match (
+                offset,
+                self.gep_offset(base.get_type(), &inst.operands[1..]),
+            ) {
+                (Some(x), Some(y)) => Some(x + y),
+                _ => None,
+            };
+            ptr = base;
+        }
+        (ptr, offset)
+    }
+
+    fn gep_offset(&self, mut ty: Type, indices: &[Operand]) -> Option<i64> {
+        let mut offset = 0i64;
+        for idx in indices {
+            let idx = match idx.as_value() {
+                Value::Immediate(ImmediateValue::Int32(i)) => *i as i64,
+                Value::Immediate(ImmediateValue::Int64(i)) => *i,
+                _ => return None,
+            };
+            let elem_off = match ty {
+                Type::Struct(id) => Some(
+                    *self.types.base.borrow().non_primitive_types[id]
+                        .as_struct()
+                        .get_elem_offset(idx as usize)?,
+                ),
+                _ => None,
+            };
+            ty = self
+                .types
+                .get_element_ty(ty, Some(&Value::new_imm_int32(idx as i32)))?;
+            offset += elem_off.map_or(idx * ty.size_in_byte(self.types) as i64, |o| o as i64);
+        }
+        Some(offset)
+    }
+
+    /// Returns true if `base` is a distinct object: an `Alloca` or a global variable
+    fn is_identified(&self, func: &Function, base: &Value) -> bool {
+        match base {
+            Value::Instruction(InstructionValue { id, .. }) => {
+                func.inst_table[*id].opcode == Opcode::Alloca
+            }
+            Value::Global(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_noalias_arg(&self, func: &Function, base: &Value) -> bool {
+        match base {
+            Value::Argument(ArgumentValue { index, .. }) => {
+                func.get_param_attr(*index).map_or(false, |a| a.noalias)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if `base` is an `Alloca` whose address doesn't escape
+    pub fn is_local(&self, func: &Function, base: &Value) -> bool {
+        match base {
+            Value::Instruction(InstructionValue { id, .. }) => {
+                func.inst_table[*id].opcode == Opcode::Alloca && !self.escaped.contains(id)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn escapes(func: &Function, ptr: InstructionId) -> bool {
+    func.inst_table[ptr].users.borrow().iter().any(|&user| {
+        let inst = &func.inst_table[user];
+        let is_ptr = |op: &Operand| match op {
+            Operand::Value(Value::Instruction(InstructionValue { id, .. })) => *id == ptr,
+            _ => false,
+        };
+        match inst.opcode {
+            Opcode::Load => false,
+            Opcode::Store => is_ptr(&inst.operands[0]),
+            Opcode::GetElementPtr if is_ptr(&inst.operands[0]) => {
+                inst.operands[1..].iter().any(is_ptr) || escapes(func, user)
+            }
+            _ => true,
+        }
+    })
+}
@@ -0,0 +1,190 @@
+use crate::ir::{
+    function::FunctionId,
+    module::Module,
+    opcode::{InstructionId, Opcode, Operand},
+    value::{FunctionValue, Value},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Call graph of a module. There's an edge for every `Call`, from the caller to a function
+/// defined in the module, to an external function (an intrinsic listed in `codegen/internals`
+/// or a function declared without a body), or to an unknown function for a call through a
+/// pointer. Calls to inline asm aren't calls to functions and have no edge.
+pub struct CallGraph {
+    /// The functions defined in the module, in the order of the module
+    nodes: Vec<FunctionId>,
+    callees: FxHashMap<FunctionId, Vec<(InstructionId, Callee)>>,
+    callers: FxHashMap<FunctionId, Vec<(FunctionId, InstructionId)>>,
+    /// Functions referred to other than by being called, which may be called from anywhere
+    address_taken: FxHashSet<FunctionId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Callee {
+    Defined(FunctionId),
+    External(FunctionId),
+    Unknown,
+}
+
+struct Tarjan<'a> {
+    graph: &'a CallGraph,
+    index: FxHashMap<FunctionId, usize>,
+    low_link: FxHashMap<FunctionId, usize>,
+    stack: Vec<FunctionId>,
+    on_stack: FxHashSet<FunctionId>,
+    sccs: Vec<Vec<FunctionId>>,
+}
+
+impl CallGraph {
+    pub fn new(module: &Module) -> Self {
+        let mut nodes = vec![];
+        let mut callees: FxHashMap<FunctionId, Vec<(InstructionId, Callee)>> = FxHashMap::default();
+        let mut callers: FxHashMap<FunctionId, Vec<(FunctionId, InstructionId)>> =
+            FxHashMap::default();
+        let mut address_taken = FxHashSet::default();
+
+        for (caller, func) in &module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+            nodes.push(caller);
+            let edges = callees.entry(caller).or_default();
+            for &bb in &func.basic_blocks.order {
+                for val in &*func.basic_blocks.arena[bb].iseq_ref() {
+                    let id = val.as_instruction().id;
+                    let inst = &func.inst_table[id];
+                    for (i, op) in inst.operands.iter().enumerate() {
+                        match op {
+                            Operand::Value(Value::Function(FunctionValue { func_id, .. }))
+                                if !(inst.opcode == Opcode::Call && i == 0) =>
+                            {
+                                address_taken.insert(*func_id);
+                            }
+                            _ => {}
+                        }
+                    }
+                    if inst.opcode != Opcode::Call {
+                        continue;
+                    }
+                    let callee = match inst.operands[0].as_value() {
+                        Value::Function(FunctionValue { func_id, .. }) => {
+                            let f = module.function_ref(*func_id);
+                            if f.is_internal || f.basic_blocks.order.len() == 0 {
+                                Callee::External(*func_id)
+                            } else {
+                                Callee::Defined(*func_id)
+                            }
+                        }
+                        Value::InlineAsm(_) => continue,
+                        _ => Callee::Unknown,
+                    };
+                    edges.push((id, callee));
+                    if let Callee::Defined(f) | Callee::External(f) = callee {
+                        callers.entry(f).or_default().push((caller, id));
+                    }
+                }
+            }
+        }
+
+        Self {
+            nodes,
+            callees,
+            callers,
+            address_taken,
+        }
+    }
+
+    /// Returns the calls made by `func` and the functions they call
+    pub fn callees(&self, func: FunctionId) -> &[(InstructionId, Callee)] {
+        self.callees.get(&func).map_or(&[], |c| c.as_slice())
+    }
+
+    /// Returns the calls of `func` found in the module
+    pub fn callers(&self, func: FunctionId) -> &[(FunctionId, InstructionId)] {
+        self.callers.get(&func).map_or(&[], |c| c.as_slice())
+    }
+
+    /// Returns true if `func` may be called through a pointer
+    pub fn is_address_taken(&self, func: FunctionId) -> bool {
+        self.address_taken.contains(&func)
+    }
+
+    /// Returns true if `to` may be called while `from` runs
+    pub fn reaches(&self, from: FunctionId, to: FunctionId) -> bool {
+        let mut visited = FxHashSet::default();
+        let mut worklist = vec![from];
+        while let Some(func) = worklist.pop() {
+            if func == to {
+                return true;
+            }
+            if visited.insert(func) {
+                worklist.extend(self.defined_callees(func));
+            }
+        }
+        false
+    }
+
+    /// Returns the strongly connected components of the functions defined in the module, callees
+    /// before their callers. Functions calling each other recursively are in the same component.
+    pub fn sccs_bottom_up(&self) -> Vec<Vec<FunctionId>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: FxHashMap::default(),
+            low_link: FxHashMap::default(),
+            stack: vec![],
+            on_stack: FxHashSet::default(),
+            sccs: vec![],
+        };
+        for &func in &self.nodes {
+            if !tarjan.index.contains_key(&func) {
+                tarjan.visit(func);
+            }
+        }
+        tarjan.sccs
+    }
+
+    fn defined_callees<'a>(&'a self, func: FunctionId) -> impl Iterator<Item = FunctionId> + 'a {
+        self.callees(func)
+            .iter()
+            .filter_map(|(_, callee)| match callee {
+                Callee::Defined(f) => Some(*f),
+                _ => None,
+            })
+    }
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, func: FunctionId) {
+        let index = self.index.len();
+        self.index.insert(func, index);
+        self.low_link.insert(func, index);
+        self.stack.push(func);
+        self.on_stack.insert(func);
+
+        let callees: Vec<FunctionId> = self.graph.defined_callees(func).collect();
+        for callee in callees {
+            if !self.index.contains_key(&callee) {
+                self.visit(callee);
+                let low = self.low_link[&func].min(self.low_link[&callee]);
+                self.low_link.insert(func, low);
+            } else if self.on_stack.contains(&callee) {
+                let low = self.low_link[&func].min(self.index[&callee]);
+                self.low_link.insert(func, low);
+            }
+        }
+
+        // 'func' is the root of a component made of the functions above it on the stack
+        if self.low_link[&func] == self.index[&func] {
+            let mut scc = vec![];
+            loop {
+                let f = self.stack.pop().unwrap();
+                self.on_stack.remove(&f);
+                scc.push(f);
+                if f == func {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
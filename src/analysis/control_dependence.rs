@@ -0,0 +1,54 @@
+use crate::analysis::post_dom_tree::PostDominatorTree;
+use crate::traits::basic_block::{BasicBlockTrait, BasicBlocksTrait};
+use id_arena::Id;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Control dependence graph. A block is control dependent on a branch if the branch decides
+/// whether the block is executed, that is, one successor of the branch always leads to the
+/// block while the branch itself isn't post-dominated by the block.
+#[derive(Debug)]
+pub struct ControlDependenceGraph<T: BasicBlockTrait> {
+    /// Blocks ending in the branches each block is control dependent on
+    pub deps: FxHashMap<Id<T>, FxHashSet<Id<T>>>,
+}
+
+impl<T: BasicBlockTrait> ControlDependenceGraph<T> {
+    /// Walks the post-dominator tree from the successors of every branch up to the branch's
+    /// immediate post-dominator. Blocks not reaching an exit have no control dependences.
+    pub fn new<BBS: BasicBlocksTrait<BB = T>>(
+        basic_blocks: &BBS,
+        post_dom_tree: &PostDominatorTree<T>,
+    ) -> Self {
+        let mut deps: FxHashMap<Id<T>, FxHashSet<Id<T>>> = FxHashMap::default();
+        for &bb in basic_blocks.get_order() {
+            let end = match post_dom_tree.ipdom.get(&bb) {
+                Some(end) => *end,
+                None => continue,
+            };
+            for &succ in basic_blocks.get_arena()[bb].get_succs() {
+                let mut runner = Some(succ);
+                while runner != end {
+                    let r = match runner {
+                        Some(r) if post_dom_tree.contains(r) => r,
+                        _ => break,
+                    };
+                    deps.entry(r).or_insert_with(FxHashSet::default).insert(bb);
+                    runner = post_dom_tree.ipdom[&r];
+                }
+            }
+        }
+        Self { deps }
+    }
+
+    /// Returns the blocks `bb` is control dependent on if any
+    pub fn dependences_of(&self, bb: Id<T>) -> Option<&FxHashSet<Id<T>>> {
+        self.deps.get(&bb)
+    }
+
+    /// Returns true if the branch ending `branch` decides whether `bb` is executed
+    pub fn depends_on(&self, bb: Id<T>, branch: Id<T>) -> bool {
+        self.deps
+            .get(&bb)
+            .map_or(false, |deps| deps.contains(&branch))
+    }
+}
@@ -117,14 +117,14 @@ impl<'a, BBS: BasicBlocksTrait> LoopsConstructor<'a, BBS> {
                 self.loops.arena[parent].sub_loops.push(sub_loop);
             } else {
                 self.loops.top_level_loops.push(sub_loop);
-                return;
             }
         }
 
-        let mut sub_loop = sub_loop;
-        while let Some(parent) = self.loops.arena[sub_loop].parent {
-            self.loops.arena[parent].set.insert(bb);
-            sub_loop = parent;
+        // A block belongs to its innermost loop and every loop enclosing it
+        let mut l = Some(sub_loop);
+        while let Some(id) = l {
+            self.loops.arena[id].set.insert(bb);
+            l = self.loops.arena[id].parent;
         }
     }
 
@@ -168,4 +168,146 @@ impl<BB: BasicBlockTrait> Loops<BB> {
     pub fn set_loop_for(&mut self, bb: Id<BB>, loop_id: Id<Loop<BB>>) {
         self.bb_to_loop.insert(bb, loop_id);
     }
+
+    /// Returns the number of loops enclosing `loop_id`, counting itself. Top level loops have
+    /// depth 1.
+    pub fn depth(&self, loop_id: Id<Loop<BB>>) -> usize {
+        let mut depth = 0;
+        let mut l = Some(loop_id);
+        while let Some(id) = l {
+            depth += 1;
+            l = self.arena[id].parent;
+        }
+        depth
+    }
+
+    /// Returns the depth of the innermost loop containing `bb`, or 0 if it's in no loop
+    pub fn depth_of(&self, bb: Id<BB>) -> usize {
+        self.get_loop_for(bb).map_or(0, |l| self.depth(l))
+    }
+
+    /// Returns all loops, each one after its sub loops
+    pub fn post_order(&self) -> Vec<Id<Loop<BB>>> {
+        fn visit<BB: BasicBlockTrait>(
+            loops: &Loops<BB>,
+            l: Id<Loop<BB>>,
+            order: &mut Vec<Id<Loop<BB>>>,
+        ) {
+            for &sub in loops.arena[l].sub_loops() {
+                visit(loops, sub, order);
+            }
+            order.push(l);
+        }
+
+        let mut order = vec![];
+        for &l in &self.top_level_loops {
+            visit(self, l, &mut order);
+        }
+        order
+    }
+}
+
+impl<BB: BasicBlockTrait> Loop<BB> {
+    pub fn header(&self) -> Id<BB> {
+        self.header
+    }
+
+    pub fn parent(&self) -> Option<Id<Loop<BB>>> {
+        self.parent
+    }
+
+    pub fn sub_loops(&self) -> &[Id<Loop<BB>>] {
+        &self.sub_loops
+    }
+
+    /// Blocks of the loop, including those of its sub loops
+    pub fn blocks(&self) -> &FxHashSet<Id<BB>> {
+        &self.set
+    }
+
+    pub fn contains(&self, bb: Id<BB>) -> bool {
+        self.set.contains(&bb)
+    }
+
+    /// Returns the blocks in the loop jumping back to the header
+    pub fn latches<BBS: BasicBlocksTrait<BB = BB>>(&self, basic_blocks: &BBS) -> Vec<Id<BB>> {
+        basic_blocks
+            .get_order()
+            .iter()
+            .copied()
+            .filter(|bb| {
+                self.contains(*bb)
+                    && basic_blocks.get_arena()[*bb]
+                        .get_succs()
+                        .contains(&self.header)
+            })
+            .collect()
+    }
+
+    /// Returns the latch if there's only one
+    pub fn latch<BBS: BasicBlocksTrait<BB = BB>>(&self, basic_blocks: &BBS) -> Option<Id<BB>> {
+        match self.latches(basic_blocks).as_slice() {
+            &[latch] => Some(latch),
+            _ => None,
+        }
+    }
+
+    /// Returns the only block outside the loop jumping to the header, if it has no other
+    /// successors
+    pub fn preheader<BBS: BasicBlocksTrait<BB = BB>>(&self, basic_blocks: &BBS) -> Option<Id<BB>> {
+        let arena = basic_blocks.get_arena();
+        let mut outside = arena[self.header]
+            .get_preds()
+            .iter()
+            .filter(|&&p| !self.contains(p));
+        match (outside.next(), outside.next()) {
+            (Some(&pred), None) if arena[pred].get_succs().len() == 1 => Some(pred),
+            _ => None,
+        }
+    }
+
+    /// Returns the blocks in the loop with a successor outside the loop
+    pub fn exiting_blocks<BBS: BasicBlocksTrait<BB = BB>>(
+        &self,
+        basic_blocks: &BBS,
+    ) -> Vec<Id<BB>> {
+        basic_blocks
+            .get_order()
+            .iter()
+            .copied()
+            .filter(|bb| {
+                self.contains(*bb)
+                    && basic_blocks.get_arena()[*bb]
+                        .get_succs()
+                        .iter()
+                        .any(|succ| !self.contains(*succ))
+            })
+            .collect()
+    }
+
+    /// Returns the blocks outside the loop with a predecessor in the loop
+    pub fn exit_blocks<BBS: BasicBlocksTrait<BB = BB>>(&self, basic_blocks: &BBS) -> Vec<Id<BB>> {
+        basic_blocks
+            .get_order()
+            .iter()
+            .copied()
+            .filter(|bb| {
+                !self.contains(*bb)
+                    && basic_blocks.get_arena()[*bb]
+                        .get_preds()
+                        .iter()
+                        .any(|pred| self.contains(*pred))
+            })
+            .collect()
+    }
+
+    /// Returns true if every exit block is only reached from the loop
+    pub fn has_dedicated_exits<BBS: BasicBlocksTrait<BB = BB>>(&self, basic_blocks: &BBS) -> bool {
+        self.exit_blocks(basic_blocks).iter().all(|&exit| {
+            basic_blocks.get_arena()[exit]
+                .get_preds()
+                .iter()
+                .all(|pred| self.contains(*pred))
+        })
+    }
 }
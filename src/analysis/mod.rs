@@ -1,2 +1,7 @@
+pub mod alias_analysis;
+pub mod call_graph;
+pub mod control_dependence;
 pub mod dom_tree;
 pub mod loops;
+pub mod post_dom_tree;
+pub mod scalar_evolution;
@@ -0,0 +1,170 @@
+use crate::traits::basic_block::{BasicBlockTrait, BasicBlocksTrait};
+use id_arena::Id;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Post-dominator tree. Its root is a virtual exit node that every block without successors
+/// (e.g. ending in `ret`) leads to, so that a function with several exits has a single tree.
+/// Blocks that never reach an exit, such as the blocks of an infinite loop, aren't in the tree.
+#[derive(Debug)]
+pub struct PostDominatorTree<T: BasicBlockTrait> {
+    /// Immediate post-dominator of each block in the tree. `None` is the virtual exit node.
+    pub ipdom: FxHashMap<Id<T>, Option<Id<T>>>,
+    /// Blocks immediately post-dominated by each block, or by the virtual exit node for `None`
+    pub tree: FxHashMap<Option<Id<T>>, FxHashSet<Id<T>>>,
+}
+
+pub struct PostDominatorTreeConstructor<'a, BBS: BasicBlocksTrait> {
+    basic_blocks: &'a BBS,
+    blocks: FxHashSet<Id<BBS::BB>>,
+    /// Numbers in the post order of the reverse CFG walked from the virtual exit node
+    po_num: FxHashMap<Option<Id<BBS::BB>>, usize>,
+    post_order: Vec<Option<Id<BBS::BB>>>,
+    idom: FxHashMap<Option<Id<BBS::BB>>, Option<Id<BBS::BB>>>,
+}
+
+impl<T: BasicBlockTrait> PostDominatorTree<T> {
+    /// Returns true if `bb` reaches an exit
+    pub fn contains(&self, bb: Id<T>) -> bool {
+        self.ipdom.contains_key(&bb)
+    }
+
+    /// Returns the immediate post-dominator of `bb`, unless it's the virtual exit node or `bb`
+    /// isn't in the tree
+    pub fn ipdom_of(&self, bb: Id<T>) -> Option<Id<T>> {
+        self.ipdom.get(&bb).copied().flatten()
+    }
+
+    pub fn post_dominate_bb(&self, bb0: Id<T>, bb1: Id<T>) -> bool {
+        if !self.contains(bb1) {
+            return false;
+        }
+        let mut runner = Some(bb1);
+        while let Some(bb) = runner {
+            if bb == bb0 {
+                return true;
+            }
+            runner = self.ipdom[&bb];
+        }
+        false
+    }
+
+    pub fn children_of(&self, bb: Option<Id<T>>) -> Option<&FxHashSet<Id<T>>> {
+        self.tree.get(&bb)
+    }
+}
+
+impl<'a, BBS: BasicBlocksTrait> PostDominatorTreeConstructor<'a, BBS> {
+    pub fn new(basic_blocks: &'a BBS) -> Self {
+        Self {
+            basic_blocks,
+            blocks: basic_blocks.get_order().iter().copied().collect(),
+            po_num: FxHashMap::default(),
+            post_order: vec![],
+            idom: FxHashMap::default(),
+        }
+    }
+
+    pub fn construct(mut self) -> PostDominatorTree<BBS::BB> {
+        self.number_by_dfs(None);
+
+        // Cooper, Harvey and Kennedy's iterative algorithm on the reverse CFG
+        self.idom.insert(None, None);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in (0..self.post_order.len() - 1).rev() {
+                let node = self.post_order[i];
+                let mut new_idom = None;
+                for succ in self.succs(node) {
+                    if !self.idom.contains_key(&succ) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => succ,
+                        Some(idom) => self.intersect(succ, idom),
+                    });
+                }
+                let new_idom = new_idom.unwrap();
+                if self.idom.get(&node) != Some(&new_idom) {
+                    self.idom.insert(node, new_idom);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut tree = PostDominatorTree {
+            ipdom: FxHashMap::default(),
+            tree: FxHashMap::default(),
+        };
+        for (&node, &idom) in &self.idom {
+            if let Some(bb) = node {
+                tree.ipdom.insert(bb, idom);
+                tree.tree
+                    .entry(idom)
+                    .or_insert_with(FxHashSet::default)
+                    .insert(bb);
+            }
+        }
+        tree
+    }
+
+    fn number_by_dfs(&mut self, node: Option<Id<BBS::BB>>) {
+        if self.po_num.contains_key(&node) {
+            return;
+        }
+        // Mark as visited; the number is fixed once every predecessor is numbered
+        self.po_num.insert(node, usize::MAX);
+        for pred in self.preds(node) {
+            self.number_by_dfs(pred);
+        }
+        self.po_num.insert(node, self.post_order.len());
+        self.post_order.push(node);
+    }
+
+    fn intersect(
+        &self,
+        mut a: Option<Id<BBS::BB>>,
+        mut b: Option<Id<BBS::BB>>,
+    ) -> Option<Id<BBS::BB>> {
+        while a != b {
+            while self.po_num[&a] < self.po_num[&b] {
+                a = self.idom[&a];
+            }
+            while self.po_num[&b] < self.po_num[&a] {
+                b = self.idom[&b];
+            }
+        }
+        a
+    }
+
+    /// Returns the predecessors of `node` in the reverse CFG. The exits are the predecessors of
+    /// the virtual exit node.
+    fn preds(&self, node: Option<Id<BBS::BB>>) -> Vec<Option<Id<BBS::BB>>> {
+        let arena = self.basic_blocks.get_arena();
+        match node {
+            None => self
+                .basic_blocks
+                .get_order()
+                .iter()
+                .filter(|&&bb| arena[bb].get_succs().len() == 0)
+                .map(|&bb| Some(bb))
+                .collect(),
+            Some(bb) => arena[bb]
+                .get_preds()
+                .iter()
+                .filter(|pred| self.blocks.contains(pred))
+                .map(|&pred| Some(pred))
+                .collect(),
+        }
+    }
+
+    /// Returns the successors of `node` in the reverse CFG
+    fn succs(&self, node: Option<Id<BBS::BB>>) -> Vec<Option<Id<BBS::BB>>> {
+        let bb = node.unwrap();
+        let succs = self.basic_blocks.get_arena()[bb].get_succs();
+        if succs.len() == 0 {
+            return vec![None];
+        }
+        succs.iter().map(|&succ| Some(succ)).collect()
+    }
+}
@@ -0,0 +1,364 @@
+use super::loops::{Loop, Loops};
+use crate::ir::{
+    basic_block::BasicBlock,
+    function::Function,
+    opcode::{ICmpKind, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+use id_arena::Id;
+use rustc_hash::FxHashMap;
+use std::fmt;
+
+pub type LoopId = Id<Loop<BasicBlock>>;
+
+/// Integer value expressed in terms of the iterations of the loops it is computed in
+#[derive(Debug, Clone, PartialEq)]
+pub enum SCEV {
+    Constant(i64),
+    /// A value the analysis can't look into
+    Unknown(Value),
+    Add(Box<SCEV>, Box<SCEV>),
+    Mul(Box<SCEV>, i64),
+    AddRec(AddRec),
+}
+
+/// `{start,+,step}`: `start` in the first iteration of the loop, incremented by `step` in each
+/// of the following ones. `start` is invariant in the loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AddRec {
+    pub start: Box<SCEV>,
+    pub step: i64,
+    pub loop_id: LoopId,
+}
+
+pub struct ScalarEvolution<'a> {
+    func: &'a Function,
+    loops: &'a Loops<BasicBlock>,
+    cache: FxHashMap<InstructionId, SCEV>,
+}
+
+impl<'a> ScalarEvolution<'a> {
+    pub fn new(func: &'a Function, loops: &'a Loops<BasicBlock>) -> Self {
+        Self {
+            func,
+            loops,
+            cache: FxHashMap::default(),
+        }
+    }
+
+    pub fn get(&mut self, val: &Value) -> SCEV {
+        match val {
+            Value::Immediate(ImmediateValue::Int8(i)) => SCEV::Constant(*i as i64),
+            Value::Immediate(ImmediateValue::Int32(i)) => SCEV::Constant(*i as i64),
+            Value::Immediate(ImmediateValue::Int64(i)) => SCEV::Constant(*i),
+            Value::Instruction(InstructionValue { id, ty, .. })
+                if matches!(ty, Type::Int32 | Type::Int64) =>
+            {
+                if let Some(scev) = self.cache.get(id) {
+                    return scev.clone();
+                }
+                let scev = self.compute(*id);
+                self.cache.insert(*id, scev.clone());
+                scev
+            }
+            _ => SCEV::Unknown(*val),
+        }
+    }
+
+    fn compute(&mut self, id: InstructionId) -> SCEV {
+        let func = self.func;
+        let inst = &func.inst_table[id];
+        let unknown = SCEV::Unknown(self.func.inst_value(id));
+        match inst.opcode {
+            Opcode::Add => {
+                let lhs = self.get(inst.operands[0].as_value());
+                let rhs = self.get(inst.operands[1].as_value());
+                self.add(lhs, rhs)
+            }
+            Opcode::Sub => {
+                let lhs = self.get(inst.operands[0].as_value());
+                let rhs = self.get(inst.operands[1].as_value());
+                let rhs = self.mul(rhs, -1);
+                self.add(lhs, rhs)
+            }
+            Opcode::Mul => {
+                let lhs = self.get(inst.operands[0].as_value());
+                let rhs = self.get(inst.operands[1].as_value());
+                match (lhs, rhs) {
+                    (x, SCEV::Constant(c)) | (SCEV::Constant(c), x) => self.mul(x, c),
+                    _ => unknown,
+                }
+            }
+            Opcode::Shl => {
+                let lhs = self.get(inst.operands[0].as_value());
+                match self.get(inst.operands[1].as_value()) {
+                    SCEV::Constant(k) if 0 <= k && k < 63 => self.mul(lhs, 1 << k),
+                    _ => unknown,
+                }
+            }
+            Opcode::Phi => self.recurrence(id).unwrap_or(unknown),
+            _ => unknown,
+        }
+    }
+
+    /// Returns the recurrence computed by a phi in a loop header, merging a value from outside
+    /// the loop with itself plus a constant from inside
+    fn recurrence(&mut self, phi: InstructionId) -> Option<SCEV> {
+        let (func, loops) = (self.func, self.loops);
+        let inst = &func.inst_table[phi];
+        let loop_id = loops.get_loop_for(inst.parent)?;
+        let l = &loops.arena[loop_id];
+        if l.header() != inst.parent || inst.operands.len() != 4 {
+            return None;
+        }
+        let (start, back) = match (
+            inst.operands[1].as_basic_block(),
+            inst.operands[3].as_basic_block(),
+        ) {
+            (&x, &y) if !l.contains(x) && l.contains(y) => (0, 2),
+            (&x, &y) if l.contains(x) && !l.contains(y) => (2, 0),
+            _ => return None,
+        };
+        let start = *inst.operands[start].as_value();
+        let back = *inst.operands[back].as_value();
+
+        // While the phi stands for itself, what is computed from it is valid only here
+        let phi_val = self.func.inst_value(phi);
+        let cache = self.cache.clone();
+        self.cache.insert(phi, SCEV::Unknown(phi_val));
+        let back = self.get(&back);
+        self.cache = cache;
+
+        let step = match back {
+            SCEV::Add(x, c) if *x == SCEV::Unknown(phi_val) => match *c {
+                SCEV::Constant(c) => c,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let start = self.get(&start);
+        if !self.is_invariant(&start, loop_id) {
+            return None;
+        }
+        Some(SCEV::AddRec(AddRec {
+            start: Box::new(start),
+            step,
+            loop_id,
+        }))
+    }
+
+    pub fn add(&self, lhs: SCEV, rhs: SCEV) -> SCEV {
+        match (lhs, rhs) {
+            (SCEV::Constant(x), SCEV::Constant(y)) => SCEV::Constant(x.wrapping_add(y)),
+            (SCEV::Constant(0), x) | (x, SCEV::Constant(0)) => x,
+            (SCEV::AddRec(x), SCEV::AddRec(y)) if x.loop_id == y.loop_id => SCEV::AddRec(AddRec {
+                start: Box::new(self.add(*x.start, *y.start)),
+                step: x.step.wrapping_add(y.step),
+                loop_id: x.loop_id,
+            }),
+            (SCEV::AddRec(x), y) | (y, SCEV::AddRec(x)) if self.is_invariant(&y, x.loop_id) => {
+                SCEV::AddRec(AddRec {
+                    start: Box::new(self.add(*x.start, y)),
+                    step: x.step,
+                    loop_id: x.loop_id,
+                })
+            }
+            // Constants are kept outermost
+            (c @ SCEV::Constant(_), x) => self.add(x, c),
+            (SCEV::Add(x, c), SCEV::Constant(y)) => match *c {
+                SCEV::Constant(c) => self.add(*x, SCEV::Constant(c.wrapping_add(y))),
+                c => SCEV::Add(
+                    Box::new(SCEV::Add(x, Box::new(c))),
+                    Box::new(SCEV::Constant(y)),
+                ),
+            },
+            (SCEV::Add(x, c), y) if matches!(*c, SCEV::Constant(_)) => {
+                let x = self.add(*x, y);
+                self.add(x, *c)
+            }
+            (x, y) => SCEV::Add(Box::new(x), Box::new(y)),
+        }
+    }
+
+    pub fn mul(&self, lhs: SCEV, rhs: i64) -> SCEV {
+        match (lhs, rhs) {
+            (_, 0) => SCEV::Constant(0),
+            (x, 1) => x,
+            (SCEV::Constant(x), y) => SCEV::Constant(x.wrapping_mul(y)),
+            (SCEV::Add(x, y), c) => {
+                let x = self.mul(*x, c);
+                let y = self.mul(*y, c);
+                self.add(x, y)
+            }
+            (SCEV::Mul(x, y), c) => self.mul(*x, y.wrapping_mul(c)),
+            (SCEV::AddRec(x), c) => SCEV::AddRec(AddRec {
+                start: Box::new(self.mul(*x.start, c)),
+                step: x.step.wrapping_mul(c),
+                loop_id: x.loop_id,
+            }),
+            (x, c) => SCEV::Mul(Box::new(x), c),
+        }
+    }
+
+    /// Returns true if `scev` has the same value in every iteration of the loop
+    pub fn is_invariant(&self, scev: &SCEV, loop_id: LoopId) -> bool {
+        let l = &self.loops.arena[loop_id];
+        match scev {
+            SCEV::Constant(_) => true,
+            SCEV::Unknown(Value::Instruction(InstructionValue { id, .. })) => {
+                !l.contains(self.func.inst_table[*id].parent)
+            }
+            SCEV::Unknown(_) => true,
+            SCEV::Add(x, y) => self.is_invariant(x, loop_id) && self.is_invariant(y, loop_id),
+            SCEV::Mul(x, _) => self.is_invariant(x, loop_id),
+            SCEV::AddRec(x) => {
+                !l.contains(self.loops.arena[x.loop_id].header())
+                    && self.is_invariant(&x.start, loop_id)
+            }
+        }
+    }
+
+    /// Returns the number of times the back edge of the loop is taken, if the loop is left only
+    /// from its header and the count is a constant
+    pub fn backedge_taken_count(&mut self, loop_id: LoopId) -> Option<i64> {
+        let (func, loops) = (self.func, self.loops);
+        let l = &loops.arena[loop_id];
+        let header = l.header();
+        let arena = &func.basic_blocks.arena;
+        if l.blocks()
+            .iter()
+            .any(|&bb| bb != header && arena[bb].succ.iter().any(|&succ| !l.contains(succ)))
+        {
+            return None;
+        }
+
+        let br = &func.inst_table[arena[header].iseq_ref().last()?.as_instruction().id];
+        if br.opcode != Opcode::CondBr {
+            return None;
+        }
+        let exit_if_true = match (
+            br.operands[1].as_basic_block(),
+            br.operands[2].as_basic_block(),
+        ) {
+            (&x, &y) if !l.contains(x) && l.contains(y) => true,
+            (&x, &y) if l.contains(x) && !l.contains(y) => false,
+            _ => return None,
+        };
+        let cond = match br.operands[0] {
+            Operand::Value(Value::Instruction(InstructionValue { id, .. })) => &func.inst_table[id],
+            _ => return None,
+        };
+        if cond.opcode != Opcode::ICmp {
+            return None;
+        }
+        let kind = *cond.operands[0].as_icmp_kind();
+        let ty = cond.operands[1].as_value().get_type();
+        let lhs = self.get(cond.operands[1].as_value());
+        let rhs = self.get(cond.operands[2].as_value());
+
+        // Stay in the loop while 'start + step * i kind limit' holds, if 'negated' is false
+        let (start, step, limit) = match (lhs, rhs) {
+            (SCEV::AddRec(x), SCEV::Constant(c)) if x.loop_id == loop_id => match *x.start {
+                SCEV::Constant(s) => (s, x.step, c),
+                _ => return None,
+            },
+            // 'c kind x' is '-x kind -c'
+            (SCEV::Constant(c), SCEV::AddRec(x)) if x.loop_id == loop_id => match *x.start {
+                SCEV::Constant(s) => (s.checked_neg()?, x.step.checked_neg()?, c.checked_neg()?),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let count = trip_count(kind, exit_if_true, start, step, limit)?;
+
+        // Every value taken must fit in the type so that none wraps around
+        let (min, max) = match ty {
+            Type::Int32 => (i32::min_value() as i64, i32::max_value() as i64),
+            Type::Int64 => (i64::min_value(), i64::max_value()),
+            _ => return None,
+        };
+        let last = step.checked_mul(count)?.checked_add(start)?;
+        let fits = |x: i64| {
+            x.checked_neg()
+                .map_or(false, |y| min <= x && x <= max && min <= y && y <= max)
+        };
+        if !fits(start) || !fits(last) {
+            return None;
+        }
+        Some(count)
+    }
+
+    /// Returns the value of `scev` in the `n`th iteration of the loop, if it is a constant
+    pub fn evaluate_at(&self, scev: &SCEV, loop_id: LoopId, n: i64) -> Option<i64> {
+        match scev {
+            SCEV::Constant(c) => Some(*c),
+            SCEV::AddRec(x) if x.loop_id == loop_id => {
+                let start = self.evaluate_at(&x.start, loop_id, n)?;
+                x.step.checked_mul(n)?.checked_add(start)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the number of iterations for which `start + step * i kind limit` (or its negation if
+/// `negated`) holds before it doesn't for the first time
+fn trip_count(kind: ICmpKind, negated: bool, start: i64, step: i64, limit: i64) -> Option<i64> {
+    // 'i < c' holds for the first 'ceil((c - s) / t)' iterations
+    let lt = |start: i64, step: i64, limit: i64| -> Option<i64> {
+        if start >= limit {
+            return Some(0);
+        }
+        if step <= 0 {
+            return None;
+        }
+        let dist = limit.checked_sub(start)?;
+        Some(dist.checked_add(step - 1)? / step)
+    };
+    match (kind, negated) {
+        (ICmpKind::Lt, false) => lt(start, step, limit),
+        (ICmpKind::Le, false) => lt(start, step, limit.checked_add(1)?),
+        // 'i >= c' is '-i < -c + 1' and 'i > c' is '-i < -c'
+        (ICmpKind::Lt, true) => lt(
+            start.checked_neg()?,
+            step.checked_neg()?,
+            1i64.checked_sub(limit)?,
+        ),
+        (ICmpKind::Le, true) => lt(
+            start.checked_neg()?,
+            step.checked_neg()?,
+            limit.checked_neg()?,
+        ),
+        (ICmpKind::Eq, false) if start != limit => Some(0),
+        (ICmpKind::Eq, false) if step != 0 => Some(1),
+        (ICmpKind::Eq, false) => None,
+        (ICmpKind::Eq, true) => {
+            if step == 0 {
+                return if start == limit { Some(0) } else { None };
+            }
+            let dist = limit.checked_sub(start)?;
+            if dist % step == 0 && dist / step >= 0 {
+                Some(dist / step)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for SCEV {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SCEV::Constant(c) => write!(f, "{}", c),
+            SCEV::Unknown(Value::Instruction(InstructionValue { id, .. })) => {
+                write!(f, "%{}", id.index())
+            }
+            SCEV::Unknown(Value::Argument(arg)) => write!(f, "%arg.{}", arg.index),
+            SCEV::Unknown(_) => write!(f, "?"),
+            SCEV::Add(x, y) => write!(f, "({} + {})", x, y),
+            SCEV::Mul(x, c) => write!(f, "({} * {})", x, c),
+            SCEV::AddRec(x) => write!(f, "{{{},+,{}}}", x.start, x.step),
+        }
+    }
+}
@@ -60,6 +60,7 @@ impl FrameObjectsInfo {
             offset_map,
             total_size,
             callee_saved_regs_byte,
+            realign: None,
         }
     }
 
@@ -29,6 +29,9 @@ impl TypeSize for Type {
             Type::Struct(id) => tys.base.borrow().non_primitive_types[*id]
                 .as_struct()
                 .size_in_byte(tys),
+            Type::Vector(id) => tys.base.borrow().non_primitive_types[*id]
+                .as_vector()
+                .size_in_byte(tys),
             Type::Pointer(_) => 8,
             Type::Function(_) => unimplemented!(),
             Type::Void => 0,
@@ -52,6 +55,9 @@ impl TypeSize for Type {
             Type::Struct(id) => tys.base.borrow().non_primitive_types[*id]
                 .as_struct()
                 .align_in_byte(tys),
+            Type::Vector(id) => tys.base.borrow().non_primitive_types[*id]
+                .as_vector()
+                .align_in_byte(tys),
             Type::Pointer(_) => 8,
             Type::Function(_) => unimplemented!(),
             Type::Void => 0,
@@ -81,6 +87,20 @@ impl TypeSize for ArrayType {
     }
 }
 
+impl TypeSize for VectorType {
+    fn size_in_byte(&self, tys: &Types) -> usize {
+        self.elem_ty.size_in_byte(tys) * self.len
+    }
+
+    fn size_in_bits(&self, tys: &Types) -> usize {
+        self.size_in_byte(tys) * 8
+    }
+
+    fn align_in_byte(&self, tys: &Types) -> usize {
+        ::std::cmp::min(self.size_in_byte(tys).next_power_of_two(), MAX_ALIGN)
+    }
+}
+
 impl TypeSize for StructType {
     fn size_in_byte(&self, _tys: &Types) -> usize {
         self.size()
@@ -47,6 +47,8 @@ impl<'a> Assembler<'a> {
     }
 
     pub fn assemble(&mut self) {
+        // TODO: Emit global variables. Thread-local ones go to .tbss/.tdata, but faerie 0.15
+        // can't emit TLS sections or symbols.
         for (_, func) in &self.module.functions {
             self.artifact
                 .declare(&func.name, Decl::function().global())
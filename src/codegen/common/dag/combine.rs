@@ -61,6 +61,7 @@ impl Combine {
             NodeKind::IR(IRNodeKind::Add) => self.combine_node_add(replace, heap, node),
             NodeKind::IR(IRNodeKind::Mul) => self.combine_node_mul(replace, heap, node),
             NodeKind::IR(IRNodeKind::BrCond) => self.combine_node_brcond(replace, heap, node),
+            NodeKind::IR(IRNodeKind::Select) => self.combine_node_select(replace, heap, node),
             _ => self.combine_operands(replace, heap, node),
         };
 
@@ -183,10 +184,67 @@ impl Combine {
                     Type::Void,
                 ))
             }
-            _ => self.combine_operands(replace, heap, node),
+            // Any other condition is 1 or 0 in a register
+            _ => {
+                let (cond_kind, lhs, rhs) = self.compare_with_one(replace, heap, cond);
+                let br = self.combine_node(replace, heap, br);
+                heap.alloc(DAGNode::new(
+                    NodeKind::IR(IRNodeKind::Brcc),
+                    vec![cond_kind, lhs, rhs, br],
+                    Type::Void,
+                ))
+            }
         }
     }
 
+    fn combine_node_select(
+        &mut self,
+        replace: &mut FxHashMap<Raw<DAGNode>, Raw<DAGNode>>,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Raw<DAGNode> {
+        let cond = node.operand[0];
+        let (cond_kind, lhs, rhs) = match cond.kind {
+            NodeKind::IR(IRNodeKind::Setcc) => {
+                // Legalization may flip the condition kind, so it's not shared with other users
+                let cond_kind = heap.alloc((*cond.operand[0]).clone());
+                let lhs = self.combine_node(replace, heap, cond.operand[1]);
+                let rhs = self.combine_node(replace, heap, cond.operand[2]);
+                (cond_kind, lhs, rhs)
+            }
+            // Any other condition is 1 or 0 in a register
+            _ => self.compare_with_one(replace, heap, cond),
+        };
+        let t = self.combine_node(replace, heap, node.operand[1]);
+        let f = self.combine_node(replace, heap, node.operand[2]);
+        heap.alloc(DAGNode::new(
+            NodeKind::IR(IRNodeKind::Selectcc),
+            vec![cond_kind, lhs, rhs, t, f],
+            node.ty,
+        ))
+    }
+
+    // Returns the operands of `cond == 1`
+    fn compare_with_one(
+        &mut self,
+        replace: &mut FxHashMap<Raw<DAGNode>, Raw<DAGNode>>,
+        heap: &mut DAGHeap,
+        cond: Raw<DAGNode>,
+    ) -> (Raw<DAGNode>, Raw<DAGNode>, Raw<DAGNode>) {
+        let cond_kind = heap.alloc(DAGNode::new(
+            NodeKind::Operand(OperandNodeKind::CondKind(CondKind::Eq)),
+            vec![],
+            Type::Void,
+        ));
+        let lhs = self.combine_node(replace, heap, cond);
+        let one = heap.alloc(DAGNode::new(
+            NodeKind::Operand(OperandNodeKind::Constant(ConstantKind::Int32(1))),
+            vec![],
+            Type::Int32,
+        ));
+        (cond_kind, lhs, one)
+    }
+
     fn combine_operands(
         &mut self,
         replace: &mut FxHashMap<Raw<DAGNode>, Raw<DAGNode>>,
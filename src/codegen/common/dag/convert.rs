@@ -75,6 +75,7 @@ impl<'a> ConvertToDAGModule<'a> {
             functions,
             types: self.module.types.clone(),
             global_vars: self.module.global_vars.clone(),
+            inline_asms: self.module.inline_asms.clone(),
         }
     }
 }
@@ -161,7 +162,10 @@ impl<'a> ConvertToDAGNode<'a> {
                 Opcode::Alloca => {
                     let ty = *inst.operands[0].as_type();
                     let fi_ty = self.func.types.new_pointer_ty(ty);
-                    let frinfo = self.local_mgr.alloc(&ty);
+                    let frinfo = match inst.mem_attr.align {
+                        Some(align) => self.local_mgr.alloc_with_align(&ty, align),
+                        None => self.local_mgr.alloc(&ty),
+                    };
                     let fi = self.alloc_node(DAGNode::new(
                         NodeKind::Operand(OperandNodeKind::FrameIndex(frinfo)), // TODO
                         vec![],
@@ -203,6 +207,8 @@ impl<'a> ConvertToDAGNode<'a> {
                     let indices: Vec<Value> =
                         inst.operands[1..].iter().map(|v| *v.as_value()).collect();
                     let gep = self.construct_node_for_gep(inst.operands[0].as_value(), &indices);
+                    // A phi may have referred to the gep already
+                    let gep = self.alloc_node_as_necessary(inst_id, (*gep).clone());
                     if self.block.liveness.borrow().live_out.contains(&inst_id) {
                         let gep = self.make_chain_with_copying(gep);
                         self.inst_to_node.insert(inst_id, gep);
@@ -337,14 +343,34 @@ impl<'a> ConvertToDAGNode<'a> {
                         vec![],
                         Type::Void,
                     ));
-                    let id = self.alloc_node_as_necessary(
-                        inst_id,
-                        DAGNode::new(NodeKind::IR(IRNodeKind::Setcc), vec![cond, v1, v2], inst.ty),
-                    );
+                    let setcc =
+                        DAGNode::new(NodeKind::IR(IRNodeKind::Setcc), vec![cond, v1, v2], inst.ty);
                     if self.block.liveness.borrow().live_out.contains(&inst_id) {
+                        // No register holds an i1, so the result used by other blocks is
+                        // materialized as 1 or 0
+                        let setcc = self.alloc_node(setcc);
+                        let one = self.alloc_node(DAGNode::new(
+                            NodeKind::Operand(OperandNodeKind::Constant(ConstantKind::Int32(1))),
+                            vec![],
+                            Type::Int32,
+                        ));
+                        let zero = self.alloc_node(DAGNode::new(
+                            NodeKind::Operand(OperandNodeKind::Constant(ConstantKind::Int32(0))),
+                            vec![],
+                            Type::Int32,
+                        ));
+                        let id = self.alloc_node_as_necessary(
+                            inst_id,
+                            DAGNode::new(
+                                NodeKind::IR(IRNodeKind::Select),
+                                vec![setcc, one, zero],
+                                Type::Int32,
+                            ),
+                        );
                         let copy_from_reg = self.make_chain_with_copying(id);
                         self.inst_to_node.insert(inst_id, copy_from_reg);
                     } else {
+                        let id = self.alloc_node_as_necessary(inst_id, setcc);
                         self.inst_to_node.insert(inst_id, id);
                     }
                 }
@@ -368,6 +394,36 @@ impl<'a> ConvertToDAGNode<'a> {
                         self.inst_to_node.insert(inst_id, id);
                     }
                 }
+                Opcode::ExtractElement
+                | Opcode::InsertElement
+                | Opcode::ShuffleVector
+                | Opcode::Select => {
+                    let operands = inst
+                        .operands
+                        .iter()
+                        .map(|v| self.get_node_from_value(v.as_value()))
+                        .collect();
+                    let id = self.alloc_node_as_necessary(
+                        inst_id,
+                        DAGNode::new(
+                            match inst.opcode {
+                                Opcode::ExtractElement => NodeKind::IR(IRNodeKind::ExtractElement),
+                                Opcode::InsertElement => NodeKind::IR(IRNodeKind::InsertElement),
+                                Opcode::ShuffleVector => NodeKind::IR(IRNodeKind::ShuffleVector),
+                                Opcode::Select => NodeKind::IR(IRNodeKind::Select),
+                                _ => unreachable!(),
+                            },
+                            operands,
+                            inst.ty,
+                        ),
+                    );
+                    if self.block.liveness.borrow().live_out.contains(&inst_id) {
+                        let copy_from_reg = self.make_chain_with_copying(id);
+                        self.inst_to_node.insert(inst_id, copy_from_reg);
+                    } else {
+                        self.inst_to_node.insert(inst_id, id);
+                    }
+                }
                 Opcode::Phi => {
                     let mut operands = vec![];
                     for i in (0..inst.operands.len()).step_by(2) {
@@ -498,12 +554,18 @@ impl<'a> ConvertToDAGNode<'a> {
                     vec![],
                     *ty,
                 ));
-                self.alloc_node(DAGNode::new(
-                    NodeKind::IR(IRNodeKind::GlobalAddr),
-                    vec![g],
-                    *ty,
-                ))
+                let kind = if self.module.global_vars.arena[*id].thread_local {
+                    IRNodeKind::ThreadLocalAddr
+                } else {
+                    IRNodeKind::GlobalAddr
+                };
+                self.alloc_node(DAGNode::new(NodeKind::IR(kind), vec![g], *ty))
             }
+            Value::InlineAsm(InlineAsmValue { id, ty }) => self.alloc_node(DAGNode::new(
+                NodeKind::Operand(OperandNodeKind::Address(AddressKind::InlineAsm(*id))),
+                vec![],
+                *ty,
+            )),
             Value::None => self.alloc_node(DAGNode::new(NodeKind::None, vec![], Type::Void)),
         }
     }
@@ -6,7 +6,7 @@ use crate::codegen::{
     },
     common::dag::basic_block::*,
 };
-use crate::ir::{global_val::GlobalVariableId, opcode::*, types::*};
+use crate::ir::{global_val::GlobalVariableId, inline_asm::InlineAsmId, opcode::*, types::*};
 use crate::util::allocator::*;
 use id_arena::*;
 use rustc_hash::FxHashMap;
@@ -72,9 +72,15 @@ pub enum IRNodeKind {
     Ret,
     Sext,
     FCmp,
+    ExtractElement, // vec, idx
+    InsertElement,  // vec, val, idx
+    ShuffleVector,  // vec1, vec2, mask indices
+    Select,         // cond, val if true, val if false
+    Selectcc,       // cond kind, lhs, rhs, val if true, val if false
 
     FIAddr,
     GlobalAddr,
+    ThreadLocalAddr,
 
     CopyToReg,
     CopyFromReg,
@@ -111,6 +117,7 @@ pub enum CondKind {
 pub enum AddressKind {
     FunctionName(String),
     Global(GlobalVariableId),
+    InlineAsm(InlineAsmId),
 }
 
 impl Into<CondKind> for ICmpKind {
@@ -11,6 +11,7 @@ pub const ALIGN: i32 = 16;
 #[derive(Debug, Clone)]
 pub struct LocalVariables {
     pub locals: Vec<FrameIndexInfo>,
+    pub aligns: FxHashMap<FrameIndexKind, usize>, // explicitly specified alignment of locals
     pub cur_idx: usize,
 }
 
@@ -19,6 +20,7 @@ pub struct FrameObjectsInfo {
     pub offset_map: FxHashMap<FrameIndexKind, i32>, // frame index -> offset
     pub total_size: i32,
     pub callee_saved_regs_byte: usize, // unaligned
+    pub realign: Option<i32>, // the frame pointer is realigned to this if locals need it
 }
 
 #[derive(Clone, PartialEq, Copy)]
@@ -37,6 +39,7 @@ impl LocalVariables {
     pub fn new() -> Self {
         Self {
             locals: vec![],
+            aligns: FxHashMap::default(),
             cur_idx: 0,
         }
     }
@@ -47,6 +50,20 @@ impl LocalVariables {
         self.locals.push(info.clone());
         info
     }
+
+    pub fn alloc_with_align(&mut self, ty: &Type, align: usize) -> FrameIndexInfo {
+        let info = self.alloc(ty);
+        self.aligns.insert(info.idx, align);
+        info
+    }
+
+    /// Returns the alignment of `info` which is at least the natural alignment of its type
+    pub fn align_of(&self, tys: &Types, info: &FrameIndexInfo) -> usize {
+        let align = info.ty.align_in_byte(tys);
+        self.aligns
+            .get(&info.idx)
+            .map_or(align, |&a| cmp::max(a, align))
+    }
 }
 
 impl FrameObjectsInfo {
@@ -5,7 +5,7 @@ use crate::codegen::arch::machine::{
     register::{rc2ty, PhysReg, RegisterClassKind, RegisterId, RegistersInfo, TargetRegisterTrait},
 };
 use crate::codegen::common::machine::{basic_block::*, const_data::DataId};
-use crate::ir::{global_val::GlobalVariableId, types::*};
+use crate::ir::{global_val::GlobalVariableId, inline_asm::InlineAsmId, types::*};
 use id_arena::*;
 use rustc_hash::FxHashMap;
 use std::{fmt, fmt::Debug};
@@ -53,6 +53,7 @@ pub enum AddressKind {
     FunctionName(String),
     Global(GlobalVariableId),
     Label(DataId),
+    InlineAsm(InlineAsmId),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -508,6 +509,13 @@ impl AddressKind {
             _ => panic!(),
         }
     }
+
+    pub fn as_inline_asm(&self) -> &InlineAsmId {
+        match self {
+            AddressKind::InlineAsm(id) => id,
+            _ => panic!(),
+        }
+    }
 }
 
 impl MachineConstant {
@@ -699,6 +707,7 @@ impl fmt::Debug for AddressKind {
             AddressKind::FunctionName(name) => write!(f, "addr<fn:{}>", name),
             AddressKind::Label(id) => write!(f, "label<{}>", id),
             AddressKind::Global(id) => write!(f, "global<{:?}>", id),
+            AddressKind::InlineAsm(id) => write!(f, "asm<{:?}>", id),
         }
     }
 }
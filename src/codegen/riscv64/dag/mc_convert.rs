@@ -29,7 +29,13 @@ pub fn convert_module(module: DAGModule) -> MachineModule {
     for (_, func) in module.functions {
         functions.alloc(convert_function(/*&module.types,*/ func));
     }
-    MachineModule::new(module.name, functions, module.types, module.global_vars)
+    MachineModule::new(
+        module.name,
+        functions,
+        module.types,
+        module.global_vars,
+        module.inline_asms,
+    )
 }
 
 pub fn convert_function(/*types: &Types,*/ dag_func: DAGFunction) -> MachineFunction {
@@ -6,13 +6,19 @@ use crate::codegen::common::machine::{
     function::{InstIter, MachineFunction},
     module::MachineModule,
 };
-use crate::ir::{global_val::GlobalVariableId, types::TypeSize};
+use crate::ir::{
+    global_val::{GlobalVariable, GlobalVariableId, TLSModel},
+    inline_asm::{InlineAsm, InlineAsmId},
+    types::TypeSize,
+};
 use rustc_hash::FxHashMap;
 
 pub struct MachineAsmPrinter {
     pub output: String,
     cur_bb_id_base: usize,
     id_to_global_name: FxHashMap<GlobalVariableId, String>,
+    id_to_tls_model: FxHashMap<GlobalVariableId, TLSModel>,
+    id_to_inline_asm: FxHashMap<InlineAsmId, InlineAsm>,
 }
 
 impl MachineAsmPrinter {
@@ -21,6 +27,8 @@ impl MachineAsmPrinter {
             output: "".to_string(),
             cur_bb_id_base: 0,
             id_to_global_name: FxHashMap::default(),
+            id_to_tls_model: FxHashMap::default(),
+            id_to_inline_asm: FxHashMap::default(),
         }
     }
 
@@ -29,11 +37,24 @@ impl MachineAsmPrinter {
         self.output.push_str("  .intel_syntax noprefix\n");
 
         for (id, g) in &m.global_vars.arena {
+            self.id_to_global_name.insert(id, g.name.clone());
+            if let Some(model) = g.tls_model() {
+                self.id_to_tls_model.insert(id, model);
+                self.run_on_thread_local_var(m, g, model);
+                continue;
+            }
             let size = g.ty.size_in_byte(&m.types);
             let align = g.ty.align_in_byte(&m.types);
+            if !g.is_externally_visible() {
+                self.output
+                    .push_str(format!("  .local {}\n", g.name).as_str());
+            }
             self.output
                 .push_str(format!("  .comm {},{},{}\n", g.name, size, align).as_str());
-            self.id_to_global_name.insert(id, g.name.clone());
+        }
+
+        for (id, asm) in &m.inline_asms.arena {
+            self.id_to_inline_asm.insert(id, asm.clone());
         }
 
         for (_, func) in &m.functions {
@@ -41,6 +62,28 @@ impl MachineAsmPrinter {
         }
     }
 
+    fn run_on_thread_local_var(&mut self, m: &MachineModule, g: &GlobalVariable, model: TLSModel) {
+        // Thread-local variables in other objects are only referenced through the GOT
+        if model == TLSModel::InitialExec {
+            return;
+        }
+        // Global variables have no initializers yet, so they all go to .tbss instead of .tdata
+        let size = g.ty.size_in_byte(&m.types);
+        let align = g.ty.align_in_byte(&m.types);
+        self.output.push_str("  .section .tbss,\"awT\",@nobits\n");
+        self.output
+            .push_str(format!("  .globl {}\n", g.name).as_str());
+        self.output
+            .push_str(format!("  .type {}, @object\n", g.name).as_str());
+        self.output
+            .push_str(format!("  .size {}, {}\n", g.name, size).as_str());
+        self.output
+            .push_str(format!("  .balign {}\n", align).as_str());
+        self.output.push_str(format!("{}:\n", g.name).as_str());
+        self.output.push_str(format!("  .zero {}\n", size).as_str());
+        self.output.push_str("  .text\n");
+    }
+
     fn run_on_function(&mut self, f: &MachineFunction) {
         if f.is_internal {
             return;
@@ -81,6 +124,25 @@ impl MachineAsmPrinter {
     }
 
     fn run_on_inst(&mut self, inst: &MachineInst, fo: &FrameObjectsInfo) {
+        if inst.opcode == MachineOpcode::TLSADDRr64 {
+            return self.run_on_tls_addr(inst);
+        }
+
+        if inst.opcode == MachineOpcode::InlineAsm {
+            return self.run_on_inline_asm(inst);
+        }
+
+        if inst.opcode == MachineOpcode::FMODSDrr {
+            return self.run_on_fmod(inst);
+        }
+
+        if inst.opcode == MachineOpcode::SHLr32CL {
+            let dst = inst.def[0].as_phys_reg();
+            return self
+                .output
+                .push_str(format!("  shl {}, cl\n", dst.name()).as_str());
+        }
+
         self.output.push_str("  ");
 
         // println!("{:?}", inst.opcode);
@@ -116,6 +178,72 @@ impl MachineAsmPrinter {
         self.output.push('\n');
     }
 
+    fn run_on_tls_addr(&mut self, inst: &MachineInst) {
+        let dst = inst.def[0].as_phys_reg();
+        let dst = dst.name();
+        let id = inst.operand[0].as_mem().as_address().as_global();
+        let name = self.global_var_name(id);
+        let access = match self.id_to_tls_model.get(id).unwrap() {
+            TLSModel::LocalExec => format!("  lea {}, [{} + {}@tpoff]\n", dst, dst, name),
+            TLSModel::InitialExec => {
+                format!("  add {}, qword ptr [rip + {}@gottpoff]\n", dst, name)
+            }
+        };
+        self.output
+            .push_str(format!("  mov {}, qword ptr fs:0\n", dst).as_str());
+        self.output.push_str(access.as_str());
+    }
+
+    fn run_on_fmod(&mut self, inst: &MachineInst) {
+        let dst = inst.def[0].as_phys_reg();
+        let src = inst.operand[1].as_register().as_phys_reg();
+        // fprem sets C2 until the remainder is complete. `1b` is the nearest preceding label `1`.
+        for line in &[
+            format!("movsd qword ptr [rsp - 16], {}", src.name()),
+            format!("movsd qword ptr [rsp - 8], {}", dst.name()),
+            "fld qword ptr [rsp - 16]".to_string(),
+            "fld qword ptr [rsp - 8]".to_string(),
+            "1:".to_string(),
+            "fprem".to_string(),
+            "fnstsw ax".to_string(),
+            "test ax, 1024".to_string(),
+            "jnz 1b".to_string(),
+            "fstp qword ptr [rsp - 8]".to_string(),
+            "fstp st(0)".to_string(),
+            format!("movsd {}, qword ptr [rsp - 8]", dst.name()),
+        ] {
+            self.output.push_str(format!("  {}\n", line).as_str());
+        }
+    }
+
+    fn run_on_inline_asm(&mut self, inst: &MachineInst) {
+        let id = inst.operand[0].as_mem().as_address().as_inline_asm();
+        // Operands are numbered from the output, then the inputs
+        let operands: Vec<String> = inst
+            .def
+            .iter()
+            .map(|r| r.as_phys_reg().name().to_string())
+            .chain(inst.operand[1..].iter().map(|o| match o {
+                MachineOperand::Register(r) => r.as_phys_reg().name().to_string(),
+                MachineOperand::Constant(c) => match c {
+                    MachineConstant::Int8(i) => format!("{}", i),
+                    MachineConstant::Int32(i) => format!("{}", i),
+                    MachineConstant::Int64(i) => format!("{}", i),
+                    MachineConstant::F64(_) => unimplemented!(),
+                },
+                e => unimplemented!("{:?}", e),
+            }))
+            .collect();
+        // The template was validated when the inline asm was created
+        let text = self.id_to_inline_asm[id]
+            .expand_template(|n| Ok(operands[n].clone()))
+            .unwrap();
+        for line in text.lines() {
+            self.output
+                .push_str(format!("  {}\n", line.trim()).as_str());
+        }
+    }
+
     fn operand2asm(
         &mut self,
         opcode: &MachineOpcode,
@@ -259,11 +387,13 @@ fn opcode2word(opcode: &MachineOpcode) -> &'static str {
         | MachineOpcode::SUBSDrm
         | MachineOpcode::MULSDrm
         | MachineOpcode::DIVSDrr => 4,
+        MachineOpcode::MOVDQUrm | MachineOpcode::MOVDQUmr => 16,
         _ => 0,
     };
     match byte {
         4 => "dword",
         8 => "qword",
+        16 => "xmmword",
         _ => "",
     }
 }
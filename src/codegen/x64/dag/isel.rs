@@ -68,6 +68,13 @@ impl MISelector {
         let mut selected = isel_pat!(
             // TODO: Refactoring
             (ir.Call _a) => { self.select_call(tys, regs_info, heap, node) }
+            (ir.Add _a, _b): Vector! => { self.select_vector_binop(tys, regs_info, heap, node) }
+            (ir.Sub _a, _b): Vector! => { self.select_vector_binop(tys, regs_info, heap, node) }
+            (ir.Mul _a, _b): Vector! => { self.select_vector_binop(tys, regs_info, heap, node) }
+            (ir.Div _a, _b): Vector! => { self.select_vector_binop(tys, regs_info, heap, node) }
+            (ir.ExtractElement _a, _b) => { self.select_extract_element(tys, regs_info, heap, node) }
+            (ir.InsertElement _a, _b, _c) => { self.select_insert_element(tys, regs_info, heap, node) }
+            (ir.ShuffleVector _a, _b) => { self.select_shuffle_vector(tys, regs_info, heap, node) }
             (ir.Add a, b) {
                 GR32 a {
                     GR32  b => (mi.ADDrr32   a, b)
@@ -137,6 +144,10 @@ impl MISelector {
             }
             (ir.SIToFP x): F64 { GR32 x => (mi.CVTSI2SDrr32 x) }
             (ir.FPToSI x): Int32 { XMM x => (mi.CVTTSD2SIr32r x) }
+            (ir.Load a): Vector! {
+                (ir.FIAddr b) a { mem b => (mi.MOVDQUrm [BaseFi %rbp, b]) }
+                GR64 a => (mi.MOVDQUrm [Base a])
+            }
             (ir.Load a) {
                 (ir.FIAddr b) a {
                     f64mem b => (mi.MOVSDrm [BaseFi %rbp, b])
@@ -149,6 +160,13 @@ impl MISelector {
                                                  GR64 a => (mi.MOVrm32 [Base a]) }
             (ir.Load a): F64      { GR64 a => (mi.MOVSDrm [Base a]) }
             (ir.Load a): Pointer! { GR64 a => (mi.MOVrm64 [Base a]) }
+            (ir.Store a, b) {
+                XMM: Vector! b {
+                    (ir.FIAddr c) a { mem c => (mi.MOVDQUmr [BaseFi %rbp, c], b) }
+                    (ir.GlobalAddr c) a => (mi.MOVDQUmr [Address c], b)
+                    GR64 a => (mi.MOVDQUmr [Base a], b)
+                }
+            }
             (ir.Store a, b) {
                 (ir.FIAddr c) a {
                     f64mem c {
@@ -176,6 +194,7 @@ impl MISelector {
                 }
             }
             (ir.FIAddr a) { mem a => (mi.LEAr64m [BaseFi %rbp, a]) }
+            (ir.ThreadLocalAddr a) => (mi.TLSADDRr64 [Address a])
             // (ir.GlobalAddr a) => (mi.Copy a)  TODO
             (ir.Br dst) => (mi.JMP dst)
             (ir.CopyFromReg a) => (mi.Copy a)
@@ -228,4 +247,177 @@ impl MISelector {
             _ => unreachable!(),
         }
     }
+
+    fn select_vector_binop(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Raw<DAGNode> {
+        let (elem_ty, _) = tys.as_vector_ty(node.ty).unwrap();
+        let opcode = match (&node.kind, elem_ty) {
+            (NodeKind::IR(IRNodeKind::Add), Type::Int32) => MINodeKind::PADDDrr,
+            (NodeKind::IR(IRNodeKind::Sub), Type::Int32) => MINodeKind::PSUBDrr,
+            (NodeKind::IR(IRNodeKind::Add), Type::F64) => MINodeKind::ADDPDrr,
+            (NodeKind::IR(IRNodeKind::Sub), Type::F64) => MINodeKind::SUBPDrr,
+            (NodeKind::IR(IRNodeKind::Mul), Type::F64) => MINodeKind::MULPDrr,
+            (NodeKind::IR(IRNodeKind::Div), Type::F64) => MINodeKind::DIVPDrr,
+            // The legalizer expands the other operations into element-wise ones
+            (kind, _) => unreachable!("{:?} on {}", kind, tys.to_string(node.ty)),
+        };
+        let lhs = self.run_on_node(tys, regs_info, heap, node.operand[0]);
+        let rhs = self.run_on_node(tys, regs_info, heap, node.operand[1]);
+        heap.alloc(DAGNode::new(NodeKind::MI(opcode), vec![lhs, rhs], node.ty))
+    }
+
+    fn select_extract_element(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Raw<DAGNode> {
+        let vec = self.run_on_node(tys, regs_info, heap, node.operand[0]);
+        let idx = node.operand[1].as_constant().as_i32();
+        match node.ty {
+            Type::Int32 => {
+                // Move the element to the lowest lane, where movd reads it from
+                let vec = if idx == 0 {
+                    vec
+                } else {
+                    let imm = alloc_imm8(heap, idx);
+                    heap.alloc(DAGNode::new(
+                        NodeKind::MI(MINodeKind::PSHUFDrri),
+                        vec![vec, imm],
+                        vec.ty,
+                    ))
+                };
+                heap.alloc(DAGNode::new(
+                    NodeKind::MI(MINodeKind::MOVDr32x),
+                    vec![vec],
+                    node.ty,
+                ))
+            }
+            Type::F64 => {
+                // Move the two dwords of the element to the lowest lane
+                let imm = alloc_imm8(heap, (idx * 2) | (idx * 2 + 1) << 2);
+                heap.alloc(DAGNode::new(
+                    NodeKind::MI(MINodeKind::PSHUFDrri),
+                    vec![vec, imm],
+                    node.ty,
+                ))
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn select_insert_element(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Raw<DAGNode> {
+        let vec = self.run_on_node(tys, regs_info, heap, node.operand[0]);
+        let val = self.select_scalar_into_reg(tys, regs_info, heap, node.operand[1]);
+        let idx = node.operand[2].as_constant().as_i32();
+        let (opcode, operands) = match val.ty {
+            Type::Int32 => {
+                // shufps takes the lower half of the result from its first operand and the upper
+                // half from its second. The element is paired with the other element of the same
+                // half first, and then the pair is merged with the vector.
+                let elem = heap.alloc(DAGNode::new(
+                    NodeKind::MI(MINodeKind::MOVDxr32),
+                    vec![val],
+                    node.ty,
+                ));
+                let other = idx ^ 1;
+                let imm = alloc_imm8(heap, shufps_imm([0, 0, other, other]));
+                let pair = heap.alloc(DAGNode::new(
+                    NodeKind::MI(MINodeKind::SHUFPSrri),
+                    vec![elem, vec, imm],
+                    node.ty,
+                ));
+                let (a, b) = if idx % 2 == 0 { (0, 2) } else { (2, 0) };
+                if idx < 2 {
+                    let imm = alloc_imm8(heap, shufps_imm([a, b, 2, 3]));
+                    (MINodeKind::SHUFPSrri, vec![pair, vec, imm])
+                } else {
+                    let imm = alloc_imm8(heap, shufps_imm([0, 1, a, b]));
+                    (MINodeKind::SHUFPSrri, vec![vec, pair, imm])
+                }
+            }
+            // shufpd takes the low element from its first operand and the high one from its second
+            Type::F64 if idx == 0 => (MINodeKind::SHUFPDrri, vec![val, vec, alloc_imm8(heap, 2)]),
+            Type::F64 => (MINodeKind::SHUFPDrri, vec![vec, val, alloc_imm8(heap, 0)]),
+            _ => unimplemented!(),
+        };
+        heap.alloc(DAGNode::new(NodeKind::MI(opcode), operands, node.ty))
+    }
+
+    // The legalizer guarantees that the mask is either a single source shuffle or takes the
+    // lower half of the result from the first operand and the upper half from the second one.
+    fn select_shuffle_vector(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Raw<DAGNode> {
+        let (elem_ty, len) = tys.as_vector_ty(node.ty).unwrap();
+        let v1 = self.run_on_node(tys, regs_info, heap, node.operand[0]);
+        let v2 = self.run_on_node(tys, regs_info, heap, node.operand[1]);
+        let mask: Vec<i32> = node.operand[2..]
+            .iter()
+            .map(|m| m.as_constant().as_i32() % len as i32)
+            .collect();
+        let (opcode, operands) = match elem_ty {
+            Type::Int32 => {
+                let imm = shufps_imm([mask[0], mask[1], mask[2], mask[3]]);
+                if node.operand[0] == node.operand[1] {
+                    (MINodeKind::PSHUFDrri, vec![v1, alloc_imm8(heap, imm)])
+                } else {
+                    (MINodeKind::SHUFPSrri, vec![v1, v2, alloc_imm8(heap, imm)])
+                }
+            }
+            Type::F64 => {
+                let imm = mask[0] | mask[1] << 1;
+                (MINodeKind::SHUFPDrri, vec![v1, v2, alloc_imm8(heap, imm)])
+            }
+            _ => unimplemented!(),
+        };
+        heap.alloc(DAGNode::new(NodeKind::MI(opcode), operands, node.ty))
+    }
+
+    fn select_scalar_into_reg(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Raw<DAGNode> {
+        if !node.is_constant() {
+            return self.run_on_node(tys, regs_info, heap, node);
+        }
+        let opcode = match node.ty {
+            Type::Int32 => MINodeKind::MOVri32,
+            Type::F64 => MINodeKind::MOVSDrm64,
+            _ => unimplemented!(),
+        };
+        heap.alloc(DAGNode::new(NodeKind::MI(opcode), vec![node], node.ty))
+    }
+}
+
+/// Encodes the lanes of a 4-element shuffle into the immediate of pshufd/shufps
+fn shufps_imm(mask: [i32; 4]) -> i32 {
+    mask[0] | mask[1] << 2 | mask[2] << 4 | mask[3] << 6
+}
+
+fn alloc_imm8(heap: &mut DAGHeap, imm: i32) -> Raw<DAGNode> {
+    heap.alloc(DAGNode::new(
+        NodeKind::Operand(OperandNodeKind::Constant(ConstantKind::Int8(imm as i8))),
+        vec![],
+        Type::Int8,
+    ))
 }
@@ -5,7 +5,10 @@ use crate::codegen::common::dag::{
 };
 use crate::{ir::types::*, traits::pass::ModulePassTrait, util::allocator::*};
 use defs::isel_pat;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Width of the vector registers (XMM), in bytes
+const VECTOR_REG_SIZE: usize = 16;
 
 impl ModulePassTrait for Legalize {
     type M = DAGModule;
@@ -21,12 +24,18 @@ impl ModulePassTrait for Legalize {
 
 pub struct Legalize {
     selected: FxHashMap<Raw<DAGNode>, Raw<DAGNode>>,
+    vector_visited: FxHashSet<Raw<DAGNode>>,
+    split_nodes: FxHashMap<Raw<DAGNode>, Vec<Raw<DAGNode>>>,
+    split_regs: FxHashMap<RegisterId, Vec<RegisterId>>,
 }
 
 impl Legalize {
     pub fn new() -> Self {
         Self {
             selected: FxHashMap::default(),
+            vector_visited: FxHashSet::default(),
+            split_nodes: FxHashMap::default(),
+            split_regs: FxHashMap::default(),
         }
     }
 
@@ -40,6 +49,11 @@ impl Legalize {
     }
 
     fn run_on_function(&mut self, tys: &Types, func: &mut DAGFunction) {
+        for bb_id in &func.dag_basic_blocks {
+            let bb = &func.dag_basic_block_arena[*bb_id];
+            self.legalize_vector_chain(tys, &func.regs_info, &mut func.dag_heap, bb.entry.unwrap());
+        }
+
         for bb_id in &func.dag_basic_blocks {
             let bb = &func.dag_basic_block_arena[*bb_id];
             self.run_on_node(tys, &func.regs_info, &mut func.dag_heap, bb.entry.unwrap());
@@ -68,7 +82,9 @@ impl Legalize {
             NodeKind::IR(IRNodeKind::Add) => self.run_on_node_add(tys, regs_info, heap, node),
             NodeKind::IR(IRNodeKind::Mul) => self.run_on_node_mul(tys, regs_info, heap, node),
             NodeKind::IR(IRNodeKind::Sext) => self.run_on_node_sext(tys, regs_info, heap, node),
-            NodeKind::IR(IRNodeKind::Brcc) => self.run_on_node_brcc(tys, regs_info, heap, node),
+            NodeKind::IR(IRNodeKind::Brcc) | NodeKind::IR(IRNodeKind::Selectcc) => {
+                self.run_on_node_brcc(tys, regs_info, heap, node)
+            }
             NodeKind::IR(IRNodeKind::FPBrcc) => self.run_on_node_fpbrcc(tys, regs_info, heap, node),
             _ => {
                 self.run_on_node_operand(tys, regs_info, heap, node);
@@ -112,6 +128,10 @@ impl Legalize {
                     imm32 y => (mi.MOVSDrm [BaseFiOff %rbp, fi, y])
                     (ir.Mul z, u) y {
                         imm32 u => (mi.MOVSDrm [BaseFiAlignOff %rbp, fi, u, z]) } } } }
+        (ir.Load dst): Vector! {
+            (ir.Add x, y) dst {
+                (ir.FIAddr fi) x {
+                    imm32 y => (mi.MOVDQUrm [BaseFiOff %rbp, fi, y]) } } }
         }
     }
 
@@ -129,6 +149,7 @@ impl Legalize {
                     imm32   src => (mi.MOVmi32 [BaseFi %rbp, fi], src)
                     GR32    src => (mi.MOVmr32 [BaseFi %rbp, fi], src)
                     GR64    src => (mi.MOVmr64 [BaseFi %rbp, fi], src)
+                    XMM: Vector! src => (mi.MOVDQUmr [BaseFi %rbp, fi], src)
                     XMM     src => (mi.MOVSDmr [BaseFi %rbp, fi], src)
                     imm_f64 src => (mi.MOVSDmr [BaseFi %rbp, fi], (mi.MOVSDrm64 src)) } }
             (ir.Add a1, a2) dst {
@@ -138,6 +159,7 @@ impl Legalize {
                             GR32    src => (mi.MOVmr32 [BaseFiOff %rbp, fi, a2], src)
                             GR64    src => (mi.MOVmr64 [BaseFiOff %rbp, fi, a2], src)
                             imm32   src => (mi.MOVmi32 [BaseFiOff %rbp, fi, a2], src)
+                            XMM: Vector! src => (mi.MOVDQUmr [BaseFiOff %rbp, fi, a2], src)
                             XMM     src => (mi.MOVSDmr [BaseFiOff %rbp, fi, a2], src)
                             imm_f64 src => (mi.MOVSDmr [BaseFiOff %rbp, fi, a2], (mi.MOVSDrm64 src)) } }
                     mem32 fi {
@@ -350,3 +372,446 @@ impl Legalize {
             .collect()
     }
 }
+
+// Vector legalization: vectors wider than an XMM register are split into 128-bit pieces, and
+// vector operations that have no SSE2 counterpart are expanded into element-wise operations.
+impl Legalize {
+    fn legalize_vector_chain(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        entry: Raw<DAGNode>,
+    ) {
+        let mut prev = entry;
+        while let Some(node) = prev.next {
+            let next = node.next;
+            self.legalize_vector(tys, regs_info, heap, node);
+            match self.split_chained_node(tys, heap, node) {
+                Some(seq) => {
+                    prev.next = Some(seq[0]);
+                    for pair in seq.windows(2) {
+                        let mut cur = pair[0];
+                        cur.next = Some(pair[1]);
+                    }
+                    prev = *seq.last().unwrap();
+                    prev.next = next;
+                }
+                None => prev = node,
+            }
+        }
+    }
+
+    fn split_chained_node(
+        &mut self,
+        tys: &Types,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Option<Vec<Raw<DAGNode>>> {
+        match node.kind {
+            NodeKind::IR(IRNodeKind::Store) if is_wide_vector(tys, node.operand[1].ty) => {
+                let dst = node.operand[0];
+                let srcs = self.split_nodes[&node.operand[1]].clone();
+                Some(
+                    srcs.into_iter()
+                        .enumerate()
+                        .map(|(i, src)| {
+                            let dst = offset_vector_ptr(tys, heap, dst, src.ty, i);
+                            heap.alloc(DAGNode::new(
+                                NodeKind::IR(IRNodeKind::Store),
+                                vec![dst, src],
+                                Type::Void,
+                            ))
+                        })
+                        .collect(),
+                )
+            }
+            NodeKind::IR(IRNodeKind::CopyToReg) if is_wide_vector(tys, node.operand[1].ty) => {
+                let dsts = self.split_nodes[&node.operand[0]].clone();
+                let srcs = self.split_nodes[&node.operand[1]].clone();
+                Some(
+                    dsts.into_iter()
+                        .zip(srcs.into_iter())
+                        .map(|(dst, src)| {
+                            heap.alloc(DAGNode::new(
+                                NodeKind::IR(IRNodeKind::CopyToReg),
+                                vec![dst, src],
+                                Type::Void,
+                            ))
+                        })
+                        .collect(),
+                )
+            }
+            NodeKind::IR(IRNodeKind::Call) | NodeKind::IR(IRNodeKind::Ret)
+                if node.operand.iter().any(|op| is_wide_vector(tys, op.ty)) =>
+            {
+                panic!(
+                    "passing vectors wider than {} bytes across calls is not supported",
+                    VECTOR_REG_SIZE
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn legalize_vector(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        mut node: Raw<DAGNode>,
+    ) {
+        if !node.may_contain_children() || !self.vector_visited.insert(node) {
+            return;
+        }
+
+        for op in node.operand.clone() {
+            // A frame index carries the type of its slot, not a value to split
+            if is_wide_vector(tys, op.ty) && !op.is_frame_index() {
+                self.split_vector(tys, regs_info, heap, op);
+            } else {
+                self.legalize_vector(tys, regs_info, heap, op);
+            }
+        }
+
+        let expanded = match node.kind {
+            NodeKind::IR(IRNodeKind::ExtractElement) => {
+                let idx = vector_index(node.operand[1]);
+                if is_wide_vector(tys, node.operand[0].ty) {
+                    let pieces = &self.split_nodes[&node.operand[0]];
+                    let lanes = vector_lanes(tys, pieces[0].ty);
+                    let piece = pieces[idx / lanes];
+                    Some(alloc_extract_element(heap, piece, idx % lanes, node.ty))
+                } else {
+                    None
+                }
+            }
+            NodeKind::IR(IRNodeKind::InsertElement) => {
+                vector_index(node.operand[2]);
+                None
+            }
+            NodeKind::IR(IRNodeKind::ShuffleVector) => {
+                let lanes = self.shuffle_lanes(tys, regs_info, heap, node);
+                Some(build_shuffle(tys, heap, node.ty, &lanes))
+            }
+            _ if needs_expansion(tys, node) => Some(expand_vector_binop(tys, heap, node)),
+            _ => {
+                vector_elems(tys, node.ty);
+                None
+            }
+        };
+
+        if let Some(expanded) = expanded {
+            *node = (*expanded).clone();
+        }
+    }
+
+    fn split_vector(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        node: Raw<DAGNode>,
+    ) -> Vec<Raw<DAGNode>> {
+        if let Some(pieces) = self.split_nodes.get(&node) {
+            return pieces.clone();
+        }
+
+        let (elem_ty, len) = vector_elems(tys, node.ty).unwrap();
+        let lanes = VECTOR_REG_SIZE / elem_ty.size_in_byte(tys);
+        let piece_ty = tys.new_vector_ty(elem_ty, lanes);
+        let num_pieces = len / lanes;
+
+        let pieces: Vec<Raw<DAGNode>> = match node.kind {
+            NodeKind::Operand(OperandNodeKind::Register(r)) => {
+                let regs = self
+                    .split_regs
+                    .entry(r)
+                    .or_insert_with(|| {
+                        let mut regs = vec![r];
+                        for _ in 1..num_pieces {
+                            regs.push(regs_info.new_virt_reg(RegisterClassKind::XMM))
+                        }
+                        regs
+                    })
+                    .clone();
+                regs.into_iter()
+                    .map(|r| {
+                        heap.alloc(DAGNode::new(
+                            NodeKind::Operand(OperandNodeKind::Register(r)),
+                            vec![],
+                            piece_ty,
+                        ))
+                    })
+                    .collect()
+            }
+            NodeKind::IR(IRNodeKind::Load) => {
+                let ptr = node.operand[0];
+                self.legalize_vector(tys, regs_info, heap, ptr);
+                (0..num_pieces)
+                    .map(|i| {
+                        let ptr = offset_vector_ptr(tys, heap, ptr, piece_ty, i);
+                        heap.alloc(DAGNode::new(
+                            NodeKind::IR(IRNodeKind::Load),
+                            vec![ptr],
+                            piece_ty,
+                        ))
+                    })
+                    .collect()
+            }
+            NodeKind::IR(IRNodeKind::Add)
+            | NodeKind::IR(IRNodeKind::Sub)
+            | NodeKind::IR(IRNodeKind::Mul)
+            | NodeKind::IR(IRNodeKind::Div)
+            | NodeKind::IR(IRNodeKind::Rem)
+            | NodeKind::IR(IRNodeKind::Shl) => {
+                let lhs = self.split_vector(tys, regs_info, heap, node.operand[0]);
+                let rhs = self.split_vector(tys, regs_info, heap, node.operand[1]);
+                lhs.into_iter()
+                    .zip(rhs.into_iter())
+                    .map(|(l, r)| heap.alloc(DAGNode::new(node.kind.clone(), vec![l, r], piece_ty)))
+                    .collect()
+            }
+            NodeKind::IR(IRNodeKind::InsertElement) => {
+                let mut pieces = self.split_vector(tys, regs_info, heap, node.operand[0]);
+                let val = node.operand[1];
+                self.legalize_vector(tys, regs_info, heap, val);
+                let idx = vector_index(node.operand[2]);
+                pieces[idx / lanes] =
+                    alloc_insert_element(heap, pieces[idx / lanes], val, idx % lanes);
+                pieces
+            }
+            NodeKind::IR(IRNodeKind::ShuffleVector) => {
+                let src_lanes = self.shuffle_lanes(tys, regs_info, heap, node);
+                src_lanes
+                    .chunks(lanes)
+                    .map(|lanes| build_shuffle(tys, heap, piece_ty, lanes))
+                    .collect()
+            }
+            NodeKind::IR(IRNodeKind::Phi) => {
+                let mut operands = vec![vec![]; num_pieces];
+                for pair in node.operand.chunks(2) {
+                    let vals = self.split_vector(tys, regs_info, heap, pair[0]);
+                    for (ops, val) in operands.iter_mut().zip(vals.into_iter()) {
+                        ops.push(val);
+                        ops.push(pair[1]);
+                    }
+                }
+                operands
+                    .into_iter()
+                    .map(|ops| {
+                        heap.alloc(DAGNode::new(NodeKind::IR(IRNodeKind::Phi), ops, piece_ty))
+                    })
+                    .collect()
+            }
+            _ => panic!(
+                "unsupported operation on vector type {}",
+                tys.to_string(node.ty)
+            ),
+        };
+
+        // Some pieces may still need to be expanded (e.g. integer multiplication)
+        for &piece in &pieces {
+            self.legalize_vector(tys, regs_info, heap, piece);
+        }
+
+        self.split_nodes.insert(node, pieces.clone());
+        pieces
+    }
+
+    /// Returns the source piece and its lane for each element of the result of `shuffle`.
+    fn shuffle_lanes(
+        &mut self,
+        tys: &Types,
+        regs_info: &RegistersInfo,
+        heap: &mut DAGHeap,
+        shuffle: Raw<DAGNode>,
+    ) -> Vec<(Raw<DAGNode>, usize)> {
+        let (_, len) = vector_elems(tys, shuffle.operand[0].ty).unwrap();
+        let mut srcs = vec![];
+        for &v in &shuffle.operand[0..2] {
+            if is_wide_vector(tys, v.ty) {
+                srcs.push(self.split_vector(tys, regs_info, heap, v))
+            } else {
+                self.legalize_vector(tys, regs_info, heap, v);
+                srcs.push(vec![v])
+            }
+        }
+        let lanes = vector_lanes(tys, srcs[0][0].ty);
+        shuffle.operand[2..]
+            .iter()
+            .map(|m| {
+                let m = vector_index(*m);
+                let (src, m) = if m < len {
+                    (&srcs[0], m)
+                } else {
+                    (&srcs[1], m - len)
+                };
+                (src[m / lanes], m % lanes)
+            })
+            .collect()
+    }
+}
+
+/// Returns the element type and the number of elements of `ty` if it's a vector type, making
+/// sure that it can be lowered onto XMM registers.
+fn vector_elems(tys: &Types, ty: Type) -> Option<(Type, usize)> {
+    let (elem_ty, len) = tys.as_vector_ty(ty)?;
+    assert!(
+        matches!(elem_ty, Type::Int32 | Type::F64)
+            && (elem_ty.size_in_byte(tys) * len) % VECTOR_REG_SIZE == 0,
+        "unsupported vector type {}",
+        tys.to_string(ty)
+    );
+    Some((elem_ty, len))
+}
+
+/// Returns true if `node` is a vector operation SSE2 can't do on whole registers
+fn needs_expansion(tys: &Types, node: Raw<DAGNode>) -> bool {
+    let elem_ty = match vector_elems(tys, node.ty) {
+        Some((elem_ty, _)) => elem_ty,
+        None => return false,
+    };
+    match node.kind {
+        // pmulld is SSE4.1 and there is no packed integer division
+        NodeKind::IR(IRNodeKind::Mul) | NodeKind::IR(IRNodeKind::Div) => elem_ty.is_integer(),
+        // psll* shifts every element by the same amount
+        NodeKind::IR(IRNodeKind::Rem) | NodeKind::IR(IRNodeKind::Shl) => true,
+        _ => false,
+    }
+}
+
+fn is_wide_vector(tys: &Types, ty: Type) -> bool {
+    vector_elems(tys, ty).map_or(false, |(elem_ty, len)| {
+        elem_ty.size_in_byte(tys) * len > VECTOR_REG_SIZE
+    })
+}
+
+fn vector_lanes(tys: &Types, ty: Type) -> usize {
+    vector_elems(tys, ty).unwrap().1
+}
+
+fn vector_index(idx: Raw<DAGNode>) -> usize {
+    match idx.kind {
+        NodeKind::Operand(OperandNodeKind::Constant(ConstantKind::Int32(i))) => i as usize,
+        _ => panic!("vector element index must be a constant"),
+    }
+}
+
+fn alloc_index(heap: &mut DAGHeap, idx: usize) -> Raw<DAGNode> {
+    heap.alloc(DAGNode::new(
+        NodeKind::Operand(OperandNodeKind::Constant(ConstantKind::Int32(idx as i32))),
+        vec![],
+        Type::Int32,
+    ))
+}
+
+fn alloc_extract_element(
+    heap: &mut DAGHeap,
+    vec: Raw<DAGNode>,
+    idx: usize,
+    elem_ty: Type,
+) -> Raw<DAGNode> {
+    let idx = alloc_index(heap, idx);
+    heap.alloc(DAGNode::new(
+        NodeKind::IR(IRNodeKind::ExtractElement),
+        vec![vec, idx],
+        elem_ty,
+    ))
+}
+
+fn alloc_insert_element(
+    heap: &mut DAGHeap,
+    vec: Raw<DAGNode>,
+    val: Raw<DAGNode>,
+    idx: usize,
+) -> Raw<DAGNode> {
+    let idx = alloc_index(heap, idx);
+    heap.alloc(DAGNode::new(
+        NodeKind::IR(IRNodeKind::InsertElement),
+        vec![vec, val, idx],
+        vec.ty,
+    ))
+}
+
+fn offset_vector_ptr(
+    tys: &Types,
+    heap: &mut DAGHeap,
+    ptr: Raw<DAGNode>,
+    piece_ty: Type,
+    i: usize,
+) -> Raw<DAGNode> {
+    if i == 0 {
+        return ptr;
+    }
+    let off = alloc_index(heap, i * VECTOR_REG_SIZE);
+    heap.alloc(DAGNode::new(
+        NodeKind::IR(IRNodeKind::Add),
+        vec![ptr, off],
+        tys.new_pointer_ty(piece_ty),
+    ))
+}
+
+/// Builds a shuffle that gathers `lanes` into a vector of `ty`. The result is always in a form
+/// that isel can select directly: a single source, a SHUFPS/SHUFPD-like two source shuffle,
+/// or otherwise a chain of element insertions.
+fn build_shuffle(
+    tys: &Types,
+    heap: &mut DAGHeap,
+    ty: Type,
+    lanes: &[(Raw<DAGNode>, usize)],
+) -> Raw<DAGNode> {
+    let (elem_ty, len) = vector_elems(tys, ty).unwrap();
+    assert_eq!(len, lanes.len());
+    let first = lanes[0].0;
+    let second = lanes.iter().map(|&(v, _)| v).find(|&v| v != first);
+    let shuffle = |heap: &mut DAGHeap, v1: Raw<DAGNode>, v2: Raw<DAGNode>, mask: Vec<usize>| {
+        let mut operands = vec![v1, v2];
+        operands.extend(mask.into_iter().map(|m| alloc_index(heap, m)));
+        heap.alloc(DAGNode::new(
+            NodeKind::IR(IRNodeKind::ShuffleVector),
+            operands,
+            ty,
+        ))
+    };
+
+    let second = match second {
+        None => return shuffle(heap, first, first, lanes.iter().map(|&(_, l)| l).collect()),
+        Some(second) => second,
+    };
+
+    let (lo, hi) = lanes.split_at(len / 2);
+    if lo.iter().all(|&(v, _)| v == first) && hi.iter().all(|&(v, _)| v == second) {
+        let mask = lanes
+            .iter()
+            .map(|&(v, l)| if v == first { l } else { len + l })
+            .collect();
+        return shuffle(heap, first, second, mask);
+    }
+
+    // Gather the elements one by one
+    let mask = lanes
+        .iter()
+        .map(|&(v, l)| if v == first { l } else { 0 })
+        .collect();
+    let mut acc = shuffle(heap, first, first, mask);
+    for (i, &(v, l)) in lanes.iter().enumerate().filter(|(_, (v, _))| *v != first) {
+        let elem = alloc_extract_element(heap, v, l, elem_ty);
+        acc = alloc_insert_element(heap, acc, elem, i);
+    }
+    acc
+}
+
+/// Expands a vector operation into the same operation on each pair of elements.
+fn expand_vector_binop(tys: &Types, heap: &mut DAGHeap, node: Raw<DAGNode>) -> Raw<DAGNode> {
+    let (elem_ty, len) = vector_elems(tys, node.ty).unwrap();
+    let (lhs, rhs) = (node.operand[0], node.operand[1]);
+    let mut acc = lhs;
+    for i in 0..len {
+        let l = alloc_extract_element(heap, lhs, i, elem_ty);
+        let r = alloc_extract_element(heap, rhs, i, elem_ty);
+        let elem = heap.alloc(DAGNode::new(node.kind.clone(), vec![l, r], elem_ty));
+        acc = alloc_insert_element(heap, acc, elem, i);
+    }
+    acc
+}
@@ -7,7 +7,10 @@ use crate::codegen::common::{
     machine::{basic_block::*, function::*, inst_def::DefOrUseReg, module::*},
 };
 use crate::codegen::x64::frame_object::FrameIndexInfo;
-use crate::ir::types::*;
+use crate::ir::{
+    inline_asm::{ConstraintLocation, InlineAsmId, InlineAsms},
+    types::*,
+};
 use crate::util::allocator::*;
 use id_arena::*;
 use rustc_hash::FxHashMap;
@@ -15,6 +18,7 @@ use std::cell::RefCell;
 
 pub struct ScheduleByBlock<'a> {
     types: &'a Types,
+    inline_asms: &'a InlineAsms,
     cur_func: &'a DAGFunction,
     inst_arena: &'a mut InstructionArena,
     node2reg: FxHashMap<Raw<DAGNode>, Option<RegisterId>>,
@@ -27,12 +31,22 @@ pub struct ScheduleByBlock<'a> {
 pub fn convert_module(module: DAGModule) -> MachineModule {
     let mut functions = Arena::new();
     for (_, func) in module.functions {
-        functions.alloc(convert_function(&module.types, func));
+        functions.alloc(convert_function(&module.types, &module.inline_asms, func));
     }
-    MachineModule::new(module.name, functions, module.types, module.global_vars)
+    MachineModule::new(
+        module.name,
+        functions,
+        module.types,
+        module.global_vars,
+        module.inline_asms,
+    )
 }
 
-pub fn convert_function(types: &Types, dag_func: DAGFunction) -> MachineFunction {
+pub fn convert_function(
+    types: &Types,
+    inline_asms: &InlineAsms,
+    dag_func: DAGFunction,
+) -> MachineFunction {
     let mut bb_map = FxHashMap::default();
     let mut mbbs = MachineBasicBlocks::new();
 
@@ -64,6 +78,7 @@ pub fn convert_function(types: &Types, dag_func: DAGFunction) -> MachineFunction
 
         ScheduleByBlock {
             types,
+            inline_asms,
             cur_func: &dag_func,
             inst_arena: &mut inst_arena,
             node2reg: FxHashMap::default(),
@@ -214,6 +229,23 @@ impl<'a> ScheduleByBlock<'a> {
                 );
                 self.append_inst(copy_inst)
             }
+            NodeKind::IR(IRNodeKind::Rem) if node.ty == Type::F64 => {
+                let eax = self.cur_func.regs_info.get_phys_reg(GR32::EAX);
+                let op1 = self.register_operand(node.operand[0]);
+                let op2 = self.register_operand(node.operand[1]);
+                let tied = *op1.as_register();
+                self.append_inst(
+                    MachineInst::new(
+                        &self.cur_func.regs_info,
+                        MachineOpcode::FMODSDrr,
+                        vec![op1, op2],
+                        Some(RegisterClassKind::XMM),
+                        self.cur_bb,
+                    )
+                    .with_imp_def(eax)
+                    .set_tie_with_def(tied),
+                )
+            }
             NodeKind::IR(IRNodeKind::Rem) => {
                 let eax = self.cur_func.regs_info.get_phys_reg(GR32::EAX);
                 let edx = self.cur_func.regs_info.get_phys_reg(GR32::EDX);
@@ -257,6 +289,31 @@ impl<'a> ScheduleByBlock<'a> {
                     self.cur_bb,
                 ))
             }
+            NodeKind::IR(IRNodeKind::Shl) => {
+                // Shift amounts not known at compile time are passed in cl
+                let ecx = self.cur_func.regs_info.get_phys_reg(GR32::ECX);
+                let op1 = self.register_operand(node.operand[0]);
+                let op2 = self.normal_operand(node.operand[1]);
+                assert_eq!(op1.get_type(&self.cur_func.regs_info), Some(Type::Int32));
+
+                self.append_inst(
+                    MachineInst::new_simple(mov_n_rx(32, &op2).unwrap(), vec![op2], self.cur_bb)
+                        .with_def(vec![ecx]),
+                );
+
+                let tied = *op1.as_register();
+                self.append_inst(
+                    MachineInst::new(
+                        &self.cur_func.regs_info,
+                        MachineOpcode::SHLr32CL,
+                        vec![op1],
+                        Some(RegisterClassKind::GR32),
+                        self.cur_bb,
+                    )
+                    .with_imp_use(ecx)
+                    .set_tie_with_def(tied),
+                )
+            }
             NodeKind::IR(IRNodeKind::Setcc) => {
                 let new_op1 = self.normal_operand(node.operand[1]);
                 let new_op2 = self.normal_operand(node.operand[2]);
@@ -305,6 +362,52 @@ impl<'a> ScheduleByBlock<'a> {
                     self.cur_bb,
                 ))
             }
+            NodeKind::IR(IRNodeKind::Selectcc) => {
+                // Both values are computed before the comparison so that nothing clobbers the
+                // flags until CMOVcc
+                let t = self.register_operand(node.operand[3]);
+                let f = self.register_operand(node.operand[4]);
+                let op0 = self.normal_operand(node.operand[1]);
+                let op1 = self.normal_operand(node.operand[2]);
+
+                self.append_inst(MachineInst::new_simple(
+                    if op0.is_register() && op1.is_constant() {
+                        MachineOpcode::CMPri
+                    } else if op0.is_register() && op1.is_register() {
+                        MachineOpcode::CMPrr
+                    } else {
+                        unreachable!()
+                    },
+                    vec![op0, op1],
+                    self.cur_bb,
+                ));
+
+                let is_64 = matches!(ty2rc(&node.ty), Some(RegisterClassKind::GR64));
+                let opcode = match (cond_kind!(node.operand[0]), is_64) {
+                    (CondKind::Eq, false) => MachineOpcode::CMOVErr32,
+                    (CondKind::Eq, true) => MachineOpcode::CMOVErr64,
+                    (CondKind::Le, false) => MachineOpcode::CMOVLErr32,
+                    (CondKind::Le, true) => MachineOpcode::CMOVLErr64,
+                    (CondKind::Lt, false) => MachineOpcode::CMOVLrr32,
+                    (CondKind::Lt, true) => MachineOpcode::CMOVLrr64,
+                    (CondKind::Ge, false) => MachineOpcode::CMOVGErr32,
+                    (CondKind::Ge, true) => MachineOpcode::CMOVGErr64,
+                    (CondKind::Gt, false) => MachineOpcode::CMOVGrr32,
+                    (CondKind::Gt, true) => MachineOpcode::CMOVGrr64,
+                    _ => unreachable!(),
+                };
+                let tied = *f.as_register();
+                self.append_inst(
+                    MachineInst::new(
+                        &self.cur_func.regs_info,
+                        opcode,
+                        vec![f, t],
+                        ty2rc(&node.ty),
+                        self.cur_bb,
+                    )
+                    .set_tie_with_def(tied),
+                )
+            }
             NodeKind::IR(IRNodeKind::FPBrcc) => {
                 let op0 = self.normal_operand(node.operand[1]);
                 let op1 = self.normal_operand(node.operand[2]);
@@ -367,6 +470,12 @@ impl<'a> ScheduleByBlock<'a> {
     }
 
     fn convert_call_dag(&mut self, node: &DAGNode) -> MachineInstId {
+        if let NodeKind::Operand(OperandNodeKind::Address(node::AddressKind::InlineAsm(id))) =
+            node.operand[0].kind
+        {
+            return self.convert_inline_asm_dag(node, id);
+        }
+
         let mut arg_regs = vec![self.cur_func.regs_info.get_phys_reg(GR64::RSP)]; // call uses RSP
         let mut off = 0i32;
 
@@ -503,6 +612,81 @@ impl<'a> ScheduleByBlock<'a> {
         self.append_inst(copy)
     }
 
+    fn convert_inline_asm_dag(&mut self, node: &DAGNode, id: InlineAsmId) -> MachineInstId {
+        let asm = &self.inline_asms.arena[id];
+        let regs_info = &self.cur_func.regs_info;
+        let fixed_reg = |name: &str| {
+            let reg = str2reg(name)
+                .unwrap_or_else(|| panic!("unknown register '{}' in inline asm", name));
+            regs_info.get_phys_reg(reg)
+        };
+
+        let mut operands = vec![MachineOperand::Mem(MachineMemOperand::Address(
+            inst::AddressKind::InlineAsm(id),
+        ))];
+        for (loc, arg) in asm.inputs().zip(node.operand[1..].iter()) {
+            let arg_ty = arg.ty;
+            let arg = self.normal_operand(*arg);
+            let operand = match loc {
+                ConstraintLocation::Imm => {
+                    assert!(arg.is_constant(), "'i' constraint needs a constant");
+                    arg
+                }
+                ConstraintLocation::Reg if arg.is_register() => arg,
+                ConstraintLocation::Reg => {
+                    let r = self
+                        .cur_func
+                        .regs_info
+                        .new_virt_reg(ty2rc(&arg_ty).unwrap());
+                    self.append_inst(self.move2reg(r, arg));
+                    MachineOperand::Register(r)
+                }
+                ConstraintLocation::FixedReg(name) => {
+                    let r = fixed_reg(name);
+                    self.append_inst(self.move2reg(r, arg));
+                    MachineOperand::Register(r)
+                }
+            };
+            operands.push(operand);
+        }
+
+        let def = match asm.output() {
+            Some(ConstraintLocation::Reg) => Some(
+                self.cur_func
+                    .regs_info
+                    .new_virt_reg(ty2rc(&node.ty).unwrap()),
+            ),
+            Some(ConstraintLocation::FixedReg(name)) => Some(fixed_reg(name)),
+            Some(ConstraintLocation::Imm) => unreachable!(),
+            None => None,
+        };
+        let clobbers = asm.clobbered_regs().map(fixed_reg).collect();
+
+        let asm_inst = self.append_inst(
+            MachineInst::new_simple(MachineOpcode::InlineAsm, operands, self.cur_bb)
+                .with_def(def.into_iter().collect())
+                .with_imp_defs(clobbers),
+        );
+
+        match asm.output() {
+            // Copy the pinned output into a virtual register so that the physical register is
+            // free again right after the asm, as is done for return values of calls
+            Some(ConstraintLocation::FixedReg(_)) => {
+                let r = def.unwrap();
+                let reg_class = self.cur_func.regs_info.arena_ref()[r].reg_class;
+                let copy = MachineInst::new(
+                    &self.cur_func.regs_info,
+                    MachineOpcode::Copy,
+                    vec![MachineOperand::Register(r)],
+                    Some(reg_class),
+                    self.cur_bb,
+                );
+                self.append_inst(copy)
+            }
+            _ => asm_inst,
+        }
+    }
+
     fn pass_struct_byval(
         &mut self,
         arg_regs_order: &mut ArgRegs,
@@ -663,6 +847,7 @@ impl<'a> ScheduleByBlock<'a> {
                     node::AddressKind::FunctionName(name) => {
                         inst::AddressKind::FunctionName(name.clone())
                     }
+                    node::AddressKind::InlineAsm(id) => inst::AddressKind::InlineAsm(*id),
                 })),
             },
             NodeKind::None => MachineOperand::None,
@@ -670,6 +855,23 @@ impl<'a> ScheduleByBlock<'a> {
         }
     }
 
+    /// Like `normal_operand`, but a constant is copied into a register
+    fn register_operand(&mut self, node: Raw<DAGNode>) -> MachineOperand {
+        match self.normal_operand(node) {
+            c @ MachineOperand::Constant(_) => {
+                let copy = self.append_inst(MachineInst::new(
+                    &self.cur_func.regs_info,
+                    MachineOpcode::Copy,
+                    vec![c],
+                    ty2rc(&node.ty),
+                    self.cur_bb,
+                ));
+                MachineOperand::Register(self.inst_arena[copy].get_def_reg().unwrap())
+            }
+            op => op,
+        }
+    }
+
     fn get_machine_bb(&self, dag_bb_id: DAGBasicBlockId) -> MachineBasicBlockId {
         *self.bb_map.get(&dag_bb_id).unwrap()
     }
@@ -698,12 +900,20 @@ pub fn mov_n_rx(bit: usize, x: &MachineOperand) -> Option<MachineOpcode> {
 }
 
 pub fn mov_rx(tys: &Types, regs_info: &RegistersInfo, x: &MachineOperand) -> Option<MachineOpcode> {
+    if x.get_type(regs_info).unwrap().is_vector() {
+        return match x {
+            MachineOperand::FrameIndex(_) | MachineOperand::Mem(_) => Some(MachineOpcode::MOVDQUrm),
+            _ => None,
+        };
+    }
+
     // TODO: special handling for float
     if x.get_type(regs_info).unwrap() == Type::F64 {
         return match x {
             MachineOperand::Constant(_) => Some(MachineOpcode::MOVSDrm64),
             MachineOperand::FrameIndex(_) | MachineOperand::Mem(_) => Some(MachineOpcode::MOVSDrm),
-            MachineOperand::Register(_) => Some(MachineOpcode::MOVSDrr),
+            // An XMM register may hold a vector, so always copy the whole register
+            MachineOperand::Register(_) => Some(MachineOpcode::MOVAPSrr),
             _ => None,
         };
     }
@@ -757,3 +967,24 @@ pub fn mov_mx(regs_info: &RegistersInfo, x: &MachineOperand) -> Option<MachineOp
         _ => None,
     }
 }
+
+/// Returns the store instruction that spills `src` into `slot`.
+pub fn spill_mx(
+    regs_info: &RegistersInfo,
+    slot: &FrameIndexInfo,
+    src: &MachineOperand,
+) -> Option<MachineOpcode> {
+    if slot.ty.is_vector() {
+        return Some(MachineOpcode::MOVDQUmr);
+    }
+    mov_mx(regs_info, src)
+}
+
+/// Returns the type of a stack slot large enough to save any register of `rc`.
+/// XMM registers may hold vectors, so their slots are always 128 bits wide.
+pub fn spill_slot_ty(tys: &Types, rc: RegisterClassKind) -> Type {
+    match rc {
+        RegisterClassKind::XMM => tys.new_vector_ty(Type::F64, 2),
+        _ => rc2ty(rc),
+    }
+}
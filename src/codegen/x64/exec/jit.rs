@@ -11,10 +11,17 @@ use crate::{
         },
     },
     ir,
-    ir::types::*,
+    ir::{global_val::GlobalVariableId, types::*},
 };
 use dynasmrt::*;
 use rustc_hash::FxHashMap;
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
 
 #[rustfmt::skip]
 macro_rules! register {
@@ -44,8 +51,33 @@ pub struct JITCompiler {
     asm: x64::Assembler,
     labels: FxHashMap<LabelKey, DynamicLabel>,
     internal_functions: FxHashMap<String, u64>, // name -> fn address
+    tls_offsets: FxHashMap<GlobalVariableId, i32>, // global -> offset from the thread pointer
+    tls_ranges: Vec<Range<usize>>,              // parts of the per-thread block reserved
+    id: usize,
 }
 
+/// Bytes of thread-local storage available to JIT-compiled code on each thread.
+const TLS_BLOCK_SIZE: usize = 4096;
+
+/// The per-thread block is reserved in units of this many bytes.
+const TLS_GRANULE: usize = 16;
+
+// The block lives in the static TLS segment, so its offset from the thread pointer is the same
+// on every thread and can be embedded into the generated code.
+#[thread_local]
+static mut TLS_BLOCK: [u128; TLS_BLOCK_SIZE / 16] = [0; TLS_BLOCK_SIZE / 16];
+
+// The compiler that last zeroed each granule of the block on this thread. The space of a dropped
+// compiler is reused, so a compiler zeroes its part of the block on each thread before running.
+#[thread_local]
+static mut TLS_BLOCK_OWNER: [usize; TLS_BLOCK_SIZE / TLS_GRANULE] =
+    [0; TLS_BLOCK_SIZE / TLS_GRANULE];
+
+// Parts of the block reserved by live compilers, sorted by their start
+static TLS_BLOCK_USED: Mutex<Vec<Range<usize>>> = Mutex::new(Vec::new());
+
+static NEXT_COMPILER_ID: AtomicUsize = AtomicUsize::new(1);
+
 impl JITExecutor {
     pub fn new(module: &mut ir::module::Module) -> Self {
         let machine_module = standard_conversion_into_machine_module(module);
@@ -114,6 +146,9 @@ impl JITCompiler {
                     .into_iter()
                     .collect::<FxHashMap<_, _>>()
             },
+            tls_offsets: FxHashMap::default(),
+            tls_ranges: vec![],
+            id: NEXT_COMPILER_ID.fetch_add(1, Ordering::SeqCst),
         }
     }
 
@@ -123,6 +158,8 @@ impl JITCompiler {
         id: MachineFunctionId,
         args: Vec<GenericValue>,
     ) -> GenericValue {
+        self.init_tls();
+
         let entry = self.asm.offset();
 
         for (idx, arg) in args.iter().enumerate() {
@@ -173,6 +210,18 @@ impl JITCompiler {
     }
 
     pub fn compile_module(&mut self, module: &MachineModule) {
+        // Reserve room for thread-local globals in the per-thread block
+        for (id, g) in &module.global_vars.arena {
+            if g.thread_local {
+                let range = alloc_tls(
+                    g.ty.size_in_byte(&module.types),
+                    g.ty.align_in_byte(&module.types),
+                );
+                self.tls_offsets.insert(id, tls_offset(range.start));
+                self.tls_ranges.push(range);
+            }
+        }
+
         // Place constant data in memory
         for (_id, f) in &module.functions {
             for (id, c) in f.const_data.id_and_data() {
@@ -228,7 +277,11 @@ impl JITCompiler {
                     MachineOpcode::MOVSDrm => self.compile_movsd_rm(&frame_objects, inst),
                     MachineOpcode::MOVSDmr => self.compile_movsd_mr(&frame_objects, inst),
                     MachineOpcode::MOVSDrr => self.compile_movsd_rr(inst),
+                    MachineOpcode::MOVAPSrr => self.compile_movaps_rr(inst),
+                    MachineOpcode::MOVDQUrm => self.compile_movdqu_rm(&frame_objects, inst),
+                    MachineOpcode::MOVDQUmr => self.compile_movdqu_mr(&frame_objects, inst),
                     MachineOpcode::LEAr64m => self.compile_lea_r64m(&frame_objects, inst),
+                    MachineOpcode::TLSADDRr64 => self.compile_tlsaddr_r64(inst),
                     MachineOpcode::RET => self.compile_ret(),
                     MachineOpcode::PUSH64 => self.compile_push64(inst),
                     MachineOpcode::POP64 => self.compile_pop64(inst),
@@ -250,15 +303,40 @@ impl JITCompiler {
                     MachineOpcode::MULSDrm => self.compile_mulsd_rm(&frame_objects, inst),
                     MachineOpcode::DIVSDrr => self.compile_divsd_rr(inst),
                     MachineOpcode::DIVSDrm => self.compile_divsd_rm(&frame_objects, inst),
+                    MachineOpcode::FMODSDrr => self.compile_fmodsd_rr(inst),
                     MachineOpcode::SQRTSDrr => self.compile_sqrtsd_rr(inst),
+                    MachineOpcode::PADDDrr => self.compile_paddd_rr(inst),
+                    MachineOpcode::PSUBDrr => self.compile_psubd_rr(inst),
+                    MachineOpcode::ADDPDrr => self.compile_addpd_rr(inst),
+                    MachineOpcode::SUBPDrr => self.compile_subpd_rr(inst),
+                    MachineOpcode::MULPDrr => self.compile_mulpd_rr(inst),
+                    MachineOpcode::DIVPDrr => self.compile_divpd_rr(inst),
+                    MachineOpcode::MOVDr32x => self.compile_movd_r32x(inst),
+                    MachineOpcode::MOVDxr32 => self.compile_movd_xr32(inst),
+                    MachineOpcode::PSHUFDrri => self.compile_pshufd_rri(inst),
+                    MachineOpcode::SHUFPSrri => self.compile_shufps_rri(inst),
+                    MachineOpcode::SHUFPDrri => self.compile_shufpd_rri(inst),
                     MachineOpcode::IDIV => self.compile_idiv(&frame_objects, inst),
                     MachineOpcode::CDQ => self.compile_cdq(&frame_objects, inst),
                     MachineOpcode::SHLr32i8 => self.compile_shl_r32i8(inst),
+                    MachineOpcode::SHLr32CL => self.compile_shl_r32cl(inst),
+                    MachineOpcode::ANDr64i32 => self.compile_and_r64i32(inst),
                     MachineOpcode::SHLr64i8 => self.compile_shl_r64i8(inst),
                     MachineOpcode::CALL => self.compile_call(module, &frame_objects, inst),
+                    MachineOpcode::InlineAsm => self.compile_inline_asm(module, inst),
                     MachineOpcode::CMPri => self.compile_cmp_ri(inst),
                     MachineOpcode::CMPrr => self.compile_cmp_rr(inst),
                     MachineOpcode::UCOMISDrr => self.compile_ucomisd_rr(inst),
+                    MachineOpcode::CMOVErr32
+                    | MachineOpcode::CMOVErr64
+                    | MachineOpcode::CMOVLrr32
+                    | MachineOpcode::CMOVLrr64
+                    | MachineOpcode::CMOVLErr32
+                    | MachineOpcode::CMOVLErr64
+                    | MachineOpcode::CMOVGrr32
+                    | MachineOpcode::CMOVGrr64
+                    | MachineOpcode::CMOVGErr32
+                    | MachineOpcode::CMOVGErr64 => self.compile_cmov_rr(inst),
                     MachineOpcode::JE => self.compile_je(inst),
                     MachineOpcode::JNE => self.compile_jne(inst),
                     MachineOpcode::JBE => self.compile_jbe(inst),
@@ -621,6 +699,94 @@ impl JITCompiler {
         }
     }
 
+    fn compile_movaps_rr(&mut self, inst: &MachineInst) {
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[0].as_register().as_phys_reg());
+        dynasm!(self.asm; movaps Rx(r0), Rx(r1));
+    }
+
+    fn compile_movdqu_rm(&mut self, fo: &FrameObjectsInfo, inst: &MachineInst) {
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        match &inst.operand[0] {
+            MachineOperand::Mem(MachineMemOperand::BaseFi(base, fi)) => {
+                let r1 = phys_reg_to_dynasm_reg(base.as_phys_reg());
+                let m2 = fi.idx;
+                dynasm!(self.asm; movdqu Rx(r0), [Rq(r1) - fo.offset(m2).unwrap()]);
+            }
+            MachineOperand::Mem(MachineMemOperand::Base(base)) => {
+                let r1 = phys_reg_to_dynasm_reg(base.as_phys_reg());
+                dynasm!(self.asm; movdqu Rx(r0), [Rq(r1)]);
+            }
+            MachineOperand::Mem(MachineMemOperand::BaseFiOff(base, fi, off)) => {
+                let r1 = phys_reg_to_dynasm_reg(base.as_phys_reg());
+                let m2 = fi.idx;
+                dynasm!(self.asm; movdqu Rx(r0), [Rq(r1) - fo.offset(m2).unwrap() + off]);
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn compile_movdqu_mr(&mut self, fo: &FrameObjectsInfo, inst: &MachineInst) {
+        match &inst.operand[0] {
+            MachineOperand::Mem(MachineMemOperand::BaseFi(base, fi)) => {
+                let r0 = phys_reg_to_dynasm_reg(base.as_phys_reg());
+                let m1 = fo.offset(fi.idx).unwrap();
+                let r2 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+                dynasm!(self.asm; movdqu [Rq(r0) - m1], Rx(r2));
+            }
+            MachineOperand::Mem(MachineMemOperand::Base(base)) => {
+                let r0 = phys_reg_to_dynasm_reg(base.as_phys_reg());
+                let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+                dynasm!(self.asm; movdqu [Rq(r0)], Rx(r1));
+            }
+            MachineOperand::Mem(MachineMemOperand::BaseFiOff(base, fi, off)) => {
+                let r0 = phys_reg_to_dynasm_reg(base.as_phys_reg());
+                let m1 = fo.offset(fi.idx).unwrap();
+                let r2 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+                dynasm!(self.asm; movdqu [Rq(r0) - m1 + off], Rx(r2));
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Zeroes the thread-local globals on the current thread unless it's already done
+    fn init_tls(&self) {
+        for range in &self.tls_ranges {
+            unsafe {
+                let owner = &mut (*::std::ptr::addr_of_mut!(TLS_BLOCK_OWNER));
+                let granules = range.start / TLS_GRANULE..range.end / TLS_GRANULE;
+                if owner[granules.start] == self.id {
+                    continue;
+                }
+                let block = ::std::ptr::addr_of_mut!(TLS_BLOCK) as *mut u8;
+                ::std::ptr::write_bytes(block.add(range.start), 0, range.len());
+                owner[granules].iter_mut().for_each(|o| *o = self.id);
+            }
+        }
+    }
+
+    fn compile_tlsaddr_r64(&mut self, inst: &MachineInst) {
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let id = inst.operand[0].as_mem().as_address().as_global();
+        let i1 = *self.tls_offsets.get(id).unwrap();
+        dynasm!(self.asm
+            ; fs mov Rq(r0), QWORD [0]
+            ; lea Rq(r0), [Rq(r0) + i1]
+        );
+    }
+
+    fn compile_inline_asm(&mut self, module: &MachineModule, inst: &MachineInst) {
+        let id = inst.operand[0].as_mem().as_address().as_inline_asm();
+        let asm = &module.inline_asms.arena[*id];
+        let bytes = asm.bytes.as_ref().unwrap_or_else(|| {
+            panic!(
+                "inline asm \"{}\" needs pre-encoded bytes to be JIT-compiled",
+                asm.template
+            )
+        });
+        dynasm!(self.asm; .bytes bytes.iter());
+    }
+
     fn compile_lea_r64m(&mut self, fo: &FrameObjectsInfo, inst: &MachineInst) {
         let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
         match &inst.operand[0] {
@@ -726,6 +892,25 @@ impl JITCompiler {
         }
     }
 
+    fn compile_cmov_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        match inst.opcode {
+            MachineOpcode::CMOVErr32 => dynasm!(self.asm; cmove Rd(r0), Rd(r1)),
+            MachineOpcode::CMOVErr64 => dynasm!(self.asm; cmove Rq(r0), Rq(r1)),
+            MachineOpcode::CMOVLrr32 => dynasm!(self.asm; cmovl Rd(r0), Rd(r1)),
+            MachineOpcode::CMOVLrr64 => dynasm!(self.asm; cmovl Rq(r0), Rq(r1)),
+            MachineOpcode::CMOVLErr32 => dynasm!(self.asm; cmovle Rd(r0), Rd(r1)),
+            MachineOpcode::CMOVLErr64 => dynasm!(self.asm; cmovle Rq(r0), Rq(r1)),
+            MachineOpcode::CMOVGrr32 => dynasm!(self.asm; cmovg Rd(r0), Rd(r1)),
+            MachineOpcode::CMOVGrr64 => dynasm!(self.asm; cmovg Rq(r0), Rq(r1)),
+            MachineOpcode::CMOVGErr32 => dynasm!(self.asm; cmovge Rd(r0), Rd(r1)),
+            MachineOpcode::CMOVGErr64 => dynasm!(self.asm; cmovge Rq(r0), Rq(r1)),
+            _ => unreachable!(),
+        }
+    }
+
     fn compile_ucomisd_rr(&mut self, inst: &MachineInst) {
         let r0 = phys_reg_to_dynasm_reg(inst.operand[0].as_register().as_phys_reg());
         let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
@@ -891,6 +1076,13 @@ impl JITCompiler {
         dynasm!(self.asm; sub Rq(r0), i1);
     }
 
+    fn compile_and_r64i32(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let i1 = inst.operand[1].as_constant().as_i32();
+        dynasm!(self.asm; and Rq(r0), i1);
+    }
+
     fn compile_subsd_rr(&mut self, inst: &MachineInst) {
         // inst.operand[0] must be the same as inst.def[0] (they're tied)
         let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
@@ -973,6 +1165,106 @@ impl JITCompiler {
         dynasm!(self.asm; sqrtsd Rx(r0), Rx(r1));
     }
 
+    fn compile_paddd_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        dynasm!(self.asm; paddd Rx(r0), Rx(r1));
+    }
+
+    fn compile_psubd_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        dynasm!(self.asm; psubd Rx(r0), Rx(r1));
+    }
+
+    fn compile_addpd_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        dynasm!(self.asm; addpd Rx(r0), Rx(r1));
+    }
+
+    fn compile_subpd_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        dynasm!(self.asm; subpd Rx(r0), Rx(r1));
+    }
+
+    fn compile_mulpd_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        dynasm!(self.asm; mulpd Rx(r0), Rx(r1));
+    }
+
+    fn compile_divpd_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        dynasm!(self.asm; divpd Rx(r0), Rx(r1));
+    }
+
+    fn compile_movd_r32x(&mut self, inst: &MachineInst) {
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[0].as_register().as_phys_reg());
+        dynasm!(self.asm; movd Rd(r0), Rx(r1));
+    }
+
+    fn compile_movd_xr32(&mut self, inst: &MachineInst) {
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[0].as_register().as_phys_reg());
+        dynasm!(self.asm; movd Rx(r0), Rd(r1));
+    }
+
+    fn compile_pshufd_rri(&mut self, inst: &MachineInst) {
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[0].as_register().as_phys_reg());
+        let i2 = inst.operand[1].as_constant().as_i8();
+        dynasm!(self.asm; pshufd Rx(r0), Rx(r1), i2);
+    }
+
+    fn compile_shufps_rri(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        let i2 = inst.operand[2].as_constant().as_i8();
+        dynasm!(self.asm; shufps Rx(r0), Rx(r1), i2);
+    }
+
+    fn compile_shufpd_rri(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        let i2 = inst.operand[2].as_constant().as_i8();
+        dynasm!(self.asm; shufpd Rx(r0), Rx(r1), i2);
+    }
+
+    fn compile_fmodsd_rr(&mut self, inst: &MachineInst) {
+        // inst.operand[0] must be the same as inst.def[0] (they're tied)
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        let r1 = phys_reg_to_dynasm_reg(inst.operand[1].as_register().as_phys_reg());
+        let partial = self.asm.new_dynamic_label();
+        // SSE has no remainder instruction. fprem computes a partial remainder and sets C2 until
+        // the remainder is complete. The red zone below rsp is free to pass values to x87.
+        dynasm!(self.asm
+            ; movsd QWORD [rsp - 16], Rx(r1)
+            ; movsd QWORD [rsp - 8], Rx(r0)
+            ; fld QWORD [rsp - 16]
+            ; fld QWORD [rsp - 8]
+            ; =>partial
+            ; fprem
+            ; fnstsw ax
+            ; test ax, 0x400
+            ; jnz =>partial
+            ; fstp QWORD [rsp - 8]
+            ; fstp st0
+            ; movsd Rx(r0), QWORD [rsp - 8]
+        );
+    }
+
     fn compile_cdq(&mut self, _fo: &FrameObjectsInfo, _inst: &MachineInst) {
         dynasm!(self.asm; cdq)
     }
@@ -991,6 +1283,11 @@ impl JITCompiler {
         dynasm!(self.asm; shl Rd(r0), i1);
     }
 
+    fn compile_shl_r32cl(&mut self, inst: &MachineInst) {
+        let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
+        dynasm!(self.asm; shl Rd(r0), cl);
+    }
+
     fn compile_shl_r64i8(&mut self, inst: &MachineInst) {
         let r0 = phys_reg_to_dynasm_reg(inst.def[0].as_phys_reg());
         let i1 = inst.operand[1].as_constant().as_i8();
@@ -1114,6 +1411,53 @@ pub extern "C" fn cilk_sin_f64_(x: f64) -> f64 {
     x.sin()
 }
 
+/// Reserves `size` bytes in the per-thread block. The first free part large enough is taken.
+fn alloc_tls(size: usize, align: usize) -> Range<usize> {
+    let align = align.max(TLS_GRANULE);
+    let size = ((size + TLS_GRANULE - 1) / TLS_GRANULE).max(1) * TLS_GRANULE;
+    let mut used = TLS_BLOCK_USED.lock().unwrap();
+    let mut start = 0;
+    let mut pos = used.len();
+    for (i, r) in used.iter().enumerate() {
+        if (start + align - 1) / align * align + size <= r.start {
+            pos = i;
+            break;
+        }
+        start = r.end;
+    }
+    let start = (start + align - 1) / align * align;
+    assert!(
+        start + size <= TLS_BLOCK_SIZE,
+        "out of thread-local storage for JIT-compiled code"
+    );
+    used.insert(pos, start..start + size);
+    start..start + size
+}
+
+fn release_tls(range: &Range<usize>) {
+    TLS_BLOCK_USED.lock().unwrap().retain(|r| r != range);
+}
+
+/// Returns the offset of `start` in the per-thread block from the thread pointer
+fn tls_offset(start: usize) -> i32 {
+    let block = ::std::ptr::addr_of!(TLS_BLOCK) as i64;
+    (block - thread_pointer() + start as i64) as i32
+}
+
+impl Drop for JITCompiler {
+    fn drop(&mut self) {
+        for range in &self.tls_ranges {
+            release_tls(range)
+        }
+    }
+}
+
+fn thread_pointer() -> i64 {
+    let tp: i64;
+    unsafe { ::std::arch::asm!("mov {}, qword ptr fs:[0]", out(reg) tp) };
+    tp
+}
+
 // EXPERIMENTAL Internal function cilk.cos.f64
 #[no_mangle]
 pub extern "C" fn cilk_cos_f64_(x: f64) -> f64 {
@@ -20,6 +20,20 @@ impl FrameObjectsInfo {
 
         let padding = |off, align| -> i32 { (align - off % align) % align };
 
+        // Locals aligned more strictly than the stack (e.g. 32-byte aligned allocas) need the
+        // frame pointer to be realigned in the prologue. The original frame pointer is saved at
+        // [rbp - 8].
+        let realign = f
+            .local_mgr
+            .locals
+            .iter()
+            .map(|info| f.local_mgr.align_of(tys, info) as i32)
+            .max()
+            .filter(|&align| align > ALIGN);
+        if realign.is_some() {
+            offset += 8;
+        }
+
         let base = &tys.base.borrow();
         let f_ty = base.as_function_ty(f.ty).unwrap();
         for (i, param_ty) in f_ty.params_ty.iter().enumerate() {
@@ -43,11 +57,12 @@ impl FrameObjectsInfo {
             }
         }
 
-        for FrameIndexInfo { idx, ty } in &f.local_mgr.locals {
-            let size = ty.size_in_byte(tys) as i32;
-            let align = ty.align_in_byte(tys) as i32;
-            offset += size + padding(offset, align);
-            offset_map.insert(*idx, offset);
+        for info in &f.local_mgr.locals {
+            let size = info.ty.size_in_byte(tys) as i32;
+            let align = f.local_mgr.align_of(tys, info) as i32;
+            // [rbp - offset] must be aligned
+            offset = roundup(offset + size, align);
+            offset_map.insert(info.idx, offset);
         }
 
         let stack_down = Self::calc_max_adjust_stack_down(f);
@@ -66,6 +81,7 @@ impl FrameObjectsInfo {
             offset_map,
             total_size: offset,
             callee_saved_regs_byte,
+            realign,
         }
     }
 
@@ -215,6 +215,24 @@ mod inst {
                 .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
                 .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
         };
+        pub static ref SHLr32CL: TargetInstDef = {
+            TargetInstDef::new("shl", TargetOpcode::SHLr32CL)
+                .set_uses(vec![TargetOperand::Register(TargetRegister::RegClass(
+                    RegisterClassKind::GR32,
+                ))])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
+                .set_imp_use(vec![TargetRegister::Specific(GR32::ECX.as_phys_reg())])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref ANDr64i32: TargetInstDef = {
+            TargetInstDef::new("and", TargetOpcode::ANDr64i32)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                    TargetOperand::Immediate(TargetImmediate::I32),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR64)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
         pub static ref SHLr64i8: TargetInstDef = {
             TargetInstDef::new("shl", TargetOpcode::SHLr64i8)
                 .set_uses(vec![
@@ -232,6 +250,16 @@ mod inst {
                 .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
                 // .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
         };
+        pub static ref FMODSDrr: TargetInstDef = {
+            TargetInstDef::new("fprem", TargetOpcode::FMODSDrr)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .set_imp_def(vec![TargetRegister::Specific(GR32::EAX.as_phys_reg())])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
         pub static ref CDQ: TargetInstDef = {
             TargetInstDef::new("cdq", TargetOpcode::CDQ)
                 .set_imp_def(vec![TargetRegister::Specific(GR32::EDX.as_phys_reg())])
@@ -370,6 +398,96 @@ mod inst {
             TargetInstDef::new("cmp", TargetOpcode::CMPrr)
                 .set_uses(vec![TargetOperand::Any, TargetOperand::Any])
         };
+        pub static ref CMOVErr32: TargetInstDef = {
+            TargetInstDef::new("cmove", TargetOpcode::CMOVErr32)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVErr64: TargetInstDef = {
+            TargetInstDef::new("cmove", TargetOpcode::CMOVErr64)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR64)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVLrr32: TargetInstDef = {
+            TargetInstDef::new("cmovl", TargetOpcode::CMOVLrr32)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVLrr64: TargetInstDef = {
+            TargetInstDef::new("cmovl", TargetOpcode::CMOVLrr64)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR64)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVLErr32: TargetInstDef = {
+            TargetInstDef::new("cmovle", TargetOpcode::CMOVLErr32)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVLErr64: TargetInstDef = {
+            TargetInstDef::new("cmovle", TargetOpcode::CMOVLErr64)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR64)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVGrr32: TargetInstDef = {
+            TargetInstDef::new("cmovg", TargetOpcode::CMOVGrr32)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVGrr64: TargetInstDef = {
+            TargetInstDef::new("cmovg", TargetOpcode::CMOVGrr64)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR64)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVGErr32: TargetInstDef = {
+            TargetInstDef::new("cmovge", TargetOpcode::CMOVGErr32)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR32)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref CMOVGErr64: TargetInstDef = {
+            TargetInstDef::new("cmovge", TargetOpcode::CMOVGErr64)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::GR64)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR64)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
         pub static ref UCOMISDrr: TargetInstDef = {
             TargetInstDef::new("ucomisd", TargetOpcode::UCOMISDrr)
                 .set_uses(vec![
@@ -382,8 +500,136 @@ mod inst {
                 // .set_uses(vec![TargetOperand::Any, TargetOperand::Any])
                 // .set_imp_use(vec![]
         };
+        pub static ref InlineAsm: TargetInstDef =
+            TargetInstDef::new("inlineasm", TargetOpcode::InlineAsm);
         pub static ref RET: TargetInstDef = TargetInstDef::new("ret", TargetOpcode::RET);
     }
+
+    lazy_static! {
+        pub static ref TLSADDRr64: TargetInstDef = {
+            TargetInstDef::new("tlsaddr", TargetOpcode::TLSADDRr64)
+                .set_uses(vec![TargetOperand::Mem])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR64)])
+        };
+    }
+
+    // SSE instructions operating on whole XMM registers
+    lazy_static! {
+        pub static ref MOVAPSrr: TargetInstDef = {
+            TargetInstDef::new("movaps", TargetOpcode::MOVAPSrr)
+                .set_uses(vec![TargetOperand::Register(TargetRegister::RegClass(
+                    RegisterClassKind::XMM,
+                ))])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+        };
+        pub static ref MOVDQUrm: TargetInstDef = {
+            TargetInstDef::new("movdqu", TargetOpcode::MOVDQUrm)
+                .set_uses(vec![TargetOperand::Mem])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+        };
+        pub static ref MOVDQUmr: TargetInstDef = {
+            TargetInstDef::new("movdqu", TargetOpcode::MOVDQUmr).set_uses(vec![
+                TargetOperand::Mem,
+                TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+            ])
+        };
+        pub static ref PADDDrr: TargetInstDef = {
+            TargetInstDef::new("paddd", TargetOpcode::PADDDrr)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref PSUBDrr: TargetInstDef = {
+            TargetInstDef::new("psubd", TargetOpcode::PSUBDrr)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref ADDPDrr: TargetInstDef = {
+            TargetInstDef::new("addpd", TargetOpcode::ADDPDrr)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref SUBPDrr: TargetInstDef = {
+            TargetInstDef::new("subpd", TargetOpcode::SUBPDrr)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref MULPDrr: TargetInstDef = {
+            TargetInstDef::new("mulpd", TargetOpcode::MULPDrr)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref DIVPDrr: TargetInstDef = {
+            TargetInstDef::new("divpd", TargetOpcode::DIVPDrr)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref MOVDr32x: TargetInstDef = {
+            TargetInstDef::new("movd", TargetOpcode::MOVDr32x)
+                .set_uses(vec![TargetOperand::Register(TargetRegister::RegClass(
+                    RegisterClassKind::XMM,
+                ))])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::GR32)])
+        };
+        pub static ref MOVDxr32: TargetInstDef = {
+            TargetInstDef::new("movd", TargetOpcode::MOVDxr32)
+                .set_uses(vec![TargetOperand::Register(TargetRegister::RegClass(
+                    RegisterClassKind::GR32,
+                ))])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+        };
+        pub static ref PSHUFDrri: TargetInstDef = {
+            TargetInstDef::new("pshufd", TargetOpcode::PSHUFDrri)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Immediate(TargetImmediate::I8),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+        };
+        pub static ref SHUFPSrri: TargetInstDef = {
+            TargetInstDef::new("shufps", TargetOpcode::SHUFPSrri)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Immediate(TargetImmediate::I8),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+        pub static ref SHUFPDrri: TargetInstDef = {
+            TargetInstDef::new("shufpd", TargetOpcode::SHUFPDrri)
+                .set_uses(vec![
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Register(TargetRegister::RegClass(RegisterClassKind::XMM)),
+                    TargetOperand::Immediate(TargetImmediate::I8),
+                ])
+                .set_defs(vec![TargetRegister::RegClass(RegisterClassKind::XMM)])
+                .add_tie(DefOrUseReg::Def(0), DefOrUseReg::Use(0))
+        };
+    }
 }
 
 // r => register
@@ -396,6 +642,9 @@ pub enum TargetOpcode {
     MOVSDmr,   // movsd MEM, r
     MOVSDrm,   // movsd r, MEM
     MOVSDrr,
+    MOVAPSrr,
+    MOVDQUrm, // movdqu r, MEM
+    MOVDQUmr, // movdqu MEM, r
 
     // TODO: MachineMemOperand is introduced, this is no longer correct info
     // out = mov [rbp  - fi.off              ] | out = mov rbp,  fi,   none,  none
@@ -425,6 +674,10 @@ pub enum TargetOpcode {
     // out = lea [base                       ] | out = lea base, none, none,  none
     LEAr64m,
 
+    // out = address of a thread-local global; expanded into `mov out, fs:0` followed by
+    // `lea`/`add` depending on the TLS model
+    TLSADDRr64,
+
     ADDrr32,
     ADDrr64,
     ADDri32,
@@ -445,11 +698,29 @@ pub enum TargetOpcode {
     IDIV,
     DIVSDrr,
     DIVSDrm,
+    // out = operand[0] (tied) % operand[1]; expanded into an x87 fprem loop through the red zone
+    FMODSDrr,
+    ANDr64i32,
     SHLr64i8,
     SHLr32i8,
+    SHLr32CL, // shl r32, cl
     CVTTSD2SIr32r,
     CVTSI2SDrr32,
     SQRTSDrr,
+
+    // Packed SSE
+    PADDDrr,
+    PSUBDrr,
+    ADDPDrr,
+    SUBPDrr,
+    MULPDrr,
+    DIVPDrr,
+    MOVDr32x,  // r32 = movd xmm
+    MOVDxr32,  // xmm = movd r32
+    PSHUFDrri, // xmm = pshufd xmm, imm8
+    SHUFPSrri,
+    SHUFPDrri,
+
     MOVrr32,
     MOVri32,
     MOVrr64,
@@ -461,6 +732,9 @@ pub enum TargetOpcode {
 
     CALL,
 
+    // out = inline asm; operand[0] identifies the asm, the rest are its inputs
+    InlineAsm,
+
     // Comparison
     Seteq,
     Setle,
@@ -472,6 +746,19 @@ pub enum TargetOpcode {
     CMPrr,
     CMPri,
     UCOMISDrr,
+
+    // out = cond ? operand[1] : operand[0] (tied)
+    CMOVErr32,
+    CMOVErr64,
+    CMOVLrr32,
+    CMOVLrr64,
+    CMOVLErr32,
+    CMOVLErr64,
+    CMOVGrr32,
+    CMOVGrr64,
+    CMOVGErr32,
+    CMOVGErr64,
+
     JE,
     JNE,
     JBE,
@@ -499,9 +786,13 @@ impl TargetOpcode {
             Self::MOVSDmr => Some(&*inst::MOVSDmr),
             Self::MOVSDrm => Some(&*inst::MOVSDrm),
             Self::MOVSDrr => Some(&*inst::MOVSDrr),
+            Self::MOVAPSrr => Some(&*inst::MOVAPSrr),
+            Self::MOVDQUrm => Some(&*inst::MOVDQUrm),
+            Self::MOVDQUmr => Some(&*inst::MOVDQUmr),
             Self::MOVSXDr64m32 => Some(&*inst::MOVSXDr64m32),
             Self::MOVSXDr64r32 => Some(&*inst::MOVSXDr64r32),
             Self::LEAr64m => Some(&*inst::LEAr64m),
+            Self::TLSADDRr64 => Some(&*inst::TLSADDRr64),
             Self::ADDrr32 => Some(&*inst::ADDrr32),
             Self::ADDrr64 => Some(&*inst::ADDrr64),
             Self::ADDri32 => Some(&*inst::ADDri32),
@@ -521,11 +812,25 @@ impl TargetOpcode {
             Self::CDQ => Some(&*inst::CDQ),
             Self::DIVSDrr => Some(&*inst::DIVSDrr),
             Self::DIVSDrm => Some(&*inst::DIVSDrm),
+            Self::FMODSDrr => Some(&*inst::FMODSDrr),
+            Self::ANDr64i32 => Some(&*inst::ANDr64i32),
             Self::SHLr64i8 => Some(&*inst::SHLr64i8),
             Self::SHLr32i8 => Some(&*inst::SHLr32i8),
+            Self::SHLr32CL => Some(&*inst::SHLr32CL),
             Self::CVTSI2SDrr32 => Some(&*inst::CVTSI2SDrr32),
             Self::CVTTSD2SIr32r => Some(&*inst::CVTTSD2SIr32r),
             Self::SQRTSDrr => Some(&*inst::SQRTSDrr),
+            Self::PADDDrr => Some(&*inst::PADDDrr),
+            Self::PSUBDrr => Some(&*inst::PSUBDrr),
+            Self::ADDPDrr => Some(&*inst::ADDPDrr),
+            Self::SUBPDrr => Some(&*inst::SUBPDrr),
+            Self::MULPDrr => Some(&*inst::MULPDrr),
+            Self::DIVPDrr => Some(&*inst::DIVPDrr),
+            Self::MOVDr32x => Some(&*inst::MOVDr32x),
+            Self::MOVDxr32 => Some(&*inst::MOVDxr32),
+            Self::PSHUFDrri => Some(&*inst::PSHUFDrri),
+            Self::SHUFPSrri => Some(&*inst::SHUFPSrri),
+            Self::SHUFPDrri => Some(&*inst::SHUFPDrri),
             Self::MOVrr32 => Some(&*inst::MOVrr32),
             Self::MOVri32 => Some(&*inst::MOVri32),
             Self::MOVrm32 => Some(&*inst::MOVrm32),
@@ -552,7 +857,18 @@ impl TargetOpcode {
             Self::JAE => Some(&*inst::JAE),
             Self::CMPri => Some(&*inst::CMPri),
             Self::CMPrr => Some(&*inst::CMPrr),
+            Self::CMOVErr32 => Some(&*inst::CMOVErr32),
+            Self::CMOVErr64 => Some(&*inst::CMOVErr64),
+            Self::CMOVLrr32 => Some(&*inst::CMOVLrr32),
+            Self::CMOVLrr64 => Some(&*inst::CMOVLrr64),
+            Self::CMOVLErr32 => Some(&*inst::CMOVLErr32),
+            Self::CMOVLErr64 => Some(&*inst::CMOVLErr64),
+            Self::CMOVGrr32 => Some(&*inst::CMOVGrr32),
+            Self::CMOVGrr64 => Some(&*inst::CMOVGrr64),
+            Self::CMOVGErr32 => Some(&*inst::CMOVGErr32),
+            Self::CMOVGErr64 => Some(&*inst::CMOVGErr64),
             Self::CALL => Some(&*inst::CALL),
+            Self::InlineAsm => Some(&*inst::InlineAsm),
             Self::RET => Some(&*inst::RET),
             Self::UCOMISDrr => Some(&*inst::UCOMISDrr),
             _ => None,
@@ -1,5 +1,5 @@
 use super::super::{
-    dag::mc_convert::{mov_mx, mov_rx},
+    dag::mc_convert::{mov_rx, spill_mx},
     frame_object::*,
     machine::register::*,
 };
@@ -110,7 +110,7 @@ impl<'a> LiveIntervalSplitter<'a> {
         let rbp = self.func.regs_info.get_phys_reg(GR64::RBP);
         let mem = MachineOperand::Mem(MachineMemOperand::BaseFi(rbp, *slot));
         let store_id = self.func.alloc_inst(MachineInst::new_simple(
-            mov_mx(&self.func.regs_info, &src).unwrap(),
+            spill_mx(&self.func.regs_info, slot, &src).unwrap(),
             vec![mem, src],
             parent,
         ));
@@ -22,6 +22,8 @@ impl ModulePassTrait for PrologueEpilogueInserter {
 
 struct CopyArgs<'a> {
     offset: i32,
+    arg_base: RegisterId, // the register pointing to the original frame
+
     builder: &'a mut Builder<'a>,
     params_ty: &'a Vec<Type>,
     params_attr: &'a FxHashMap<usize, ParamAttribute>,
@@ -45,9 +47,10 @@ impl PrologueEpilogueInserter {
 
         let frame_objects = FrameObjectsInfo::new(tys, cur_func);
         let adjust = frame_objects.total_size();
+        let realign = frame_objects.realign;
         Self::remove_adjust_stack_inst(cur_func);
-        self.insert_prologue(tys, cur_func, adjust);
-        self.insert_epilogue(cur_func, adjust);
+        self.insert_prologue(tys, cur_func, adjust, realign);
+        self.insert_epilogue(cur_func, adjust, realign);
         cur_func.frame_objects = Some(frame_objects);
     }
 
@@ -68,7 +71,13 @@ impl PrologueEpilogueInserter {
         }
     }
 
-    fn insert_prologue(&mut self, tys: &Types, cur_func: &mut MachineFunction, adjust: i32) {
+    fn insert_prologue(
+        &mut self,
+        tys: &Types,
+        cur_func: &mut MachineFunction,
+        adjust: i32,
+        realign: Option<i32>,
+    ) {
         let has_call = cur_func.body.has_call();
         let mut builder = Builder::new(cur_func);
         builder.set_insert_point_at_entry_block();
@@ -102,7 +111,51 @@ impl PrologueEpilogueInserter {
         .with_def(vec![builder.function.regs_info.get_phys_reg(GR64::RBP)]);
         builder.insert(mov_rbp_rsp);
 
-        if has_call {
+        let rbp = builder.function.regs_info.get_phys_reg(GR64::RBP);
+        let rsp = builder.function.regs_info.get_phys_reg(GR64::RSP);
+        let mut arg_base = rbp;
+
+        if let Some(align) = realign {
+            // mov r11, rbp
+            // and rbp, -align
+            // mov [rbp - 8], r11
+            // mov rsp, rbp
+            // sub rsp, adjust
+            let r11 = builder.function.regs_info.get_phys_reg(GR64::R11);
+            let mov_r11_rbp = MachineInst::new_simple(
+                MachineOpcode::MOVrr64,
+                vec![MachineOperand::Register(rbp)],
+                builder.get_cur_bb().unwrap(),
+            )
+            .with_def(vec![r11]);
+            builder.insert(mov_r11_rbp);
+            let and_rbp = MachineInst::new_simple(
+                MachineOpcode::ANDr64i32,
+                vec![MachineOperand::Register(rbp), MachineOperand::imm_i32(-align)],
+                builder.get_cur_bb().unwrap(),
+            )
+            .with_def(vec![rbp]);
+            builder.insert(and_rbp);
+            let save_r11 = MachineInst::new_simple(
+                MachineOpcode::MOVmr64,
+                vec![
+                    MachineOperand::Mem(MachineMemOperand::BaseOff(rbp, -8)),
+                    MachineOperand::Register(r11),
+                ],
+                builder.get_cur_bb().unwrap(),
+            );
+            builder.insert(save_r11);
+            let mov_rsp_rbp = MachineInst::new_simple(
+                MachineOpcode::MOVrr64,
+                vec![MachineOperand::Register(rbp)],
+                builder.get_cur_bb().unwrap(),
+            )
+            .with_def(vec![rsp]);
+            builder.insert(mov_rsp_rbp);
+            arg_base = r11;
+        }
+
+        if has_call || realign.is_some() {
             // sub rsp, adjust
             let sub_rsp = MachineInst::new_simple(
                 MachineOpcode::SUBr64i32,
@@ -116,19 +169,25 @@ impl PrologueEpilogueInserter {
             builder.insert(sub_rsp);
         }
 
-        self.insert_arg_copy(&tys.base.borrow(), &mut builder);
+        self.insert_arg_copy(&tys.base.borrow(), &mut builder, arg_base);
     }
 
-    fn insert_arg_copy<'a>(&mut self, tys: &'a TypesBase, builder: &'a mut Builder<'a>) {
+    fn insert_arg_copy<'a>(
+        &mut self,
+        tys: &'a TypesBase,
+        builder: &'a mut Builder<'a>,
+        arg_base: RegisterId,
+    ) {
         CopyArgs::new(
             builder,
+            arg_base,
             &tys.as_function_ty(builder.function.ty).unwrap().params_ty,
             &tys.as_function_ty(builder.function.ty).unwrap().params_attr,
         )
         .copy();
     }
 
-    fn insert_epilogue(&mut self, cur_func: &mut MachineFunction, adjust: i32) {
+    fn insert_epilogue(&mut self, cur_func: &mut MachineFunction, adjust: i32, realign: Option<i32>) {
         let mut bb_iseq = vec![];
         let has_call = cur_func.body.has_call();
 
@@ -142,7 +201,17 @@ impl PrologueEpilogueInserter {
 
             let mut iseq = vec![];
 
-            if has_call && adjust > 0 {
+            if realign.is_some() {
+                // mov rsp, [rbp - 8]
+                let rbp = cur_func.regs_info.get_phys_reg(GR64::RBP);
+                let i = MachineInst::new_simple(
+                    MachineOpcode::MOVrm64,
+                    vec![MachineOperand::Mem(MachineMemOperand::BaseOff(rbp, -8))],
+                    bb_id,
+                )
+                .with_def(vec![cur_func.regs_info.get_phys_reg(GR64::RSP)]);
+                iseq.push(cur_func.body.inst_arena.alloc(&cur_func.regs_info, i));
+            } else if has_call && adjust > 0 {
                 // add rsp, adjust
                 let i = MachineInst::new_simple(
                     MachineOpcode::ADDr64i32,
@@ -182,11 +251,13 @@ impl PrologueEpilogueInserter {
 impl<'a> CopyArgs<'a> {
     pub fn new(
         builder: &'a mut Builder<'a>,
+        arg_base: RegisterId,
         params_ty: &'a Vec<Type>,
         params_attr: &'a FxHashMap<usize, ParamAttribute>,
     ) -> Self {
         Self {
             builder,
+            arg_base,
             params_ty,
             params_attr,
             offset: 16, // call + push rbp. TODO: this may vary if there're more pushes
@@ -279,7 +350,7 @@ impl<'a> CopyArgs<'a> {
                 let mov = MachineInst::new_simple(
                     rm,
                     vec![MachineOperand::Mem(MachineMemOperand::BaseOff(
-                        rbp,
+                        self.arg_base,
                         self.offset,
                     ))],
                     self.builder.get_cur_bb().unwrap(),
@@ -320,7 +391,7 @@ impl<'a> CopyArgs<'a> {
                 let inst = MachineInst::new_simple(
                     MachineOpcode::MOVSDrm,
                     vec![MachineOperand::Mem(MachineMemOperand::BaseOff(
-                        self.builder.function.regs_info.get_phys_reg(GR64::RBP),
+                        self.arg_base,
                         self.offset,
                     ))],
                     self.builder.get_cur_bb().unwrap(),
@@ -367,7 +438,7 @@ impl<'a> CopyArgs<'a> {
                 let inst = MachineInst::new_simple(
                     movrm,
                     vec![MachineOperand::Mem(MachineMemOperand::BaseOff(
-                        self.builder.function.regs_info.get_phys_reg(GR64::RBP),
+                        self.arg_base,
                         self.offset,
                     ))],
                     self.builder.get_cur_bb().unwrap(),
@@ -1,5 +1,5 @@
 use super::super::{
-    dag::mc_convert::{mov_mx, mov_rx},
+    dag::mc_convert::{mov_rx, spill_mx, spill_slot_ty},
     frame_object::*,
     machine::register::*,
 };
@@ -184,21 +184,23 @@ impl RegisterAllocator {
     ) {
         // TODO: Refine code. It's hard to understand.
         fn find_unused_slot(
+            tys: &Types,
             cur_func: &mut MachineFunction,
             occupied: &mut FxHashSet<FrameIndexKind>,
             r: RegisterId,
         ) -> FrameIndexInfo {
             let rc = cur_func.regs_info.arena_ref()[r].reg_class;
+            let ty = spill_slot_ty(tys, rc);
             for slot in &*cur_func.local_mgr.locals {
                 if occupied.contains(&slot.idx) {
                     continue;
                 }
-                if Some(rc) == ty2rc(&slot.ty) {
+                if Some(rc) == ty2rc(&slot.ty) && (!ty.is_vector() || slot.ty == ty) {
                     occupied.insert(slot.idx);
                     return slot.clone();
                 }
             }
-            let slot = cur_func.local_mgr.alloc(&rc2ty(rc));
+            let slot = cur_func.local_mgr.alloc(&ty);
             occupied.insert(slot.idx);
             slot
         }
@@ -233,7 +235,7 @@ impl RegisterAllocator {
 
         let mut slots_to_save_regs = vec![];
         for r in &regs_to_save {
-            slots_to_save_regs.push(find_unused_slot(cur_func, occupied, *r));
+            slots_to_save_regs.push(find_unused_slot(tys, cur_func, occupied, *r));
         }
 
         let call_inst_parent = cur_func.body.inst_arena[call_inst_id].parent;
@@ -259,7 +261,7 @@ impl RegisterAllocator {
             let rbp = cur_func.regs_info.get_phys_reg(GR64::RBP);
             let store_inst_id = cur_func.alloc_inst(MachineInst::new(
                 &cur_func.regs_info,
-                mov_mx(&cur_func.regs_info, &src).unwrap(),
+                spill_mx(&cur_func.regs_info, &frinfo, &src).unwrap(),
                 vec![
                     MachineOperand::Mem(MachineMemOperand::BaseFi(rbp, *dst.as_frame_index())),
                     src,
@@ -17,7 +17,7 @@ registers! {
         R8, R9, R10, R11, R12, R13, R14, R15
     }
 
-    class XMM (128, F64, [F64], [XMM0]) {
+    class XMM (128, F64, [F64, Vector!], [XMM0]) {
         XMM0, XMM1, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7,
         XMM8, XMM9, XMM10, XMM11, XMM12, XMM13, XMM14, XMM15
     }
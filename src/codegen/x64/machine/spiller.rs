@@ -1,7 +1,7 @@
-use super::super::dag::mc_convert::{mov_mx, mov_rx};
+use super::super::dag::mc_convert::{mov_rx, spill_mx, spill_slot_ty};
 use super::super::{
     frame_object::FrameIndexInfo,
-    machine::register::{rc2ty, RegisterId, VirtReg, GR64},
+    machine::register::{RegisterId, VirtReg, GR64},
 };
 use super::inst::{MachineInst, MachineMemOperand, MachineOpcode, MachineOperand};
 use crate::codegen::common::machine::{
@@ -100,7 +100,7 @@ impl<'a> Spiller<'a> {
             let src = MachineOperand::Register(new_reg);
             let rbp = self.func.regs_info.get_phys_reg(GR64::RBP);
             let store = MachineInst::new_simple(
-                mov_mx(&self.func.regs_info, &src).unwrap(),
+                spill_mx(&self.func.regs_info, slot, &src).unwrap(),
                 vec![
                     MachineOperand::Mem(MachineMemOperand::BaseFi(rbp, *dst.as_frame_index())),
                     src.clone(),
@@ -212,10 +212,10 @@ impl<'a> Spiller<'a> {
 
     pub fn spill(&mut self, tys: &Types, vreg: VirtReg) -> Vec<VirtReg> {
         let reg_id = *self.matrix.get_entity_by_vreg(vreg).unwrap();
-        let slot = self
-            .func
-            .local_mgr
-            .alloc(&rc2ty(self.func.regs_info.arena_ref()[reg_id].reg_class)); // TODO
+        let slot = self.func.local_mgr.alloc(&spill_slot_ty(
+            tys,
+            self.func.regs_info.arena_ref()[reg_id].reg_class,
+        ));
 
         let mut new_regs = self.insert_evict(reg_id, &slot);
         new_regs.append(&mut self.insert_reload(tys, reg_id, &slot));
@@ -1,4 +1,4 @@
-use super::opcode::{Instruction, Opcode, Operand};
+use super::opcode::{Instruction, InstructionId, Opcode, Operand};
 use crate::traits::basic_block::BasicBlocksTrait;
 use rustc_hash::FxHashSet;
 
@@ -111,6 +111,19 @@ impl BranchFolding {
                     f.remove_inst(id);
                 }
 
+                // succ has only one pred, so its phis have only one incoming value
+                let phis: Vec<InstructionId> = f.basic_blocks.get_arena()[succ]
+                    .iseq_ref()
+                    .iter()
+                    .map(|val| val.as_instruction().id)
+                    .filter(|&id| f.inst_table[id].opcode == Opcode::Phi)
+                    .collect();
+                for phi in phis {
+                    let incoming = f.inst_table[phi].operands[0];
+                    Instruction::replace_all_uses(&mut f.inst_table, phi, incoming);
+                    f.remove_inst(phi);
+                }
+
                 for &val in &*f.basic_blocks.get_arena()[succ].iseq_ref() {
                     let inst_id = val.as_instruction().id;
                     f.inst_table[inst_id].parent = block;
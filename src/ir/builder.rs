@@ -91,6 +91,18 @@ impl<F: FuncRef> Builder<F> {
         inst
     }
 
+    pub fn build_alloca_with_align(&mut self, ty: Type, align: usize) -> Value {
+        let inst = self.build_alloca(ty);
+        self.set_mem_attr(
+            inst,
+            MemoryAttribute {
+                volatile: false,
+                align: Some(align),
+            },
+        );
+        inst
+    }
+
     pub fn build_gep(&mut self, v: Value, indices: Vec<Value>) -> Value {
         let elem_ty = self
             .func
@@ -131,6 +143,18 @@ impl<F: FuncRef> Builder<F> {
         inst
     }
 
+    pub fn build_load_with_attr(&mut self, v: Value, attr: MemoryAttribute) -> Value {
+        let inst = self.build_load(v);
+        self.set_mem_attr(inst, attr);
+        inst
+    }
+
+    pub fn build_store_with_attr(&mut self, src: Value, dst: Value, attr: MemoryAttribute) -> Value {
+        let inst = self.build_store(src, dst);
+        self.set_mem_attr(inst, attr);
+        inst
+    }
+
     pub fn build_add(&mut self, v1: Value, v2: Value) -> Value {
         if let Some(konst) = v1.const_add(&v2) {
             return konst;
@@ -255,6 +279,86 @@ impl<F: FuncRef> Builder<F> {
         inst
     }
 
+    /// Builds an extraction of the `idx`-th element of `vec`. `idx` must be an `i32` constant
+    /// within `vec` since the backends only access a fixed element.
+    pub fn build_extract_element(&mut self, vec: Value, idx: Value) -> Value {
+        self.check_element_index(vec, idx);
+        let elem_ty = self
+            .func
+            .func_ref()
+            .types
+            .get_element_ty(vec.get_type(), None)
+            .unwrap();
+        let inst = self.create_inst_value(
+            Opcode::ExtractElement,
+            vec![Operand::Value(vec), Operand::Value(idx)],
+            elem_ty,
+        );
+        self.append_inst_to_cur_bb(inst);
+        inst
+    }
+
+    /// Builds `vec` with its `idx`-th element replaced by `val`. `idx` must be an `i32` constant
+    /// within `vec` as in `build_extract_element`.
+    pub fn build_insert_element(&mut self, vec: Value, val: Value, idx: Value) -> Value {
+        self.check_element_index(vec, idx);
+        let inst = self.create_inst_value(
+            Opcode::InsertElement,
+            vec![
+                Operand::Value(vec),
+                Operand::Value(val),
+                Operand::Value(idx),
+            ],
+            vec.get_type(),
+        );
+        self.append_inst_to_cur_bb(inst);
+        inst
+    }
+
+    fn check_element_index(&self, vec: Value, idx: Value) {
+        let (_, len) = self
+            .func
+            .func_ref()
+            .types
+            .as_vector_ty(vec.get_type())
+            .unwrap();
+        match idx {
+            Value::Immediate(ImmediateValue::Int32(i)) if 0 <= i && (i as usize) < len => {}
+            _ => panic!("vector element index must be a constant within the vector"),
+        }
+    }
+
+    /// Builds a shuffle of the elements of `v1` and `v2`. Each element of `mask` selects an
+    /// element of the concatenation of `v1` and `v2`, so the result has `mask.len()` elements.
+    pub fn build_shuffle_vector(&mut self, v1: Value, v2: Value, mask: Vec<u32>) -> Value {
+        let ty = {
+            let types = &self.func.func_ref().types;
+            let (elem_ty, len) = types.as_vector_ty(v1.get_type()).unwrap();
+            assert!(mask.iter().all(|&i| (i as usize) < len * 2));
+            types.new_vector_ty(elem_ty, mask.len())
+        };
+        let mut operands = vec![Operand::Value(v1), Operand::Value(v2)];
+        operands.extend(
+            mask.into_iter()
+                .map(|i| Operand::Value(Value::new_imm_int32(i as i32))),
+        );
+        let inst = self.create_inst_value(Opcode::ShuffleVector, operands, ty);
+        self.append_inst_to_cur_bb(inst);
+        inst
+    }
+
+    /// Builds a selection of `t` if `cond` holds, or `f` otherwise
+    pub fn build_select(&mut self, cond: Value, t: Value, f: Value) -> Value {
+        assert_eq!(t.get_type(), f.get_type());
+        let inst = self.create_inst_value(
+            Opcode::Select,
+            vec![Operand::Value(cond), Operand::Value(t), Operand::Value(f)],
+            t.get_type(),
+        );
+        self.append_inst_to_cur_bb(inst);
+        inst
+    }
+
     pub fn build_br(&mut self, dst_id: BasicBlockId) -> Value {
         let inst =
             self.create_inst_value(Opcode::Br, vec![Operand::BasicBlock(dst_id)], Type::Void);
@@ -350,6 +454,11 @@ impl<F: FuncRef> Builder<F> {
         })
     }
 
+    fn set_mem_attr(&mut self, inst: Value, attr: MemoryAttribute) {
+        let id = inst.as_instruction().id;
+        self.func.func_ref_mut().inst_table[id].mem_attr = attr;
+    }
+
     fn append_inst_to_cur_bb(&mut self, inst: Value) {
         let bb_id = self.cur_bb.unwrap();
         let insert_point = self.insert_point;
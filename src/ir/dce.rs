@@ -0,0 +1,220 @@
+use crate::analysis::{
+    control_dependence::ControlDependenceGraph, post_dom_tree::PostDominatorTreeConstructor,
+};
+use crate::ir::{
+    function::Function,
+    inline_asm::InlineAsms,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{InstructionValue, Value},
+};
+use rustc_hash::FxHashSet;
+
+/// Removes unreachable blocks and instructions whose results are never used. In the aggressive
+/// mode (ADCE), every instruction is assumed to be dead until it is proven to be live, either by
+/// having side effects or by being needed by a live instruction or a live branch. This also
+/// removes loops and branches that compute nothing.
+pub struct DeadCodeElimination {
+    aggressive: bool,
+}
+
+struct DeadCodeEliminationOnFunction<'a> {
+    func: &'a mut Function,
+    inline_asms: &'a InlineAsms,
+    removed_insts: usize,
+}
+
+impl DeadCodeElimination {
+    pub fn new() -> Self {
+        Self { aggressive: false }
+    }
+
+    pub fn new_aggressive() -> Self {
+        Self { aggressive: true }
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let Module {
+            functions,
+            inline_asms,
+            ..
+        } = module;
+        for (_, func) in functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            let mut dce = DeadCodeEliminationOnFunction {
+                func,
+                inline_asms,
+                removed_insts: 0,
+            };
+            if self.aggressive {
+                dce.run_aggressive()
+            } else {
+                dce.run()
+            }
+        }
+    }
+}
+
+impl<'a> DeadCodeEliminationOnFunction<'a> {
+    pub fn run(&mut self) {
+        self.removed_insts += self.func.remove_unreachable_blocks();
+
+        let mut worklist: Vec<InstructionId> = self
+            .func
+            .basic_blocks
+            .order
+            .iter()
+            .flat_map(|&bb| {
+                self.func.basic_blocks.arena[bb]
+                    .iseq_ref()
+                    .iter()
+                    .map(|v| v.as_instruction().id)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut removed = FxHashSet::default();
+
+        while let Some(id) = worklist.pop() {
+            if removed.contains(&id) || !self.is_trivially_dead(id) {
+                continue;
+            }
+            for op in &self.func.inst_table[id].operands {
+                if let Operand::Value(Value::Instruction(InstructionValue { id, .. })) = op {
+                    worklist.push(*id);
+                }
+            }
+            self.func.remove_inst(id);
+            removed.insert(id);
+        }
+
+        self.removed_insts += removed.len();
+        debug!(println!(
+            "function '{}': {} insts removed",
+            self.func.name, self.removed_insts
+        ));
+    }
+
+    pub fn run_aggressive(&mut self) {
+        self.removed_insts += self.func.remove_unreachable_blocks();
+
+        let post_dom_tree = PostDominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+        let control_deps = ControlDependenceGraph::new(&self.func.basic_blocks, &post_dom_tree);
+
+        // Mark live instructions
+        let mut live = FxHashSet::default();
+        let mut live_blocks = FxHashSet::default();
+        let mut worklist = vec![];
+        for &bb in &self.func.basic_blocks.order {
+            let block = &self.func.basic_blocks.arena[bb];
+            // Keep every branch that may lead into an infinite loop
+            let may_not_exit = !post_dom_tree.contains(bb)
+                || block.succ.iter().any(|&succ| !post_dom_tree.contains(succ));
+            for val in &*block.iseq_ref() {
+                let id = val.as_instruction().id;
+                let inst = &self.func.inst_table[id];
+                let is_branch = matches!(inst.opcode, Opcode::Br | Opcode::CondBr);
+                if inst.has_side_effects(self.inline_asms) && (!is_branch || may_not_exit) {
+                    worklist.push(id);
+                }
+            }
+        }
+
+        while let Some(id) = worklist.pop() {
+            if !live.insert(id) {
+                continue;
+            }
+
+            let inst = &self.func.inst_table[id];
+            for op in &inst.operands {
+                match op {
+                    Operand::Value(Value::Instruction(InstructionValue { id, .. })) => {
+                        worklist.push(*id)
+                    }
+                    // The branch selecting the incoming value must survive
+                    Operand::BasicBlock(pred) if inst.opcode == Opcode::Phi => {
+                        worklist.push(self.func.terminator(*pred))
+                    }
+                    _ => {}
+                }
+            }
+
+            // The block is executed only if the branches it depends on are taken
+            if live_blocks.insert(inst.parent) {
+                for &dep in control_deps
+                    .dependences_of(inst.parent)
+                    .unwrap_or(&FxHashSet::default())
+                {
+                    worklist.push(self.func.terminator(dep));
+                }
+            }
+        }
+
+        // Remove dead instructions. A dead conditional branch jumps to the immediate
+        // post-dominator instead, since no live instruction depends on the path it selects.
+        let mut dead = vec![];
+        let mut dead_branches = vec![];
+        for &bb in &self.func.basic_blocks.order {
+            for val in &*self.func.basic_blocks.arena[bb].iseq_ref() {
+                let id = val.as_instruction().id;
+                if live.contains(&id) {
+                    continue;
+                }
+                match self.func.inst_table[id].opcode {
+                    Opcode::CondBr => dead_branches.push((bb, id)),
+                    Opcode::Br | Opcode::Ret => {}
+                    _ => dead.push(id),
+                }
+            }
+        }
+
+        for &id in &dead {
+            self.func.remove_inst(id);
+        }
+        self.removed_insts += dead.len() + dead_branches.len();
+
+        for (bb, br) in dead_branches {
+            let new_dst = post_dom_tree.ipdom_of(bb).unwrap();
+            let func_id = self.func.id.unwrap();
+            self.func.remove_inst(br);
+            for succ in self.func.basic_blocks.arena[bb].succ.clone() {
+                self.func.basic_blocks.arena[succ].pred.remove(&bb);
+                if succ != new_dst {
+                    self.func.remove_phi_incoming(succ, bb);
+                }
+            }
+            let block = &mut self.func.basic_blocks.arena[bb];
+            block.succ.clear();
+            block.succ.insert(new_dst);
+            self.func.basic_blocks.arena[new_dst].pred.insert(bb);
+            let id = self.func.alloc_inst(Instruction::new(
+                Opcode::Br,
+                vec![Operand::BasicBlock(new_dst)],
+                Type::Void,
+                bb,
+            ));
+            self.func.basic_blocks.arena[bb]
+                .iseq_ref_mut()
+                .push(Value::Instruction(InstructionValue {
+                    func_id,
+                    id,
+                    ty: Type::Void,
+                }));
+        }
+
+        self.removed_insts += self.func.remove_unreachable_blocks();
+
+        debug!(println!(
+            "function '{}': {} insts removed",
+            self.func.name, self.removed_insts
+        ));
+    }
+
+    fn is_trivially_dead(&self, id: InstructionId) -> bool {
+        let inst = &self.func.inst_table[id];
+        inst.users.borrow().len() == 0 && !inst.has_side_effects(self.inline_asms)
+    }
+}
@@ -0,0 +1,161 @@
+use crate::analysis::alias_analysis::{AliasAnalysis, MemoryLocation};
+use crate::ir::{
+    basic_block::BasicBlockId,
+    function::{Function, FunctionId},
+    module::Module,
+    opcode::{Instruction, Opcode},
+    types::Types,
+    value::{FunctionValue, Value},
+};
+use rustc_hash::FxHashSet;
+
+/// Dead store elimination. A store is removed if, on every path from it, the location it writes
+/// is overwritten before anything may read it, or the function returns and the location is an
+/// `Alloca` whose address doesn't escape. Calls are assumed to read any memory they can reach,
+/// unless the callee is marked `readnone`.
+pub struct DeadStoreElimination {}
+
+struct DeadStoreEliminationOnFunction<'a> {
+    func: &'a mut Function,
+    types: &'a Types,
+    aa: AliasAnalysis<'a>,
+    read_none: &'a FxHashSet<FunctionId>,
+}
+
+impl DeadStoreElimination {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let Module {
+            functions,
+            types,
+            inline_asms,
+            ..
+        } = module;
+        let read_none: FxHashSet<FunctionId> = functions
+            .iter()
+            .filter_map(|(id, f)| if f.attr.read_none { Some(id) } else { None })
+            .collect();
+
+        for (_, func) in functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            let aa = AliasAnalysis::new(func, types, inline_asms);
+            DeadStoreEliminationOnFunction {
+                func,
+                types,
+                aa,
+                read_none: &read_none,
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> DeadStoreEliminationOnFunction<'a> {
+    fn run(self) {
+        let mut dead = vec![];
+        for &bb in &self.func.basic_blocks.order {
+            for (pos, val) in self.func.basic_blocks.arena[bb]
+                .iseq_ref()
+                .iter()
+                .enumerate()
+            {
+                let id = val.as_instruction().id;
+                let inst = &self.func.inst_table[id];
+                if inst.opcode != Opcode::Store || inst.is_volatile() {
+                    continue;
+                }
+                let loc = MemoryLocation::of(inst, self.types).unwrap();
+                if self.is_dead(&loc, bb, pos + 1) {
+                    dead.push(id)
+                }
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} stores removed",
+            self.func.name,
+            dead.len()
+        ));
+
+        // Every store found is dead no matter whether the others are removed, since removing a
+        // store never adds a read
+        for id in dead {
+            self.func.remove_inst(id);
+        }
+    }
+
+    /// Returns true if `loc` is never read after the instruction at `pos` in `bb`
+    fn is_dead(&self, loc: &MemoryLocation, bb: BasicBlockId, pos: usize) -> bool {
+        let mut visited = FxHashSet::default();
+        let mut worklist = vec![(bb, pos)];
+        while let Some((bb, pos)) = worklist.pop() {
+            let block = &self.func.basic_blocks.arena[bb];
+            let mut killed = false;
+            for val in &block.iseq_ref()[pos..] {
+                let inst = &self.func.inst_table[val.as_instruction().id];
+                if self.may_read(inst, loc) {
+                    return false;
+                }
+                if inst.opcode == Opcode::Store && self.covers(inst, loc) {
+                    killed = true;
+                    break;
+                }
+                if inst.opcode == Opcode::Ret {
+                    // Only a local object dies when the function returns
+                    let (base, _) = self.aa.decompose(self.func, loc.ptr);
+                    if !self.aa.is_local(self.func, &base) {
+                        return false;
+                    }
+                    killed = true;
+                    break;
+                }
+            }
+            if killed {
+                continue;
+            }
+            if block.succ.is_empty() {
+                return false;
+            }
+            // A block visited again is being checked from its start already
+            for &succ in &block.succ {
+                if visited.insert(succ) {
+                    worklist.push((succ, 0))
+                }
+            }
+        }
+        true
+    }
+
+    fn may_read(&self, inst: &Instruction, loc: &MemoryLocation) -> bool {
+        if inst.opcode == Opcode::Call {
+            if let Value::Function(FunctionValue { func_id, .. }) = inst.operands[0].as_value() {
+                if self.read_none.contains(func_id) {
+                    return false;
+                }
+            }
+        }
+        self.aa.may_read(self.func, inst, loc)
+    }
+
+    /// Returns true if `store` writes every byte of `loc`
+    fn covers(&self, store: &Instruction, loc: &MemoryLocation) -> bool {
+        let dst = MemoryLocation::of(store, self.types).unwrap();
+        if dst.ptr == loc.ptr {
+            return dst.size >= loc.size;
+        }
+        let (base_dst, off_dst) = self.aa.decompose(self.func, dst.ptr);
+        let (base_loc, off_loc) = self.aa.decompose(self.func, loc.ptr);
+        match (off_dst, off_loc) {
+            (Some(x), Some(y)) if base_dst == base_loc => {
+                x <= y && y + loc.size as i64 <= x + dst.size as i64
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use super::{basic_block::*, module::Module, opcode::*, types::*, value::*, DumpToString};
+use super::{
+    basic_block::*, global_val::Linkage, module::Module, opcode::*, types::*, value::*,
+    DumpToString,
+};
 use crate::codegen::is_internal_function;
 use crate::traits::function::FunctionTrait;
 use id_arena::*;
+use rustc_hash::FxHashSet;
 
 pub type FunctionId = Id<Function>;
 
@@ -38,6 +42,21 @@ pub struct Function {
     pub types: Types,
 
     pub is_internal: bool,
+
+    pub linkage: Linkage,
+
+    pub attr: FunctionAttribute,
+}
+
+/// Attributes of a function guiding optimizations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FunctionAttribute {
+    /// Inline the function into every caller regardless of its size
+    pub always_inline: bool,
+    /// Never inline the function
+    pub no_inline: bool,
+    /// The function neither reads nor writes memory visible to its callers
+    pub read_none: bool,
 }
 
 impl Function {
@@ -51,6 +70,8 @@ impl Function {
             id: None,
             types: module.types.clone(),
             is_internal: is_internal_function(name),
+            linkage: Linkage::External,
+            attr: FunctionAttribute::default(),
         })
     }
 
@@ -72,6 +93,11 @@ impl Function {
         &mut self.basic_blocks.arena[id]
     }
 
+    /// Returns true if code outside the module may call the function
+    pub fn is_externally_visible(&self) -> bool {
+        self.linkage != Linkage::Internal
+    }
+
     pub fn get_return_type(&self) -> Type {
         let base = self.types.base.borrow();
         base.as_function_ty(self.ty).unwrap().ret_ty
@@ -101,6 +127,12 @@ impl Function {
         params_attr.get(&idx).map_or(None, |&a| Some(a))
     }
 
+    /// Sets the attribute of the `idx`th parameter. The function gets a new type, so calls built
+    /// before refer to the old one.
+    pub fn set_param_attr(&mut self, idx: usize, attr: ParamAttribute) {
+        self.ty = self.types.with_param_attr(self.ty, idx, attr);
+    }
+
     pub fn get_params_len(&self) -> usize {
         let base = self.types.base.borrow();
         base.as_function_ty(self.ty).unwrap().params_ty.len()
@@ -113,12 +145,162 @@ impl Function {
             .map(|pos| (parent, pos))
     }
 
+    /// Returns the value standing for the result of the instruction `id`
+    pub fn inst_value(&self, id: InstructionId) -> Value {
+        Value::Instruction(InstructionValue {
+            func_id: self.id.unwrap(),
+            id,
+            ty: self.inst_table[id].ty,
+        })
+    }
+
+    /// Returns the instructions in `bb`, in order
+    pub fn insts_of(&self, bb: BasicBlockId) -> Vec<InstructionId> {
+        self.basic_blocks.arena[bb]
+            .iseq_ref()
+            .iter()
+            .map(|v| v.as_instruction().id)
+            .collect()
+    }
+
+    /// Returns the branch or return terminating `bb`
+    pub fn terminator(&self, bb: BasicBlockId) -> InstructionId {
+        let block = &self.basic_blocks.arena[bb];
+        let last = *block.iseq_ref().last().unwrap();
+        last.as_instruction().id
+    }
+
+    /// Returns the value `phi` takes when control comes from `pred`
+    pub fn phi_incoming(&self, phi: InstructionId, pred: BasicBlockId) -> Option<Value> {
+        self.inst_table[phi]
+            .operands
+            .chunks(2)
+            .find(|pair| pair[1] == Operand::BasicBlock(pred))
+            .map(|pair| *pair[0].as_value())
+    }
+
+    /// Makes the terminator of `bb` jump to `to` instead of `from`
+    pub fn replace_branch_target(
+        &mut self,
+        bb: BasicBlockId,
+        from: BasicBlockId,
+        to: BasicBlockId,
+    ) {
+        let term = self.terminator(bb);
+        Instruction::replace_operand(
+            &mut self.inst_table,
+            term,
+            &Operand::BasicBlock(from),
+            Operand::BasicBlock(to),
+        );
+    }
+
+    /// Replaces the terminator of `bb` with an unconditional branch to `dst`
+    pub fn replace_terminator(&mut self, bb: BasicBlockId, dst: BasicBlockId) {
+        let term = self.terminator(bb);
+        self.change_inst(
+            term,
+            Instruction::new(Opcode::Br, vec![Operand::BasicBlock(dst)], Type::Void, bb),
+        );
+    }
+
     pub fn remove_inst(&self, inst_id: InstructionId) {
         let (bb_id, pos) = self.find_inst_pos(inst_id).unwrap();
         self.inst_table[inst_id].remove(&self.inst_table);
         self.basic_blocks.arena[bb_id].iseq_ref_mut().remove(pos);
     }
 
+    /// Removes the incoming values from `pred` of every phi in `bb`
+    pub fn remove_phi_incoming(&mut self, bb: BasicBlockId, pred: BasicBlockId) {
+        for val in &*self.basic_blocks.arena[bb].iseq_ref() {
+            let id = val.as_instruction().id;
+            if self.inst_table[id].opcode != Opcode::Phi {
+                continue;
+            }
+            let operands = &self.inst_table[id].operands;
+            let mut removed = vec![];
+            let mut new_operands = vec![];
+            for pair in operands.chunks(2) {
+                if pair[1] == Operand::BasicBlock(pred) {
+                    removed.push(pair[0]);
+                } else {
+                    new_operands.extend_from_slice(pair);
+                }
+            }
+            for op in removed {
+                if !new_operands.contains(&op) {
+                    op.remove_from_users(&self.inst_table, id);
+                }
+            }
+            self.inst_table[id].operands = new_operands;
+        }
+    }
+
+    /// Removes blocks not reachable from the entry block. Returns the number of instructions
+    /// removed with them.
+    pub fn remove_unreachable_blocks(&mut self) -> usize {
+        let entry = self.basic_blocks.order[0];
+        let mut reachable = FxHashSet::default();
+        let mut worklist = vec![entry];
+        while let Some(bb) = worklist.pop() {
+            if reachable.insert(bb) {
+                worklist.extend(self.basic_blocks.arena[bb].succ.iter().copied());
+            }
+        }
+
+        let unreachable: Vec<BasicBlockId> = self
+            .basic_blocks
+            .order
+            .iter()
+            .copied()
+            .filter(|bb| !reachable.contains(bb))
+            .collect();
+
+        let mut removed_insts = 0;
+        for &bb in &unreachable {
+            for succ in self.basic_blocks.arena[bb].succ.clone() {
+                self.basic_blocks.arena[succ].pred.remove(&bb);
+                if reachable.contains(&succ) {
+                    self.remove_phi_incoming(succ, bb);
+                }
+            }
+            let block = &self.basic_blocks.arena[bb];
+            for val in &*block.iseq_ref() {
+                self.inst_table[val.as_instruction().id].remove(&self.inst_table);
+            }
+            removed_insts += block.iseq_ref().len();
+        }
+
+        self.basic_blocks.order.retain(|bb| reachable.contains(bb));
+        removed_insts
+    }
+
+    /// Rebuilds `pred` and `succ` of every block from the branch terminating it
+    pub fn recompute_cfg(&mut self) {
+        for &bb in &self.basic_blocks.order {
+            let block = &mut self.basic_blocks.arena[bb];
+            block.pred.clear();
+            block.succ.clear();
+        }
+        for bb in self.basic_blocks.order.clone() {
+            let succs: Vec<BasicBlockId> = match self.basic_blocks.arena[bb].iseq_ref().last() {
+                Some(last) => self.inst_table[last.as_instruction().id]
+                    .operands
+                    .iter()
+                    .filter_map(|op| match op {
+                        Operand::BasicBlock(succ) => Some(*succ),
+                        _ => None,
+                    })
+                    .collect(),
+                None => continue,
+            };
+            for succ in succs {
+                self.basic_blocks.arena[bb].succ.insert(succ);
+                self.basic_blocks.arena[succ].pred.insert(bb);
+            }
+        }
+    }
+
     fn remove_inst_left_in_bb(&self, inst_id: InstructionId) {
         self.inst_table[inst_id].remove(&self.inst_table);
     }
@@ -159,7 +341,12 @@ impl DumpToString for &Function {
         let base = module.types.base.borrow();
         let ty = base.as_function_ty(self.ty).unwrap();
         format!(
-            "define {} {}({}) {}",
+            "define {}{} {}({}){} {}",
+            if self.is_externally_visible() {
+                ""
+            } else {
+                "internal "
+            },
             base.to_string(ty.ret_ty),
             self.name,
             ty.params_ty
@@ -167,13 +354,15 @@ impl DumpToString for &Function {
                 .enumerate()
                 .fold("".to_string(), |mut s, (i, p)| {
                     s += &(base.to_string(*p)
-                        + ty.params_attr
+                        + &ty
+                            .params_attr
                             .get(&i)
-                            .map_or("", |a| if a.byval { " byval" } else { "" })
+                            .map_or("".to_string(), |a| a.to_string())
                         + ", ");
                     s
                 })
                 .trim_matches(&[',', ' '][0..]),
+            self.attr.to_string(),
             if self.is_internal {
                 "internal;".to_owned()
             } else {
@@ -183,6 +372,22 @@ impl DumpToString for &Function {
     }
 }
 
+impl FunctionAttribute {
+    pub fn to_string(&self) -> String {
+        let mut s = "".to_string();
+        if self.always_inline {
+            s += " alwaysinline"
+        }
+        if self.no_inline {
+            s += " noinline"
+        }
+        if self.read_none {
+            s += " readnone"
+        }
+        s
+    }
+}
+
 impl DumpToString for FunctionId {
     fn dump(&self, module: &Module) -> String {
         module.function_ref(*self).dump(module)
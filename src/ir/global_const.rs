@@ -0,0 +1,80 @@
+use crate::ir::{
+    function::FunctionId,
+    global_val::GlobalVariableId,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{GlobalValue, ImmediateValue, Value},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Global constant promotion. A global with internal linkage that's never stored to keeps its
+/// initial value, zero, for the whole run, so the loads from it are replaced with zero. Other
+/// globals may be written from outside the module, and a global whose address is used other than
+/// by a load may be written through the address, so they're left alone. Promoted globals are left
+/// unused for `GlobalDeadCodeElimination` to delete.
+pub struct GlobalConstantPromotion {}
+
+impl GlobalConstantPromotion {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let mut loads: FxHashMap<GlobalVariableId, Vec<(FunctionId, InstructionId)>> =
+            FxHashMap::default();
+        let mut escaped = FxHashSet::default();
+        for (func_id, f) in &module.functions {
+            for &bb in &f.basic_blocks.order {
+                for val in &*f.basic_blocks.arena[bb].iseq_ref() {
+                    let id = val.as_instruction().id;
+                    let inst = &f.inst_table[id];
+                    for (i, op) in inst.operands.iter().enumerate() {
+                        let g = match op {
+                            Operand::Value(Value::Global(GlobalValue { id, .. })) => *id,
+                            _ => continue,
+                        };
+                        if inst.opcode == Opcode::Load && i == 0 && !inst.is_volatile() {
+                            loads.entry(g).or_default().push((func_id, id));
+                        } else {
+                            escaped.insert(g);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut promoted = 0;
+        for (g, loads) in loads {
+            if escaped.contains(&g) || module.global_vars.arena[g].is_externally_visible() {
+                continue;
+            }
+            let zeros: Option<Vec<Value>> = loads
+                .iter()
+                .map(|&(f, load)| zero_of(module.function_ref(f).inst_table[load].ty))
+                .collect();
+            let zeros = match zeros {
+                Some(zeros) => zeros,
+                None => continue,
+            };
+            for (&(f, load), zero) in loads.iter().zip(zeros) {
+                let f = module.function_ref_mut(f);
+                Instruction::replace_all_uses(&mut f.inst_table, load, Operand::Value(zero));
+                f.remove_inst(load);
+            }
+            promoted += 1;
+        }
+
+        debug!(println!("{} globals promoted to constants", promoted));
+    }
+}
+
+fn zero_of(ty: Type) -> Option<Value> {
+    match ty {
+        Type::Int8 => Some(Value::Immediate(ImmediateValue::Int8(0))),
+        Type::Int32 => Some(Value::Immediate(ImmediateValue::Int32(0))),
+        Type::Int64 => Some(Value::Immediate(ImmediateValue::Int64(0))),
+        Type::F64 => Some(Value::Immediate(ImmediateValue::F64(0.0))),
+        _ => None,
+    }
+}
@@ -0,0 +1,143 @@
+use crate::ir::{
+    function::{Function, FunctionId},
+    global_val::GlobalVariableId,
+    module::Module,
+    opcode::Operand,
+    value::{FunctionValue, GlobalValue, Value},
+};
+use id_arena::Arena;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::mem;
+
+/// Dead global elimination. Functions reachable from the roots through calls and references, and
+/// the globals they refer to, are kept; every other function and global is deleted from the
+/// module. The roots are the functions without internal linkage, or the functions given by name.
+/// Globals without internal linkage may be used outside the module and are always kept.
+/// Functions and globals are renumbered if anything is deleted, so ids obtained before running
+/// the pass must be looked up again.
+pub struct GlobalDeadCodeElimination {
+    roots: Option<Vec<String>>,
+}
+
+impl GlobalDeadCodeElimination {
+    pub fn new() -> Self {
+        Self { roots: None }
+    }
+
+    /// Keeps only the functions named `roots` and what they reach
+    pub fn with_roots(mut self, roots: &[&str]) -> Self {
+        self.roots = Some(roots.iter().map(|r| r.to_string()).collect());
+        self
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let mut live_funcs = FxHashSet::default();
+        let mut live_globals: FxHashSet<GlobalVariableId> = module
+            .global_vars
+            .arena
+            .iter()
+            .filter(|(_, g)| g.is_externally_visible())
+            .map(|(id, _)| id)
+            .collect();
+        let mut worklist: Vec<FunctionId> = module
+            .functions
+            .iter()
+            .filter(|(_, f)| self.is_root(f))
+            .map(|(id, _)| id)
+            .collect();
+        while let Some(id) = worklist.pop() {
+            if !live_funcs.insert(id) {
+                continue;
+            }
+            let f = module.function_ref(id);
+            for &bb in &f.basic_blocks.order {
+                for val in &*f.basic_blocks.arena[bb].iseq_ref() {
+                    for op in &f.inst_table[val.as_instruction().id].operands {
+                        match op {
+                            Operand::Value(Value::Function(FunctionValue { func_id, .. })) => {
+                                worklist.push(*func_id)
+                            }
+                            Operand::Value(Value::Global(GlobalValue { id, .. })) => {
+                                live_globals.insert(*id);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+
+        let dead_funcs = module.functions.len() - live_funcs.len();
+        let dead_globals = module.global_vars.arena.len() - live_globals.len();
+
+        debug!(println!(
+            "{} functions and {} globals removed",
+            dead_funcs, dead_globals
+        ));
+
+        if dead_funcs == 0 && dead_globals == 0 {
+            return;
+        }
+
+        let mut func_map = FxHashMap::default();
+        for (id, f) in mem::replace(&mut module.functions, Arena::new()) {
+            if live_funcs.contains(&id) {
+                func_map.insert(id, module.add_function(f));
+            }
+        }
+        let mut global_map = FxHashMap::default();
+        for (id, g) in mem::replace(&mut module.global_vars.arena, Arena::new()) {
+            if live_globals.contains(&id) {
+                global_map.insert(id, module.global_vars.arena.alloc(g));
+            }
+        }
+        for (id, f) in &mut module.functions {
+            renumber(f, id, &func_map, &global_map);
+        }
+    }
+
+    fn is_root(&self, f: &Function) -> bool {
+        match &self.roots {
+            Some(roots) => roots.contains(&f.name),
+            None => f.is_externally_visible(),
+        }
+    }
+}
+
+/// Updates the ids in the values of `func`, now numbered `id`
+fn renumber(
+    func: &mut Function,
+    id: FunctionId,
+    func_map: &FxHashMap<FunctionId, FunctionId>,
+    global_map: &FxHashMap<GlobalVariableId, GlobalVariableId>,
+) {
+    let renumber_value = |val: &mut Value| match val {
+        Value::Instruction(v) => v.func_id = id,
+        Value::Argument(v) => v.func_id = id,
+        // Removed instructions may still refer to deleted functions and globals
+        Value::Function(v) => {
+            if let Some(&new) = func_map.get(&v.func_id) {
+                v.func_id = new
+            }
+        }
+        Value::Global(v) => {
+            if let Some(&new) = global_map.get(&v.id) {
+                v.id = new
+            }
+        }
+        _ => {}
+    };
+
+    for (_, block) in func.basic_blocks.arena.iter_mut() {
+        for val in block.iseq_ref_mut().iter_mut() {
+            renumber_value(val)
+        }
+    }
+    for (_, inst) in func.inst_table.iter_mut() {
+        for op in &mut inst.operands {
+            if let Operand::Value(val) = op {
+                renumber_value(val)
+            }
+        }
+    }
+}
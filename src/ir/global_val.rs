@@ -15,15 +15,27 @@ pub struct GlobalVariable {
     pub ty: Type,
     pub linkage: Linkage,
     pub name: String,
+    pub thread_local: bool,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Hash)]
 pub enum Linkage {
     Common,
     External,
+    /// Not visible outside the module. A variable is zero-initialized like `Common`.
+    Internal,
     // TODO ...
 }
 
+/// How generated code reaches a thread-local variable.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum TLSModel {
+    /// Defined in this module; the offset from the thread pointer is fixed at link time.
+    LocalExec,
+    /// Defined in another object; the offset is loaded from the GOT.
+    InitialExec,
+}
+
 impl GlobalVariables {
     pub fn new(types: Types) -> Self {
         Self {
@@ -42,6 +54,21 @@ impl GlobalVariables {
             ty,
             linkage,
             name: name.to_string(),
+            thread_local: false,
+        })
+    }
+
+    pub fn new_thread_local_var_with_name(
+        &mut self,
+        ty: Type,
+        linkage: Linkage,
+        name: &str,
+    ) -> GlobalVariableId {
+        self.arena.alloc(GlobalVariable {
+            ty,
+            linkage,
+            name: name.to_string(),
+            thread_local: true,
         })
     }
 
@@ -50,6 +77,24 @@ impl GlobalVariables {
             ty,
             linkage,
             name: "anony".to_string(),
+            thread_local: false,
+        })
+    }
+}
+
+impl GlobalVariable {
+    /// Returns true if code outside the module may refer to the global
+    pub fn is_externally_visible(&self) -> bool {
+        self.linkage != Linkage::Internal
+    }
+
+    pub fn tls_model(&self) -> Option<TLSModel> {
+        if !self.thread_local {
+            return None;
+        }
+        Some(match self.linkage {
+            Linkage::Common | Linkage::Internal => TLSModel::LocalExec,
+            Linkage::External => TLSModel::InitialExec,
         })
     }
 }
@@ -59,9 +104,10 @@ impl fmt::Debug for GlobalVariables {
         for (_, g) in &self.arena {
             writeln!(
                 f,
-                "@{} = {:?} global {}",
+                "@{} = {:?} {}global {}",
                 g.name,
                 g.linkage,
+                if g.thread_local { "thread_local " } else { "" },
                 self.types.to_string(g.ty)
             )?;
         }
@@ -74,6 +120,7 @@ impl fmt::Debug for Linkage {
         match self {
             Self::Common => write!(f, "common"),
             Self::External => write!(f, "external"),
+            Self::Internal => write!(f, "internal"),
         }
     }
 }
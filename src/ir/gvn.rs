@@ -0,0 +1,242 @@
+use crate::analysis::{
+    alias_analysis::{AliasAnalysis, AliasResult, MemoryLocation},
+    dom_tree::{DominatorTree, DominatorTreeConstructor},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    module::Module,
+    opcode::{FCmpKind, ICmpKind, Instruction, InstructionId, Opcode, Operand},
+    types::{Type, Types},
+    value::{InstructionValue, Value},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Global value numbering. Walking the dominator tree, an instruction computing the same
+/// expression as a dominating one is replaced by it. Operands of commutative operations are
+/// put in a canonical order first, so `a + b` and `b + a` get the same number. Loads are
+/// numbered too: a load is replaced by the value of an earlier load or store to the same
+/// address, unless a store or call in between may write to it.
+pub struct GlobalValueNumbering {}
+
+struct GlobalValueNumberingOnFunction<'a> {
+    func: &'a mut Function,
+    types: &'a Types,
+    aa: AliasAnalysis<'a>,
+    removal_list: Vec<InstructionId>,
+}
+
+type Expression = (Opcode, Type, Vec<Operand>);
+type Leaders = FxHashMap<Expression, Value>;
+
+/// A value known to be in memory
+#[derive(Clone)]
+struct AvailableValue {
+    loc: MemoryLocation,
+    ty: Type,
+    val: Value,
+}
+
+impl GlobalValueNumbering {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let Module {
+            functions,
+            types,
+            inline_asms,
+            ..
+        } = module;
+        for (_, func) in functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            let aa = AliasAnalysis::new(func, types, inline_asms);
+            GlobalValueNumberingOnFunction {
+                func,
+                types,
+                aa,
+                removal_list: vec![],
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> GlobalValueNumberingOnFunction<'a> {
+    pub fn run(mut self) {
+        let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+
+        self.run_sub(
+            &dom_tree,
+            self.func.basic_blocks.order[0],
+            FxHashMap::default(),
+            vec![],
+        );
+
+        debug!(println!(
+            "function '{}': {} insts removed",
+            self.func.name,
+            self.removal_list.len()
+        ));
+
+        for remove in self.removal_list {
+            self.func.remove_inst(remove);
+        }
+    }
+
+    fn run_sub(
+        &mut self,
+        dom_tree: &DominatorTree<BasicBlock>,
+        root: BasicBlockId,
+        mut leaders: Leaders,
+        mut avails: Vec<AvailableValue>,
+    ) {
+        let iseq: Vec<InstructionId> = self.func.basic_blocks.arena[root]
+            .iseq_ref()
+            .iter()
+            .map(|v| v.as_instruction().id)
+            .collect();
+
+        for id in iseq {
+            let inst = &self.func.inst_table[id];
+            match inst.opcode {
+                Opcode::Load if !inst.is_volatile() => {
+                    let loc = MemoryLocation::of(inst, self.types).unwrap();
+                    let ty = inst.ty;
+                    let func = &*self.func;
+                    let aa = &self.aa;
+                    let found = avails.iter().find(|a| {
+                        a.ty == ty && aa.alias(func, &a.loc, &loc) == AliasResult::MustAlias
+                    });
+                    match found {
+                        Some(a) => {
+                            let val = a.val;
+                            self.replace(id, val)
+                        }
+                        None => avails.push(AvailableValue {
+                            loc,
+                            ty,
+                            val: self.func.inst_value(id),
+                        }),
+                    }
+                }
+                Opcode::Store => {
+                    let loc = MemoryLocation::of(inst, self.types).unwrap();
+                    let val = *inst.operands[0].as_value();
+                    self.kill(&mut avails, id);
+                    if !self.func.inst_table[id].is_volatile() {
+                        avails.push(AvailableValue {
+                            loc,
+                            ty: val.get_type(),
+                            val,
+                        });
+                    }
+                }
+                Opcode::Call => self.kill(&mut avails, id),
+                Opcode::GetElementPtr
+                | Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Rem
+                | Opcode::Shl
+                | Opcode::SIToFP
+                | Opcode::FPToSI
+                | Opcode::ICmp
+                | Opcode::FCmp
+                | Opcode::ExtractElement
+                | Opcode::InsertElement
+                | Opcode::ShuffleVector
+                | Opcode::Select => {
+                    let expr = expression(inst);
+                    match leaders.get(&expr) {
+                        Some(&leader) => self.replace(id, leader),
+                        None => {
+                            leaders.insert(expr, self.func.inst_value(id));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for &child in dom_tree.tree.get(&root).unwrap_or(&FxHashSet::default()) {
+            let mut avails = avails.clone();
+            for clobber in self.clobbers_between(root, child) {
+                self.kill(&mut avails, clobber);
+            }
+            self.run_sub(dom_tree, child, leaders.clone(), avails)
+        }
+    }
+
+    /// Returns the stores and calls that may be executed after leaving `idom` and before
+    /// entering `bb`, i.e. those in blocks reaching `bb` without passing through `idom`.
+    fn clobbers_between(&self, idom: BasicBlockId, bb: BasicBlockId) -> Vec<InstructionId> {
+        let mut clobbers = vec![];
+        let mut visited = FxHashSet::default();
+        let mut worklist: Vec<BasicBlockId> = self.func.basic_blocks.arena[bb]
+            .pred
+            .iter()
+            .copied()
+            .collect();
+        while let Some(block) = worklist.pop() {
+            if block == idom || !visited.insert(block) {
+                continue;
+            }
+            let block = &self.func.basic_blocks.arena[block];
+            for val in &*block.iseq_ref() {
+                let id = val.as_instruction().id;
+                if matches!(
+                    self.func.inst_table[id].opcode,
+                    Opcode::Store | Opcode::Call
+                ) {
+                    clobbers.push(id)
+                }
+            }
+            worklist.extend(block.pred.iter().copied());
+        }
+        clobbers
+    }
+
+    /// Forgets the values in memory that `clobber` may overwrite
+    fn kill(&self, avails: &mut Vec<AvailableValue>, clobber: InstructionId) {
+        let inst = &self.func.inst_table[clobber];
+        avails.retain(|a| !self.aa.may_modify(self.func, inst, &a.loc));
+    }
+
+    fn replace(&mut self, id: InstructionId, val: Value) {
+        Instruction::replace_all_uses(&mut self.func.inst_table, id, Operand::Value(val));
+        self.removal_list.push(id);
+    }
+}
+
+fn expression(inst: &Instruction) -> Expression {
+    let mut operands = inst.operands.clone();
+    let commutative = match inst.opcode {
+        Opcode::Add | Opcode::Mul => Some(0),
+        Opcode::ICmp if *inst.operands[0].as_icmp_kind() == ICmpKind::Eq => Some(1),
+        Opcode::FCmp if *inst.operands[0].as_fcmp_kind() == FCmpKind::UEq => Some(1),
+        _ => None,
+    };
+    if let Some(i) = commutative {
+        if rank(&operands[i + 1]) < rank(&operands[i]) {
+            operands.swap(i, i + 1);
+        }
+    }
+    (inst.opcode, inst.ty, operands)
+}
+
+/// Orders operands of commutative operations: instructions, then arguments, then anything else
+/// with immediates last
+fn rank(op: &Operand) -> (usize, usize) {
+    match op {
+        Operand::Value(Value::Instruction(InstructionValue { id, .. })) => (0, id.index()),
+        Operand::Value(Value::Argument(arg)) => (1, arg.index),
+        Operand::Value(Value::Immediate(_)) => (3, 0),
+        _ => (2, 0),
+    }
+}
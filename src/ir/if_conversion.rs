@@ -0,0 +1,265 @@
+use crate::ir::{
+    basic_block::BasicBlockId,
+    function::Function,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{InstructionValue, Value},
+};
+
+/// If-conversion. A `CondBr` on a comparison starting a diamond (or a triangle) whose arms
+/// compute a few cheap values without side effects is removed: the arms are speculated in the
+/// block branching, and the phis merging their values become `Select`s on the comparison.
+pub struct IfConversion {
+    /// Number of instructions (`Select`s included) a conversion may add to the branching block
+    threshold: usize,
+}
+
+struct IfConversionOnFunction<'a> {
+    func: &'a mut Function,
+    threshold: usize,
+    converted: usize,
+}
+
+/// `head` branches on `cond` to the arms, which jump to `join`
+struct Candidate {
+    head: BasicBlockId,
+    cond: InstructionId,
+    join: BasicBlockId,
+    arms: Vec<BasicBlockId>,
+    /// The predecessors of `join` on the paths taken if `cond` holds and if it doesn't
+    on_true: BasicBlockId,
+    on_false: BasicBlockId,
+}
+
+impl IfConversion {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold }
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            IfConversionOnFunction {
+                func,
+                threshold: self.threshold,
+                converted: 0,
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> IfConversionOnFunction<'a> {
+    fn run(mut self) {
+        while let Some(c) = self
+            .func
+            .basic_blocks
+            .order
+            .iter()
+            .find_map(|&bb| self.analyze(bb))
+        {
+            self.convert(c);
+            self.converted += 1;
+        }
+
+        debug!(println!(
+            "function '{}': {} branches converted",
+            self.func.name, self.converted
+        ));
+    }
+
+    fn analyze(&self, head: BasicBlockId) -> Option<Candidate> {
+        let br = &self.func.inst_table[self.func.terminator(head)];
+        if br.opcode != Opcode::CondBr {
+            return None;
+        }
+
+        // The selects take the place of the branch as the only users of the comparison, so
+        // that the comparison is computed right before them
+        let cond = match br.operands[0].as_value() {
+            Value::Instruction(InstructionValue { id, .. }) => *id,
+            _ => return None,
+        };
+        let cmp = &self.func.inst_table[cond];
+        if cmp.opcode != Opcode::ICmp
+            || cmp.parent != head
+            || cmp.users.borrow().len() != 1
+            || !matches!(
+                cmp.operands[1].as_value().get_type(),
+                Type::Int32 | Type::Int64
+            )
+            || matches!(
+                (cmp.operands[1].as_value(), cmp.operands[2].as_value()),
+                (Value::Immediate(_), Value::Immediate(_))
+            )
+        {
+            return None;
+        }
+
+        let (t, f) = (
+            *br.operands[1].as_basic_block(),
+            *br.operands[2].as_basic_block(),
+        );
+        if t == f {
+            return None;
+        }
+        let (join, arms, on_true, on_false) = match (self.arm(head, t), self.arm(head, f)) {
+            (Some(x), Some(y)) if x == y => (x, vec![t, f], t, f),
+            (Some(x), _) if x == f => (f, vec![t], t, head),
+            (_, Some(y)) if y == t => (t, vec![f], head, f),
+            _ => return None,
+        };
+        if join == head {
+            return None;
+        }
+
+        let mut cost = arms
+            .iter()
+            .map(|&bb| self.func.basic_blocks.arena[bb].iseq_ref().len() - 1)
+            .sum::<usize>();
+        for id in self.func.insts_of(join) {
+            let phi = &self.func.inst_table[id];
+            if phi.opcode != Opcode::Phi {
+                break;
+            }
+            if self.func.phi_incoming(id, on_true).unwrap()
+                == self.func.phi_incoming(id, on_false).unwrap()
+            {
+                continue;
+            }
+            if !matches!(phi.ty, Type::Int32 | Type::Int64 | Type::Pointer(_)) {
+                return None;
+            }
+            cost += 1;
+        }
+        if cost > self.threshold {
+            return None;
+        }
+
+        Some(Candidate {
+            head,
+            cond,
+            join,
+            arms,
+            on_true,
+            on_false,
+        })
+    }
+
+    /// Returns the block `bb` jumps to if `bb` is entered only from `head` and can be speculated
+    /// there
+    fn arm(&self, head: BasicBlockId, bb: BasicBlockId) -> Option<BasicBlockId> {
+        let block = &self.func.basic_blocks.arena[bb];
+        if block.pred.len() != 1 || !block.pred.contains(&head) || block.succ.len() != 1 {
+            return None;
+        }
+        let iseq = block.iseq_ref();
+        let (last, rest) = iseq.split_last().unwrap();
+        if self.func.inst_table[last.as_instruction().id].opcode != Opcode::Br {
+            return None;
+        }
+        let speculatable = rest.iter().all(|v| {
+            matches!(
+                self.func.inst_table[v.as_instruction().id].opcode,
+                Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Shl | Opcode::GetElementPtr
+            )
+        });
+        if !speculatable {
+            return None;
+        }
+        block.succ.iter().next().copied()
+    }
+
+    fn convert(&mut self, c: Candidate) {
+        for &arm in &c.arms {
+            let insts = self.func.insts_of(arm);
+            for &id in &insts[..insts.len() - 1] {
+                let val = self.func.inst_value(id);
+                self.func.basic_blocks.arena[arm]
+                    .iseq_ref_mut()
+                    .retain(|v| *v != val);
+                self.func.inst_table[id].parent = c.head;
+                self.insert(c.head, val);
+            }
+        }
+
+        // The join is left entered from the head alone, unless it's entered from elsewhere too
+        let only_from_head = self.func.basic_blocks.arena[c.join]
+            .pred
+            .iter()
+            .all(|p| *p == c.head || c.arms.contains(p));
+        let cond = self.func.inst_value(c.cond);
+        for phi in self.func.insts_of(c.join) {
+            if self.func.inst_table[phi].opcode != Opcode::Phi {
+                break;
+            }
+            let (t, f) = (
+                self.func.phi_incoming(phi, c.on_true).unwrap(),
+                self.func.phi_incoming(phi, c.on_false).unwrap(),
+            );
+            let val = if t == f {
+                t
+            } else {
+                let ty = self.func.inst_table[phi].ty;
+                let select = self.func.alloc_inst(Instruction::new(
+                    Opcode::Select,
+                    vec![Operand::Value(cond), Operand::Value(t), Operand::Value(f)],
+                    ty,
+                    c.head,
+                ));
+                let select = self.func.inst_value(select);
+                self.insert(c.head, select);
+                select
+            };
+
+            if only_from_head {
+                Instruction::replace_all_uses(&mut self.func.inst_table, phi, Operand::Value(val));
+                self.func.remove_inst(phi);
+                continue;
+            }
+            let phi_inst = &self.func.inst_table[phi];
+            let mut operands: Vec<Operand> = phi_inst
+                .operands
+                .chunks(2)
+                .filter(|pair| {
+                    let bb = *pair[1].as_basic_block();
+                    bb != c.head && !c.arms.contains(&bb)
+                })
+                .flatten()
+                .copied()
+                .collect();
+            operands.push(Operand::Value(val));
+            operands.push(Operand::BasicBlock(c.head));
+            let new = Instruction::new(Opcode::Phi, operands, phi_inst.ty, c.join);
+            self.func.change_inst(phi, new);
+        }
+
+        let br = self.func.terminator(c.head);
+        self.func.change_inst(
+            br,
+            Instruction::new(
+                Opcode::Br,
+                vec![Operand::BasicBlock(c.join)],
+                Type::Void,
+                c.head,
+            ),
+        );
+        if self.func.inst_table[c.cond].users.borrow().is_empty() {
+            self.func.remove_inst(c.cond);
+        }
+
+        self.func.recompute_cfg();
+        self.func.remove_unreachable_blocks();
+    }
+
+    /// Inserts `val` before the terminator of `bb`
+    fn insert(&mut self, bb: BasicBlockId, val: Value) {
+        let mut iseq = self.func.basic_blocks.arena[bb].iseq_ref_mut();
+        let pos = iseq.len() - 1;
+        iseq.insert(pos, val);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::analysis::{
+    dom_tree::DominatorTreeConstructor,
+    loops::LoopsConstructor,
+    scalar_evolution::{AddRec, ScalarEvolution, SCEV},
+};
+use crate::ir::{
+    function::Function,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+
+/// Induction variable simplification. Phis in a loop header computing the same recurrence are
+/// merged into one, preferably the canonical induction variable `{0,+,1}`. If the number of
+/// iterations of a loop is a constant, the values computed in its header and used after the
+/// loop are replaced with the ones they have when the loop is left.
+pub struct IndVarSimplify {}
+
+struct IndVarSimplifyOnFunction<'a> {
+    func: &'a mut Function,
+}
+
+impl IndVarSimplify {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            IndVarSimplifyOnFunction { func }.run()
+        }
+    }
+}
+
+impl<'a> IndVarSimplifyOnFunction<'a> {
+    fn run(self) {
+        let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+        let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+
+        // (phi, phi computing the same recurrence)
+        let mut merges = vec![];
+        // (value, its value after the loop, users after the loop)
+        let mut exit_values = vec![];
+
+        let mut se = ScalarEvolution::new(self.func, &loops);
+        for (loop_id, l) in &loops.arena {
+            let header: Vec<InstructionId> = self.func.basic_blocks.arena[l.header()]
+                .iseq_ref()
+                .iter()
+                .map(|v| v.as_instruction().id)
+                .collect();
+
+            let mut recs: Vec<(InstructionId, AddRec)> = header
+                .iter()
+                .filter(|&&id| self.func.inst_table[id].opcode == Opcode::Phi)
+                .filter_map(|&id| match se.get(&self.func.inst_value(id)) {
+                    SCEV::AddRec(rec) if rec.loop_id == loop_id => Some((id, rec)),
+                    _ => None,
+                })
+                .collect();
+            recs.sort_by_key(|(_, rec)| !is_canonical(rec));
+            let mut kept: Vec<(InstructionId, AddRec)> = vec![];
+            for (id, rec) in recs {
+                let ty = self.func.inst_table[id].ty;
+                match kept
+                    .iter()
+                    .find(|(k, r)| *r == rec && self.func.inst_table[*k].ty == ty)
+                {
+                    Some(&(k, _)) => merges.push((id, k)),
+                    None => kept.push((id, rec)),
+                }
+            }
+
+            let count = match se.backedge_taken_count(loop_id) {
+                Some(count) => count,
+                None => continue,
+            };
+            for &id in &header {
+                let users: Vec<InstructionId> = self.func.inst_table[id]
+                    .users
+                    .borrow()
+                    .iter()
+                    .copied()
+                    .filter(|&u| !l.contains(self.func.inst_table[u].parent))
+                    .collect();
+                if users.is_empty() {
+                    continue;
+                }
+                let scev = se.get(&self.func.inst_value(id));
+                let val = match (
+                    se.evaluate_at(&scev, loop_id, count),
+                    self.func.inst_table[id].ty,
+                ) {
+                    (Some(x), Type::Int32) if x as i32 as i64 == x => {
+                        Value::new_imm_int32(x as i32)
+                    }
+                    (Some(x), Type::Int64) => Value::Immediate(ImmediateValue::Int64(x)),
+                    _ => continue,
+                };
+                exit_values.push((id, val, users));
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} induction variables merged, {} exit values replaced",
+            self.func.name,
+            merges.len(),
+            exit_values.len()
+        ));
+
+        for (id, val, users) in exit_values {
+            let from = Operand::Value(self.func.inst_value(id));
+            for user in users {
+                Instruction::replace_operand(
+                    &mut self.func.inst_table,
+                    user,
+                    &from,
+                    Operand::Value(val),
+                );
+            }
+        }
+
+        for (phi, to) in merges {
+            let to = Operand::Value(self.func.inst_value(to));
+            Instruction::replace_all_uses(&mut self.func.inst_table, phi, to);
+            let incoming: Vec<Operand> = self.func.inst_table[phi].operands.clone();
+            self.func.remove_inst(phi);
+
+            // The increment is dead unless something else in the loop uses it
+            for op in incoming {
+                if let Operand::Value(Value::Instruction(InstructionValue { id, .. })) = op {
+                    let inst = &self.func.inst_table[id];
+                    if matches!(inst.opcode, Opcode::Add | Opcode::Sub)
+                        && inst.users.borrow().is_empty()
+                    {
+                        self.func.remove_inst(id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_canonical(rec: &AddRec) -> bool {
+    *rec.start == SCEV::Constant(0) && rec.step == 1
+}
@@ -0,0 +1,227 @@
+use id_arena::{Arena, Id};
+use std::fmt;
+
+pub type InlineAsmId = Id<InlineAsm>;
+
+#[derive(Clone)]
+pub struct InlineAsms {
+    pub arena: Arena<InlineAsm>,
+}
+
+/// A piece of assembly embedded into generated code. It is called like a function: the call's
+/// arguments are bound to the input constraints, and its result to the output constraint.
+#[derive(Debug, Clone)]
+pub struct InlineAsm {
+    /// Assembly text. `$N` is replaced by the N-th operand, counting the output first and then
+    /// the inputs. `$$` stands for a literal `$`.
+    pub template: String,
+    pub constraints: Vec<Constraint>,
+    pub side_effects: bool,
+    /// Machine code equivalent to `template`, for backends that cannot assemble text (the JIT)
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// One entry of a comma-separated constraint string such as `={eax},r,~{edx},~{memory}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    /// `=r` or `={reg}`
+    Output(ConstraintLocation),
+    /// `r`, `{reg}` or `i`
+    Input(ConstraintLocation),
+    /// `~{reg}`, `~{memory}` or `~{cc}`
+    Clobber(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstraintLocation {
+    /// Any general-purpose register chosen by the register allocator
+    Reg,
+    /// The named physical register
+    FixedReg(String),
+    /// An immediate operand
+    Imm,
+}
+
+impl InlineAsms {
+    pub fn new() -> Self {
+        Self {
+            arena: Arena::new(),
+        }
+    }
+
+    pub fn add(&mut self, asm: InlineAsm) -> InlineAsmId {
+        self.arena.alloc(asm)
+    }
+}
+
+impl InlineAsm {
+    /// Returns an error if the constraints or the template are malformed
+    pub fn new(template: &str, constraints: &str, side_effects: bool) -> Result<Self, String> {
+        let constraints = parse_constraints(constraints)?;
+        let outputs = constraints
+            .iter()
+            .filter(|c| matches!(c, Constraint::Output(_)))
+            .count();
+        if outputs > 1 {
+            return Err("inline asm supports at most one output".to_string());
+        }
+        let asm = Self {
+            template: template.to_string(),
+            constraints,
+            side_effects,
+            bytes: None,
+        };
+        let num_operands = outputs + asm.inputs().count();
+        asm.expand_template(|n| {
+            if n < num_operands {
+                Ok(String::new())
+            } else {
+                Err(format!("inline asm has no operand ${}", n))
+            }
+        })?;
+        Ok(asm)
+    }
+
+    /// Attaches the pre-encoded machine code of the template. Since the bytes are copied as they
+    /// are, every register operand must be pinned to a physical register.
+    pub fn with_bytes(mut self, bytes: Vec<u8>) -> Self {
+        assert!(
+            self.constraints.iter().all(|c| match c {
+                Constraint::Output(l) | Constraint::Input(l) => l != &ConstraintLocation::Reg,
+                Constraint::Clobber(_) => true,
+            }),
+            "pre-encoded inline asm can't refer to allocated registers"
+        );
+        self.bytes = Some(bytes);
+        self
+    }
+
+    pub fn output(&self) -> Option<&ConstraintLocation> {
+        self.constraints.iter().find_map(|c| match c {
+            Constraint::Output(l) => Some(l),
+            _ => None,
+        })
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = &ConstraintLocation> {
+        self.constraints.iter().filter_map(|c| match c {
+            Constraint::Input(l) => Some(l),
+            _ => None,
+        })
+    }
+
+    /// Clobbered registers, excluding the `memory` and `cc` pseudo clobbers
+    pub fn clobbered_regs(&self) -> impl Iterator<Item = &str> {
+        self.constraints.iter().filter_map(|c| match c {
+            Constraint::Clobber(r) if r != "memory" && r != "cc" => Some(r.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn clobbers_memory(&self) -> bool {
+        self.constraints
+            .iter()
+            .any(|c| c == &Constraint::Clobber("memory".to_string()))
+    }
+
+    /// Substitutes operands into the template. `operand(n)` returns the text of the n-th operand.
+    pub fn expand_template<F: Fn(usize) -> Result<String, String>>(
+        &self,
+        operand: F,
+    ) -> Result<String, String> {
+        let mut output = String::new();
+        let mut chars = self.template.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                output.push(c);
+                continue;
+            }
+            if chars.peek() == Some(&'$') {
+                chars.next();
+                output.push('$');
+                continue;
+            }
+            let mut n = String::new();
+            while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                n.push(*d);
+                chars.next();
+            }
+            if n.is_empty() {
+                return Err("expected an operand number after '$' in inline asm".to_string());
+            }
+            let n = n
+                .parse()
+                .map_err(|_| format!("invalid inline asm operand ${}", n))?;
+            output.push_str(operand(n)?.as_str());
+        }
+        Ok(output)
+    }
+}
+
+fn parse_constraints(s: &str) -> Result<Vec<Constraint>, String> {
+    fn location(s: &str) -> Result<ConstraintLocation, String> {
+        match s {
+            "r" => Ok(ConstraintLocation::Reg),
+            "i" => Ok(ConstraintLocation::Imm),
+            s if s.starts_with('{') && s.ends_with('}') && s.len() > 2 => {
+                Ok(ConstraintLocation::FixedReg(s[1..s.len() - 1].to_string()))
+            }
+            _ => Err(format!("invalid inline asm constraint '{}'", s)),
+        }
+    }
+
+    s.split(',')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            if let Some(c) = c.strip_prefix('=') {
+                match location(c)? {
+                    ConstraintLocation::Imm => {
+                        Err("inline asm output can't be an immediate".to_string())
+                    }
+                    loc => Ok(Constraint::Output(loc)),
+                }
+            } else if let Some(c) = c.strip_prefix('~') {
+                match location(c) {
+                    Ok(ConstraintLocation::FixedReg(r)) => Ok(Constraint::Clobber(r)),
+                    _ => Err(format!("invalid inline asm clobber '~{}'", c)),
+                }
+            } else {
+                Ok(Constraint::Input(location(c)?))
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn loc(l: &ConstraintLocation) -> String {
+            match l {
+                ConstraintLocation::Reg => "r".to_string(),
+                ConstraintLocation::FixedReg(r) => format!("{{{}}}", r),
+                ConstraintLocation::Imm => "i".to_string(),
+            }
+        }
+        match self {
+            Constraint::Output(l) => write!(f, "={}", loc(l)),
+            Constraint::Input(l) => write!(f, "{}", loc(l)),
+            Constraint::Clobber(r) => write!(f, "~{{{}}}", r),
+        }
+    }
+}
+
+impl fmt::Display for InlineAsm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "asm {}\"{}\", \"{}\"",
+            if self.side_effects { "sideeffect " } else { "" },
+            self.template.escape_default(),
+            self.constraints
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
@@ -0,0 +1,292 @@
+use crate::analysis::call_graph::{CallGraph, Callee};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::{Function, FunctionId},
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ArgumentValue, InstructionValue, Value},
+};
+use rustc_hash::FxHashMap;
+
+/// Inlines calls to functions defined in the module. Functions are visited bottom-up in the
+/// call graph, so a callee has already got its own calls inlined when it's inlined into its
+/// callers. The cost of inlining a callee is the number of its instructions. A callee is inlined
+/// if the cost is within the threshold or it's marked `alwaysinline`, and never if it's marked
+/// `noinline` or recursive.
+pub struct Inliner {
+    threshold: usize,
+}
+
+struct InlinerOnFunction<'a> {
+    func: &'a mut Function,
+    callee: &'a Function,
+}
+
+impl Inliner {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold }
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let graph = CallGraph::new(module);
+        let order: Vec<FunctionId> = graph.sccs_bottom_up().into_iter().flatten().collect();
+
+        for caller in order {
+            let mut inlined = 0;
+            for &(call, callee) in graph.callees(caller) {
+                let callee_id = match callee {
+                    Callee::Defined(callee) => callee,
+                    _ => continue,
+                };
+                if !self.should_inline(module, &graph, caller, callee_id) {
+                    continue;
+                }
+                let callee = module.function_ref(callee_id).clone();
+                InlinerOnFunction {
+                    func: module.function_ref_mut(caller),
+                    callee: &callee,
+                }
+                .inline(call);
+                inlined += 1;
+            }
+
+            debug!(println!(
+                "function '{}': {} calls inlined",
+                module.function_ref(caller).name,
+                inlined
+            ));
+        }
+    }
+
+    fn should_inline(
+        &self,
+        module: &Module,
+        graph: &CallGraph,
+        caller: FunctionId,
+        callee: FunctionId,
+    ) -> bool {
+        let f = module.function_ref(callee);
+        if f.is_internal || f.basic_blocks.order.len() == 0 || f.attr.no_inline {
+            return false;
+        }
+        // Parameters passed by value need a copy made at the call site
+        if (0..f.get_params_len()).any(|i| f.get_param_attr(i).map_or(false, |a| a.byval)) {
+            return false;
+        }
+        // A callee without `ret` never returns, leaving nothing to replace the call with
+        if !f.basic_blocks.order.iter().any(|&bb| {
+            f.basic_blocks.arena[bb]
+                .iseq_ref()
+                .last()
+                .map_or(false, |v| {
+                    f.inst_table[v.as_instruction().id].opcode == Opcode::Ret
+                })
+        }) {
+            return false;
+        }
+        if graph.reaches(callee, caller) {
+            return false;
+        }
+        f.attr.always_inline || cost(f) <= self.threshold
+    }
+}
+
+impl<'a> InlinerOnFunction<'a> {
+    /// Replaces `call` with the body of the callee
+    fn inline(&mut self, call: InstructionId) {
+        let (bb, pos) = self.func.find_inst_pos(call).unwrap();
+        let cont = self.split_block(bb, pos + 1);
+
+        let callee_blocks = self.callee.basic_blocks.order.clone();
+        let bbs: FxHashMap<BasicBlockId, BasicBlockId> = callee_blocks
+            .iter()
+            .map(|&b| (b, self.func.basic_blocks.arena.alloc(BasicBlock::new())))
+            .collect();
+        let at = self
+            .func
+            .basic_blocks
+            .order
+            .iter()
+            .position(|&b| b == bb)
+            .unwrap();
+        for (i, &b) in callee_blocks.iter().enumerate() {
+            self.func.basic_blocks.order.insert(at + 1 + i, bbs[&b]);
+        }
+
+        let args: Vec<Value> = self.func.inst_table[call].operands[1..]
+            .iter()
+            .map(|op| *op.as_value())
+            .collect();
+
+        // Instructions are allocated first and given operands afterwards, since a phi may refer
+        // to an instruction appearing later
+        let mut values: FxHashMap<InstructionId, Value> = FxHashMap::default();
+        let mut cloned = vec![];
+        let mut allocas = vec![];
+        let mut rets = vec![];
+        for &b in &callee_blocks {
+            for val in &*self.callee.basic_blocks.arena[b].iseq_ref() {
+                let id = val.as_instruction().id;
+                let inst = &self.callee.inst_table[id];
+                if inst.opcode == Opcode::Ret {
+                    rets.push((id, bbs[&b]));
+                    continue;
+                }
+                let new = self.func.alloc_inst(
+                    Instruction::new(inst.opcode, vec![], inst.ty, bbs[&b])
+                        .with_mem_attr(inst.mem_attr),
+                );
+                let val = self.func.inst_value(new);
+                values.insert(id, val);
+                cloned.push((id, new));
+                if inst.opcode == Opcode::Alloca {
+                    allocas.push(val);
+                } else {
+                    self.func.basic_blocks.arena[bbs[&b]]
+                        .iseq_ref_mut()
+                        .push(val);
+                }
+            }
+        }
+        for (id, new) in cloned {
+            let operands =
+                self.map_operands(&self.callee.inst_table[id].operands, &values, &bbs, &args);
+            self.func.inst_table[new].operands = operands;
+            self.func.inst_table[new].set_users(&self.func.inst_table);
+        }
+
+        // Allocas go to the entry of the caller so that they are allocated once
+        let entry = self.func.basic_blocks.order[0];
+        for (i, alloca) in allocas.into_iter().enumerate() {
+            self.func.inst_table[alloca.as_instruction().id].parent = entry;
+            self.func.basic_blocks.arena[entry]
+                .iseq_ref_mut()
+                .insert(i, alloca);
+        }
+
+        // Every `ret` jumps to the continuation, where a phi merges the returned values
+        let mut incomings = vec![];
+        for (ret, b) in rets {
+            let ret = &self.callee.inst_table[ret];
+            if let Some(op) = ret.operands.get(0) {
+                let op = self.map_operands(&[*op], &values, &bbs, &args)[0];
+                incomings.push(op);
+                incomings.push(Operand::BasicBlock(b));
+            }
+            let br = self.func.alloc_inst(Instruction::new(
+                Opcode::Br,
+                vec![Operand::BasicBlock(cont)],
+                Type::Void,
+                b,
+            ));
+            let br = self.func.inst_value(br);
+            self.func.basic_blocks.arena[b].iseq_ref_mut().push(br);
+        }
+        if self.func.inst_table[call].ty != Type::Void {
+            let ret = match incomings.len() {
+                2 => incomings[0],
+                _ => {
+                    let ty = self.func.inst_table[call].ty;
+                    let phi =
+                        self.func
+                            .alloc_inst(Instruction::new(Opcode::Phi, incomings, ty, cont));
+                    let phi = self.func.inst_value(phi);
+                    self.func.basic_blocks.arena[cont]
+                        .iseq_ref_mut()
+                        .insert(0, phi);
+                    Operand::Value(phi)
+                }
+            };
+            Instruction::replace_all_uses(&mut self.func.inst_table, call, ret);
+        }
+
+        self.func.remove_inst(call);
+        let br = self.func.alloc_inst(Instruction::new(
+            Opcode::Br,
+            vec![Operand::BasicBlock(bbs[&callee_blocks[0]])],
+            Type::Void,
+            bb,
+        ));
+        let br = self.func.inst_value(br);
+        self.func.basic_blocks.arena[bb].iseq_ref_mut().push(br);
+
+        self.func.recompute_cfg();
+    }
+
+    /// Moves the instructions of `bb` from `pos` on to a new block placed after `bb`, and
+    /// returns the new block
+    fn split_block(&mut self, bb: BasicBlockId, pos: usize) -> BasicBlockId {
+        let new = self.func.basic_blocks.arena.alloc(BasicBlock::new());
+        let at = self
+            .func
+            .basic_blocks
+            .order
+            .iter()
+            .position(|&b| b == bb)
+            .unwrap();
+        self.func.basic_blocks.order.insert(at + 1, new);
+
+        let tail: Vec<Value> = self.func.basic_blocks.arena[bb]
+            .iseq_ref_mut()
+            .drain(pos..)
+            .collect();
+        for val in &tail {
+            self.func.inst_table[val.as_instruction().id].parent = new;
+        }
+        *self.func.basic_blocks.arena[new].iseq_ref_mut() = tail;
+
+        // Phis in the successors now have their incoming values from the new block
+        let succs: Vec<BasicBlockId> = self.func.basic_blocks.arena[bb]
+            .succ
+            .iter()
+            .copied()
+            .collect();
+        for succ in succs {
+            for val in &*self.func.basic_blocks.arena[succ].iseq_ref() {
+                let id = val.as_instruction().id;
+                if self.func.inst_table[id].opcode != Opcode::Phi {
+                    continue;
+                }
+                for op in &mut self.func.inst_table[id].operands {
+                    if *op == Operand::BasicBlock(bb) {
+                        *op = Operand::BasicBlock(new);
+                    }
+                }
+            }
+        }
+
+        new
+    }
+
+    fn map_operands(
+        &self,
+        operands: &[Operand],
+        values: &FxHashMap<InstructionId, Value>,
+        bbs: &FxHashMap<BasicBlockId, BasicBlockId>,
+        args: &[Value],
+    ) -> Vec<Operand> {
+        operands
+            .iter()
+            .map(|op| match op {
+                Operand::Value(Value::Instruction(InstructionValue { id, .. })) => {
+                    Operand::Value(values[id])
+                }
+                Operand::Value(Value::Argument(ArgumentValue { index, .. })) => {
+                    Operand::Value(args[*index])
+                }
+                Operand::BasicBlock(b) => Operand::BasicBlock(bbs[b]),
+                op => *op,
+            })
+            .collect()
+    }
+}
+
+/// Returns the size of `func` in instructions
+fn cost(func: &Function) -> usize {
+    func.basic_blocks
+        .order
+        .iter()
+        .map(|&bb| func.basic_blocks.arena[bb].iseq_ref().len())
+        .sum()
+}
@@ -0,0 +1,393 @@
+use crate::ir::{
+    function::Function,
+    module::Module,
+    opcode::{FCmpKind, ICmpKind, Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+use rustc_hash::FxHashSet;
+use std::collections::VecDeque;
+
+/// Peephole simplification of instructions by a table of algebraic rewrites. Whenever an
+/// instruction is rewritten, its users are visited again, so rewrites are applied until none
+/// matches. Instructions left without users are removed along the way.
+pub struct InstCombine {}
+
+struct InstCombineOnFunction<'a> {
+    func: &'a mut Function,
+    worklist: VecDeque<InstructionId>,
+    removed: FxHashSet<InstructionId>,
+    num_combined: usize,
+}
+
+/// The result of a rewrite
+enum Rewrite {
+    /// Replace the instruction with an existing value
+    Value(Value),
+    /// Change the instruction into another one computing the same value
+    Inst(Instruction),
+}
+
+type Rule = fn(&Function, &Instruction) -> Option<Rewrite>;
+
+/// Returns the rewrites tried on an instruction of `opcode`, in order
+fn rules(opcode: Opcode) -> &'static [Rule] {
+    match opcode {
+        Opcode::Add => &[fold_const, move_const_right, add_zero, add_add_const],
+        Opcode::Sub => &[fold_const, sub_self, sub_zero, sub_const],
+        Opcode::Mul => &[
+            fold_const,
+            move_const_right,
+            mul_one,
+            mul_zero,
+            mul_power_of_two,
+        ],
+        Opcode::ICmp => &[icmp_move_const_right, icmp_le_const],
+        Opcode::FCmp => &[fcmp_move_const_right],
+        Opcode::FPToSI => &[fptosi_sitofp],
+        Opcode::GetElementPtr => &[gep_gep],
+        _ => &[],
+    }
+}
+
+impl InstCombine {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            InstCombineOnFunction {
+                func,
+                worklist: VecDeque::new(),
+                removed: FxHashSet::default(),
+                num_combined: 0,
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> InstCombineOnFunction<'a> {
+    fn run(mut self) {
+        for &bb in &self.func.basic_blocks.order {
+            for val in &*self.func.basic_blocks.arena[bb].iseq_ref() {
+                self.worklist.push_back(val.as_instruction().id)
+            }
+        }
+
+        while let Some(id) = self.worklist.pop_front() {
+            if self.removed.contains(&id) {
+                continue;
+            }
+
+            let inst = &self.func.inst_table[id];
+            if inst.users.borrow().is_empty() && is_pure(inst.opcode) {
+                self.remove(id);
+                continue;
+            }
+
+            let rewrite = rules(inst.opcode)
+                .iter()
+                .find_map(|rule| rule(self.func, inst));
+            match rewrite {
+                Some(Rewrite::Value(val)) => {
+                    self.push_users(id);
+                    Instruction::replace_all_uses(
+                        &mut self.func.inst_table,
+                        id,
+                        Operand::Value(val),
+                    );
+                    self.remove(id);
+                }
+                Some(Rewrite::Inst(new)) => {
+                    self.push_operands(id);
+                    self.push_users(id);
+                    self.func.change_inst(id, new);
+                    self.worklist.push_back(id);
+                }
+                None => continue,
+            }
+            self.num_combined += 1;
+        }
+
+        debug!(println!(
+            "function '{}': {} insts combined",
+            self.func.name, self.num_combined
+        ));
+    }
+
+    /// Removes `id` and visits its operands again since they may have lost their last user
+    fn remove(&mut self, id: InstructionId) {
+        self.push_operands(id);
+        self.func.remove_inst(id);
+        self.removed.insert(id);
+    }
+
+    fn push_users(&mut self, id: InstructionId) {
+        let users = self.func.inst_table[id].users.borrow().clone();
+        self.worklist.extend(users)
+    }
+
+    fn push_operands(&mut self, id: InstructionId) {
+        for op in &self.func.inst_table[id].operands {
+            if let Operand::Value(Value::Instruction(InstructionValue { id, .. })) = op {
+                self.worklist.push_back(*id)
+            }
+        }
+    }
+}
+
+/// Returns true if an instruction of `opcode` does nothing but compute its value
+fn is_pure(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::GetElementPtr
+            | Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Rem
+            | Opcode::Shl
+            | Opcode::SIToFP
+            | Opcode::FPToSI
+            | Opcode::ICmp
+            | Opcode::FCmp
+            | Opcode::ExtractElement
+            | Opcode::InsertElement
+            | Opcode::ShuffleVector
+            | Opcode::Select
+            | Opcode::Phi
+    )
+}
+
+// c1 op c2 -> c
+fn fold_const(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if inst.operands.iter().all(|op| imm(op).is_some()) {
+        return inst.fold_const().map(Rewrite::Value);
+    }
+    None
+}
+
+// c op x -> x op c
+fn move_const_right(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if imm(&inst.operands[0]).is_some() && imm(&inst.operands[1]).is_none() {
+        return Some(Rewrite::Inst(with_operands(
+            inst,
+            vec![inst.operands[1], inst.operands[0]],
+        )));
+    }
+    None
+}
+
+// x + 0 -> x
+fn add_zero(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if int_const(&inst.operands[1]) == Some(0) {
+        return Some(Rewrite::Value(*inst.operands[0].as_value()));
+    }
+    None
+}
+
+// (x + c1) + c2 -> x + (c1 + c2)
+fn add_add_const(func: &Function, inst: &Instruction) -> Option<Rewrite> {
+    int_const(&inst.operands[1])?;
+    let inner = def(func, &inst.operands[0])?;
+    if inner.opcode != Opcode::Add || int_const(&inner.operands[1]).is_none() {
+        return None;
+    }
+    let c = inner.operands[1]
+        .as_value()
+        .const_add(inst.operands[1].as_value())?;
+    Some(Rewrite::Inst(with_operands(
+        inst,
+        vec![inner.operands[0], Operand::Value(c)],
+    )))
+}
+
+// x - x -> 0
+fn sub_self(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if inst.operands[0] == inst.operands[1] {
+        return zero(inst.ty).map(Rewrite::Value);
+    }
+    None
+}
+
+// x - 0 -> x
+fn sub_zero(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if int_const(&inst.operands[1]) == Some(0) {
+        return Some(Rewrite::Value(*inst.operands[0].as_value()));
+    }
+    None
+}
+
+// x - c -> x + (-c)
+fn sub_const(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    let neg = match imm(&inst.operands[1])? {
+        ImmediateValue::Int32(i) => ImmediateValue::Int32(i.checked_neg()?),
+        ImmediateValue::Int64(i) => ImmediateValue::Int64(i.checked_neg()?),
+        _ => return None,
+    };
+    let mut new = with_operands(inst, vec![inst.operands[0], imm_operand(neg)]);
+    new.opcode = Opcode::Add;
+    Some(Rewrite::Inst(new))
+}
+
+// x * 1 -> x
+fn mul_one(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if int_const(&inst.operands[1]) == Some(1) {
+        return Some(Rewrite::Value(*inst.operands[0].as_value()));
+    }
+    None
+}
+
+// x * 0 -> 0
+fn mul_zero(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if int_const(&inst.operands[1]) == Some(0) {
+        return zero(inst.ty).map(Rewrite::Value);
+    }
+    None
+}
+
+// x * 2^k -> x << k
+fn mul_power_of_two(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if !matches!(inst.ty, Type::Int32 | Type::Int64) {
+        return None;
+    }
+    let k = imm(&inst.operands[1])?.is_power_of_two()?;
+    let mut new = with_operands(
+        inst,
+        vec![inst.operands[0], imm_operand(ImmediateValue::Int8(k as i8))],
+    );
+    new.opcode = Opcode::Shl;
+    Some(Rewrite::Inst(new))
+}
+
+// c == x -> x == c
+fn icmp_move_const_right(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if *inst.operands[0].as_icmp_kind() == ICmpKind::Eq
+        && imm(&inst.operands[1]).is_some()
+        && imm(&inst.operands[2]).is_none()
+    {
+        return Some(Rewrite::Inst(with_operands(
+            inst,
+            vec![inst.operands[0], inst.operands[2], inst.operands[1]],
+        )));
+    }
+    None
+}
+
+// x <= c -> x < c + 1
+fn icmp_le_const(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if *inst.operands[0].as_icmp_kind() != ICmpKind::Le || imm(&inst.operands[1]).is_some() {
+        return None;
+    }
+    let succ = match imm(&inst.operands[2])? {
+        ImmediateValue::Int32(i) => ImmediateValue::Int32(i.checked_add(1)?),
+        ImmediateValue::Int64(i) => ImmediateValue::Int64(i.checked_add(1)?),
+        _ => return None,
+    };
+    Some(Rewrite::Inst(with_operands(
+        inst,
+        vec![
+            Operand::ICmpKind(ICmpKind::Lt),
+            inst.operands[1],
+            imm_operand(succ),
+        ],
+    )))
+}
+
+// c == x -> x == c
+fn fcmp_move_const_right(_: &Function, inst: &Instruction) -> Option<Rewrite> {
+    if *inst.operands[0].as_fcmp_kind() == FCmpKind::UEq
+        && imm(&inst.operands[1]).is_some()
+        && imm(&inst.operands[2]).is_none()
+    {
+        return Some(Rewrite::Inst(with_operands(
+            inst,
+            vec![inst.operands[0], inst.operands[2], inst.operands[1]],
+        )));
+    }
+    None
+}
+
+// fptosi(sitofp(x)) -> x. Every integer converted is exactly representable as `f64`. The
+// other way around, sitofp(fptosi(x)), truncates and is left as it is.
+fn fptosi_sitofp(func: &Function, inst: &Instruction) -> Option<Rewrite> {
+    let inner = def(func, &inst.operands[0])?;
+    if inner.opcode != Opcode::SIToFP {
+        return None;
+    }
+    let x = *inner.operands[0].as_value();
+    if x.get_type() != inst.ty || !matches!(x.get_type(), Type::Int8 | Type::Int32) {
+        return None;
+    }
+    Some(Rewrite::Value(x))
+}
+
+// gep (gep p, i.., a), 0, j.. -> gep p, i.., a, j..
+// gep (gep p, a), b, j.. -> gep p, a + b, j..
+fn gep_gep(func: &Function, inst: &Instruction) -> Option<Rewrite> {
+    let inner = def(func, &inst.operands[0])?;
+    if inner.opcode != Opcode::GetElementPtr {
+        return None;
+    }
+    let mut operands = if int_const(&inst.operands[1]) == Some(0) {
+        inner.operands.clone()
+    } else if inner.operands.len() == 2 {
+        let idx = inner.operands[1]
+            .as_value()
+            .const_add(inst.operands[1].as_value())?;
+        vec![inner.operands[0], Operand::Value(idx)]
+    } else {
+        return None;
+    };
+    operands.extend(inst.operands[2..].iter().copied());
+    Some(Rewrite::Inst(with_operands(inst, operands)))
+}
+
+fn with_operands(inst: &Instruction, operands: Vec<Operand>) -> Instruction {
+    Instruction::new(inst.opcode, operands, inst.ty, inst.parent).with_mem_attr(inst.mem_attr)
+}
+
+/// Returns the instruction defining `op`
+fn def<'a>(func: &'a Function, op: &Operand) -> Option<&'a Instruction> {
+    match op {
+        Operand::Value(Value::Instruction(InstructionValue { id, .. })) => {
+            Some(&func.inst_table[*id])
+        }
+        _ => None,
+    }
+}
+
+fn imm(op: &Operand) -> Option<&ImmediateValue> {
+    match op {
+        Operand::Value(Value::Immediate(imm)) => Some(imm),
+        _ => None,
+    }
+}
+
+fn imm_operand(imm: ImmediateValue) -> Operand {
+    Operand::Value(Value::Immediate(imm))
+}
+
+fn int_const(op: &Operand) -> Option<i64> {
+    match imm(op)? {
+        ImmediateValue::Int8(i) => Some(*i as i64),
+        ImmediateValue::Int32(i) => Some(*i as i64),
+        ImmediateValue::Int64(i) => Some(*i),
+        ImmediateValue::F64(_) => None,
+    }
+}
+
+fn zero(ty: Type) -> Option<Value> {
+    match ty {
+        Type::Int8 => Some(Value::new_imm_int8(0)),
+        Type::Int32 => Some(Value::new_imm_int32(0)),
+        Type::Int64 => Some(Value::Immediate(ImmediateValue::Int64(0))),
+        _ => None,
+    }
+}
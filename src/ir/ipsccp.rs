@@ -0,0 +1,324 @@
+use crate::analysis::{
+    call_graph::CallGraph, dom_tree::DominatorTreeConstructor, loops::LoopsConstructor,
+};
+use crate::ir::{
+    function::{Function, FunctionId},
+    global_val::Linkage,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    sccp::SparseConditionalConstantPropagation,
+    value::{FunctionValue, ImmediateValue, Value},
+};
+
+/// Interprocedural sparse conditional constant propagation. All the call sites of a function
+/// with internal linkage are known, so an argument passed the same constant at every call site
+/// becomes that constant in the function, and a constant returned by every `ret` of the
+/// function replaces the results of the calls. Functions are folded by SCCP in between, so
+/// constants found in a caller reach its callees and the other way around, until nothing
+/// changes.
+///
+/// With specialization enabled, a call made from a loop and passing constants to a small enough
+/// function is redirected to a clone of the function with the constants propagated into it,
+/// even if the function is called with other arguments elsewhere.
+pub struct InterproceduralSCCP {
+    /// Maximum number of instructions of a function cloned for constant arguments
+    specialization_threshold: Option<usize>,
+    /// Clones made for calls passing the listed constants
+    specializations: Vec<(FunctionId, Vec<(usize, ImmediateValue)>, FunctionId)>,
+}
+
+impl InterproceduralSCCP {
+    pub fn new() -> Self {
+        Self {
+            specialization_threshold: None,
+            specializations: vec![],
+        }
+    }
+
+    /// Enables function specialization for functions of up to `threshold` instructions
+    pub fn with_specialization(mut self, threshold: usize) -> Self {
+        self.specialization_threshold = Some(threshold);
+        self
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let (mut args, mut rets) = (0, 0);
+        loop {
+            SparseConditionalConstantPropagation::new().run_on_module(module);
+
+            let graph = CallGraph::new(module);
+            let propagated = (
+                self.propagate_arguments(module, &graph),
+                self.propagate_returns(module, &graph),
+                self.specialize(module),
+            );
+            args += propagated.0;
+            rets += propagated.1;
+            if propagated == (0, 0, 0) {
+                break;
+            }
+        }
+
+        debug!(println!(
+            "{} arguments and {} return values propagated, {} functions specialized",
+            args,
+            rets,
+            self.specializations.len()
+        ));
+    }
+
+    fn propagate_arguments(&self, module: &mut Module, graph: &CallGraph) -> usize {
+        let mut propagated = 0;
+        let ids: Vec<FunctionId> = module.functions.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            let calls = match known_calls(module, graph, id) {
+                Some(calls) => calls,
+                None => continue,
+            };
+            let num_params = num_params(module.function_ref(id));
+            for i in 0..num_params {
+                let param = module.function_ref(id).get_param_value(i).unwrap();
+                if !is_used(module.function_ref(id), param) {
+                    continue;
+                }
+                let mut konst = None;
+                let mut same = true;
+                for &(caller, call) in calls {
+                    let arg =
+                        *module.function_ref(caller).inst_table[call].operands[i + 1].as_value();
+                    // A recursive call passing the parameter on doesn't change it
+                    if caller == id && arg == param {
+                        continue;
+                    }
+                    match (arg, konst) {
+                        (Value::Immediate(c), None) => konst = Some(c),
+                        (Value::Immediate(c), Some(k)) if c == k => {}
+                        _ => same = false,
+                    }
+                }
+                if let (true, Some(c)) = (same, konst) {
+                    replace_param(module.function_ref_mut(id), i, Value::Immediate(c));
+                    propagated += 1;
+                }
+            }
+        }
+        propagated
+    }
+
+    fn propagate_returns(&self, module: &mut Module, graph: &CallGraph) -> usize {
+        let mut propagated = 0;
+        let ids: Vec<FunctionId> = module.functions.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            let calls = match known_calls(module, graph, id) {
+                Some(calls) => calls,
+                None => continue,
+            };
+            let c = match returned_constant(module.function_ref(id)) {
+                Some(c) => c,
+                None => continue,
+            };
+            for &(caller, call) in calls {
+                let caller = module.function_ref_mut(caller);
+                if caller.inst_table[call].users.borrow().is_empty() {
+                    continue;
+                }
+                Instruction::replace_all_uses(
+                    &mut caller.inst_table,
+                    call,
+                    Operand::Value(Value::Immediate(c)),
+                );
+                propagated += 1;
+            }
+        }
+        propagated
+    }
+
+    fn specialize(&mut self, module: &mut Module) -> usize {
+        let threshold = match self.specialization_threshold {
+            Some(threshold) => threshold,
+            None => return 0,
+        };
+
+        let mut redirected = 0;
+        let ids: Vec<FunctionId> = module.functions.iter().map(|(id, _)| id).collect();
+        for caller in ids {
+            let f = module.function_ref(caller);
+            // Clones aren't specialized any further, which bounds the number of clones
+            if f.is_internal
+                || f.basic_blocks.order.len() == 0
+                || self.specializations.iter().any(|(_, _, s)| *s == caller)
+            {
+                continue;
+            }
+
+            for call in hot_calls(f) {
+                let f = module.function_ref(caller);
+                let callee = match f.inst_table[call].operands[0].as_value() {
+                    Value::Function(FunctionValue { func_id, .. }) => *func_id,
+                    _ => continue,
+                };
+                let g = module.function_ref(callee);
+                if g.is_internal
+                    || g.basic_blocks.order.len() == 0
+                    || num_insts(g) > threshold
+                    || self.specializations.iter().any(|(_, _, s)| *s == callee)
+                {
+                    continue;
+                }
+                let konsts: Vec<(usize, ImmediateValue)> = f.inst_table[call].operands[1..]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, arg)| match arg.as_value() {
+                        Value::Immediate(c) if is_used(g, g.get_param_value(i).unwrap()) => {
+                            Some((i, *c))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                if konsts.len() == 0 {
+                    continue;
+                }
+
+                let clone = match self
+                    .specializations
+                    .iter()
+                    .find(|(orig, args, _)| *orig == callee && *args == konsts)
+                {
+                    Some((_, _, clone)) => *clone,
+                    None => {
+                        let clone = clone_function(module, callee);
+                        for &(i, c) in &konsts {
+                            replace_param(module.function_ref_mut(clone), i, Value::Immediate(c));
+                        }
+                        self.specializations.push((callee, konsts, clone));
+                        clone
+                    }
+                };
+                let ty = module.function_ref(clone).ty;
+                module.function_ref_mut(caller).inst_table[call].operands[0] =
+                    Operand::Value(Value::Function(FunctionValue { func_id: clone, ty }));
+                redirected += 1;
+            }
+        }
+        redirected
+    }
+}
+
+/// Returns the calls of `id` if they're all known
+fn known_calls<'a>(
+    module: &Module,
+    graph: &'a CallGraph,
+    id: FunctionId,
+) -> Option<&'a [(FunctionId, InstructionId)]> {
+    let f = module.function_ref(id);
+    if f.is_externally_visible()
+        || f.is_internal
+        || f.basic_blocks.order.len() == 0
+        || graph.is_address_taken(id)
+    {
+        return None;
+    }
+    Some(graph.callers(id))
+}
+
+/// Returns the constant returned by every `ret` of `func` if any
+fn returned_constant(func: &Function) -> Option<ImmediateValue> {
+    let mut konst = None;
+    for &bb in &func.basic_blocks.order {
+        let last = *func.basic_blocks.arena[bb].iseq_ref().last()?;
+        let inst = &func.inst_table[last.as_instruction().id];
+        if inst.opcode != Opcode::Ret {
+            continue;
+        }
+        match (inst.operands.get(0).and_then(|op| op.get_value()), konst) {
+            (Some(Value::Immediate(c)), None) => konst = Some(*c),
+            (Some(Value::Immediate(c)), Some(k)) if *c == k => {}
+            _ => return None,
+        }
+    }
+    konst
+}
+
+/// Returns the calls made from loops in `func`
+fn hot_calls(func: &Function) -> Vec<InstructionId> {
+    let dom_tree = DominatorTreeConstructor::new(&func.basic_blocks).construct();
+    let loops = LoopsConstructor::new(&dom_tree, &func.basic_blocks).analyze();
+    let mut calls = vec![];
+    for &bb in &func.basic_blocks.order {
+        if loops.get_loop_for(bb).is_none() {
+            continue;
+        }
+        for val in &*func.basic_blocks.arena[bb].iseq_ref() {
+            let id = val.as_instruction().id;
+            if func.inst_table[id].opcode == Opcode::Call {
+                calls.push(id)
+            }
+        }
+    }
+    calls
+}
+
+/// Adds a copy of `id` with internal linkage to `module`
+fn clone_function(module: &mut Module, id: FunctionId) -> FunctionId {
+    let mut clone = module.function_ref(id).clone();
+    clone.name = format!("{}.spec.{}", clone.name, module.functions.len());
+    clone.linkage = Linkage::Internal;
+    let clone_id = module.add_function(clone);
+
+    // Values refer to the function they belong to
+    let f = module.function_ref_mut(clone_id);
+    for (_, block) in f.basic_blocks.arena.iter_mut() {
+        for val in block.iseq_ref_mut().iter_mut() {
+            if let Value::Instruction(v) = val {
+                v.func_id = clone_id
+            }
+        }
+    }
+    for (_, inst) in f.inst_table.iter_mut() {
+        for op in &mut inst.operands {
+            match op {
+                Operand::Value(Value::Instruction(v)) => v.func_id = clone_id,
+                Operand::Value(Value::Argument(v)) => v.func_id = clone_id,
+                _ => {}
+            }
+        }
+    }
+    clone_id
+}
+
+fn replace_param(func: &mut Function, idx: usize, to: Value) {
+    let param = Operand::Value(func.get_param_value(idx).unwrap());
+    for &bb in &func.basic_blocks.order {
+        let iseq = func.basic_blocks.arena[bb].iseq_ref().clone();
+        for val in iseq {
+            let id = val.as_instruction().id;
+            if func.inst_table[id].operands.contains(&param) {
+                Instruction::replace_operand(&mut func.inst_table, id, &param, Operand::Value(to));
+            }
+        }
+    }
+}
+
+fn is_used(func: &Function, param: Value) -> bool {
+    let param = Operand::Value(param);
+    func.basic_blocks.order.iter().any(|&bb| {
+        func.basic_blocks.arena[bb].iseq_ref().iter().any(|val| {
+            func.inst_table[val.as_instruction().id]
+                .operands
+                .contains(&param)
+        })
+    })
+}
+
+fn num_params(func: &Function) -> usize {
+    let base = func.types.base.borrow();
+    base.as_function_ty(func.ty).unwrap().params_ty.len()
+}
+
+fn num_insts(func: &Function) -> usize {
+    func.basic_blocks
+        .order
+        .iter()
+        .map(|&bb| func.basic_blocks.arena[bb].iseq_ref().len())
+        .sum()
+}
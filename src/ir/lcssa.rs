@@ -0,0 +1,196 @@
+use crate::analysis::{
+    dom_tree::DominatorTreeConstructor,
+    loops::{Loop, LoopsConstructor},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    loop_simplify::LoopSimplify,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    value::{InstructionValue, Value},
+};
+use rustc_hash::FxHashMap;
+
+/// Loop-closed SSA construction. A value defined in a loop and used outside of it is passed
+/// through a phi in each exit block of the loop, so that the uses outside the loop refer to
+/// the phis instead. Where the paths from several exits meet before a use, another phi merges
+/// the exit phis. Loops are put into the canonical form by `LoopSimplify` first, which gives
+/// them dedicated exits.
+pub struct LoopClosedSSA {}
+
+struct LoopClosedSSAOnFunction<'a> {
+    func: &'a mut Function,
+    inserted: usize,
+}
+
+impl LoopClosedSSA {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        LoopSimplify::new().run_on_module(module);
+
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            LoopClosedSSAOnFunction { func, inserted: 0 }.run()
+        }
+    }
+}
+
+impl<'a> LoopClosedSSAOnFunction<'a> {
+    fn run(mut self) {
+        let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+        let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+
+        // Inner loops first, so that the phis closing an inner loop are closed by the outer one
+        for l in loops.post_order() {
+            self.run_on_loop(&loops.arena[l]);
+        }
+
+        debug!(println!(
+            "function '{}': {} phis inserted",
+            self.func.name, self.inserted
+        ));
+    }
+
+    fn run_on_loop(&mut self, l: &Loop<BasicBlock>) {
+        let mut insts = vec![];
+        for &bb in &self.func.basic_blocks.order {
+            if l.contains(bb) {
+                for val in &*self.func.basic_blocks.arena[bb].iseq_ref() {
+                    insts.push(val.as_instruction().id)
+                }
+            }
+        }
+
+        for id in insts {
+            // Uses in phis count as uses at the end of the incoming block
+            let users = self.func.inst_table[id].users.borrow().clone();
+            let mut outside = vec![];
+            for user in users {
+                let inst = &self.func.inst_table[user];
+                if inst.opcode == Opcode::Phi {
+                    if inst
+                        .operands
+                        .chunks(2)
+                        .any(|pair| is_inst(&pair[0], id) && !l.contains(*pair[1].as_basic_block()))
+                    {
+                        outside.push(user)
+                    }
+                } else if !l.contains(inst.parent) {
+                    outside.push(user)
+                }
+            }
+            if outside.len() == 0 {
+                continue;
+            }
+
+            let mut values = FxHashMap::default();
+            for user in outside {
+                let inst = &self.func.inst_table[user];
+                if inst.opcode != Opcode::Phi {
+                    let val = self.value_in(l, id, inst.parent, &mut values);
+                    Instruction::replace_operand_inst(
+                        &mut self.func.inst_table,
+                        user,
+                        id,
+                        Operand::Value(val),
+                    );
+                    continue;
+                }
+
+                let (ty, parent) = (inst.ty, inst.parent);
+                let mut operands = inst.operands.clone();
+                for pair in operands.chunks_mut(2) {
+                    let pred = *pair[1].as_basic_block();
+                    if is_inst(&pair[0], id) && !l.contains(pred) {
+                        pair[0] = Operand::Value(self.value_in(l, id, pred, &mut values));
+                    }
+                }
+                self.func
+                    .change_inst(user, Instruction::new(Opcode::Phi, operands, ty, parent));
+            }
+        }
+    }
+
+    /// Returns the value of `id` at `bb`, outside the loop. Phis are inserted in the exit blocks
+    /// on the way, and where the values from several predecessors are different.
+    fn value_in(
+        &mut self,
+        l: &Loop<BasicBlock>,
+        id: InstructionId,
+        bb: BasicBlockId,
+        values: &mut FxHashMap<BasicBlockId, Value>,
+    ) -> Value {
+        if let Some(val) = values.get(&bb) {
+            return *val;
+        }
+        if l.contains(bb) {
+            return self.func.inst_value(id);
+        }
+
+        let preds: Vec<BasicBlockId> = self.func.basic_blocks.arena[bb]
+            .pred
+            .iter()
+            .copied()
+            .collect();
+        let is_exit = preds.iter().any(|&p| l.contains(p));
+        if preds.len() == 1 && !is_exit {
+            let val = self.value_in(l, id, preds[0], values);
+            values.insert(bb, val);
+            return val;
+        }
+
+        // Register the phi before visiting the predecessors, which may be reached from `bb`
+        let ty = self.func.inst_table[id].ty;
+        let phi = self
+            .func
+            .alloc_inst(Instruction::new(Opcode::Phi, vec![], ty, bb));
+        let phi_val = self.func.inst_value(phi);
+        self.func.basic_blocks.arena[bb]
+            .iseq_ref_mut()
+            .insert(0, phi_val);
+        values.insert(bb, phi_val);
+
+        let mut operands = vec![];
+        for &pred in &preds {
+            operands.push(Operand::Value(self.value_in(l, id, pred, values)));
+            operands.push(Operand::BasicBlock(pred));
+        }
+
+        // A phi merging a single value other than itself isn't needed, except in an exit block
+        let mut incoming = operands
+            .chunks(2)
+            .map(|pair| pair[0])
+            .filter(|&op| op != Operand::Value(phi_val));
+        let first = incoming.next().unwrap();
+        if !is_exit && incoming.all(|op| op == first) {
+            let val = *first.as_value();
+            Instruction::replace_all_uses(&mut self.func.inst_table, phi, first);
+            self.func.remove_inst(phi);
+            for v in values.values_mut() {
+                if *v == phi_val {
+                    *v = val
+                }
+            }
+            return val;
+        }
+
+        self.func
+            .change_inst(phi, Instruction::new(Opcode::Phi, operands, ty, bb));
+        self.inserted += 1;
+        phi_val
+    }
+}
+
+fn is_inst(op: &Operand, id: InstructionId) -> bool {
+    match op {
+        Operand::Value(Value::Instruction(InstructionValue { id: i, .. })) => *i == id,
+        _ => false,
+    }
+}
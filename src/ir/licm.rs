@@ -0,0 +1,266 @@
+use crate::analysis::{
+    alias_analysis::{AliasAnalysis, MemoryLocation},
+    dom_tree::{DominatorTree, DominatorTreeConstructor},
+    loops::{Loop, LoopsConstructor},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    loop_simplify::LoopSimplify,
+    module::Module,
+    opcode::{InstructionId, Opcode},
+    types::{Type, Types},
+    value::{ImmediateValue, InstructionValue, Value},
+};
+use rustc_hash::FxHashSet;
+
+/// Loop-invariant code motion. Loops are put into the canonical form by `LoopSimplify` first,
+/// which gives them a preheader, the single block jumping into the header from outside.
+/// Instructions whose operands are defined outside the loop are hoisted into the preheader if
+/// executing them speculatively is harmless, and stores to an invariant address nothing else
+/// in the loop accesses are sunk into the loop's exit block.
+pub struct LoopInvariantCodeMotion {}
+
+struct LoopInvariantCodeMotionOnFunction<'a> {
+    func: &'a mut Function,
+    types: &'a Types,
+    aa: AliasAnalysis<'a>,
+    hoisted: usize,
+    sunk: usize,
+}
+
+impl LoopInvariantCodeMotion {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        LoopSimplify::new().run_on_module(module);
+
+        let Module {
+            functions,
+            types,
+            inline_asms,
+            ..
+        } = module;
+        for (_, func) in functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            let aa = AliasAnalysis::new(func, types, inline_asms);
+            LoopInvariantCodeMotionOnFunction {
+                func,
+                types,
+                aa,
+                hoisted: 0,
+                sunk: 0,
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> LoopInvariantCodeMotionOnFunction<'a> {
+    pub fn run(mut self) {
+        let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+        let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+
+        // Inner loops first, so that what is hoisted into their preheaders can move further out
+        for l in loops.post_order() {
+            self.run_on_loop(&dom_tree, &loops.arena[l]);
+        }
+
+        debug!(println!(
+            "function '{}': {} insts hoisted, {} stores sunk",
+            self.func.name, self.hoisted, self.sunk
+        ));
+    }
+
+    fn run_on_loop(&mut self, dom_tree: &DominatorTree<BasicBlock>, l: &Loop<BasicBlock>) {
+        let preheader = match l.preheader(&self.func.basic_blocks) {
+            Some(preheader) => preheader,
+            None => return,
+        };
+        // A block dominating every exit runs before the loop is left, which proves nothing when
+        // the loop has no exits
+        let exiting = l.exiting_blocks(&self.func.basic_blocks);
+        let guaranteed_to_execute = |bb: BasicBlockId| {
+            exiting.len() > 0 && exiting.iter().all(|&e| dom_tree.dominate_bb(bb, e))
+        };
+
+        // Visit blocks in dominator tree order so that operands are hoisted before their users
+        let mut blocks = vec![];
+        let mut worklist = vec![l.header()];
+        while let Some(bb) = worklist.pop() {
+            blocks.push(bb);
+            for &child in dom_tree.tree.get(&bb).unwrap_or(&FxHashSet::default()) {
+                if l.contains(child) {
+                    worklist.push(child)
+                }
+            }
+        }
+
+        for &bb in &blocks {
+            let iseq: Vec<InstructionId> = self.func.basic_blocks.arena[bb]
+                .iseq_ref()
+                .iter()
+                .map(|v| v.as_instruction().id)
+                .collect();
+            for id in iseq {
+                if self.is_invariant(l, id)
+                    && self.is_safe_to_hoist(l, id, guaranteed_to_execute(bb))
+                {
+                    self.hoist(id, preheader);
+                }
+            }
+        }
+
+        self.sink_stores(l, &blocks, &guaranteed_to_execute);
+    }
+
+    /// Moves stores to the exit block. A store can be sunk if the loop has a single exit, the
+    /// store is executed in every iteration reaching it, and no other instruction in the loop
+    /// accesses the stored location: then only the value stored in the last iteration matters.
+    fn sink_stores<F: Fn(BasicBlockId) -> bool>(
+        &mut self,
+        l: &Loop<BasicBlock>,
+        blocks: &[BasicBlockId],
+        guaranteed_to_execute: &F,
+    ) {
+        let exit = match l.exit_blocks(&self.func.basic_blocks).as_slice() {
+            &[exit] if l.has_dedicated_exits(&self.func.basic_blocks) => exit,
+            _ => return,
+        };
+
+        let mem_insts = |func: &Function| -> Vec<InstructionId> {
+            blocks
+                .iter()
+                .flat_map(|&bb| {
+                    func.basic_blocks.arena[bb]
+                        .iseq_ref()
+                        .iter()
+                        .map(|v| v.as_instruction().id)
+                        .filter(|&id| {
+                            matches!(
+                                func.inst_table[id].opcode,
+                                Opcode::Load | Opcode::Store | Opcode::Call
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+
+        for id in mem_insts(self.func) {
+            let inst = &self.func.inst_table[id];
+            if inst.opcode != Opcode::Store
+                || inst.is_volatile()
+                || !guaranteed_to_execute(inst.parent)
+                || !self.is_invariant_value(l, inst.operands[1].as_value())
+            {
+                continue;
+            }
+            let loc = MemoryLocation::of(inst, self.types).unwrap();
+            let accessed = mem_insts(self.func).into_iter().any(|other| {
+                let other = &self.func.inst_table[other];
+                other.id != Some(id)
+                    && (self.aa.may_modify(self.func, other, &loc)
+                        || self.aa.may_read(self.func, other, &loc))
+            });
+            if accessed {
+                continue;
+            }
+
+            let (bb, pos) = self.func.find_inst_pos(id).unwrap();
+            let val = self.func.basic_blocks.arena[bb].iseq_ref_mut().remove(pos);
+            let insert_pt = self.first_non_phi(exit);
+            self.func.basic_blocks.arena[exit]
+                .iseq_ref_mut()
+                .insert(insert_pt, val);
+            self.func.inst_table[id].parent = exit;
+            self.sunk += 1;
+        }
+    }
+
+    fn is_invariant(&self, l: &Loop<BasicBlock>, id: InstructionId) -> bool {
+        self.func.inst_table[id].operands.iter().all(|op| {
+            op.get_value()
+                .map_or(true, |v| self.is_invariant_value(l, v))
+        })
+    }
+
+    fn is_invariant_value(&self, l: &Loop<BasicBlock>, v: &Value) -> bool {
+        match v {
+            Value::Instruction(InstructionValue { id, .. }) => {
+                !l.contains(self.func.inst_table[*id].parent)
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns true if executing `id` in the preheader, possibly when the loop body would never
+    /// have executed it, computes the same value and can't fault
+    fn is_safe_to_hoist(
+        &self,
+        l: &Loop<BasicBlock>,
+        id: InstructionId,
+        guaranteed_to_execute: bool,
+    ) -> bool {
+        let inst = &self.func.inst_table[id];
+        match inst.opcode {
+            Opcode::GetElementPtr
+            | Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Shl
+            | Opcode::SIToFP
+            | Opcode::FPToSI
+            | Opcode::ICmp
+            | Opcode::FCmp
+            | Opcode::ExtractElement
+            | Opcode::InsertElement
+            | Opcode::ShuffleVector
+            | Opcode::Select => true,
+            // Integer division traps on a zero divisor and on overflow
+            Opcode::Div | Opcode::Rem => {
+                inst.ty == Type::F64
+                    || guaranteed_to_execute
+                    || match inst.operands[1].as_value() {
+                        Value::Immediate(ImmediateValue::Int32(i)) => *i != 0 && *i != -1,
+                        Value::Immediate(ImmediateValue::Int64(i)) => *i != 0 && *i != -1,
+                        _ => false,
+                    }
+            }
+            Opcode::Load if !inst.is_volatile() => {
+                let loc = MemoryLocation::of(inst, self.types).unwrap();
+                let clobbered = l.blocks().iter().any(|&bb| {
+                    self.func.basic_blocks.arena[bb].iseq_ref().iter().any(|v| {
+                        let other = &self.func.inst_table[v.as_instruction().id];
+                        self.aa.may_modify(self.func, other, &loc)
+                    })
+                });
+                !clobbered && (guaranteed_to_execute || self.aa.is_dereferenceable(self.func, &loc))
+            }
+            _ => false,
+        }
+    }
+
+    fn hoist(&mut self, id: InstructionId, preheader: BasicBlockId) {
+        let (bb, pos) = self.func.find_inst_pos(id).unwrap();
+        let val = self.func.basic_blocks.arena[bb].iseq_ref_mut().remove(pos);
+        let mut iseq = self.func.basic_blocks.arena[preheader].iseq_ref_mut();
+        let terminator = iseq.len() - 1;
+        iseq.insert(terminator, val);
+        self.func.inst_table[id].parent = preheader;
+        self.hoisted += 1;
+    }
+
+    fn first_non_phi(&self, bb: BasicBlockId) -> usize {
+        self.func.basic_blocks.arena[bb]
+            .iseq_ref()
+            .iter()
+            .position(|v| self.func.inst_table[v.as_instruction().id].opcode != Opcode::Phi)
+            .unwrap()
+    }
+}
@@ -83,6 +83,12 @@ impl<'a> IRLivenessAnalyzer<'a> {
             let mut bb_liveness = bb.liveness.borrow_mut();
 
             if bb_liveness.def.contains(&inst_id) {
+                drop(bb_liveness);
+                // A phi may take a value defined in its own block from a back edge, which
+                // makes the value live out of the incoming block all the same
+                if let Some(phi_incoming) = phi_incoming {
+                    self.propagate_if_necessary(f, phi_incoming, inst_id);
+                }
                 return;
             }
 
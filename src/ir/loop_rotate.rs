@@ -0,0 +1,363 @@
+use crate::analysis::{
+    dom_tree::DominatorTreeConstructor,
+    loops::{Loop, LoopsConstructor},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    inline_asm::InlineAsms,
+    module::Module,
+    opcode::{ICmpKind, Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Headers with more instructions than this are not duplicated
+const MAX_HEADER_SIZE: usize = 8;
+
+/// Loop rotation. A loop testing its condition in the header, as a `while` statement produces,
+/// is turned into a `do-while` loop guarded by a copy of the test in the preheader. The header
+/// becomes the latch, so the exit test is done at the end of each iteration, and the block
+/// following it becomes the header. Phis in the new header and in the exit block merge the
+/// values computed by the guard with those computed by the old header.
+pub struct LoopRotate {}
+
+struct LoopRotateOnFunction<'a> {
+    func: &'a mut Function,
+    inline_asms: &'a InlineAsms,
+    rotated: FxHashSet<BasicBlockId>,
+}
+
+struct RotatableLoop {
+    preheader: BasicBlockId,
+    header: BasicBlockId,
+    latch: BasicBlockId,
+    body: BasicBlockId,
+    exit: BasicBlockId,
+    blocks: FxHashSet<BasicBlockId>,
+}
+
+impl LoopRotate {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let Module {
+            functions,
+            inline_asms,
+            ..
+        } = module;
+        for (_, func) in functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            LoopRotateOnFunction {
+                func,
+                inline_asms,
+                rotated: FxHashSet::default(),
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> LoopRotateOnFunction<'a> {
+    fn run(mut self) {
+        // Loops are analyzed again after each rotation since the blocks of enclosing loops
+        // change
+        loop {
+            let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+            let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+            let l = loops
+                .arena
+                .iter()
+                .filter(|(_, l)| !self.rotated.contains(&l.header()))
+                .find_map(|(_, l)| self.analyze(l));
+            match l {
+                Some(l) => {
+                    self.rotated.insert(l.header);
+                    self.rotate(l)
+                }
+                None => break,
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} loops rotated",
+            self.func.name,
+            self.rotated.len()
+        ));
+    }
+
+    fn analyze(&self, l: &Loop<BasicBlock>) -> Option<RotatableLoop> {
+        let bbs = &self.func.basic_blocks;
+        let arena = &bbs.arena;
+        let header = l.header();
+        let (preheader, latch) = match (l.preheader(bbs), l.latch(bbs)) {
+            (Some(preheader), Some(latch)) if latch != header => (preheader, latch),
+            _ => return None,
+        };
+        if self.func.inst_table[self.func.terminator(latch)].opcode != Opcode::Br {
+            return None;
+        }
+
+        // Only the header may leave the loop
+        if l.exiting_blocks(bbs).iter().any(|&bb| bb != header) {
+            return None;
+        }
+        let br = &self.func.inst_table[self.func.terminator(header)];
+        if br.opcode != Opcode::CondBr {
+            return None;
+        }
+        let (body, exit) = match (
+            br.operands[1].as_basic_block(),
+            br.operands[2].as_basic_block(),
+        ) {
+            (&x, &y) if l.contains(x) && !l.contains(y) => (x, y),
+            (&x, &y) if !l.contains(x) && l.contains(y) => (y, x),
+            _ => return None,
+        };
+        if arena[body].pred.len() != 1 || arena[exit].pred.len() != 1 {
+            return None;
+        }
+
+        // A value carried around the loop must be computed after the header, which is where it's
+        // used from now on
+        let carried_from_header = arena[header].iseq_ref().iter().any(|v| {
+            let inst = &self.func.inst_table[v.as_instruction().id];
+            inst.opcode == Opcode::Phi
+                && match self.func.phi_incoming(inst.id.unwrap(), latch).unwrap() {
+                    Value::Instruction(InstructionValue { id, .. }) => {
+                        self.func.inst_table[id].parent == header
+                    }
+                    _ => false,
+                }
+        });
+        if carried_from_header {
+            return None;
+        }
+
+        let size = arena[header]
+            .iseq_ref()
+            .iter()
+            .filter(|v| self.func.inst_table[v.as_instruction().id].opcode != Opcode::Phi)
+            .count();
+        if size > MAX_HEADER_SIZE + 1 {
+            return None;
+        }
+
+        Some(RotatableLoop {
+            preheader,
+            header,
+            latch,
+            body,
+            exit,
+            blocks: l.blocks().clone(),
+        })
+    }
+
+    fn rotate(&mut self, l: RotatableLoop) {
+        let header = self.func.insts_of(l.header);
+        let (phis, insts): (Vec<InstructionId>, Vec<InstructionId>) = header[..header.len() - 1]
+            .iter()
+            .partition(|&&id| self.func.inst_table[id].opcode == Opcode::Phi);
+
+        // The guard: the header as executed when coming from the preheader
+        let mut values: FxHashMap<InstructionId, Value> = phis
+            .iter()
+            .map(|&phi| (phi, self.func.phi_incoming(phi, l.preheader).unwrap()))
+            .collect();
+        let mut guard = vec![];
+        for &id in &insts {
+            let inst = &self.func.inst_table[id];
+            let operands = inst
+                .operands
+                .iter()
+                .map(|op| match op {
+                    Operand::Value(v) => Operand::Value(v.remap(&values)),
+                    op => *op,
+                })
+                .collect();
+            let new = Instruction::new(inst.opcode, operands, inst.ty, l.preheader)
+                .with_mem_attr(inst.mem_attr);
+            if let Some(konst) = new.fold_const() {
+                values.insert(id, konst);
+                continue;
+            }
+            let new = self.func.alloc_inst(new);
+            let val = self.func.inst_value(new);
+            let mut iseq = self.func.basic_blocks.arena[l.preheader].iseq_ref_mut();
+            let pos = iseq.len() - 1;
+            iseq.insert(pos, val);
+            values.insert(id, val);
+            guard.push(new);
+        }
+
+        let br = &self.func.inst_table[self.func.terminator(l.header)];
+        let cond = br.operands[0].as_value().remap(&values);
+        let (on_true, on_false) = (br.operands[1], br.operands[2]);
+        let enters = match self.eval(&cond) {
+            Some(taken) => {
+                (taken && on_true == Operand::BasicBlock(l.body))
+                    || (!taken && on_false == Operand::BasicBlock(l.body))
+            }
+            None => {
+                let guard_br = Instruction::new(
+                    Opcode::CondBr,
+                    vec![Operand::Value(cond), on_true, on_false],
+                    Type::Void,
+                    l.preheader,
+                );
+                self.func
+                    .change_inst(self.func.terminator(l.preheader), guard_br);
+                self.merge_into(&l, &phis, &insts, &values, l.exit);
+                self.merge_into(&l, &phis, &insts, &values, l.body);
+                self.finish(&l, &phis, &guard);
+                return;
+            }
+        };
+
+        // The guard is always true: the preheader jumps into the body. The loop is never entered
+        // if it's always false, in which case the preheader jumps to the exit.
+        let dst = if enters { l.body } else { l.exit };
+        let br = Instruction::new(
+            Opcode::Br,
+            vec![Operand::BasicBlock(dst)],
+            Type::Void,
+            l.preheader,
+        );
+        self.func.change_inst(self.func.terminator(l.preheader), br);
+        self.merge_into(&l, &phis, &insts, &values, dst);
+        self.finish(&l, &phis, &guard);
+    }
+
+    /// Makes the values of the header available in `bb`, which is entered from the preheader
+    /// and the header from now on. Phis already in `bb` get the values of the guard, and the
+    /// uses of a header value dominated by `bb` are replaced with a new phi.
+    fn merge_into(
+        &mut self,
+        l: &RotatableLoop,
+        phis: &[InstructionId],
+        insts: &[InstructionId],
+        values: &FxHashMap<InstructionId, Value>,
+        bb: BasicBlockId,
+    ) {
+        for id in self.func.insts_of(bb) {
+            if self.func.inst_table[id].opcode != Opcode::Phi {
+                continue;
+            }
+            let val = self.func.phi_incoming(id, l.header).unwrap().remap(values);
+            Instruction::add_operand(&mut self.func.inst_table, id, Operand::Value(val));
+            Instruction::add_operand(
+                &mut self.func.inst_table,
+                id,
+                Operand::BasicBlock(l.preheader),
+            );
+        }
+
+        let in_loop = bb == l.body;
+        for &id in phis.iter().chain(insts.iter()) {
+            let users: Vec<InstructionId> = self.func.inst_table[id]
+                .users
+                .borrow()
+                .iter()
+                .copied()
+                .filter(|&u| {
+                    let user = &self.func.inst_table[u];
+                    let parent = user.parent;
+                    parent != l.header
+                        && l.blocks.contains(&parent) == in_loop
+                        && !(parent == bb && user.opcode == Opcode::Phi)
+                })
+                .collect();
+            if users.is_empty() {
+                continue;
+            }
+            let ty = self.func.inst_table[id].ty;
+            let phi = self.func.alloc_inst(Instruction::new(
+                Opcode::Phi,
+                vec![
+                    Operand::Value(values[&id]),
+                    Operand::BasicBlock(l.preheader),
+                    Operand::Value(self.func.inst_value(id)),
+                    Operand::BasicBlock(l.header),
+                ],
+                ty,
+                bb,
+            ));
+            let phi = self.func.inst_value(phi);
+            self.func.basic_blocks.arena[bb]
+                .iseq_ref_mut()
+                .insert(0, phi);
+            let from = Operand::Value(self.func.inst_value(id));
+            for user in users {
+                Instruction::replace_operand(
+                    &mut self.func.inst_table,
+                    user,
+                    &from,
+                    Operand::Value(phi),
+                );
+            }
+        }
+    }
+
+    /// The header is entered only from the latch now, so its phis are replaced with the values
+    /// coming from there
+    fn finish(&mut self, l: &RotatableLoop, phis: &[InstructionId], guard: &[InstructionId]) {
+        for &phi in phis {
+            let init = self.func.phi_incoming(phi, l.preheader).unwrap();
+            let next = self.func.phi_incoming(phi, l.latch).unwrap();
+            let to = if next == self.func.inst_value(phi) {
+                init
+            } else {
+                next
+            };
+            Instruction::replace_all_uses(&mut self.func.inst_table, phi, Operand::Value(to));
+            self.func.remove_inst(phi);
+        }
+
+        // What the guard computes for nothing
+        for &id in guard.iter().rev() {
+            let inst = &self.func.inst_table[id];
+            if inst.users.borrow().is_empty() && !inst.has_side_effects(self.inline_asms) {
+                self.func.remove_inst(id);
+            }
+        }
+
+        // The old header is laid out after the latch, where it is executed now
+        let order = &mut self.func.basic_blocks.order;
+        order.retain(|&bb| bb != l.header);
+        let pos = order.iter().position(|&bb| bb == l.latch).unwrap();
+        order.insert(pos + 1, l.header);
+
+        self.func.recompute_cfg();
+        self.func.remove_unreachable_blocks();
+    }
+
+    /// Evaluates a comparison of constants
+    fn eval(&self, cond: &Value) -> Option<bool> {
+        let id = match cond {
+            Value::Instruction(InstructionValue { id, .. }) => *id,
+            _ => return None,
+        };
+        let inst = &self.func.inst_table[id];
+        if inst.opcode != Opcode::ICmp {
+            return None;
+        }
+        let (x, y) = match (inst.operands[1].as_value(), inst.operands[2].as_value()) {
+            (
+                Value::Immediate(ImmediateValue::Int32(x)),
+                Value::Immediate(ImmediateValue::Int32(y)),
+            ) => (*x, *y),
+            _ => return None,
+        };
+        Some(match inst.operands[0].as_icmp_kind() {
+            ICmpKind::Eq => x == y,
+            ICmpKind::Le => x <= y,
+            ICmpKind::Lt => x < y,
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use crate::analysis::{
+    dom_tree::DominatorTreeConstructor,
+    loops::{Loop, LoopsConstructor},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+};
+
+/// Loop simplification. Puts every loop into the canonical form the loop passes rely on: the
+/// loop has a preheader, the single block outside the loop jumping into the header; a single
+/// latch, the only block in the loop jumping back to the header; and dedicated exits, blocks
+/// outside the loop reached only from the loop. New blocks are inserted between a block and
+/// some of its predecessors, and phis merge the incoming values from those predecessors.
+pub struct LoopSimplify {}
+
+struct LoopSimplifyOnFunction<'a> {
+    func: &'a mut Function,
+    inserted: usize,
+}
+
+impl LoopSimplify {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            LoopSimplifyOnFunction { func, inserted: 0 }.run()
+        }
+    }
+}
+
+impl<'a> LoopSimplifyOnFunction<'a> {
+    fn run(mut self) {
+        // Inserting a block changes the loops, so they're analyzed again after every change
+        loop {
+            let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+            let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+            let mut changed = false;
+            for (_, l) in &loops.arena {
+                if self.simplify(l) {
+                    changed = true;
+                    break;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} blocks inserted",
+            self.func.name, self.inserted
+        ));
+    }
+
+    /// Inserts at most one block to bring `l` closer to the canonical form
+    fn simplify(&mut self, l: &Loop<BasicBlock>) -> bool {
+        let header = l.header();
+
+        if l.preheader(&self.func.basic_blocks).is_none() {
+            let outside: Vec<BasicBlockId> = self.func.basic_blocks.arena[header]
+                .pred
+                .iter()
+                .copied()
+                .filter(|&p| !l.contains(p))
+                .collect();
+            // The entry block has no predecessors to give it a preheader
+            if outside.len() > 0 {
+                self.split_predecessors(header, &outside, self.position(header));
+                return true;
+            }
+        }
+
+        let latches = l.latches(&self.func.basic_blocks);
+        if latches.len() > 1 {
+            let pos = latches.iter().map(|&bb| self.position(bb)).max().unwrap() + 1;
+            self.split_predecessors(header, &latches, pos);
+            return true;
+        }
+
+        for exit in l.exit_blocks(&self.func.basic_blocks) {
+            let (inside, outside): (Vec<BasicBlockId>, Vec<BasicBlockId>) =
+                self.func.basic_blocks.arena[exit]
+                    .pred
+                    .iter()
+                    .partition(|&&p| l.contains(p));
+            if outside.len() > 0 {
+                let pos = self.position(exit);
+                self.split_predecessors(exit, &inside, pos);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Inserts a block at `pos` in the layout, between `bb` and `preds`. Incoming values of the
+    /// phis in `bb` from `preds` are merged by phis in the new block.
+    fn split_predecessors(&mut self, bb: BasicBlockId, preds: &[BasicBlockId], pos: usize) {
+        let new = self.func.append_basic_block();
+        let order = &mut self.func.basic_blocks.order;
+        order.pop();
+        order.insert(pos, new);
+
+        for &pred in preds {
+            let terminator = self.func.terminator(pred);
+            Instruction::replace_operand(
+                &mut self.func.inst_table,
+                terminator,
+                &Operand::BasicBlock(bb),
+                Operand::BasicBlock(new),
+            );
+            let arena = &mut self.func.basic_blocks.arena;
+            arena[pred].succ.remove(&bb);
+            arena[pred].succ.insert(new);
+            arena[new].pred.insert(pred);
+            arena[bb].pred.remove(&pred);
+        }
+        let arena = &mut self.func.basic_blocks.arena;
+        arena[new].succ.insert(bb);
+        arena[bb].pred.insert(new);
+
+        let phis: Vec<InstructionId> = self.func.basic_blocks.arena[bb]
+            .iseq_ref()
+            .iter()
+            .map(|v| v.as_instruction().id)
+            .filter(|&id| self.func.inst_table[id].opcode == Opcode::Phi)
+            .collect();
+        for phi in phis {
+            let ty = self.func.inst_table[phi].ty;
+            let (moved, kept): (Vec<&[Operand]>, Vec<&[Operand]>) = self.func.inst_table[phi]
+                .operands
+                .chunks(2)
+                .partition(|pair| preds.contains(pair[1].as_basic_block()));
+            let (moved, mut operands) = (moved.concat(), kept.concat());
+            if moved.len() == 0 {
+                continue;
+            }
+            let incoming = if moved.chunks(2).all(|pair| pair[0] == moved[0]) {
+                moved[0]
+            } else {
+                let id = self
+                    .func
+                    .alloc_inst(Instruction::new(Opcode::Phi, moved, ty, new));
+                let val = self.func.inst_value(id);
+                self.func.basic_blocks.arena[new].iseq_ref_mut().push(val);
+                Operand::Value(val)
+            };
+            operands.push(incoming);
+            operands.push(Operand::BasicBlock(new));
+            self.func
+                .change_inst(phi, Instruction::new(Opcode::Phi, operands, ty, bb));
+        }
+
+        let id = self.func.alloc_inst(Instruction::new(
+            Opcode::Br,
+            vec![Operand::BasicBlock(bb)],
+            Type::Void,
+            new,
+        ));
+        let val = self.func.inst_value(id);
+        self.func.basic_blocks.arena[new].iseq_ref_mut().push(val);
+        self.inserted += 1;
+    }
+
+    fn position(&self, bb: BasicBlockId) -> usize {
+        let order = &self.func.basic_blocks.order;
+        order.iter().position(|&b| b == bb).unwrap()
+    }
+}
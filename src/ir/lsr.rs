@@ -0,0 +1,263 @@
+use crate::analysis::{
+    dom_tree::DominatorTreeConstructor,
+    loops::{Loop, Loops, LoopsConstructor},
+    scalar_evolution::{ScalarEvolution, SCEV},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+
+/// Loop strength reduction. In an innermost loop with a preheader and a single latch, a
+/// `GetElementPtr` whose last index is an affine recurrence `{start,+,step}` of the loop, the
+/// other operands being invariant, is replaced by a pointer starting from the address of the
+/// `start`th element and advanced by `step` elements at the end of each iteration. Likewise, a
+/// `Mul` or `Shl` computing a recurrence becomes a phi incremented by a constant. Either way no
+/// multiplication is left in the loop.
+pub struct LoopStrengthReduction {}
+
+struct LoopStrengthReductionOnFunction<'a> {
+    func: &'a mut Function,
+}
+
+struct Candidate {
+    inst: InstructionId,
+    preheader: BasicBlockId,
+    header: BasicBlockId,
+    latch: BasicBlockId,
+    /// The first value of `inst`, computable in the preheader
+    start: SCEV,
+    step: i64,
+}
+
+impl LoopStrengthReduction {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            LoopStrengthReductionOnFunction { func }.run()
+        }
+    }
+}
+
+impl<'a> LoopStrengthReductionOnFunction<'a> {
+    fn run(mut self) {
+        let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+        let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+
+        let mut candidates = vec![];
+        let mut se = ScalarEvolution::new(self.func, &loops);
+        for (loop_id, l) in &loops.arena {
+            if l.sub_loops().len() > 0 {
+                continue;
+            }
+            let bbs = &self.func.basic_blocks;
+            let (preheader, latch) = match (l.preheader(bbs), l.latch(bbs)) {
+                (Some(preheader), Some(latch)) => (preheader, latch),
+                _ => continue,
+            };
+
+            for &bb in l.blocks() {
+                for val in &*self.func.basic_blocks.arena[bb].iseq_ref() {
+                    let id = val.as_instruction().id;
+                    let inst = &self.func.inst_table[id];
+                    let scev = match inst.opcode {
+                        Opcode::GetElementPtr => {
+                            let (last, rest) = inst.operands.split_last().unwrap();
+                            if !rest.iter().all(|op| is_invariant(self.func, l, op)) {
+                                continue;
+                            }
+                            se.get(last.as_value())
+                        }
+                        Opcode::Mul | Opcode::Shl => se.get(val),
+                        _ => continue,
+                    };
+                    let rec = match scev {
+                        SCEV::AddRec(rec) if rec.loop_id == loop_id && rec.step != 0 => rec,
+                        _ => continue,
+                    };
+                    if let Some(start) = expandable(self.func, &loops, &mut se, l, &rec.start) {
+                        candidates.push(Candidate {
+                            inst: id,
+                            preheader,
+                            header: l.header(),
+                            latch,
+                            start,
+                            step: rec.step,
+                        })
+                    }
+                }
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} multiplications strength-reduced",
+            self.func.name,
+            candidates.len()
+        ));
+
+        // A multiplication computing an index may be left unused once the address is reduced
+        candidates.sort_by_key(|c| self.func.inst_table[c.inst].opcode != Opcode::GetElementPtr);
+        for c in candidates {
+            if self.func.inst_table[c.inst].users.borrow().is_empty() {
+                self.func.remove_inst(c.inst);
+                continue;
+            }
+            self.reduce(c)
+        }
+    }
+
+    fn reduce(&mut self, c: Candidate) {
+        let inst = &self.func.inst_table[c.inst];
+        let (opcode, ty) = (inst.opcode, inst.ty);
+
+        let (start, next) = if opcode == Opcode::GetElementPtr {
+            // gep base, idx.., start
+            let mut operands = inst.operands.clone();
+            let last = operands.pop().unwrap();
+            let idx_ty = last.as_value().get_type();
+            let start = self.expand(&c.start, idx_ty, c.preheader);
+            operands.push(Operand::Value(start));
+            let start = self.insert(c.preheader, Opcode::GetElementPtr, operands, ty);
+            (start, (Opcode::GetElementPtr, imm(c.step, idx_ty)))
+        } else {
+            let start = self.expand(&c.start, ty, c.preheader);
+            (start, (Opcode::Add, imm(c.step, ty)))
+        };
+
+        let phi = self.func.alloc_inst(Instruction::new(
+            Opcode::Phi,
+            vec![Operand::Value(start), Operand::BasicBlock(c.preheader)],
+            ty,
+            c.header,
+        ));
+        let phi_val = self.func.inst_value(phi);
+        self.func.basic_blocks.arena[c.header]
+            .iseq_ref_mut()
+            .insert(0, phi_val);
+
+        let (opcode, step) = next;
+        let next = self.insert(
+            c.latch,
+            opcode,
+            vec![Operand::Value(phi_val), Operand::Value(step)],
+            ty,
+        );
+        Instruction::add_operand(&mut self.func.inst_table, phi, Operand::Value(next));
+        Instruction::add_operand(&mut self.func.inst_table, phi, Operand::BasicBlock(c.latch));
+
+        Instruction::replace_all_uses(&mut self.func.inst_table, c.inst, Operand::Value(phi_val));
+        self.func.remove_inst(c.inst);
+    }
+
+    /// Computes `scev` at the end of `bb`
+    fn expand(&mut self, scev: &SCEV, ty: Type, bb: BasicBlockId) -> Value {
+        match scev {
+            SCEV::Constant(c) => imm(*c, ty),
+            SCEV::Unknown(val) => *val,
+            SCEV::Add(x, y) => {
+                let x = self.expand(x, ty, bb);
+                let y = self.expand(y, ty, bb);
+                let operands = vec![Operand::Value(x), Operand::Value(y)];
+                self.insert(bb, Opcode::Add, operands, ty)
+            }
+            SCEV::Mul(x, c) => {
+                let x = self.expand(x, ty, bb);
+                let operands = vec![Operand::Value(x), Operand::Value(imm(*c, ty))];
+                self.insert(bb, Opcode::Mul, operands, ty)
+            }
+            SCEV::AddRec(_) => unreachable!(),
+        }
+    }
+
+    /// Inserts an instruction before the terminator of `bb`
+    fn insert(
+        &mut self,
+        bb: BasicBlockId,
+        opcode: Opcode,
+        operands: Vec<Operand>,
+        ty: Type,
+    ) -> Value {
+        let id = self
+            .func
+            .alloc_inst(Instruction::new(opcode, operands, ty, bb));
+        let val = self.func.inst_value(id);
+        let iseq = &mut self.func.basic_blocks.arena[bb].iseq_ref_mut();
+        let pos = iseq.len() - 1;
+        iseq.insert(pos, val);
+        val
+    }
+}
+
+fn is_invariant(func: &Function, l: &Loop<BasicBlock>, op: &Operand) -> bool {
+    match op {
+        Operand::Value(Value::Instruction(InstructionValue { id, .. })) => {
+            !l.contains(func.inst_table[*id].parent)
+        }
+        _ => true,
+    }
+}
+
+/// Returns `scev` in a form computable in the preheader of the loop. The recurrence of an
+/// enclosing loop is replaced with the header phi of that loop computing it, plus a constant.
+fn expandable(
+    func: &Function,
+    loops: &Loops<BasicBlock>,
+    se: &mut ScalarEvolution,
+    l: &Loop<BasicBlock>,
+    scev: &SCEV,
+) -> Option<SCEV> {
+    match scev {
+        SCEV::Constant(_) => Some(scev.clone()),
+        SCEV::Unknown(val) if is_invariant(func, l, &Operand::Value(*val)) => Some(scev.clone()),
+        SCEV::Unknown(_) => None,
+        SCEV::Add(x, y) => {
+            let x = expandable(func, loops, se, l, x)?;
+            let y = expandable(func, loops, se, l, y)?;
+            Some(se.add(x, y))
+        }
+        SCEV::Mul(x, c) => {
+            let x = expandable(func, loops, se, l, x)?;
+            Some(se.mul(x, *c))
+        }
+        SCEV::AddRec(rec) => {
+            let outer = &loops.arena[rec.loop_id];
+            if !outer.contains(l.header()) {
+                return None;
+            }
+            let header = &func.basic_blocks.arena[outer.header()];
+            for val in &*header.iseq_ref() {
+                if func.inst_table[val.as_instruction().id].opcode != Opcode::Phi {
+                    continue;
+                }
+                if let SCEV::AddRec(phi) = se.get(val) {
+                    if phi.loop_id != rec.loop_id || phi.step != rec.step {
+                        continue;
+                    }
+                    let start = se.mul(*phi.start, -1);
+                    if let SCEV::Constant(c) = se.add(*rec.start.clone(), start) {
+                        return Some(se.add(SCEV::Unknown(*val), SCEV::Constant(c)));
+                    }
+                }
+            }
+            None
+        }
+    }
+}
+
+fn imm(x: i64, ty: Type) -> Value {
+    match ty {
+        Type::Int32 => Value::new_imm_int32(x as i32),
+        _ => Value::Immediate(ImmediateValue::Int64(x)),
+    }
+}
@@ -443,7 +443,8 @@ impl<'a> Mem2RegOnFunction<'a> {
             .is_atomic()
             && alloca.users.borrow().iter().all(|&use_id| {
                 let inst = &self.cur_func.inst_table[use_id];
-                matches!(inst.opcode, Opcode::Load | Opcode::Store)
+                // volatile accesses must be kept as they are
+                matches!(inst.opcode, Opcode::Load | Opcode::Store) && !inst.is_volatile()
             })
     }
 
@@ -3,14 +3,36 @@ pub mod branch_folding;
 pub mod builder;
 pub mod const_folding;
 pub mod cse;
+pub mod dce;
+pub mod dse;
 pub mod function;
+pub mod global_const;
+pub mod global_dce;
 pub mod global_val;
+pub mod gvn;
+pub mod if_conversion;
+pub mod indvars;
+pub mod inline_asm;
+pub mod inliner;
+pub mod instcombine;
+pub mod ipsccp;
+pub mod lcssa;
+pub mod licm;
 pub mod liveness;
+pub mod loop_rotate;
+pub mod loop_simplify;
+pub mod lsr;
 pub mod mem2reg;
 pub mod merge_ret;
 pub mod module;
 pub mod opcode;
+pub mod sccp;
+pub mod simplify_cfg;
+pub mod sroa;
+pub mod tail_recursion;
 pub mod types;
+pub mod unroll;
+pub mod unswitch;
 pub mod value;
 
 pub trait DumpToString {
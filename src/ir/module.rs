@@ -1,4 +1,4 @@
-use super::{function::*, global_val::*, types::*, DumpToString};
+use super::{function::*, global_val::*, inline_asm::*, types::*, value::*, DumpToString};
 use id_arena::*;
 use std::fmt;
 
@@ -7,6 +7,7 @@ pub struct Module {
     pub name: String,
     pub functions: Arena<Function>,
     pub global_vars: GlobalVariables,
+    pub inline_asms: InlineAsms,
     pub types: Types,
 }
 
@@ -17,6 +18,7 @@ impl Module {
             name: name.to_string(),
             functions: Arena::new(),
             global_vars: GlobalVariables::new(types.clone()),
+            inline_asms: InlineAsms::new(),
             types,
         }
     }
@@ -30,6 +32,30 @@ impl Module {
         Function::new(self, name, ret_ty, params_ty)
     }
 
+    /// Registers `asm` and returns a value that can be passed to `Builder::build_call`
+    pub fn create_inline_asm(
+        &mut self,
+        ret_ty: Type,
+        params_ty: Vec<Type>,
+        asm: InlineAsm,
+    ) -> Value {
+        assert_eq!(
+            asm.inputs().count(),
+            params_ty.len(),
+            "inline asm inputs and parameters don't match"
+        );
+        assert_eq!(
+            asm.output().is_some(),
+            ret_ty != Type::Void,
+            "inline asm output and return type don't match"
+        );
+        let ty = self.types.new_function_ty(ret_ty, params_ty);
+        Value::InlineAsm(InlineAsmValue {
+            id: self.inline_asms.add(asm),
+            ty,
+        })
+    }
+
     pub fn add_function(&mut self, f: Function) -> FunctionId {
         let id = self.functions.alloc(f);
         self.function_ref_mut(id).id = Some(id);
@@ -62,6 +88,12 @@ impl Module {
 impl fmt::Debug for Module {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Module (name: {})", self.name)?;
+        {
+            let base = self.types.base.borrow();
+            for ty in base.named_struct_tys() {
+                writeln!(f, "{}", base.struct_definition_to_string(ty))?;
+            }
+        }
         writeln!(f, "{:?}", self.global_vars)?;
         for (_, func) in &self.functions {
             writeln!(f, "{}", self.dump(func))?;
@@ -1,4 +1,7 @@
-use super::{basic_block::BasicBlockId, function::FunctionId, module::Module, types::*, value::*};
+use super::{
+    basic_block::BasicBlockId, function::FunctionId, inline_asm::InlineAsms, module::Module,
+    types::*, value::*,
+};
 use id_arena::{Arena, Id};
 use std::cell::RefCell;
 
@@ -16,6 +19,15 @@ pub struct Instruction {
     pub id: Option<InstructionId>,
     pub parent: BasicBlockId,
     pub users: RefCell<Vec<InstructionId>>,
+    pub mem_attr: MemoryAttribute,
+}
+
+/// Attributes of `Alloca`, `Load` and `Store`. `align` overrides the natural alignment of the
+/// accessed (or allocated) type.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MemoryAttribute {
+    pub volatile: bool,
+    pub align: Option<usize>,
 }
 
 #[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
@@ -34,6 +46,10 @@ pub enum Opcode {
     FPToSI,
     ICmp,
     FCmp,
+    ExtractElement, // vec, idx
+    InsertElement,  // vec, val, idx
+    ShuffleVector,  // vec1, vec2, mask indices
+    Select,         // cond, val if true, val if false
     Br,
     CondBr,
     Phi,
@@ -75,6 +91,30 @@ impl Instruction {
             id: None,
             parent,
             users: RefCell::new(vec![]),
+            mem_attr: MemoryAttribute::default(),
+        }
+    }
+
+    pub fn with_mem_attr(mut self, mem_attr: MemoryAttribute) -> Self {
+        self.mem_attr = mem_attr;
+        self
+    }
+
+    pub fn is_volatile(&self) -> bool {
+        matches!(self.opcode, Opcode::Load | Opcode::Store) && self.mem_attr.volatile
+    }
+
+    /// Returns true if removing the instruction could change the program's behavior even when its
+    /// result is unused
+    pub fn has_side_effects(&self, inline_asms: &InlineAsms) -> bool {
+        match self.opcode {
+            Opcode::Store | Opcode::Br | Opcode::CondBr | Opcode::Ret => true,
+            Opcode::Load => self.mem_attr.volatile,
+            Opcode::Call => match self.operands[0].as_value() {
+                Value::InlineAsm(InlineAsmValue { id, .. }) => inline_asms.arena[*id].side_effects,
+                _ => true,
+            },
+            _ => false,
         }
     }
 
@@ -198,6 +238,9 @@ impl Instruction {
 
     pub fn to_string(&self, parent: &Module) -> String {
         let mut output = self.opcode.to_string().to_owned();
+        if self.mem_attr.volatile {
+            output += " volatile";
+        }
         for (i, operand) in self.operands.iter().enumerate() {
             output = format!(
                 "{}{}{}",
@@ -206,6 +249,9 @@ impl Instruction {
                 operand.to_string(parent)
             );
         }
+        if let Some(align) = self.mem_attr.align {
+            output = format!("{}, align {}", output, align);
+        }
 
         format!(
             "{} ",
@@ -245,6 +291,10 @@ impl Opcode {
             Opcode::FPToSI => "fptosi",
             Opcode::ICmp => "icmp",
             Opcode::FCmp => "fcmp",
+            Opcode::ExtractElement => "extractelement",
+            Opcode::InsertElement => "insertelement",
+            Opcode::ShuffleVector => "shufflevector",
+            Opcode::Select => "select",
             Opcode::Br => "br",
             Opcode::CondBr => "br",
             Opcode::Phi => "phi",
@@ -0,0 +1,337 @@
+use crate::ir::{
+    basic_block::BasicBlockId,
+    function::Function,
+    module::Module,
+    opcode::{FCmpKind, ICmpKind, Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Sparse conditional constant propagation. Values are propagated only along edges that can be
+/// executed, so constants flow through phis and branches on constants are folded into jumps.
+pub struct SparseConditionalConstantPropagation {}
+
+struct SCCPOnFunction<'a> {
+    func: &'a mut Function,
+    lattice: FxHashMap<InstructionId, LatticeValue>,
+    executable_blocks: FxHashSet<BasicBlockId>,
+    executable_edges: FxHashSet<(BasicBlockId, BasicBlockId)>,
+    cfg_worklist: Vec<(Option<BasicBlockId>, BasicBlockId)>,
+    ssa_worklist: Vec<InstructionId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LatticeValue {
+    /// Not known to have any value yet
+    Undef,
+    Const(ImmediateValue),
+    /// Result of a comparison
+    Bool(bool),
+    Overdefined,
+}
+
+impl SparseConditionalConstantPropagation {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            SCCPOnFunction {
+                func,
+                lattice: FxHashMap::default(),
+                executable_blocks: FxHashSet::default(),
+                executable_edges: FxHashSet::default(),
+                cfg_worklist: vec![],
+                ssa_worklist: vec![],
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> SCCPOnFunction<'a> {
+    pub fn run(mut self) {
+        self.solve();
+        self.rewrite();
+    }
+
+    fn solve(&mut self) {
+        self.cfg_worklist
+            .push((None, self.func.basic_blocks.order[0]));
+
+        while self.cfg_worklist.len() > 0 || self.ssa_worklist.len() > 0 {
+            while let Some((from, to)) = self.cfg_worklist.pop() {
+                if let Some(from) = from {
+                    if !self.executable_edges.insert((from, to)) {
+                        continue;
+                    }
+                }
+                let first_visit = self.executable_blocks.insert(to);
+                let iseq: Vec<InstructionId> = self.func.basic_blocks.arena[to]
+                    .iseq_ref()
+                    .iter()
+                    .map(|v| v.as_instruction().id)
+                    .collect();
+                for id in iseq {
+                    // A new incoming edge only affects phis
+                    if first_visit || self.func.inst_table[id].opcode == Opcode::Phi {
+                        self.visit(id);
+                    }
+                }
+            }
+
+            while let Some(id) = self.ssa_worklist.pop() {
+                let users = self.func.inst_table[id].users.borrow().clone();
+                for user in users {
+                    if self
+                        .executable_blocks
+                        .contains(&self.func.inst_table[user].parent)
+                    {
+                        self.visit(user);
+                    }
+                }
+            }
+        }
+    }
+
+    fn visit(&mut self, id: InstructionId) {
+        let inst = &self.func.inst_table[id];
+        let parent = inst.parent;
+        let new = match inst.opcode {
+            Opcode::Br => {
+                let dst = *inst.operands[0].as_basic_block();
+                self.cfg_worklist.push((Some(parent), dst));
+                return;
+            }
+            Opcode::CondBr => {
+                let then_ = *inst.operands[1].as_basic_block();
+                let else_ = *inst.operands[2].as_basic_block();
+                match self.value_of(inst.operands[0].as_value()) {
+                    LatticeValue::Bool(true) => self.cfg_worklist.push((Some(parent), then_)),
+                    LatticeValue::Bool(false) => self.cfg_worklist.push((Some(parent), else_)),
+                    LatticeValue::Undef => {}
+                    _ => {
+                        self.cfg_worklist.push((Some(parent), then_));
+                        self.cfg_worklist.push((Some(parent), else_));
+                    }
+                }
+                return;
+            }
+            Opcode::Phi => inst
+                .operands
+                .chunks(2)
+                .filter(|pair| {
+                    self.executable_edges
+                        .contains(&(*pair[1].as_basic_block(), parent))
+                })
+                .fold(LatticeValue::Undef, |acc, pair| {
+                    acc.meet(self.value_of(pair[0].as_value()))
+                }),
+            Opcode::Add
+            | Opcode::Sub
+            | Opcode::Mul
+            | Opcode::Div
+            | Opcode::Rem
+            | Opcode::Shl
+            | Opcode::ICmp
+            | Opcode::FCmp
+            | Opcode::SIToFP
+            | Opcode::FPToSI => self.evaluate(inst),
+            _ => LatticeValue::Overdefined,
+        };
+
+        let old = self
+            .lattice
+            .get(&id)
+            .copied()
+            .unwrap_or(LatticeValue::Undef);
+        // Values only move down the lattice
+        let new = old.meet(new);
+        if new != old {
+            self.lattice.insert(id, new);
+            self.ssa_worklist.push(id);
+        }
+    }
+
+    fn evaluate(&self, inst: &Instruction) -> LatticeValue {
+        use ImmediateValue::*;
+
+        let values: Vec<LatticeValue> = inst
+            .operands
+            .iter()
+            .filter_map(|op| op.get_value().map(|v| self.value_of(v)))
+            .collect();
+        if values.iter().any(|v| *v == LatticeValue::Overdefined) {
+            return LatticeValue::Overdefined;
+        }
+        if values.iter().any(|v| *v == LatticeValue::Undef) {
+            return LatticeValue::Undef;
+        }
+        let imms: Vec<Value> = values
+            .iter()
+            .map(|v| match v {
+                LatticeValue::Const(c) => Value::Immediate(*c),
+                _ => Value::None,
+            })
+            .collect();
+
+        let folded = match inst.opcode {
+            Opcode::Add => imms[0].const_add(&imms[1]),
+            Opcode::Sub => imms[0].const_sub(&imms[1]),
+            Opcode::Mul => imms[0].const_mul(&imms[1]),
+            Opcode::Div => imms[0].const_div(&imms[1]),
+            Opcode::Rem => imms[0].const_rem(&imms[1]),
+            Opcode::Shl => {
+                let amount = match &imms[1] {
+                    Value::Immediate(Int8(y)) => *y as u32,
+                    Value::Immediate(Int32(y)) => *y as u32,
+                    _ => return LatticeValue::Overdefined,
+                };
+                match &imms[0] {
+                    Value::Immediate(Int32(x)) => {
+                        Some(Value::new_imm_int32(x.wrapping_shl(amount)))
+                    }
+                    Value::Immediate(Int64(x)) => {
+                        Some(Value::Immediate(Int64(x.wrapping_shl(amount))))
+                    }
+                    _ => None,
+                }
+            }
+            Opcode::SIToFP => match (&imms[0], inst.ty) {
+                (Value::Immediate(Int32(x)), Type::F64) => Some(Value::new_imm_f64(*x as f64)),
+                _ => None,
+            },
+            Opcode::FPToSI => match (&imms[0], inst.ty) {
+                (Value::Immediate(F64(x)), Type::Int32) => Some(Value::new_imm_int32(*x as i32)),
+                _ => None,
+            },
+            Opcode::ICmp => {
+                let kind = *inst.operands[0].as_icmp_kind();
+                return match (&imms[0], &imms[1]) {
+                    (Value::Immediate(Int8(x)), Value::Immediate(Int8(y))) => {
+                        LatticeValue::Bool(icmp(kind, x, y))
+                    }
+                    (Value::Immediate(Int32(x)), Value::Immediate(Int32(y))) => {
+                        LatticeValue::Bool(icmp(kind, x, y))
+                    }
+                    (Value::Immediate(Int64(x)), Value::Immediate(Int64(y))) => {
+                        LatticeValue::Bool(icmp(kind, x, y))
+                    }
+                    _ => LatticeValue::Overdefined,
+                };
+            }
+            Opcode::FCmp => {
+                return match (&imms[0], &imms[1]) {
+                    (Value::Immediate(F64(x)), Value::Immediate(F64(y))) => {
+                        LatticeValue::Bool(match inst.operands[0].as_fcmp_kind() {
+                            FCmpKind::UEq => x == y,
+                            FCmpKind::ULt => x < y,
+                            FCmpKind::ULe => x <= y,
+                        })
+                    }
+                    _ => LatticeValue::Overdefined,
+                };
+            }
+            _ => None,
+        };
+
+        match folded {
+            Some(Value::Immediate(c)) => LatticeValue::Const(c),
+            _ => LatticeValue::Overdefined,
+        }
+    }
+
+    fn value_of(&self, v: &Value) -> LatticeValue {
+        match v {
+            Value::Immediate(c) => LatticeValue::Const(*c),
+            Value::Instruction(InstructionValue { id, .. }) => {
+                self.lattice.get(id).copied().unwrap_or(LatticeValue::Undef)
+            }
+            _ => LatticeValue::Overdefined,
+        }
+    }
+
+    fn rewrite(&mut self) {
+        let mut folded = vec![];
+        let mut branches = vec![];
+        for &bb in &self.func.basic_blocks.order {
+            if !self.executable_blocks.contains(&bb) {
+                continue;
+            }
+            for val in &*self.func.basic_blocks.arena[bb].iseq_ref() {
+                let id = val.as_instruction().id;
+                let inst = &self.func.inst_table[id];
+                match (inst.opcode, self.lattice.get(&id)) {
+                    (Opcode::CondBr, _) => {
+                        if let LatticeValue::Bool(b) = self.value_of(inst.operands[0].as_value()) {
+                            branches.push((bb, id, b))
+                        }
+                    }
+                    (_, Some(LatticeValue::Const(c))) => folded.push((id, *c)),
+                    _ => {}
+                }
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} insts folded, {} branches folded",
+            self.func.name,
+            folded.len(),
+            branches.len()
+        ));
+
+        for (id, c) in folded {
+            Instruction::replace_all_uses(
+                &mut self.func.inst_table,
+                id,
+                Operand::Value(Value::Immediate(c)),
+            );
+            self.func.remove_inst(id);
+        }
+
+        for (bb, br, cond) in branches {
+            let inst = &self.func.inst_table[br];
+            let (taken, not_taken) = if cond {
+                (inst.operands[1], inst.operands[2])
+            } else {
+                (inst.operands[2], inst.operands[1])
+            };
+            let (taken, not_taken) = (*taken.as_basic_block(), *not_taken.as_basic_block());
+            self.func.change_inst(
+                br,
+                Instruction::new(Opcode::Br, vec![Operand::BasicBlock(taken)], Type::Void, bb),
+            );
+            if taken != not_taken {
+                self.func.basic_blocks.arena[bb].succ.remove(&not_taken);
+                self.func.basic_blocks.arena[not_taken].pred.remove(&bb);
+                self.func.remove_phi_incoming(not_taken, bb);
+            }
+        }
+
+        self.func.remove_unreachable_blocks();
+    }
+}
+
+impl LatticeValue {
+    fn meet(self, other: Self) -> Self {
+        match (self, other) {
+            (LatticeValue::Undef, x) | (x, LatticeValue::Undef) => x,
+            (x, y) if x == y => x,
+            _ => LatticeValue::Overdefined,
+        }
+    }
+}
+
+fn icmp<T: PartialOrd>(kind: ICmpKind, x: T, y: T) -> bool {
+    match kind {
+        ICmpKind::Eq => x == y,
+        ICmpKind::Le => x <= y,
+        ICmpKind::Lt => x < y,
+    }
+}
@@ -0,0 +1,369 @@
+use crate::ir::{
+    basic_block::BasicBlockId,
+    function::Function,
+    module::Module,
+    opcode::{ICmpKind, Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, Value},
+};
+use rustc_hash::FxHashMap;
+
+/// Simplifies the control flow graph:
+///
+/// - blocks that only jump to another block are removed
+/// - `CondBr` whose targets are the same becomes `Br`
+/// - instructions common to the beginning of both targets of a `CondBr` are hoisted above it
+/// - a predecessor of a block branching on a comparison of a phi with a constant jumps
+///   straight to the target if the phi's incoming value from it decides the comparison
+/// - blocks returning the same value are merged
+///
+/// Rewrites are applied one at a time until none applies. `pred` and `succ` of blocks are
+/// recomputed and unreachable blocks removed after each, so dominator trees and loops can be
+/// constructed again right after the pass.
+pub struct SimplifyCFG {}
+
+struct SimplifyCFGOnFunction<'a> {
+    func: &'a mut Function,
+    num_simplified: usize,
+}
+
+impl SimplifyCFG {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            SimplifyCFGOnFunction {
+                func,
+                num_simplified: 0,
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> SimplifyCFGOnFunction<'a> {
+    fn run(mut self) {
+        self.func.recompute_cfg();
+        self.func.remove_unreachable_blocks();
+
+        while self.fold_cond_br()
+            || self.remove_forwarding_block()
+            || self.hoist_common_insts()
+            || self.thread_jump()
+            || self.merge_returns()
+        {
+            self.num_simplified += 1;
+            self.func.recompute_cfg();
+            self.func.remove_unreachable_blocks();
+        }
+
+        debug!(println!(
+            "function '{}': {} simplifications",
+            self.func.name, self.num_simplified
+        ));
+    }
+
+    // br %c, %a, %a -> br %a
+    fn fold_cond_br(&mut self) -> bool {
+        for bb in self.func.basic_blocks.order.clone() {
+            let term = self.func.terminator(bb);
+            let inst = &self.func.inst_table[term];
+            if inst.opcode == Opcode::CondBr && inst.operands[1] == inst.operands[2] {
+                let br = Instruction::new(Opcode::Br, vec![inst.operands[1]], Type::Void, bb);
+                self.func.change_inst(term, br);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Removes a block containing only `br`, making its predecessors jump to its successor
+    fn remove_forwarding_block(&mut self) -> bool {
+        for bb in self.func.basic_blocks.order[1..].to_vec() {
+            let block = &self.func.basic_blocks.arena[bb];
+            if block.iseq_ref().len() != 1 {
+                continue;
+            }
+            let br = &self.func.inst_table[self.func.terminator(bb)];
+            if br.opcode != Opcode::Br {
+                continue;
+            }
+            let succ = *br.operands[0].as_basic_block();
+            if succ == bb {
+                continue;
+            }
+
+            // A predecessor already jumping to `succ` must agree on the incoming values
+            let preds: Vec<BasicBlockId> = block.pred.iter().copied().collect();
+            let phis = self.phis(succ);
+            let conflicts = phis.iter().any(|&phi| {
+                let val = self.func.phi_incoming(phi, bb);
+                preds.iter().any(|&pred| {
+                    self.func
+                        .phi_incoming(phi, pred)
+                        .map_or(false, |other| Some(other) != val)
+                })
+            });
+            if conflicts {
+                continue;
+            }
+
+            for phi in phis {
+                let val = self.func.phi_incoming(phi, bb).unwrap();
+                let mut operands = vec![];
+                for pair in self.func.inst_table[phi].operands.chunks(2) {
+                    if pair[1] != Operand::BasicBlock(bb) {
+                        operands.extend_from_slice(pair);
+                    }
+                }
+                for &pred in &preds {
+                    if self.func.phi_incoming(phi, pred).is_none() {
+                        operands.push(Operand::Value(val));
+                        operands.push(Operand::BasicBlock(pred));
+                    }
+                }
+                self.func.inst_table[phi].operands = operands;
+            }
+            for pred in preds {
+                self.func.replace_branch_target(pred, bb, succ);
+            }
+            self.func.remove_inst(self.func.terminator(bb));
+            self.func.basic_blocks.order.retain(|&b| b != bb);
+            return true;
+        }
+        false
+    }
+
+    /// Moves instructions at the beginning of both targets of a `CondBr` above it if they are
+    /// the same
+    fn hoist_common_insts(&mut self) -> bool {
+        for bb in self.func.basic_blocks.order.clone() {
+            let term = self.func.terminator(bb);
+            let inst = &self.func.inst_table[term];
+            if inst.opcode != Opcode::CondBr {
+                continue;
+            }
+            let (t, f) = (
+                *inst.operands[1].as_basic_block(),
+                *inst.operands[2].as_basic_block(),
+            );
+            let single_pred = |b: BasicBlockId| {
+                let pred = &self.func.basic_blocks.arena[b].pred;
+                pred.len() == 1 && pred.contains(&bb)
+            };
+            if t == f || !single_pred(t) || !single_pred(f) {
+                continue;
+            }
+
+            let mut hoisted = false;
+            loop {
+                let t0 = self.func.basic_blocks.arena[t].iseq_ref()[0]
+                    .as_instruction()
+                    .id;
+                let f0 = self.func.basic_blocks.arena[f].iseq_ref()[0]
+                    .as_instruction()
+                    .id;
+                let (ti, fi) = (&self.func.inst_table[t0], &self.func.inst_table[f0]);
+                // Both are executed right after the branch anyway
+                let same = ti.opcode == fi.opcode
+                    && ti.operands == fi.operands
+                    && ti.ty == fi.ty
+                    && ti.mem_attr == fi.mem_attr;
+                if !same || ti.opcode.is_terminator() || ti.opcode == Opcode::Phi {
+                    break;
+                }
+
+                let val = self.func.basic_blocks.arena[t].iseq_ref_mut().remove(0);
+                let mut iseq = self.func.basic_blocks.arena[bb].iseq_ref_mut();
+                let pos = iseq.len() - 1;
+                iseq.insert(pos, val);
+                drop(iseq);
+                self.func.inst_table[t0].parent = bb;
+                Instruction::replace_all_uses(&mut self.func.inst_table, f0, Operand::Value(val));
+                self.func.remove_inst(f0);
+                hoisted = true;
+            }
+            if hoisted {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Makes a predecessor of a block whose branch is decided by a phi skip the block
+    fn thread_jump(&mut self) -> bool {
+        for bb in self.func.basic_blocks.order[1..].to_vec() {
+            let term = self.func.terminator(bb);
+            let br = &self.func.inst_table[term];
+            if br.opcode != Opcode::CondBr {
+                continue;
+            }
+            let cond = match br.operands[0].as_value().get_inst_id() {
+                Some(cond) if self.func.inst_table[cond].opcode == Opcode::ICmp => cond,
+                _ => continue,
+            };
+            let icmp = &self.func.inst_table[cond];
+            let (kind, rhs) = match icmp.operands[2].as_value() {
+                Value::Immediate(rhs) => (*icmp.operands[0].as_icmp_kind(), *rhs),
+                _ => continue,
+            };
+            let phi = match icmp.operands[1].as_value().get_inst_id() {
+                Some(phi) if self.func.inst_table[phi].parent == bb => phi,
+                _ => continue,
+            };
+            if self.func.inst_table[phi].opcode != Opcode::Phi {
+                continue;
+            }
+            let targets = [
+                *br.operands[1].as_basic_block(),
+                *br.operands[2].as_basic_block(),
+            ];
+            if !self.is_threadable(bb, cond, &targets) {
+                continue;
+            }
+
+            let preds: Vec<BasicBlockId> = self.func.basic_blocks.arena[bb]
+                .pred
+                .iter()
+                .copied()
+                .collect();
+            for pred in preds {
+                let lhs = match self.func.phi_incoming(phi, pred) {
+                    Some(Value::Immediate(lhs)) => lhs,
+                    _ => continue,
+                };
+                let target = match eval_icmp(kind, &lhs, &rhs) {
+                    Some(true) => targets[0],
+                    Some(false) => targets[1],
+                    None => continue,
+                };
+                // Redirecting the branch of `bb` itself would affect every path through it
+                if target == bb || pred == bb {
+                    continue;
+                }
+
+                // The incoming values of phis in `target` for the new edge from `pred`
+                let mut incomings = vec![];
+                for q in self.phis(target) {
+                    let val = self.func.phi_incoming(q, bb).unwrap();
+                    let val = match val.get_inst_id() {
+                        Some(id) if self.func.inst_table[id].parent == bb => {
+                            self.func.phi_incoming(id, pred).unwrap()
+                        }
+                        _ => val,
+                    };
+                    incomings.push((q, val));
+                }
+                let conflicts = incomings.iter().any(|&(q, val)| {
+                    self.func
+                        .phi_incoming(q, pred)
+                        .map_or(false, |other| other != val)
+                });
+                if conflicts {
+                    continue;
+                }
+
+                for (q, val) in incomings {
+                    if self.func.phi_incoming(q, pred).is_none() {
+                        Instruction::add_operand(&mut self.func.inst_table, q, Operand::Value(val));
+                        Instruction::add_operand(
+                            &mut self.func.inst_table,
+                            q,
+                            Operand::BasicBlock(pred),
+                        );
+                    }
+                }
+                self.func.replace_branch_target(pred, bb, target);
+                self.func.remove_phi_incoming(bb, pred);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns true if `bb` consists of phis, `cond` and a `CondBr`, and its values are used only
+    /// in itself or by phis in `targets`
+    fn is_threadable(
+        &self,
+        bb: BasicBlockId,
+        cond: InstructionId,
+        targets: &[BasicBlockId],
+    ) -> bool {
+        let iseq = self.func.basic_blocks.arena[bb].iseq_ref();
+        iseq[..iseq.len() - 1].iter().all(|val| {
+            let id = val.as_instruction().id;
+            let inst = &self.func.inst_table[id];
+            (inst.opcode == Opcode::Phi || id == cond)
+                && inst.users.borrow().iter().all(|&user| {
+                    let user = &self.func.inst_table[user];
+                    user.parent == bb
+                        || (id != cond
+                            && user.opcode == Opcode::Phi
+                            && targets.contains(&user.parent))
+                })
+        })
+    }
+
+    /// Makes blocks only returning the same value share one of them
+    fn merge_returns(&mut self) -> bool {
+        let mut rets: FxHashMap<Vec<Operand>, BasicBlockId> = FxHashMap::default();
+        for bb in self.func.basic_blocks.order.clone() {
+            if self.func.basic_blocks.arena[bb].iseq_ref().len() != 1 {
+                continue;
+            }
+            let ret = &self.func.inst_table[self.func.terminator(bb)];
+            if ret.opcode != Opcode::Ret {
+                continue;
+            }
+            let merged = match rets.get(&ret.operands) {
+                Some(&merged) => merged,
+                None => {
+                    rets.insert(ret.operands.clone(), bb);
+                    continue;
+                }
+            };
+
+            let preds: Vec<BasicBlockId> = self.func.basic_blocks.arena[bb]
+                .pred
+                .iter()
+                .copied()
+                .collect();
+            for pred in preds {
+                self.func.replace_branch_target(pred, bb, merged);
+            }
+            // The entry comes first, so it's never the one merged away
+            self.func.remove_inst(self.func.terminator(bb));
+            self.func.basic_blocks.order.retain(|&b| b != bb);
+            return true;
+        }
+        false
+    }
+
+    fn phis(&self, bb: BasicBlockId) -> Vec<InstructionId> {
+        self.func.basic_blocks.arena[bb]
+            .iseq_ref()
+            .iter()
+            .map(|v| v.as_instruction().id)
+            .filter(|&id| self.func.inst_table[id].opcode == Opcode::Phi)
+            .collect()
+    }
+}
+
+fn eval_icmp(kind: ICmpKind, lhs: &ImmediateValue, rhs: &ImmediateValue) -> Option<bool> {
+    let (lhs, rhs) = match (lhs, rhs) {
+        (ImmediateValue::Int32(l), ImmediateValue::Int32(r)) => (*l as i64, *r as i64),
+        (ImmediateValue::Int64(l), ImmediateValue::Int64(r)) => (*l, *r),
+        _ => return None,
+    };
+    Some(match kind {
+        ICmpKind::Eq => lhs == rhs,
+        ICmpKind::Le => lhs <= rhs,
+        ICmpKind::Lt => lhs < rhs,
+    })
+}
@@ -0,0 +1,197 @@
+use crate::ir::{
+    function::Function,
+    mem2reg::Mem2Reg,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+use rustc_hash::FxHashMap;
+
+/// Arrays longer than this are left in memory
+const MAX_ARRAY_LEN: usize = 16;
+
+/// Scalar replacement of aggregates. An `Alloca` of a struct or a small array accessed only
+/// through `GetElementPtr`s with constant indices is split into an `Alloca` per element, which
+/// is split again if the element is an aggregate itself. The resulting scalar allocas are then
+/// promoted to registers by `Mem2Reg`.
+pub struct ScalarReplacementOfAggregates {}
+
+struct ScalarReplacementOfAggregatesOnFunction<'a> {
+    func: &'a mut Function,
+    num_split: usize,
+}
+
+impl ScalarReplacementOfAggregates {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            ScalarReplacementOfAggregatesOnFunction { func, num_split: 0 }.run()
+        }
+
+        Mem2Reg::new().run_on_module(module)
+    }
+}
+
+impl<'a> ScalarReplacementOfAggregatesOnFunction<'a> {
+    fn run(mut self) {
+        let mut worklist = vec![];
+        for &bb in &self.func.basic_blocks.order {
+            for val in &*self.func.basic_blocks.arena[bb].iseq_ref() {
+                let id = val.as_instruction().id;
+                if self.func.inst_table[id].opcode == Opcode::Alloca {
+                    worklist.push(id)
+                }
+            }
+        }
+
+        while let Some(alloca) = worklist.pop() {
+            if self.is_splittable(alloca) {
+                worklist.extend(self.split(alloca))
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} allocas split",
+            self.func.name, self.num_split
+        ));
+    }
+
+    /// Replaces `alloca` with an alloca per element, and returns the new allocas
+    fn split(&mut self, alloca: InstructionId) -> Vec<InstructionId> {
+        let (bb, pos) = self.func.find_inst_pos(alloca).unwrap();
+        let mut elems: FxHashMap<usize, InstructionId> = FxHashMap::default();
+        let users = self.func.inst_table[alloca].users.borrow().clone();
+
+        for gep in users {
+            let idx = const_index(&self.func.inst_table[gep].operands[2]).unwrap() as usize;
+            let elem = match elems.get(&idx) {
+                Some(&elem) => elem,
+                None => {
+                    let ty = self.elem_ty(alloca, idx);
+                    let ptr_ty = self.func.types.new_pointer_ty(ty);
+                    let elem = self.func.alloc_inst(
+                        Instruction::new(Opcode::Alloca, vec![Operand::Type(ty)], ptr_ty, bb)
+                            .with_mem_attr(self.func.inst_table[alloca].mem_attr),
+                    );
+                    let val = self.func.inst_value(elem);
+                    self.func.basic_blocks.arena[bb]
+                        .iseq_ref_mut()
+                        .insert(pos, val);
+                    elems.insert(idx, elem);
+                    elem
+                }
+            };
+
+            let inst = &self.func.inst_table[gep];
+            if inst.operands.len() == 3 {
+                // The element itself
+                let to = Operand::Value(self.func.inst_value(elem));
+                Instruction::replace_all_uses(&mut self.func.inst_table, gep, to);
+                self.func.remove_inst(gep);
+            } else {
+                // Somewhere inside the element
+                let mut operands = vec![
+                    Operand::Value(self.func.inst_value(elem)),
+                    Operand::Value(Value::new_imm_int32(0)),
+                ];
+                operands.extend(inst.operands[3..].iter().copied());
+                let new = Instruction::new(Opcode::GetElementPtr, operands, inst.ty, inst.parent);
+                self.func.change_inst(gep, new);
+            }
+        }
+
+        self.func.remove_inst(alloca);
+        self.num_split += 1;
+
+        elems.into_iter().map(|(_, elem)| elem).collect()
+    }
+
+    /// Returns true if `alloca` is an aggregate whose elements are accessed separately
+    fn is_splittable(&self, alloca: InstructionId) -> bool {
+        let inst = &self.func.inst_table[alloca];
+        let len = match *inst.operands[0].as_type() {
+            Type::Struct(id) => {
+                let base = self.func.types.base.borrow();
+                let st = base.non_primitive_types[id].as_struct();
+                (0..)
+                    .take_while(|&i| st.get_elem_offset(i).is_some())
+                    .count()
+            }
+            Type::Array(id) => {
+                let len = self.func.types.base.borrow().non_primitive_types[id]
+                    .as_array()
+                    .len;
+                if len > MAX_ARRAY_LEN {
+                    return false;
+                }
+                len
+            }
+            _ => return false,
+        };
+
+        let users = inst.users.borrow();
+        users.iter().all(|&user| {
+            let gep = &self.func.inst_table[user];
+            gep.opcode == Opcode::GetElementPtr
+                && gep.operands.len() >= 3
+                && self.is_ptr(&gep.operands[0], alloca)
+                && const_index(&gep.operands[1]) == Some(0)
+                && const_index(&gep.operands[2]).map_or(false, |i| 0 <= i && (i as usize) < len)
+                && !gep.operands[3..].iter().any(|op| self.is_ptr(op, alloca))
+                && self.stays_inside(user)
+        })
+    }
+
+    /// Returns true if every access through `ptr` stays within the object it points to
+    fn stays_inside(&self, ptr: InstructionId) -> bool {
+        self.func.inst_table[ptr]
+            .users
+            .borrow()
+            .iter()
+            .all(|&user| {
+                let inst = &self.func.inst_table[user];
+                match inst.opcode {
+                    Opcode::Load => true,
+                    Opcode::Store => !self.is_ptr(&inst.operands[0], ptr),
+                    Opcode::GetElementPtr => {
+                        self.is_ptr(&inst.operands[0], ptr)
+                            && const_index(&inst.operands[1]) == Some(0)
+                            && !inst.operands[1..].iter().any(|op| self.is_ptr(op, ptr))
+                            && self.stays_inside(user)
+                    }
+                    _ => false,
+                }
+            })
+    }
+
+    fn is_ptr(&self, op: &Operand, ptr: InstructionId) -> bool {
+        match op {
+            Operand::Value(Value::Instruction(InstructionValue { id, .. })) => *id == ptr,
+            _ => false,
+        }
+    }
+
+    fn elem_ty(&self, alloca: InstructionId, idx: usize) -> Type {
+        let ty = *self.func.inst_table[alloca].operands[0].as_type();
+        self.func
+            .types
+            .get_element_ty(ty, Some(&Value::new_imm_int32(idx as i32)))
+            .unwrap()
+    }
+}
+
+fn const_index(op: &Operand) -> Option<i64> {
+    match op {
+        Operand::Value(Value::Immediate(ImmediateValue::Int32(i))) => Some(*i as i64),
+        Operand::Value(Value::Immediate(ImmediateValue::Int64(i))) => Some(*i),
+        _ => None,
+    }
+}
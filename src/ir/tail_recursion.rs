@@ -0,0 +1,332 @@
+use crate::analysis::alias_analysis::AliasAnalysis;
+use crate::ir::{
+    basic_block::BasicBlockId,
+    function::Function,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{FunctionValue, ImmediateValue, Value},
+};
+
+/// Tail recursion elimination. Self-recursive calls in tail position become jumps back to the
+/// beginning of the function, where a phi per parameter merges the initial arguments with those
+/// of the calls. A call whose result is added to (or multiplied by) a value right before being
+/// returned is handled too: the values are kept in an accumulator phi and applied to whatever
+/// the function eventually returns.
+pub struct TailRecursionElimination {}
+
+struct TailRecursionEliminationOnFunction<'a> {
+    func: &'a mut Function,
+    aa: AliasAnalysis<'a>,
+}
+
+/// A recursive call followed by `ret`
+struct TailCall {
+    call: InstructionId,
+    /// `op %call, val` returned instead of the call itself
+    accumulate: Option<InstructionId>,
+    ret: InstructionId,
+}
+
+impl TailRecursionElimination {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        let Module {
+            functions,
+            types,
+            inline_asms,
+            ..
+        } = module;
+        for (_, func) in functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            let aa = AliasAnalysis::new(func, types, inline_asms);
+            TailRecursionEliminationOnFunction { func, aa }.run()
+        }
+    }
+}
+
+impl<'a> TailRecursionEliminationOnFunction<'a> {
+    fn run(self) {
+        let mut calls = self.tail_calls();
+        if calls.is_empty() || !self.is_eliminable() {
+            return;
+        }
+
+        // Calls accumulating with an operation other than the first one found stay as they are
+        let op = calls
+            .iter()
+            .find_map(|c| c.accumulate.map(|id| self.func.inst_table[id].opcode));
+        calls.retain(|c| {
+            c.accumulate
+                .map_or(true, |id| Some(self.func.inst_table[id].opcode) == op)
+        });
+
+        debug!(println!(
+            "function '{}': {} tail calls eliminated",
+            self.func.name,
+            calls.len()
+        ));
+
+        self.eliminate(calls, op)
+    }
+
+    fn eliminate(self, calls: Vec<TailCall>, op: Option<Opcode>) {
+        let func = self.func;
+        let header = func.basic_blocks.order[0];
+        let entry = func.append_basic_block();
+        func.basic_blocks.order.pop();
+        func.basic_blocks.order.insert(0, entry);
+
+        // Allocas are made once, before the loop
+        let allocas: Vec<Value> = func.basic_blocks.arena[header]
+            .iseq_ref()
+            .iter()
+            .copied()
+            .filter(|v| func.inst_table[v.as_instruction().id].opcode == Opcode::Alloca)
+            .collect();
+        for alloca in allocas {
+            let id = alloca.as_instruction().id;
+            func.basic_blocks.arena[header]
+                .iseq_ref_mut()
+                .retain(|v| *v != alloca);
+            func.inst_table[id].parent = entry;
+            func.basic_blocks.arena[entry].iseq_ref_mut().push(alloca);
+        }
+        let br = func.alloc_inst(Instruction::new(
+            Opcode::Br,
+            vec![Operand::BasicBlock(header)],
+            Type::Void,
+            entry,
+        ));
+        let br = func.inst_value(br);
+        func.basic_blocks.arena[entry].iseq_ref_mut().push(br);
+
+        // A phi per parameter replaces the uses of the parameter
+        let mut params = vec![];
+        for i in 0..func.get_params_len() {
+            let arg = func.get_param_value(i).unwrap();
+            let phi = insert_phi(
+                func,
+                header,
+                arg.get_type(),
+                vec![Operand::Value(arg), Operand::BasicBlock(entry)],
+            );
+            let ids: Vec<InstructionId> = func.inst_table.iter().map(|(id, _)| id).collect();
+            for id in ids {
+                if id != phi.as_instruction().id {
+                    Instruction::replace_operand(
+                        &mut func.inst_table,
+                        id,
+                        &Operand::Value(arg),
+                        Operand::Value(phi),
+                    );
+                }
+            }
+            params.push(phi.as_instruction().id);
+        }
+
+        let acc = op.map(|op| {
+            let ty = func.get_return_type();
+            let identity = match (op, ty) {
+                (Opcode::Add, Type::Int32) => Value::new_imm_int32(0),
+                (Opcode::Add, _) => Value::Immediate(ImmediateValue::Int64(0)),
+                (_, Type::Int32) => Value::new_imm_int32(1),
+                (_, _) => Value::Immediate(ImmediateValue::Int64(1)),
+            };
+            let phi = insert_phi(
+                func,
+                header,
+                ty,
+                vec![Operand::Value(identity), Operand::BasicBlock(entry)],
+            );
+            (op, phi)
+        });
+
+        // Every other return gives back the accumulated value applied to its own
+        if let Some((op, acc)) = acc {
+            let rets: Vec<InstructionId> = func
+                .basic_blocks
+                .order
+                .iter()
+                .map(|&bb| {
+                    *func.basic_blocks.arena[bb]
+                        .iseq_ref()
+                        .last()
+                        .unwrap()
+                        .as_instruction()
+                })
+                .filter(|v| func.inst_table[v.id].opcode == Opcode::Ret)
+                .map(|v| v.id)
+                .filter(|id| calls.iter().all(|c| c.ret != *id))
+                .collect();
+            for ret in rets {
+                let val = *func.inst_table[ret].operands[0].as_value();
+                let bb = func.inst_table[ret].parent;
+                let new = func.alloc_inst(Instruction::new(
+                    op,
+                    vec![Operand::Value(acc), Operand::Value(val)],
+                    val.get_type(),
+                    bb,
+                ));
+                let new = func.inst_value(new);
+                let pos = func.basic_blocks.arena[bb].iseq_ref().len() - 1;
+                func.basic_blocks.arena[bb].iseq_ref_mut().insert(pos, new);
+                Instruction::replace_operand(
+                    &mut func.inst_table,
+                    ret,
+                    &Operand::Value(val),
+                    Operand::Value(new),
+                );
+            }
+        }
+
+        for call in calls {
+            let bb = func.inst_table[call.call].parent;
+            let args: Vec<Operand> = func.inst_table[call.call].operands[1..].to_vec();
+            for (&phi, arg) in params.iter().zip(args) {
+                Instruction::add_operand(&mut func.inst_table, phi, arg);
+                Instruction::add_operand(&mut func.inst_table, phi, Operand::BasicBlock(bb));
+            }
+
+            func.remove_inst(call.ret);
+            if let Some(op_inst) = call.accumulate {
+                let (_, acc) = acc.unwrap();
+                // Read after the parameters are replaced by their phis
+                let operands = &func.inst_table[op_inst].operands;
+                let val = if operands[0] == Operand::Value(func.inst_value(call.call)) {
+                    *operands[1].as_value()
+                } else {
+                    *operands[0].as_value()
+                };
+                func.remove_inst(op_inst);
+                let new = func.alloc_inst(Instruction::new(
+                    op.unwrap(),
+                    vec![Operand::Value(acc), Operand::Value(val)],
+                    val.get_type(),
+                    bb,
+                ));
+                let new = func.inst_value(new);
+                func.basic_blocks.arena[bb].iseq_ref_mut().push(new);
+                let acc = acc.as_instruction().id;
+                Instruction::add_operand(&mut func.inst_table, acc, Operand::Value(new));
+                Instruction::add_operand(&mut func.inst_table, acc, Operand::BasicBlock(bb));
+            } else if let Some((_, acc)) = acc {
+                let acc = acc.as_instruction().id;
+                let same = Operand::Value(func.inst_value(acc));
+                Instruction::add_operand(&mut func.inst_table, acc, same);
+                Instruction::add_operand(&mut func.inst_table, acc, Operand::BasicBlock(bb));
+            }
+            func.remove_inst(call.call);
+
+            let br = func.alloc_inst(Instruction::new(
+                Opcode::Br,
+                vec![Operand::BasicBlock(header)],
+                Type::Void,
+                bb,
+            ));
+            let br = func.inst_value(br);
+            func.basic_blocks.arena[bb].iseq_ref_mut().push(br);
+        }
+
+        func.recompute_cfg();
+    }
+
+    /// Returns the recursive calls whose result is returned, possibly after an addition or a
+    /// multiplication
+    fn tail_calls(&self) -> Vec<TailCall> {
+        let mut calls = vec![];
+        for &bb in &self.func.basic_blocks.order {
+            let iseq: Vec<InstructionId> = self.func.basic_blocks.arena[bb]
+                .iseq_ref()
+                .iter()
+                .map(|v| v.as_instruction().id)
+                .collect();
+            let ret = *iseq.last().unwrap();
+            if self.func.inst_table[ret].opcode != Opcode::Ret {
+                continue;
+            }
+            let returned = self.func.inst_table[ret].operands.get(0);
+
+            if iseq.len() >= 2 && self.is_self_call(iseq[iseq.len() - 2]) {
+                let call = iseq[iseq.len() - 2];
+                let returns_call = match returned {
+                    Some(op) => *op == self.operand_of(call),
+                    None => true,
+                };
+                if returns_call {
+                    calls.push(TailCall {
+                        call,
+                        accumulate: None,
+                        ret,
+                    });
+                }
+                continue;
+            }
+
+            if iseq.len() >= 3 && self.is_self_call(iseq[iseq.len() - 3]) {
+                let call = iseq[iseq.len() - 3];
+                let op = iseq[iseq.len() - 2];
+                let inst = &self.func.inst_table[op];
+                let call_val = self.operand_of(call);
+                if !matches!(inst.opcode, Opcode::Add | Opcode::Mul)
+                    || !matches!(inst.ty, Type::Int32 | Type::Int64)
+                    || returned != Some(&self.operand_of(op))
+                    || self.func.inst_table[call].users.borrow().len() != 1
+                {
+                    continue;
+                }
+                // The call must be used only once, so that 'op' doesn't square its result
+                if inst.operands[0] == call_val && inst.operands[1] == call_val {
+                    continue;
+                }
+                calls.push(TailCall {
+                    call,
+                    accumulate: Some(op),
+                    ret,
+                });
+            }
+        }
+        calls
+    }
+
+    /// Returns true if the frame of the function may be reused by the recursive calls, i.e. no
+    /// alloca outlives an iteration and every parameter is passed as it is
+    fn is_eliminable(&self) -> bool {
+        let func = &*self.func;
+        let byval =
+            (0..func.get_params_len()).any(|i| func.get_param_attr(i).map_or(false, |a| a.byval));
+        let escapes = func.basic_blocks.order.iter().any(|&bb| {
+            func.basic_blocks.arena[bb].iseq_ref().iter().any(|v| {
+                func.inst_table[v.as_instruction().id].opcode == Opcode::Alloca
+                    && !self.aa.is_local(func, v)
+            })
+        });
+        !byval && !escapes
+    }
+
+    fn is_self_call(&self, id: InstructionId) -> bool {
+        let inst = &self.func.inst_table[id];
+        inst.opcode == Opcode::Call
+            && match inst.operands[0].as_value() {
+                Value::Function(FunctionValue { func_id, .. }) => Some(*func_id) == self.func.id,
+                _ => false,
+            }
+    }
+
+    fn operand_of(&self, id: InstructionId) -> Operand {
+        Operand::Value(self.func.inst_value(id))
+    }
+}
+
+fn insert_phi(func: &mut Function, bb: BasicBlockId, ty: Type, operands: Vec<Operand>) -> Value {
+    let phi = func.alloc_inst(Instruction::new(Opcode::Phi, operands, ty, bb));
+    let phi = func.inst_value(phi);
+    func.basic_blocks.arena[bb].iseq_ref_mut().insert(0, phi);
+    phi
+}
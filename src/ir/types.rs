@@ -23,6 +23,7 @@ pub enum NonPrimitiveType {
     Array(ArrayType),
     Function(FunctionType),
     Struct(StructType),
+    Vector(VectorType),
 }
 
 #[derive(Clone, PartialEq, Eq, Copy, Hash)]
@@ -37,6 +38,7 @@ pub enum Type {
     Array(NonPrimitiveTypeId),
     Function(NonPrimitiveTypeId),
     Struct(NonPrimitiveTypeId),
+    Vector(NonPrimitiveTypeId),
 }
 
 pub trait TypeSize {
@@ -55,6 +57,9 @@ pub struct FunctionType {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct ParamAttribute {
     pub byval: bool,
+    /// The memory accessed through the pointer isn't accessed through any pointer not derived
+    /// from it while the function runs
+    pub noalias: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -63,8 +68,18 @@ pub struct ArrayType {
     pub len: usize,
 }
 
+/// A fixed-width vector of primitive elements, e.g. `<4 x i32>`. Arithmetic opcodes operate
+/// on vectors element-wise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VectorType {
+    pub elem_ty: Type,
+    pub len: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct StructType {
+    name: Option<String>,
+    is_opaque: bool,
     fields_ty: Vec<Type>,
     fields_offset: Vec<usize>,
     align: usize,
@@ -99,6 +114,12 @@ impl Types {
         Type::Array(id)
     }
 
+    pub fn new_vector_ty(&self, elem_ty: Type, len: usize) -> Type {
+        assert!(elem_ty.is_atomic() && elem_ty != Type::Void && len > 0);
+        let id = self.new_non_primitive_ty(NonPrimitiveType::Vector(VectorType::new(elem_ty, len)));
+        Type::Vector(id)
+    }
+
     pub fn new_function_ty(&self, ret_ty: Type, mut params_ty: Vec<Type>) -> Type {
         let mut params_attr = FxHashMap::default();
         for (i, ty) in params_ty.iter_mut().enumerate() {
@@ -106,7 +127,13 @@ impl Types {
                 Type::Struct(_) => {
                     let ptr = self.new_pointer_ty(*ty);
                     *ty = ptr;
-                    params_attr.insert(i, ParamAttribute { byval: true });
+                    params_attr.insert(
+                        i,
+                        ParamAttribute {
+                            byval: true,
+                            ..Default::default()
+                        },
+                    );
                 }
                 _ => {}
             }
@@ -119,12 +146,55 @@ impl Types {
         Type::Function(id)
     }
 
+    /// Returns the function type `ty` with the attribute of its `idx`th parameter set to `attr`
+    pub fn with_param_attr(&self, ty: Type, idx: usize, attr: ParamAttribute) -> Type {
+        let mut f_ty = self.base.borrow().as_function_ty(ty).unwrap().clone();
+        f_ty.params_attr.insert(idx, attr);
+        let id = self.new_non_primitive_ty(NonPrimitiveType::Function(f_ty));
+        Type::Function(id)
+    }
+
     pub fn new_struct_ty(&self, fields_ty: Vec<Type>) -> Type {
         let id =
             self.new_non_primitive_ty(NonPrimitiveType::Struct(StructType::new(self, fields_ty)));
         Type::Struct(id)
     }
 
+    /// Creates an identified struct type. Unlike `new_struct_ty`, the result is never interned
+    /// with an existing type, and its body is left opaque until `set_struct_body` is called.
+    /// This allows a struct to contain a pointer to itself.
+    pub fn new_named_struct_ty(&self, name: impl Into<String>) -> Type {
+        let name = name.into();
+        assert!(
+            self.get_named_struct_ty(name.as_str()).is_none(),
+            "struct type '{}' is already defined",
+            name
+        );
+        let id = self
+            .base
+            .borrow_mut()
+            .non_primitive_types
+            .alloc(NonPrimitiveType::Struct(StructType::new_opaque(name)));
+        Type::Struct(id)
+    }
+
+    pub fn set_struct_body(&self, ty: Type, fields_ty: Vec<Type>) {
+        let id = match ty {
+            Type::Struct(id) => id,
+            _ => panic!("set_struct_body: {} is not a struct type", self.to_string(ty)),
+        };
+        let mut body = StructType::new(self, fields_ty);
+        let mut base = self.base.borrow_mut();
+        let s = base.non_primitive_types[id].as_struct_mut();
+        assert!(s.is_opaque(), "the body of a struct type can be set only once");
+        body.name = s.name.take();
+        *s = body;
+    }
+
+    pub fn get_named_struct_ty(&self, name: &str) -> Option<Type> {
+        self.base.borrow().get_named_struct_ty(name)
+    }
+
     // pub fn as_function_ty(&self, ty: Type) -> Option<&FunctionType> {
     //     match ty {
     //         Type::Function(id) => Some(self.non_primitive_types[id].as_function()),
@@ -140,6 +210,11 @@ impl Types {
                     .as_array()
                     .elem_ty,
             ),
+            Type::Vector(id) => Some(
+                self.base.borrow().non_primitive_types[id]
+                    .as_vector()
+                    .elem_ty,
+            ),
             Type::Struct(id) => Some(
                 self.base.borrow().non_primitive_types[id]
                     .as_struct()
@@ -167,6 +242,7 @@ impl Types {
             | Type::Int32
             | Type::Int64
             | Type::F64
+            | Type::Vector(_)
             | Type::Function(_) => None,
             Type::Pointer(id) => match indices.len() {
                 1 => Some(*self.base.borrow().non_primitive_types[id].as_pointer()),
@@ -208,6 +284,14 @@ impl Types {
         self.base.borrow().to_string(ty)
     }
 
+    /// Returns the element type and the number of elements of a vector type.
+    pub fn as_vector_ty(&self, ty: Type) -> Option<(Type, usize)> {
+        self.base
+            .borrow()
+            .as_vector_ty(ty)
+            .map(|v| (v.elem_ty, v.len))
+    }
+
     // pub fn get_pointer_ty(&self) -> Type {
     //     Type::Pointer(Box::new(self.clone()))
     // }
@@ -246,7 +330,13 @@ impl TypesBase {
                 Type::Struct(_) => {
                     let ptr = self.new_pointer_ty(*ty);
                     *ty = ptr;
-                    params_attr.insert(i, ParamAttribute { byval: true });
+                    params_attr.insert(
+                        i,
+                        ParamAttribute {
+                            byval: true,
+                            ..Default::default()
+                        },
+                    );
                 }
                 _ => {}
             }
@@ -278,10 +368,41 @@ impl TypesBase {
         }
     }
 
+    pub fn as_vector_ty(&self, ty: Type) -> Option<&VectorType> {
+        match ty {
+            Type::Vector(id) => Some(self.non_primitive_types[id].as_vector()),
+            _ => None,
+        }
+    }
+
+    pub fn get_named_struct_ty(&self, name: &str) -> Option<Type> {
+        self.non_primitive_types.iter().find_map(|(id, t)| match t {
+            NonPrimitiveType::Struct(s) if s.name() == Some(name) => Some(Type::Struct(id)),
+            _ => None,
+        })
+    }
+
+    pub fn named_struct_tys(&self) -> Vec<Type> {
+        self.non_primitive_types
+            .iter()
+            .filter_map(|(id, t)| match t {
+                NonPrimitiveType::Struct(s) if s.name().is_some() => Some(Type::Struct(id)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Prints the body of an identified struct type, e.g. `struct Node = {struct Node*, i32}`.
+    pub fn struct_definition_to_string(&self, ty: Type) -> String {
+        let s = self.as_struct_ty(ty).unwrap();
+        format!("{} = {}", self.to_string(ty), s.body_to_string(self))
+    }
+
     pub fn get_element_ty(&self, ty: Type, index: Option<&Value>) -> Option<Type> {
         match ty {
             Type::Pointer(id) => Some(*self.non_primitive_types[id].as_pointer()),
             Type::Array(id) => Some(self.non_primitive_types[id].as_array().elem_ty),
+            Type::Vector(id) => Some(self.non_primitive_types[id].as_vector().elem_ty),
             Type::Struct(id) => Some(
                 self.non_primitive_types[id].as_struct().fields_ty
                     [index.unwrap().as_imm().as_int32() as usize],
@@ -308,6 +429,7 @@ impl TypesBase {
             | Type::Int32
             | Type::Int64
             | Type::F64
+            | Type::Vector(_)
             | Type::Function(_) => None,
             Type::Pointer(id) => match indices.len() {
                 1 => Some(*self.non_primitive_types[id].as_pointer()),
@@ -361,6 +483,10 @@ impl TypesBase {
                 let s = self.non_primitive_types[id].as_struct();
                 s.to_string(self)
             }
+            Type::Vector(id) => {
+                let v = self.non_primitive_types[id].as_vector();
+                v.to_string(self)
+            }
         }
     }
 
@@ -385,6 +511,10 @@ impl Type {
         matches!(self, Self::F64)
     }
 
+    pub fn is_vector(&self) -> bool {
+        matches!(self, Self::Vector(_))
+    }
+
     pub fn to_string(&self) -> String {
         match self {
             Type::Void => "void".to_string(),
@@ -397,6 +527,7 @@ impl Type {
             Type::Array(id) => format!("arrty:{}", id.index()),
             Type::Function(id) => format!("functy:{}", id.index()),
             Type::Struct(id) => format!("structty:{}", id.index()),
+            Type::Vector(id) => format!("vecty:{}", id.index()),
         }
     }
 }
@@ -423,10 +554,10 @@ impl FunctionType {
                 .enumerate()
                 .fold("".to_string(), |mut s, (i, p)| {
                     s += &(tys.to_string(*p)
-                        + self
+                        + &self
                             .params_attr
                             .get(&i)
-                            .map_or("", |a| if a.byval { " byval" } else { "" })
+                            .map_or("".to_string(), |a| a.to_string())
                         + ", ");
                     s
                 })
@@ -435,6 +566,19 @@ impl FunctionType {
     }
 }
 
+impl ParamAttribute {
+    pub fn to_string(&self) -> String {
+        let mut s = "".to_string();
+        if self.byval {
+            s += " byval"
+        }
+        if self.noalias {
+            s += " noalias"
+        }
+        s
+    }
+}
+
 impl ArrayType {
     pub fn new(elem_ty: Type, len: usize) -> Self {
         Self { elem_ty, len }
@@ -445,9 +589,21 @@ impl ArrayType {
     }
 }
 
+impl VectorType {
+    pub fn new(elem_ty: Type, len: usize) -> Self {
+        Self { elem_ty, len }
+    }
+
+    pub fn to_string(&self, tys: &TypesBase) -> String {
+        format!("<{} x {}>", self.len, tys.to_string(self.elem_ty))
+    }
+}
+
 impl StructType {
     pub fn new(tys: &Types, fields_ty: Vec<Type>) -> Self {
         let mut self_ = Self {
+            name: None,
+            is_opaque: false,
             fields_ty,
             fields_offset: vec![],
             align: 0,
@@ -457,6 +613,17 @@ impl StructType {
         self_
     }
 
+    pub fn new_opaque(name: String) -> Self {
+        Self {
+            name: Some(name),
+            is_opaque: true,
+            fields_ty: vec![],
+            fields_offset: vec![],
+            align: 1,
+            size: 0,
+        }
+    }
+
     pub fn compute_elem_offsets(&mut self, tys: &Types) {
         let mut align = 1;
         let mut offset = 0;
@@ -481,6 +648,14 @@ impl StructType {
         self.align
     }
 
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub const fn is_opaque(&self) -> bool {
+        self.is_opaque
+    }
+
     pub fn get_elem_offset(&self, i: usize) -> Option<&usize> {
         self.fields_offset.get(i)
     }
@@ -493,8 +668,19 @@ impl StructType {
     }
 
     pub fn to_string(&self, tys: &TypesBase) -> String {
+        match &self.name {
+            // Printing the body of an identified struct here could recurse forever
+            Some(name) => format!("struct {}", name),
+            None => format!("struct {}", self.body_to_string(tys)),
+        }
+    }
+
+    pub fn body_to_string(&self, tys: &TypesBase) -> String {
+        if self.is_opaque {
+            return "opaque".to_string();
+        }
         format!(
-            "struct {{{}}}",
+            "{{{}}}",
             self.fields_ty
                 .iter()
                 .fold("".to_string(), |mut s, t| {
@@ -534,6 +720,20 @@ impl NonPrimitiveType {
             _ => panic!(),
         }
     }
+
+    pub fn as_struct_mut(&mut self) -> &mut StructType {
+        match self {
+            NonPrimitiveType::Struct(s) => s,
+            _ => panic!(),
+        }
+    }
+
+    pub fn as_vector(&self) -> &VectorType {
+        match self {
+            NonPrimitiveType::Vector(v) => v,
+            _ => panic!(),
+        }
+    }
 }
 
 impl fmt::Debug for Type {
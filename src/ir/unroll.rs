@@ -0,0 +1,549 @@
+use crate::analysis::{
+    dom_tree::DominatorTreeConstructor,
+    loops::{Loop, LoopsConstructor},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    module::Module,
+    opcode::{ICmpKind, Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{ImmediateValue, InstructionValue, Value},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Unrolls innermost loops whose trip count is a constant. A loop is unrolled fully if it fits
+/// within the threshold. Otherwise its body is replicated `factor` times into a new loop, and
+/// the original loop runs the iterations left over.
+///
+/// Only loops of the shape a `while` statement produces are handled: a preheader, a header
+/// testing `iv < limit` (or `<=`) and leaving the loop, and a single latch. `iv` must be a phi
+/// in the header starting from a constant and incremented by a constant.
+pub struct LoopUnroll {
+    /// Number of instructions unrolling may add to a function
+    threshold: usize,
+    factor: usize,
+}
+
+struct LoopUnrollOnFunction<'a> {
+    func: &'a mut Function,
+    budget: usize,
+    factor: usize,
+    fully_unrolled: usize,
+    partially_unrolled: usize,
+}
+
+struct UnrollableLoop {
+    preheader: BasicBlockId,
+    header: BasicBlockId,
+    latch: BasicBlockId,
+    exit: BasicBlockId,
+    /// Blocks of the loop in reverse post order, starting from the header
+    blocks: Vec<BasicBlockId>,
+    kind: ICmpKind,
+    iv: InstructionId,
+    limit: i32,
+    step: i32,
+    trip_count: usize,
+}
+
+impl LoopUnroll {
+    pub fn new(threshold: usize, factor: usize) -> Self {
+        Self { threshold, factor }
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            LoopUnrollOnFunction {
+                func,
+                budget: self.threshold,
+                factor: self.factor,
+                fully_unrolled: 0,
+                partially_unrolled: 0,
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> LoopUnrollOnFunction<'a> {
+    pub fn run(mut self) {
+        let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+        let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+
+        // Innermost loops don't share blocks, so unrolling one leaves the others intact
+        let candidates: Vec<UnrollableLoop> = loops
+            .arena
+            .iter()
+            .filter_map(|(_, l)| self.analyze(l))
+            .collect();
+
+        for l in candidates {
+            let size: usize = l
+                .blocks
+                .iter()
+                .map(|&bb| self.func.basic_blocks.arena[bb].iseq_ref().len())
+                .sum();
+            if size * l.trip_count <= self.budget {
+                self.budget -= size * l.trip_count;
+                self.unroll_fully(&l);
+                self.fully_unrolled += 1;
+            } else if self.factor > 1
+                && l.trip_count >= self.factor
+                && size * self.factor <= self.budget
+            {
+                if self.unroll_partially(&l) {
+                    self.budget -= size * self.factor;
+                    self.partially_unrolled += 1;
+                }
+            }
+        }
+
+        self.func.recompute_cfg();
+        self.func.remove_unreachable_blocks();
+
+        debug!(println!(
+            "function '{}': {} loops fully unrolled, {} loops partially unrolled",
+            self.func.name, self.fully_unrolled, self.partially_unrolled
+        ));
+    }
+
+    fn analyze(&self, l: &Loop<BasicBlock>) -> Option<UnrollableLoop> {
+        if l.sub_loops().len() > 0 {
+            return None;
+        }
+
+        let bbs = &self.func.basic_blocks;
+        let header = l.header();
+        let (preheader, latch) = match (l.preheader(bbs), l.latch(bbs)) {
+            (Some(preheader), Some(latch)) => (preheader, latch),
+            _ => return None,
+        };
+        // Only the header may leave the loop
+        if l.exiting_blocks(bbs).iter().any(|&bb| bb != header) {
+            return None;
+        }
+
+        let br = &self.func.inst_table[self.func.terminator(header)];
+        if br.opcode != Opcode::CondBr {
+            return None;
+        }
+        let (body, exit) = (
+            *br.operands[1].as_basic_block(),
+            *br.operands[2].as_basic_block(),
+        );
+        if !l.contains(body) || l.contains(exit) {
+            return None;
+        }
+
+        let cond = match br.operands[0].as_value() {
+            Value::Instruction(InstructionValue { id, .. }) => &self.func.inst_table[*id],
+            _ => return None,
+        };
+        if cond.opcode != Opcode::ICmp {
+            return None;
+        }
+        let kind = *cond.operands[0].as_icmp_kind();
+        let (iv, limit) = match (cond.operands[1].as_value(), cond.operands[2].as_value()) {
+            (
+                Value::Instruction(InstructionValue { id, .. }),
+                Value::Immediate(ImmediateValue::Int32(limit)),
+            ) if kind != ICmpKind::Eq => (*id, *limit),
+            _ => return None,
+        };
+
+        let phi = &self.func.inst_table[iv];
+        if phi.opcode != Opcode::Phi || phi.parent != header {
+            return None;
+        }
+        let incoming = |bb: BasicBlockId| {
+            phi.operands
+                .chunks(2)
+                .find(|pair| *pair[1].as_basic_block() == bb)
+                .map(|pair| *pair[0].as_value())
+        };
+        let init = match incoming(preheader)? {
+            Value::Immediate(ImmediateValue::Int32(init)) => init,
+            _ => return None,
+        };
+        let next = match incoming(latch)? {
+            Value::Instruction(InstructionValue { id, .. }) => &self.func.inst_table[id],
+            _ => return None,
+        };
+        let is_iv = |op: &Operand| op == &Operand::Value(self.func.inst_value(iv));
+        let step = match (next.opcode, &next.operands[0], &next.operands[1]) {
+            (Opcode::Add, x, Operand::Value(Value::Immediate(ImmediateValue::Int32(s))))
+            | (Opcode::Add, Operand::Value(Value::Immediate(ImmediateValue::Int32(s))), x)
+                if is_iv(x) && *s > 0 =>
+            {
+                *s
+            }
+            _ => return None,
+        };
+
+        let (init, limit_, step_) = (init as i64, limit as i64, step as i64);
+        let trip_count = match kind {
+            ICmpKind::Lt if init < limit_ => (limit_ - init + step_ - 1) / step_,
+            ICmpKind::Le if init <= limit_ => (limit_ - init) / step_ + 1,
+            _ => 0,
+        };
+        // The induction variable must not wrap around
+        if init + trip_count * step_ > i32::MAX as i64 {
+            return None;
+        }
+
+        Some(UnrollableLoop {
+            preheader,
+            header,
+            latch,
+            exit,
+            blocks: self.reverse_post_order(l),
+            kind,
+            iv,
+            limit,
+            step,
+            trip_count: trip_count as usize,
+        })
+    }
+
+    /// Replaces the loop with `trip_count` copies of its blocks followed by a copy of the
+    /// header, which leaves to the exit.
+    fn unroll_fully(&mut self, l: &UnrollableLoop) {
+        let phis = self.header_phis(l);
+
+        // Header phis of the first iteration take the values from the preheader
+        let mut values: FxHashMap<InstructionId, Value> = phis
+            .iter()
+            .map(|&phi| (phi, self.func.phi_incoming(phi, l.preheader).unwrap()))
+            .collect();
+        // The latch of the previous iteration and the header it jumps to
+        let mut prev_latch: Option<(BasicBlockId, BasicBlockId)> = None;
+        let mut first_header = None;
+        let mut new_blocks = vec![];
+
+        for k in 0..=l.trip_count {
+            let last = k == l.trip_count;
+            let blocks = if last { &l.blocks[..1] } else { &l.blocks[..] };
+            let bbs = self.clone_blocks(blocks, &mut values);
+            new_blocks.extend(blocks.iter().map(|bb| bbs[bb]));
+            let header = bbs[&l.header];
+            first_header.get_or_insert(header);
+
+            if let Some((prev_latch, prev_header)) = prev_latch {
+                self.func
+                    .replace_branch_target(prev_latch, prev_header, header);
+            }
+            let dst = if last { l.exit } else { bbs[&self.body(l)] };
+            self.replace_cond_br(header, dst);
+
+            if last {
+                // Values computed in the header are used after the loop
+                for val in self.func.insts_of(l.header) {
+                    let new = values[&val];
+                    Instruction::replace_all_uses(
+                        &mut self.func.inst_table,
+                        val,
+                        Operand::Value(new),
+                    );
+                }
+                self.replace_phi_block(l.exit, l.header, header);
+            } else {
+                prev_latch = Some((bbs[&l.latch], header));
+                values = phis
+                    .iter()
+                    .map(|&phi| {
+                        let v = self.func.phi_incoming(phi, l.latch).unwrap();
+                        (phi, v.remap(&values))
+                    })
+                    .collect();
+            }
+        }
+
+        self.func
+            .replace_branch_target(l.preheader, l.header, first_header.unwrap());
+        self.place_before(&new_blocks, l.header);
+    }
+
+    /// Builds a new loop executing `factor` iterations of the loop at a time while at least
+    /// `factor` iterations remain. The original loop then executes the remaining ones.
+    fn unroll_partially(&mut self, l: &UnrollableLoop) -> bool {
+        // The unrolled loop continues while `iv + (factor - 1) * step <kind> limit`
+        let new_limit = l.limit as i64 - (self.factor as i64 - 1) * l.step as i64;
+        if new_limit < i32::MIN as i64 {
+            return false;
+        }
+
+        let phis = self.header_phis(l);
+        let mut values = FxHashMap::default();
+        let mut iterations = vec![];
+        let mut new_blocks = vec![];
+
+        for k in 0..self.factor {
+            let mut next_values = FxHashMap::default();
+            if k > 0 {
+                let prev: &FxHashMap<InstructionId, Value> = &values;
+                for &phi in &phis {
+                    let v = self.func.phi_incoming(phi, l.latch).unwrap();
+                    next_values.insert(phi, v.remap(prev));
+                }
+            }
+            values = next_values;
+            let bbs = self.clone_blocks(&l.blocks, &mut values);
+            new_blocks.extend(l.blocks.iter().map(|bb| bbs[bb]));
+            iterations.push((bbs, values.clone()));
+        }
+
+        let first_header = iterations[0].0[&l.header];
+        let last_latch = iterations[self.factor - 1].0[&l.latch];
+        for k in 0..self.factor {
+            let header = iterations[k].0[&l.header];
+            let latch = iterations[k].0[&l.latch];
+            let next_header = iterations[(k + 1) % self.factor].0[&l.header];
+            self.func.replace_branch_target(latch, header, next_header);
+            if k > 0 {
+                let body = iterations[k].0[&self.body(l)];
+                self.replace_cond_br(header, body);
+            }
+        }
+
+        // The first header of the unrolled loop tests the new limit and falls into the
+        // original loop when leaving
+        let iv = iterations[0].1[&l.iv];
+        let cmp = self.func.alloc_inst(Instruction::new(
+            Opcode::ICmp,
+            vec![
+                Operand::ICmpKind(l.kind),
+                Operand::Value(iv),
+                Operand::Value(Value::new_imm_int32(new_limit as i32)),
+            ],
+            Type::Int1,
+            first_header,
+        ));
+        let cmp = self.func.inst_value(cmp);
+        {
+            let mut iseq = self.func.basic_blocks.arena[first_header].iseq_ref_mut();
+            let terminator = iseq.len() - 1;
+            iseq.insert(terminator, cmp);
+        }
+        let br = self.func.terminator(first_header);
+        let old_cond = self.func.inst_table[br].operands[0];
+        Instruction::replace_operand(
+            &mut self.func.inst_table,
+            br,
+            &old_cond,
+            Operand::Value(cmp),
+        );
+        self.remove_if_unused(old_cond);
+        self.func
+            .replace_branch_target(first_header, l.exit, l.header);
+
+        for &phi in &phis {
+            let ty = self.func.inst_table[phi].ty;
+            let init = self.func.phi_incoming(phi, l.preheader).unwrap();
+            let next = self
+                .func
+                .phi_incoming(phi, l.latch)
+                .unwrap()
+                .remap(&iterations[self.factor - 1].1);
+            let new_phi = iterations[0].1[&phi].as_instruction().id;
+            self.func.change_inst(
+                new_phi,
+                Instruction::new(
+                    Opcode::Phi,
+                    vec![
+                        Operand::Value(init),
+                        Operand::BasicBlock(l.preheader),
+                        Operand::Value(next),
+                        Operand::BasicBlock(last_latch),
+                    ],
+                    ty,
+                    first_header,
+                ),
+            );
+
+            // The original loop starts from where the unrolled loop stopped
+            let operands = self.func.inst_table[phi]
+                .operands
+                .chunks(2)
+                .flat_map(|pair| {
+                    if *pair[1].as_basic_block() == l.preheader {
+                        vec![
+                            Operand::Value(iterations[0].1[&phi]),
+                            Operand::BasicBlock(first_header),
+                        ]
+                    } else {
+                        pair.to_vec()
+                    }
+                })
+                .collect();
+            self.func
+                .change_inst(phi, Instruction::new(Opcode::Phi, operands, ty, l.header));
+        }
+
+        self.func
+            .replace_branch_target(l.preheader, l.header, first_header);
+        self.place_before(&new_blocks, l.header);
+        true
+    }
+
+    /// Copies `blocks` for one iteration. `values` maps instructions of the loop to their
+    /// values in the iteration. It may be filled in advance for the header phis, which are then
+    /// not copied. Returns the copy of every block.
+    fn clone_blocks(
+        &mut self,
+        blocks: &[BasicBlockId],
+        values: &mut FxHashMap<InstructionId, Value>,
+    ) -> FxHashMap<BasicBlockId, BasicBlockId> {
+        let bbs: FxHashMap<BasicBlockId, BasicBlockId> = blocks
+            .iter()
+            .map(|&bb| (bb, self.func.append_basic_block()))
+            .collect();
+
+        for &bb in blocks {
+            let iseq: Vec<InstructionId> = self.func.basic_blocks.arena[bb]
+                .iseq_ref()
+                .iter()
+                .map(|v| v.as_instruction().id)
+                .collect();
+            for id in iseq {
+                if values.contains_key(&id) {
+                    continue;
+                }
+                let inst = &self.func.inst_table[id];
+                let operands = inst
+                    .operands
+                    .iter()
+                    .map(|op| match op {
+                        Operand::Value(v) => Operand::Value(v.remap(values)),
+                        Operand::BasicBlock(b) => Operand::BasicBlock(*bbs.get(b).unwrap_or(b)),
+                        op => *op,
+                    })
+                    .collect();
+                let mut new = Instruction::new(inst.opcode, operands, inst.ty, bbs[&bb])
+                    .with_mem_attr(inst.mem_attr);
+                // Header phis replaced by constants often make instructions constant or
+                // trivial, which must be simplified like the builder does
+                if let Some(val) = simplify(&mut new) {
+                    values.insert(id, val);
+                    continue;
+                }
+                let new = self.func.alloc_inst(new);
+                let val = self.func.inst_value(new);
+                self.func.basic_blocks.arena[bbs[&bb]]
+                    .iseq_ref_mut()
+                    .push(val);
+                values.insert(id, val);
+            }
+        }
+
+        bbs
+    }
+
+    fn header_phis(&self, l: &UnrollableLoop) -> Vec<InstructionId> {
+        self.func
+            .insts_of(l.header)
+            .into_iter()
+            .filter(|&id| self.func.inst_table[id].opcode == Opcode::Phi)
+            .collect()
+    }
+
+    fn body(&self, l: &UnrollableLoop) -> BasicBlockId {
+        let br = &self.func.inst_table[self.func.terminator(l.header)];
+        *br.operands[1].as_basic_block()
+    }
+
+    /// Replaces the conditional branch terminating `bb` with an unconditional branch to `dst`
+    fn replace_cond_br(&mut self, bb: BasicBlockId, dst: BasicBlockId) {
+        let cond = self.func.inst_table[self.func.terminator(bb)].operands[0];
+        self.func.replace_terminator(bb, dst);
+        self.remove_if_unused(cond);
+    }
+
+    fn remove_if_unused(&mut self, cond: Operand) {
+        if let Operand::Value(Value::Instruction(InstructionValue { id, .. })) = cond {
+            if self.func.inst_table[id].users.borrow().len() == 0 {
+                self.func.remove_inst(id);
+            }
+        }
+    }
+
+    /// Makes the phis in `bb` take the values incoming from `from` from `to` instead
+    fn replace_phi_block(&mut self, bb: BasicBlockId, from: BasicBlockId, to: BasicBlockId) {
+        for id in self.func.insts_of(bb) {
+            if self.func.inst_table[id].opcode == Opcode::Phi {
+                Instruction::replace_operand(
+                    &mut self.func.inst_table,
+                    id,
+                    &Operand::BasicBlock(from),
+                    Operand::BasicBlock(to),
+                );
+            }
+        }
+    }
+
+    /// Moves `blocks`, which were appended to the function, right before `bb`
+    fn place_before(&mut self, blocks: &[BasicBlockId], bb: BasicBlockId) {
+        let order = &mut self.func.basic_blocks.order;
+        order.retain(|b| !blocks.contains(b));
+        let pos = order.iter().position(|&b| b == bb).unwrap();
+        for (i, &b) in blocks.iter().enumerate() {
+            order.insert(pos + i, b);
+        }
+    }
+
+    fn reverse_post_order(&self, l: &Loop<BasicBlock>) -> Vec<BasicBlockId> {
+        fn visit(
+            func: &Function,
+            l: &Loop<BasicBlock>,
+            bb: BasicBlockId,
+            visited: &mut FxHashSet<BasicBlockId>,
+            order: &mut Vec<BasicBlockId>,
+        ) {
+            visited.insert(bb);
+            for &succ in &func.basic_blocks.arena[bb].succ {
+                if succ != l.header() && l.contains(succ) && !visited.contains(&succ) {
+                    visit(func, l, succ, visited, order);
+                }
+            }
+            order.push(bb);
+        }
+
+        let mut order = vec![];
+        visit(
+            self.func,
+            l,
+            l.header(),
+            &mut FxHashSet::default(),
+            &mut order,
+        );
+        order.reverse();
+        order
+    }
+}
+
+/// Folds `inst` into a value if possible. Otherwise, puts an immediate operand of a commutative
+/// operation second.
+fn simplify(inst: &mut Instruction) -> Option<Value> {
+    if let Some(konst) = inst.fold_const() {
+        return Some(konst);
+    }
+    if !matches!(inst.opcode, Opcode::Add | Opcode::Sub | Opcode::Mul) {
+        return None;
+    }
+    if inst.opcode != Opcode::Sub && matches!(inst.operands[0], Operand::Value(Value::Immediate(_)))
+    {
+        inst.operands.swap(0, 1);
+    }
+    let x = *inst.operands[0].as_value();
+    match (inst.opcode, inst.operands[1].as_value()) {
+        (Opcode::Add, Value::Immediate(ImmediateValue::Int32(0)))
+        | (Opcode::Sub, Value::Immediate(ImmediateValue::Int32(0)))
+        | (Opcode::Mul, Value::Immediate(ImmediateValue::Int32(1))) => Some(x),
+        (Opcode::Mul, Value::Immediate(ImmediateValue::Int32(0))) => Some(Value::new_imm_int32(0)),
+        _ => None,
+    }
+}
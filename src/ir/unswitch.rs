@@ -0,0 +1,353 @@
+use crate::analysis::{
+    dom_tree::{DominatorTree, DominatorTreeConstructor},
+    loops::{Loop, LoopsConstructor},
+};
+use crate::ir::{
+    basic_block::{BasicBlock, BasicBlockId},
+    function::Function,
+    module::Module,
+    opcode::{Instruction, InstructionId, Opcode, Operand},
+    types::Type,
+    value::{InstructionValue, Value},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+/// Loop unswitching. A conditional branch in a loop whose condition is loop invariant is hoisted
+/// out of the loop: the loop is cloned, the preheader branches to the original loop if the
+/// condition holds and to the clone otherwise, and in each copy the branch becomes an
+/// unconditional one to the side it always takes.
+///
+/// Only loops with a preheader and no `Alloca` are handled. Values of the loop used after it are
+/// merged by phis in the exit blocks.
+pub struct LoopUnswitch {
+    /// Number of instructions unswitching may add to a function
+    threshold: usize,
+}
+
+struct LoopUnswitchOnFunction<'a> {
+    func: &'a mut Function,
+    budget: usize,
+    unswitched: usize,
+}
+
+struct UnswitchableLoop {
+    preheader: BasicBlockId,
+    header: BasicBlockId,
+    /// Blocks of the loop in the order of the function
+    blocks: Vec<BasicBlockId>,
+    /// The branch whose condition is invariant
+    branch: InstructionId,
+    /// The only exit of the loop, if any
+    exit: Option<BasicBlockId>,
+    /// (value, its users after the loop needing a phi in `exit` to see the value of the clone)
+    merges: Vec<(InstructionId, Vec<InstructionId>)>,
+}
+
+impl LoopUnswitch {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold }
+    }
+
+    pub fn run_on_module(&mut self, module: &mut Module) {
+        for (_, func) in &mut module.functions {
+            if func.is_internal || func.basic_blocks.order.len() == 0 {
+                continue;
+            }
+
+            LoopUnswitchOnFunction {
+                func,
+                budget: self.threshold,
+                unswitched: 0,
+            }
+            .run()
+        }
+    }
+}
+
+impl<'a> LoopUnswitchOnFunction<'a> {
+    fn run(mut self) {
+        // Both copies of an unswitched loop may have other invariant branches, so loops are
+        // analyzed again after each unswitching
+        loop {
+            let dom_tree = DominatorTreeConstructor::new(&self.func.basic_blocks).construct();
+            let loops = LoopsConstructor::new(&dom_tree, &self.func.basic_blocks).analyze();
+            let l = loops
+                .arena
+                .iter()
+                .find_map(|(_, l)| self.analyze(l, &dom_tree));
+            match l {
+                Some(l) => self.unswitch(l),
+                None => break,
+            }
+        }
+
+        debug!(println!(
+            "function '{}': {} loops unswitched",
+            self.func.name, self.unswitched
+        ));
+    }
+
+    fn analyze(
+        &self,
+        l: &Loop<BasicBlock>,
+        dom_tree: &DominatorTree<BasicBlock>,
+    ) -> Option<UnswitchableLoop> {
+        let arena = &self.func.basic_blocks.arena;
+        let preheader = l.preheader(&self.func.basic_blocks)?;
+
+        let blocks: Vec<BasicBlockId> = self
+            .func
+            .basic_blocks
+            .order
+            .iter()
+            .copied()
+            .filter(|&bb| l.contains(bb))
+            .collect();
+        let insts: Vec<InstructionId> = blocks
+            .iter()
+            .flat_map(|&bb| self.func.insts_of(bb))
+            .collect();
+        if insts.len() > self.budget
+            || insts
+                .iter()
+                .any(|&id| self.func.inst_table[id].opcode == Opcode::Alloca)
+        {
+            return None;
+        }
+
+        let branch = blocks
+            .iter()
+            .map(|&bb| self.func.terminator(bb))
+            .find(|&id| {
+                let inst = &self.func.inst_table[id];
+                inst.opcode == Opcode::CondBr
+                    && inst.operands[1] != inst.operands[2]
+                    && match inst.operands[0].as_value() {
+                        Value::Argument(_) => true,
+                        Value::Instruction(InstructionValue { id, .. }) => {
+                            !l.contains(self.func.inst_table[*id].parent)
+                        }
+                        _ => false,
+                    }
+            })?;
+
+        let exits: FxHashSet<BasicBlockId> = blocks
+            .iter()
+            .flat_map(|&bb| arena[bb].succ.iter().copied())
+            .filter(|&succ| !l.contains(succ))
+            .collect();
+        let exit = match exits.len() {
+            1 => exits.iter().next().copied(),
+            _ => None,
+        };
+
+        // Phis in the exits get the values of the clone by themselves. Other uses need a phi
+        // merging the values of both loops, which is only possible in a single exit entered
+        // from the loop alone.
+        let mut merges = vec![];
+        for &id in &insts {
+            let users: Vec<InstructionId> = self.func.inst_table[id]
+                .users
+                .borrow()
+                .iter()
+                .copied()
+                .filter(|&u| {
+                    let user = &self.func.inst_table[u];
+                    !l.contains(user.parent)
+                        && (user.opcode != Opcode::Phi
+                            || user
+                                .operands
+                                .chunks(2)
+                                .filter(|pair| *pair[0].as_value() == self.func.inst_value(id))
+                                .any(|pair| !l.contains(*pair[1].as_basic_block())))
+                })
+                .collect();
+            if users.is_empty() {
+                continue;
+            }
+            let exit = exit?;
+            let def = self.func.inst_table[id].parent;
+            if arena[exit]
+                .pred
+                .iter()
+                .any(|&p| !l.contains(p) || !dom_tree.dominate_bb(def, p))
+            {
+                return None;
+            }
+            merges.push((id, users));
+        }
+
+        Some(UnswitchableLoop {
+            preheader,
+            header: l.header(),
+            blocks,
+            branch,
+            exit,
+            merges,
+        })
+    }
+
+    fn unswitch(&mut self, l: UnswitchableLoop) {
+        let (bbs, values) = self.clone_blocks(&l.blocks);
+        self.budget -= values.len();
+        self.unswitched += 1;
+
+        let loop_blocks: FxHashSet<BasicBlockId> = l.blocks.iter().copied().collect();
+        let exits: FxHashSet<BasicBlockId> = l
+            .blocks
+            .iter()
+            .flat_map(|&bb| self.func.basic_blocks.arena[bb].succ.clone())
+            .filter(|succ| !loop_blocks.contains(succ))
+            .collect();
+        for exit in exits {
+            for id in self.func.insts_of(exit) {
+                if self.func.inst_table[id].opcode != Opcode::Phi {
+                    continue;
+                }
+                let incoming: Vec<(Value, BasicBlockId)> = self.func.inst_table[id]
+                    .operands
+                    .chunks(2)
+                    .map(|pair| (*pair[0].as_value(), *pair[1].as_basic_block()))
+                    .filter(|(_, bb)| loop_blocks.contains(bb))
+                    .collect();
+                for (val, bb) in incoming {
+                    let val = val.remap(&values);
+                    Instruction::add_operand(&mut self.func.inst_table, id, Operand::Value(val));
+                    Instruction::add_operand(
+                        &mut self.func.inst_table,
+                        id,
+                        Operand::BasicBlock(bbs[&bb]),
+                    );
+                }
+            }
+        }
+
+        for (id, users) in &l.merges {
+            let exit = l.exit.unwrap();
+            let val = self.func.inst_value(*id);
+            let mut operands = vec![];
+            for &pred in &self.func.basic_blocks.arena[exit].pred {
+                operands.push(Operand::Value(val));
+                operands.push(Operand::BasicBlock(pred));
+                operands.push(Operand::Value(values[id]));
+                operands.push(Operand::BasicBlock(bbs[&pred]));
+            }
+            let ty = self.func.inst_table[*id].ty;
+            let phi = self
+                .func
+                .alloc_inst(Instruction::new(Opcode::Phi, operands, ty, exit));
+            let phi = self.func.inst_value(phi);
+            self.func.basic_blocks.arena[exit]
+                .iseq_ref_mut()
+                .insert(0, phi);
+            for &user in users {
+                Instruction::replace_operand(
+                    &mut self.func.inst_table,
+                    user,
+                    &Operand::Value(val),
+                    Operand::Value(phi),
+                );
+            }
+        }
+
+        // The original loop is entered if the condition holds, the clone otherwise
+        let br = &self.func.inst_table[l.branch];
+        let cond = br.operands[0];
+        let (on_true, on_false) = (
+            *br.operands[1].as_basic_block(),
+            *br.operands[2].as_basic_block(),
+        );
+        let bb = br.parent;
+        let map_bb = |b: BasicBlockId| *bbs.get(&b).unwrap_or(&b);
+        self.func.replace_terminator(bb, on_true);
+        self.func.remove_phi_incoming(on_false, bb);
+        self.func.replace_terminator(bbs[&bb], map_bb(on_false));
+        self.func.remove_phi_incoming(map_bb(on_true), bbs[&bb]);
+
+        let br = self.func.terminator(l.preheader);
+        self.func.change_inst(
+            br,
+            Instruction::new(
+                Opcode::CondBr,
+                vec![
+                    cond,
+                    Operand::BasicBlock(l.header),
+                    Operand::BasicBlock(bbs[&l.header]),
+                ],
+                Type::Void,
+                l.preheader,
+            ),
+        );
+
+        // The clone precedes the original loop
+        let clones: Vec<BasicBlockId> = l.blocks.iter().map(|bb| bbs[bb]).collect();
+        let order = &mut self.func.basic_blocks.order;
+        order.retain(|b| !clones.contains(b));
+        let pos = order.iter().position(|&b| b == l.header).unwrap();
+        for (i, &b) in clones.iter().enumerate() {
+            order.insert(pos + i, b);
+        }
+
+        self.func.recompute_cfg();
+        self.func.remove_unreachable_blocks();
+    }
+
+    /// Clones `blocks`, returning the clone of each block and of each instruction in them
+    fn clone_blocks(
+        &mut self,
+        blocks: &[BasicBlockId],
+    ) -> (
+        FxHashMap<BasicBlockId, BasicBlockId>,
+        FxHashMap<InstructionId, Value>,
+    ) {
+        let bbs: FxHashMap<BasicBlockId, BasicBlockId> = blocks
+            .iter()
+            .map(|&bb| (bb, self.func.append_basic_block()))
+            .collect();
+
+        let mut values = FxHashMap::default();
+        let mut clones = vec![];
+        for &bb in blocks {
+            for id in self.func.insts_of(bb) {
+                let inst = &self.func.inst_table[id];
+                let operands = inst
+                    .operands
+                    .iter()
+                    .map(|op| match op {
+                        Operand::Value(v) => Operand::Value(v.remap(&values)),
+                        Operand::BasicBlock(b) => Operand::BasicBlock(*bbs.get(b).unwrap_or(b)),
+                        op => *op,
+                    })
+                    .collect();
+                let new = Instruction::new(inst.opcode, operands, inst.ty, bbs[&bb])
+                    .with_mem_attr(inst.mem_attr);
+                let new = self.func.alloc_inst(new);
+                let val = self.func.inst_value(new);
+                self.func.basic_blocks.arena[bbs[&bb]]
+                    .iseq_ref_mut()
+                    .push(val);
+                values.insert(id, val);
+                clones.push(new);
+            }
+        }
+
+        // Phis refer to values defined later in the loop
+        for id in clones {
+            let operands = self.func.inst_table[id].operands.clone();
+            for op in operands {
+                if let Operand::Value(Value::Instruction(InstructionValue { id: orig, .. })) = op {
+                    if let Some(&to) = values.get(&orig) {
+                        Instruction::replace_operand(
+                            &mut self.func.inst_table,
+                            id,
+                            &op,
+                            Operand::Value(to),
+                        );
+                    }
+                }
+            }
+        }
+
+        (bbs, values)
+    }
+}
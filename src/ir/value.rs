@@ -1,23 +1,29 @@
 use super::{
-    function::*, global_val::GlobalVariableId, module::*, opcode::*, types::*, DumpToString,
+    function::*, global_val::GlobalVariableId, inline_asm::InlineAsmId, module::*, opcode::*,
+    types::*, DumpToString,
 };
+use rustc_hash::FxHashMap;
 use std::hash;
 
 macro_rules! const_op {
-    ($name:ident, $op:tt) => {
-    pub fn $name(&self, v: &Value) -> Option<Value> {
+    ($vis:vis $name:ident, $wrapping:ident, $op:tt) => {
+    $vis fn $name(&self, v: &Value) -> Option<Value> {
         use ImmediateValue::*;
         match (self, v) {
-            (Value::Immediate(Int32(i1)), Value::Immediate(Int32(i2))) => Some(Value::Immediate(Int32(i1 $op i2))),
+            (Value::Immediate(Int8(i1)), Value::Immediate(Int8(i2))) => Some(Value::Immediate(Int8(i1.$wrapping(*i2)))),
+            (Value::Immediate(Int32(i1)), Value::Immediate(Int32(i2))) => Some(Value::Immediate(Int32(i1.$wrapping(*i2)))),
+            (Value::Immediate(Int64(i1)), Value::Immediate(Int64(i2))) => Some(Value::Immediate(Int64(i1.$wrapping(*i2)))),
             (Value::Immediate(F64(i1)), Value::Immediate(F64(i2))) => Some(Value::Immediate(F64(i1 $op i2))),
             _ => None,
         }
     } };
-    (int_only $name:ident, $op:tt) => {
-    pub fn $name(&self, v: &Value) -> Option<Value> {
+    (int_only $name:ident, $wrapping:ident) => {
+    fn $name(&self, v: &Value) -> Option<Value> {
         use ImmediateValue::*;
         match (self, v) {
-            (Value::Immediate(Int32(i1)), Value::Immediate(Int32(i2))) => Some(Value::Immediate(Int32(i1 $op i2))),
+            (Value::Immediate(Int8(i1)), Value::Immediate(Int8(i2))) => Some(Value::Immediate(Int8(i1.$wrapping(*i2)))),
+            (Value::Immediate(Int32(i1)), Value::Immediate(Int32(i2))) => Some(Value::Immediate(Int32(i1.$wrapping(*i2)))),
+            (Value::Immediate(Int64(i1)), Value::Immediate(Int64(i2))) => Some(Value::Immediate(Int64(i1.$wrapping(*i2)))),
             _ => None,
         }
     } }
@@ -30,6 +36,7 @@ pub enum Value {
     Instruction(InstructionValue),
     Function(FunctionValue),
     Global(GlobalValue),
+    InlineAsm(InlineAsmValue),
     None,
 }
 
@@ -59,6 +66,12 @@ pub struct GlobalValue {
     pub ty: Type,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+pub struct InlineAsmValue {
+    pub id: InlineAsmId,
+    pub ty: Type,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ImmediateValue {
     Int8(i8),
@@ -103,6 +116,7 @@ impl Value {
             Value::Instruction(InstructionValue { ty, .. }) => *ty,
             Value::Function(FunctionValue { ty, .. }) => *ty,
             Value::Global(GlobalValue { ty, .. }) => *ty,
+            Value::InlineAsm(InlineAsmValue { ty, .. }) => *ty,
             Value::Immediate(ref im) => *im.get_type(),
             Value::None => Type::Void,
         }
@@ -115,13 +129,45 @@ impl Value {
         }
     }
 
+    /// Returns the value `self` is mapped to in `values` if `self` is an instruction in it
+    pub fn remap(self, values: &FxHashMap<InstructionId, Value>) -> Value {
+        match self {
+            Value::Instruction(InstructionValue { id, .. }) => *values.get(&id).unwrap_or(&self),
+            v => v,
+        }
+    }
+
     // Constant folding
 
-    const_op!(const_add, +);
-    const_op!(const_sub, -);
-    const_op!(const_mul, *);
-    const_op!(const_div, /);
-    const_op!(int_only const_rem, %);
+    const_op!(pub const_add, wrapping_add, +);
+    const_op!(pub const_sub, wrapping_sub, -);
+    const_op!(pub const_mul, wrapping_mul, *);
+    const_op!(fold_div, wrapping_div, /);
+    const_op!(int_only fold_rem, wrapping_rem);
+
+    // Integer division by zero traps at run time, so it's never folded
+    pub fn const_div(&self, v: &Value) -> Option<Value> {
+        if v.is_int_zero() {
+            return None;
+        }
+        self.fold_div(v)
+    }
+
+    pub fn const_rem(&self, v: &Value) -> Option<Value> {
+        if v.is_int_zero() {
+            return None;
+        }
+        self.fold_rem(v)
+    }
+
+    fn is_int_zero(&self) -> bool {
+        matches!(
+            self,
+            Value::Immediate(ImmediateValue::Int8(0))
+                | Value::Immediate(ImmediateValue::Int32(0))
+                | Value::Immediate(ImmediateValue::Int64(0))
+        )
+    }
 
     // Utils
 
@@ -174,6 +220,20 @@ impl Value {
                 let g = &parent.global_vars.arena[*id];
                 format!("{} @{}", parent.types.to_string(*ty), g.name)
             }
+            Value::InlineAsm(InlineAsmValue { id, ty }) => {
+                let ret_ty = parent
+                    .types
+                    .base
+                    .borrow()
+                    .as_function_ty(*ty)
+                    .unwrap()
+                    .ret_ty;
+                format!(
+                    "{} {}",
+                    parent.types.to_string(ret_ty),
+                    parent.inline_asms.arena[*id]
+                )
+            }
             Value::None => "".to_string(),
         }
     }
@@ -2,7 +2,8 @@
 #![feature(stmt_expr_attributes)]
 #![feature(drain_filter)]
 #![feature(vec_remove_item)]
-#![recursion_limit = "128"]
+#![feature(thread_local)]
+#![recursion_limit = "256"]
 
 #[macro_use]
 pub mod macros;
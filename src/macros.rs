@@ -64,6 +64,10 @@ macro_rules! cilk_parse_ty {
         let e = {cilk_parse_ty!($tys, $($elem)*)};
         $tys.new_array_ty(e, $n)
     }};
+    ($tys:expr, <$n:literal x $ty:ident>) => {{
+        let e = { cilk_parse_ty!($tys, $ty)};
+        $tys.new_vector_ty(e, $n)
+    }};
 }
 
 #[macro_export]
@@ -133,6 +137,25 @@ macro_rules! cilk_expr {
     };
     cilk_expr!($builder; $bb_map; $( $remain )*);
 };
+($builder:expr; $bb_map:expr; $x:ident = alloca_ ($($ty:tt)*), align $align:expr; $($remain:tt)*) => {
+    let $x = {
+        let types = &mut $builder.func.func_ref_mut().types;
+        let ty = cilk_parse_ty!(types, $( $ty )*);
+        $builder.build_alloca_with_align(ty, $align)
+    };
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
+($builder:expr; $bb_map:expr; $x:ident = load volatile ($($val:tt)*); $($remain:tt)*) => {
+    let val = cilk_value!($builder; $( $val )*);
+    let $x = $builder.build_load_with_attr(val, opcode::MemoryAttribute { volatile: true, align: None });
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
+($builder:expr; $bb_map:expr; store volatile ($($val1:tt)*), ($($val2:tt)*); $($remain:tt)*) => {
+    let src = cilk_value!($builder; $( $val1 )*);
+    let dst = cilk_value!($builder; $( $val2 )*);
+    $builder.build_store_with_attr(src, dst, opcode::MemoryAttribute { volatile: true, align: None });
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
 ($builder:expr; $bb_map:expr; $x:ident = load ($($val:tt)*); $($remain:tt)*) => {
     let val = cilk_value!($builder; $( $val )*);
     let $x = $builder.build_load(val);
@@ -174,6 +197,12 @@ macro_rules! cilk_expr {
     let $x = $builder.build_rem(val1, val2);
     cilk_expr!($builder; $bb_map; $( $remain )*);
 };
+($builder:expr; $bb_map:expr; $x:ident = shl ($($val1:tt)*), ($($val2:tt)*); $($remain:tt)*) => {
+    let val1 = cilk_value!($builder; $( $val1 )*);
+    let val2 = cilk_value!($builder; $( $val2 )*);
+    let $x = $builder.build_shl(val1, val2);
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
 ($builder:expr; $bb_map:expr; $x:ident = gep ($($val:tt)*), [$( ( $($idx:tt)* ) ),*] ; $($remain:tt)*) => {
     let val = cilk_value!($builder; $( $val )*);
     let indices = vec![$( cilk_value!($builder; $( $idx )*) ),*];
@@ -206,6 +235,11 @@ macro_rules! cilk_expr {
         }), args);
         cilk_expr!($builder; $bb_map; $( $remain )*);
 };
+($builder:expr; $bb_map:expr; $x:ident = call (% $callee:ident) [$( ( $($arg:tt)* ) ),*] ; $($remain:tt)*) => {
+        let args = vec![ $( cilk_value!($builder; $( $arg )*) ),* ];
+        let $x = $builder.build_call($callee, args);
+        cilk_expr!($builder; $bb_map; $( $remain )*);
+};
 ($builder:expr; $bb_map:expr; $x:ident = icmp $kind:ident ($($val1:tt)*), ($($val2:tt)*); $($remain:tt)*) => {
     let val1 = cilk_value!($builder; $( $val1 )*);
     let val2 = cilk_value!($builder; $( $val2 )*);
@@ -218,6 +252,32 @@ macro_rules! cilk_expr {
     let $x = $builder.build_fcmp(fcmp_kind!($kind), val1, val2);
     cilk_expr!($builder; $bb_map; $( $remain )*);
 };
+($builder:expr; $bb_map:expr; $x:ident = extractelement ($($vec:tt)*), ($($idx:tt)*); $($remain:tt)*) => {
+    let vec = cilk_value!($builder; $( $vec )*);
+    let idx = cilk_value!($builder; $( $idx )*);
+    let $x = $builder.build_extract_element(vec, idx);
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
+($builder:expr; $bb_map:expr; $x:ident = insertelement ($($vec:tt)*), ($($val:tt)*), ($($idx:tt)*); $($remain:tt)*) => {
+    let vec = cilk_value!($builder; $( $vec )*);
+    let val = cilk_value!($builder; $( $val )*);
+    let idx = cilk_value!($builder; $( $idx )*);
+    let $x = $builder.build_insert_element(vec, val, idx);
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
+($builder:expr; $bb_map:expr; $x:ident = shufflevector ($($v1:tt)*), ($($v2:tt)*), [$($mask:expr),*]; $($remain:tt)*) => {
+    let v1 = cilk_value!($builder; $( $v1 )*);
+    let v2 = cilk_value!($builder; $( $v2 )*);
+    let $x = $builder.build_shuffle_vector(v1, v2, vec![$( $mask ),*]);
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
+($builder:expr; $bb_map:expr; $x:ident = select ($($cond:tt)*), ($($t:tt)*), ($($f:tt)*); $($remain:tt)*) => {
+    let cond = cilk_value!($builder; $( $cond )*);
+    let t = cilk_value!($builder; $( $t )*);
+    let f = cilk_value!($builder; $( $f )*);
+    let $x = $builder.build_select(cond, t, f);
+    cilk_expr!($builder; $bb_map; $( $remain )*);
+};
 ($builder:expr; $bb_map:expr; br ($($cond:tt)*) $l1:ident, $l2:ident; $($remain:tt)*) => {
     let bb1 = *$bb_map.entry(stringify!($l1)).or_insert_with(|| $builder.append_basic_block());
     let bb2 = *$bb_map.entry(stringify!($l2)).or_insert_with(|| $builder.append_basic_block());
@@ -4,22 +4,2401 @@ mod x86_64 {
         codegen::x64::exec,
         ir::builder::FuncRef,
         // exec::{interpreter::interp, jit::x64::compiler},
-        ir::{builder, opcode, types, value},
+        ir::{builder, global_val, inline_asm, opcode, types, value},
         *,
     };
 
+    fn count_opcode(f: &ir::function::Function, opcode: opcode::Opcode) -> usize {
+        f.basic_blocks
+            .order
+            .iter()
+            .flat_map(|&bb| f.basic_block_ref(bb).iseq_ref().clone())
+            .filter(|v| f.inst_table[v.as_instruction().id].opcode == opcode)
+            .count()
+    }
+
+    #[test]
+    fn test0_mem2reg() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [] {
+        entry:
+            i = alloca i32;
+            store (i32 3), (%i);
+            li = load (%i);
+            // tmp = add (%li), (i32 2);
+            // store (%tmp), (%i);
+            // li = load (%i);
+            ret (%li);
+        });
+
+        println!("{}", m.dump(func));
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(3));
+    }
+
+    #[test]
+    fn dce() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            store (i32 3), (%i);
+            x = add (%arg.0), (i32 1);
+            y = mul (%x), (i32 2);
+            li = load (%i);
+            r = add (%arg.0), (i32 5);
+            ret (%r);
+        });
+
+        ir::dce::DeadCodeElimination::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'x', 'y' and 'li' are unused. 'store' keeps 'i' alive.
+        let f = m.function_ref(func);
+        let entry = f.basic_blocks.order[0];
+        assert_eq!(f.basic_block_ref(entry).iseq_ref().len(), 4);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(2)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(7));
+    }
+
+    #[test]
+    fn adce_dead_loop() {
+        let mut m = module::Module::new("cilk");
+
+        let dead_loop = cilk_ir!(m; define [i32] dead_loop [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            ret (i32 7);
+        });
+
+        let live_loop = cilk_ir!(m; define [i32] live_loop [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::dce::DeadCodeElimination::new_aggressive().run_on_module(&mut m);
+        println!("{}", m.dump(dead_loop));
+        println!("{}", m.dump(live_loop));
+
+        // The loop in 'dead_loop' computes nothing used, so its body is gone and no phi remains
+        let f = m.function_ref(dead_loop);
+        assert_eq!(f.basic_blocks.order.len(), 3);
+        assert!(f.basic_blocks.order.iter().all(|&bb| {
+            f.basic_block_ref(bb)
+                .iseq_ref()
+                .iter()
+                .all(|v| f.inst_table[v.as_instruction().id].opcode != opcode::Opcode::Phi)
+        }));
+        assert_eq!(m.function_ref(live_loop).basic_blocks.order.len(), 4);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("dead_loop").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(10)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(7));
+        let func = jit.find_function_by_name("live_loop").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(10)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(45));
+    }
+
+    #[test]
+    fn post_dom_tree() {
+        use cilk::analysis::{
+            control_dependence::ControlDependenceGraph, post_dom_tree::PostDominatorTreeConstructor,
+        };
+
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            c = icmp eq (%arg.0), (i32 0);
+            br (%c) a, b;
+        a:
+            c2 = icmp lt (%arg.0), (i32 10);
+            br (%c2) early, join;
+        b:
+            c3 = icmp eq (%arg.0), (i32 5);
+            br (%c3) spin, join;
+        early:
+            ret (i32 1);
+        join:
+            ret (i32 2);
+        spin:
+            br spin;
+        });
+
+        let f = m.function_ref(func);
+        let order = &f.basic_blocks.order;
+        let (entry, a, b, early, join, spin) =
+            (order[0], order[1], order[2], order[3], order[4], order[5]);
+
+        // Both returns lead to the virtual exit node, and 'spin' never reaches it
+        let pdt = PostDominatorTreeConstructor::new(&f.basic_blocks).construct();
+        assert!(!pdt.contains(spin));
+        assert_eq!(pdt.ipdom_of(entry), None);
+        assert_eq!(pdt.ipdom_of(a), None);
+        assert_eq!(pdt.ipdom_of(b), Some(join));
+        assert!(pdt.post_dominate_bb(join, b));
+        assert!(!pdt.post_dominate_bb(join, entry));
+        assert!(!pdt.post_dominate_bb(early, a));
+        assert_eq!(pdt.children_of(None).unwrap().len(), 4);
+
+        let cdg = ControlDependenceGraph::new(&f.basic_blocks, &pdt);
+        assert!(cdg.dependences_of(entry).is_none());
+        assert!(cdg.depends_on(a, entry));
+        assert!(cdg.depends_on(b, entry));
+        assert!(cdg.depends_on(early, a));
+        assert!(cdg.depends_on(join, a));
+        assert!(!cdg.depends_on(join, b));
+    }
+
+    #[test]
+    fn sccp() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            a = alloca i32;
+            store (i32 10), (%a);
+            la = load (%a);
+            c = icmp lt (%la), (i32 5);
+            br (%c) then_, else_;
+        then_:
+            store (i32 1), (%a);
+            br merge;
+        else_:
+            lb = load (%a);
+            b = mul (%lb), (i32 2);
+            store (%b), (%a);
+            br merge;
+        merge:
+            r = load (%a);
+            s = add (%r), (%arg.0);
+            ret (%s);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::sccp::SparseConditionalConstantPropagation::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'then_' is never executed and the phi in 'merge' folds into 20
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 3);
+        for &bb in &f.basic_blocks.order {
+            for v in &*f.basic_block_ref(bb).iseq_ref() {
+                let opcode = f.inst_table[v.as_instruction().id].opcode;
+                assert!(!matches!(
+                    opcode,
+                    opcode::Opcode::CondBr | opcode::Opcode::Phi | opcode::Opcode::Mul
+                ));
+            }
+        }
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(3)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(23));
+    }
+
+    #[test]
+    fn sccp_loop() {
+        let mut m = module::Module::new("cilk");
+
+        // 'x' stays 1 through the loop since it is only ever multiplied by 1
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            x = alloca i32;
+            store (i32 0), (%i);
+            store (i32 1), (%x);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            lx = load (%x);
+            nx = mul (%lx), (i32 1);
+            store (%nx), (%x);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%x);
+            e = icmp eq (%r), (i32 1);
+            br (%e) one, other;
+        one:
+            ret (i32 100);
+        other:
+            ret (i32 200);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::sccp::SparseConditionalConstantPropagation::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'other' is dropped
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 5);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(5)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(100));
+    }
+
+    #[test]
+    fn ipsccp() {
+        let mut m = module::Module::new("cilk");
+
+        let scale = cilk_ir!(m; define [i32] scale [(i32), (i32)] {
+        entry:
+            c = icmp eq (%arg.1), (i32 0);
+            br (%c) zero, nonzero;
+        zero:
+            ret (i32 0);
+        nonzero:
+            x = mul (%arg.0), (%arg.1);
+            ret (%x);
+        });
+
+        let seven = cilk_ir!(m; define [i32] seven [(i32)] {
+        entry:
+            c = icmp lt (%arg.0), (i32 0);
+            br (%c) neg, pos;
+        neg:
+            ret (i32 7);
+        pos:
+            ret (i32 7);
+        });
+
+        let main = cilk_ir!(m; define [i32] main [(i32)] {
+        entry:
+            a = call scale [(%arg.0), (i32 3)];
+            b = call scale [(i32 2), (i32 3)];
+            s = call seven [(%arg.0)];
+            r = add (%a), (%b);
+            r = add (%r), (%s);
+            ret (%r);
+        });
+
+        m.function_ref_mut(scale).linkage = global_val::Linkage::Internal;
+        m.function_ref_mut(seven).linkage = global_val::Linkage::Internal;
+
+        ir::ipsccp::InterproceduralSCCP::new().run_on_module(&mut m);
+        println!("{}", m.dump(scale));
+        println!("{}", m.dump(main));
+
+        // 'scale' is always called with 3 and 'seven' always returns 7
+        let f = m.function_ref(scale);
+        for &bb in &f.basic_blocks.order {
+            let last = *f.basic_block_ref(bb).iseq_ref().last().unwrap();
+            assert_ne!(
+                f.inst_table[last.as_instruction().id].opcode,
+                opcode::Opcode::CondBr
+            );
+        }
+        let f = m.function_ref(main);
+        let iseq = f
+            .basic_block_ref(f.basic_blocks.order[0])
+            .iseq_ref()
+            .clone();
+        let last_add = &f.inst_table[iseq[iseq.len() - 2].as_instruction().id];
+        assert_eq!(
+            last_add.operands[1],
+            opcode::Operand::Value(value::Value::new_imm_int32(7))
+        );
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let main = jit.find_function_by_name("main").unwrap();
+        let ret = jit.run(main, vec![exec::jit::GenericValue::Int32(5)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(28));
+    }
+
+    #[test]
+    fn function_specialization() {
+        let mut m = module::Module::new("cilk");
+
+        let pow = cilk_ir!(m; define [i32] pow [(i32), (i32)] {
+        entry:
+            i = alloca i32;
+            x = alloca i32;
+            store (i32 0), (%i);
+            store (i32 1), (%x);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.1);
+            br (%c) body, end;
+        body:
+            lx = load (%x);
+            nx = mul (%lx), (%arg.0);
+            store (%nx), (%x);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%x);
+            ret (%r);
+        });
+
+        let main = cilk_ir!(m; define [i32] main [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            p = call pow [(%li), (i32 2)];
+            ls = load (%s);
+            ns = add (%ls), (%p);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            q = call pow [(i32 2), (%arg.0)];
+            r = load (%s);
+            r = add (%r), (%q);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::ipsccp::InterproceduralSCCP::new()
+            .with_specialization(100)
+            .run_on_module(&mut m);
+        println!("{:?}", m);
+
+        // Only the call in the loop is redirected to a clone of 'pow' squaring its argument
+        assert_eq!(m.functions.len(), 3);
+        let f = m.function_ref(main);
+        let mut callees = vec![];
+        for &bb in &f.basic_blocks.order {
+            for val in &*f.basic_block_ref(bb).iseq_ref() {
+                let inst = &f.inst_table[val.as_instruction().id];
+                if inst.opcode == opcode::Opcode::Call {
+                    match inst.operands[0].as_value() {
+                        value::Value::Function(value::FunctionValue { func_id, .. }) => {
+                            callees.push(*func_id)
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+        assert_eq!(callees.len(), 2);
+        assert_eq!(callees.iter().filter(|&&id| id == pow).count(), 1);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let main = jit.find_function_by_name("main").unwrap();
+        let ret = jit.run(main, vec![exec::jit::GenericValue::Int32(4)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(30));
+    }
+
+    #[test]
+    fn gvn() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32), (i32)] {
+        entry:
+            a = alloca i32;
+            b = alloca i32;
+            store (%arg.0), (%a);
+            x = add (%arg.0), (%arg.1);
+            store (i32 5), (%b);
+            la = load (%a);
+            c = icmp lt (%la), (i32 10);
+            br (%c) then_, merge;
+        then_:
+            y = add (%arg.1), (%arg.0);
+            lb = load (%b);
+            z = add (%y), (%lb);
+            store (%z), (%b);
+            br merge;
+        merge:
+            la2 = load (%a);
+            lb2 = load (%b);
+            w = add (%x), (%la2);
+            r = add (%w), (%lb2);
+            ret (%r);
+        });
+
+        ir::gvn::GlobalValueNumbering::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'y' is 'x' with its operands swapped. Every load but 'lb2' reads a known value: the
+        // store to 'b' in 'then_' can't clobber 'a', but 'b' differs between the paths to 'merge'.
+        let f = m.function_ref(func);
+        assert_eq!(count_opcode(f, opcode::Opcode::Load), 1);
+        assert_eq!(count_opcode(f, opcode::Opcode::Add), 4);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(
+            func,
+            vec![
+                exec::jit::GenericValue::Int32(2),
+                exec::jit::GenericValue::Int32(3),
+            ],
+        );
+        assert_eq!(ret, exec::jit::GenericValue::Int32(17));
+    }
+
+    #[test]
+    fn gvn_clobber() {
+        let mut m = module::Module::new("cilk");
+
+        cilk_ir!(m; define [void] set [(ptr i32)] {
+        entry:
+            store (i32 4), (%arg.0);
+            ret (void);
+        });
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            a = alloca i32;
+            i = alloca i32;
+            store (%arg.0), (%a);
+            store (i32 0), (%i);
+            __ = call set [(%a)];
+            l1 = load (%a);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (i32 3);
+            br (%c) body, end;
+        body:
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            l2 = load (%a);
+            li2 = load (%i);
+            s = add (%l1), (%l2);
+            r = add (%s), (%li2);
+            ret (%r);
+        });
+
+        ir::gvn::GlobalValueNumbering::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'l1' must be reloaded after the call, and 'li' after the store in the loop. 'l2' is
+        // 'l1' since the loop never writes to 'a', and 'li2' is 'li'.
+        let f = m.function_ref(func);
+        assert_eq!(count_opcode(f, opcode::Opcode::Load), 2);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(9)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(11));
+    }
+
+    #[test]
+    fn alias_analysis() {
+        use cilk::analysis::alias_analysis::{
+            AliasAnalysis, AliasAnalysisTrait, AliasResult, MemoryLocation, ModRefInfo,
+        };
+
+        // Knows that different arguments never alias
+        struct DistinctArgs {}
+        impl AliasAnalysisTrait for DistinctArgs {
+            fn alias(
+                &self,
+                _: &function::Function,
+                a: &MemoryLocation,
+                b: &MemoryLocation,
+            ) -> AliasResult {
+                match (a.ptr, b.ptr) {
+                    (value::Value::Argument(x), value::Value::Argument(y))
+                        if x.index != y.index =>
+                    {
+                        AliasResult::NoAlias
+                    }
+                    _ => AliasResult::MayAlias,
+                }
+            }
+        }
+
+        let mut m = module::Module::new("cilk");
+        let g = m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Common,
+            "g",
+        );
+        let g = value::Value::Global(value::GlobalValue {
+            id: g,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+
+        cilk_ir!(m; define [void] set [(ptr i32)] {
+        entry:
+            store (i32 4), (%arg.0);
+            ret (void);
+        });
+
+        let func = cilk_ir!(m; define [i32] func [(ptr i32), (ptr i32), (ptr i32)] {
+        entry:
+            s = alloca i32;
+            store (i32 1), (%s);
+            x = load (%arg.0);
+            store (i32 5), (%arg.1);
+            y = load (%arg.0);
+            store (i32 6), (%arg.2);
+            z = load (%arg.1);
+            __ = call set [(%arg.2)];
+            w = load (%s);
+            r1 = add (%x), (%y);
+            r2 = add (%r1), (%z);
+            r3 = add (%r2), (%w);
+            ret (%r3);
+        });
+        m.function_ref_mut(func).set_param_attr(
+            0,
+            types::ParamAttribute {
+                noalias: true,
+                ..Default::default()
+            },
+        );
+        println!("{}", m.dump(func));
+
+        {
+            let f = m.function_ref(func);
+            let loc = |ptr| MemoryLocation::new(ptr, 4);
+            let (p, q, r) = (
+                loc(f.get_param_value(0).unwrap()),
+                loc(f.get_param_value(1).unwrap()),
+                loc(f.get_param_value(2).unwrap()),
+            );
+            let iseq = f
+                .basic_block_ref(f.basic_blocks.order[0])
+                .iseq_ref()
+                .clone();
+            let (s, g) = (loc(iseq[0]), loc(g));
+            let inst = |i: usize| &f.inst_table[iseq[i].as_instruction().id];
+
+            let aa = AliasAnalysis::new(f, &m.types, &m.inline_asms);
+            // 'p' is noalias, and 's' never escapes
+            assert_eq!(aa.alias(f, &p, &q), AliasResult::NoAlias);
+            assert_eq!(aa.alias(f, &q, &r), AliasResult::MayAlias);
+            assert_eq!(aa.alias(f, &s, &g), AliasResult::NoAlias);
+            assert_eq!(aa.alias(f, &s, &q), AliasResult::NoAlias);
+            assert!(aa.may_alias(f, &g, &q));
+            assert_eq!(aa.mod_ref(f, inst(3), &q), ModRefInfo::Mod);
+            assert_eq!(aa.mod_ref(f, inst(3), &p), ModRefInfo::NoModRef);
+            assert_eq!(aa.mod_ref(f, inst(4), &p), ModRefInfo::Ref);
+            assert_eq!(aa.mod_ref(f, inst(7), &s), ModRefInfo::NoModRef);
+            assert_eq!(aa.mod_ref(f, inst(7), &g), ModRefInfo::ModRef);
+
+            let aa = AliasAnalysis::new(f, &m.types, &m.inline_asms).with(DistinctArgs {});
+            assert_eq!(aa.alias(f, &q, &r), AliasResult::NoAlias);
+            assert_eq!(aa.mod_ref(f, inst(5), &q), ModRefInfo::NoModRef);
+        }
+
+        // 'y' is 'x' since the store between them can't write to the noalias 'p', and the call
+        // can't write to 's'
+        ir::gvn::GlobalValueNumbering::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+        let f = m.function_ref(func);
+        assert_eq!(count_opcode(f, opcode::Opcode::Load), 2);
+
+        cilk_ir!(m; define [i32] main [] {
+        entry:
+            a = alloca i32;
+            b = alloca i32;
+            c = alloca i32;
+            store (i32 3), (%a);
+            r = call func [(%a), (%b), (%c)];
+            ret (%r);
+        });
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("main").unwrap();
+        let ret = jit.run(func, vec![]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(12));
+    }
+
+    #[test]
+    fn licm() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32), (i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            g = icmp le (%arg.0), (i32 0);
+            br (%g) end, cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            x = add (%arg.1), (i32 3);
+            d = div (%x), (i32 2);
+            ls = load (%s);
+            ns = add (%ls), (%d);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::licm::LoopInvariantCodeMotion::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // A preheader is inserted before 'cond', and 'x' and 'd' are hoisted into it. 'end' also
+        // gets a dedicated exit block from 'LoopSimplify'.
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 6);
+        let preheader = f.basic_blocks.order[3];
+        let hoisted: Vec<opcode::Opcode> = f
+            .basic_block_ref(preheader)
+            .iseq_ref()
+            .iter()
+            .map(|v| f.inst_table[v.as_instruction().id].opcode)
+            .collect();
+        assert_eq!(
+            hoisted,
+            vec![opcode::Opcode::Add, opcode::Opcode::Div, opcode::Opcode::Br]
+        );
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(
+            func,
+            vec![
+                exec::jit::GenericValue::Int32(4),
+                exec::jit::GenericValue::Int32(5),
+            ],
+        );
+        assert_eq!(ret, exec::jit::GenericValue::Int32(16));
+        let ret = jit.run(
+            func,
+            vec![
+                exec::jit::GenericValue::Int32(0),
+                exec::jit::GenericValue::Int32(5),
+            ],
+        );
+        assert_eq!(ret, exec::jit::GenericValue::Int32(0));
+    }
+
+    #[test]
+    fn licm_memory() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            k = alloca i32;
+            t = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            store (%arg.0), (%k);
+            store (i32 0), (%t);
+            br cond;
+        cond:
+            li = load (%i);
+            store (%li), (%t);
+            c = icmp lt (%li), (i32 10);
+            br (%c) body, end;
+        body:
+            lk = load (%k);
+            x = mul (%lk), (i32 2);
+            ls = load (%s);
+            ns = add (%ls), (%x);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            lt = load (%t);
+            r = load (%s);
+            q = add (%r), (%lt);
+            ret (%q);
+        });
+
+        ir::licm::LoopInvariantCodeMotion::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'lk' and 'x' are hoisted since nothing in the loop writes to 'k'. The store to 't' is
+        // sunk into 'end' since nothing in the loop reads it.
+        let f = m.function_ref(func);
+        let opcodes = |nth: usize| -> Vec<opcode::Opcode> {
+            f.basic_block_ref(f.basic_blocks.order[nth])
+                .iseq_ref()
+                .iter()
+                .map(|v| f.inst_table[v.as_instruction().id].opcode)
+                .collect()
+        };
+        assert_eq!(
+            &opcodes(0)[8..],
+            &[
+                opcode::Opcode::Load,
+                opcode::Opcode::Mul,
+                opcode::Opcode::Br
+            ]
+        );
+        assert!(!opcodes(1).contains(&opcode::Opcode::Store));
+        assert_eq!(opcodes(3)[0], opcode::Opcode::Store);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(3)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(70));
+    }
+
+    #[test]
+    fn licm_no_exit() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [void] func [(i32), (ptr i32)] {
+        entry:
+            br loop;
+        loop:
+            c = icmp lt (%arg.0), (i32 10);
+            br (%c) small, large;
+        small:
+            d = div (i32 12), (%arg.0);
+            store (%d), (%arg.1);
+            br loop;
+        large:
+            store (i32 0), (%arg.1);
+            br loop;
+        });
+
+        ir::licm::LoopInvariantCodeMotion::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // The loop never exits, so nothing guarantees 'small' executes and 'd' may trap
+        let f = m.function_ref(func);
+        let entry = f.basic_blocks.order[0];
+        assert!(f
+            .insts_of(entry)
+            .iter()
+            .all(|&id| f.inst_table[id].opcode != opcode::Opcode::Div));
+    }
+
+    #[test]
+    fn loop_simplify() {
+        use cilk::analysis::{dom_tree::DominatorTreeConstructor, loops::LoopsConstructor};
+
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            g = icmp le (%arg.0), (i32 0);
+            br (%g) end, cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            e = icmp lt (%li), (i32 5);
+            br (%e) small, big;
+        small:
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            br cond;
+        big:
+            ls2 = load (%s);
+            ns2 = add (%ls2), (i32 1);
+            store (%ns2), (%s);
+            br cond;
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::loop_simplify::LoopSimplify::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // A preheader, a latch merging 'small' and 'big', and a block between 'cond' and 'end'
+        // are inserted
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 9);
+        let dom_tree = DominatorTreeConstructor::new(&f.basic_blocks).construct();
+        let loops = LoopsConstructor::new(&dom_tree, &f.basic_blocks).analyze();
+        assert_eq!(loops.arena.len(), 1);
+        let (id, l) = loops.arena.iter().next().unwrap();
+        assert_eq!(loops.depth(id), 1);
+        assert_eq!(loops.depth_of(l.header()), 1);
+        assert!(l.preheader(&f.basic_blocks).is_some());
+        assert!(l.latch(&f.basic_blocks).is_some());
+        assert_eq!(l.exiting_blocks(&f.basic_blocks), vec![l.header()]);
+        assert_eq!(l.exit_blocks(&f.basic_blocks).len(), 1);
+        assert!(l.has_dedicated_exits(&f.basic_blocks));
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(10)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(15));
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(0)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(0));
+    }
+
+    #[test]
+    fn lcssa() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            store (i32 0), (%i);
+            br cond;
+        cond:
+            li = load (%i);
+            x = mul (%li), (i32 3);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, out2;
+        body:
+            e = icmp eq (%x), (i32 12);
+            br (%e) out1, latch;
+        out2:
+            br end;
+        out1:
+            br end;
+        latch:
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = add (%x), (i32 1);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::lcssa::LoopClosedSSA::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'x' goes through a phi in each exit, and a phi in 'end' merges them
+        let f = m.function_ref(func);
+        let first = |bb| {
+            let id = f.basic_block_ref(bb).iseq_ref()[0].as_instruction().id;
+            (id, f.inst_table[id].opcode)
+        };
+        let order = &f.basic_blocks.order;
+        let (out2, out1, end) = (order[3], order[4], order[6]);
+        assert_eq!(first(out1).1, opcode::Opcode::Phi);
+        assert_eq!(first(out2).1, opcode::Opcode::Phi);
+        let (merge, opcode) = first(end);
+        assert_eq!(opcode, opcode::Opcode::Phi);
+        let r = f.basic_block_ref(end).iseq_ref()[1].as_instruction().id;
+        assert_eq!(
+            f.inst_table[r].operands[0].as_value().get_inst_id(),
+            Some(merge)
+        );
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(10)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(13));
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(2)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(7));
+    }
+
+    #[test]
+    fn unroll_fully() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (i32 4);
+            br (%c) body, end;
+        body:
+            x = mul (%li), (%arg.0);
+            ls = load (%s);
+            ns = add (%ls), (%x);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::unroll::LoopUnroll::new(100, 1).run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 4 copies of 'cond' and 'body', and a copy of 'cond' jumping to 'end'
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 11);
+        for &bb in &f.basic_blocks.order {
+            for v in &*f.basic_block_ref(bb).iseq_ref() {
+                let opcode = f.inst_table[v.as_instruction().id].opcode;
+                assert!(!matches!(
+                    opcode,
+                    opcode::Opcode::CondBr | opcode::Opcode::Phi
+                ));
+            }
+        }
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(5)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(30));
+    }
+
+    #[test]
+    fn scalar_evolution_overflowing_bound() {
+        use cilk::analysis::{
+            dom_tree::DominatorTreeConstructor, loops::LoopsConstructor,
+            scalar_evolution::ScalarEvolution,
+        };
+
+        let mut m = module::Module::new("cilk");
+
+        // 'MIN < i' can't be negated into '-i < -MIN', so the trip count is unknown
+        let func = cilk_ir!(m; define [i32] func [] {
+        entry:
+            i = alloca i64;
+            store (i64 0), (%i);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (i64 i64::min_value()), (%li);
+            br (%c) end, body;
+        body:
+            ni = add (%li), (i64 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            ret (i32 1);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        let f = m.function_ref(func);
+        let dom_tree = DominatorTreeConstructor::new(&f.basic_blocks).construct();
+        let loops = LoopsConstructor::new(&dom_tree, &f.basic_blocks).analyze();
+        let (id, _) = loops.arena.iter().next().unwrap();
+        assert_eq!(
+            ScalarEvolution::new(f, &loops).backedge_taken_count(id),
+            None
+        );
+    }
+
+    #[test]
+    fn unroll_guarded_division() {
+        let mut m = module::Module::new("cilk");
+
+        // The copy of 'div' for i = 0 divides by zero but never executes
+        let func = cilk_ir!(m; define [i32] func [] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (i32 4);
+            br (%c) body, end;
+        body:
+            z = icmp eq (%li), (i32 0);
+            br (%z) latch, div;
+        div:
+            q = div (i32 12), (%li);
+            ls = load (%s);
+            ns = add (%ls), (%q);
+            store (%ns), (%s);
+            br latch;
+        latch:
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::unroll::LoopUnroll::new(100, 1).run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // Only the division by zero is left unfolded
+        let f = m.function_ref(func);
+        assert_eq!(count_opcode(f, opcode::Opcode::Div), 1);
+
+        ir::sccp::SparseConditionalConstantPropagation::new().run_on_module(&mut m);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        assert_eq!(
+            jit.run(func, vec![]),
+            exec::jit::GenericValue::Int32(12 + 6 + 4)
+        );
+    }
+
+    #[test]
+    fn unroll_partially() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (i32 10);
+            br (%c) body, end;
+        body:
+            p = icmp lt (%li), (i32 5);
+            br (%p) small, large;
+        small:
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            br latch;
+        large:
+            ls2 = load (%s);
+            ns2 = add (%ls2), (%arg.0);
+            store (%ns2), (%s);
+            br latch;
+        latch:
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::unroll::LoopUnroll::new(60, 4).run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // The loop is too large to unroll fully. The unrolled loop runs 8 iterations and the
+        // original loop the last 2.
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 7 + 4 * 5);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(7)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(45));
+    }
+
+    #[test]
+    fn inline() {
+        let mut m = module::Module::new("cilk");
+
+        cilk_ir!(m; define [i32] abs [(i32)] {
+        entry:
+            c = icmp lt (%arg.0), (i32 0);
+            br (%c) neg, pos;
+        neg:
+            d = add (%arg.0), (%arg.0);
+            n = sub (%arg.0), (%d);
+            ret (%n);
+        pos:
+            ret (%arg.0);
+        });
+
+        cilk_ir!(m; define [i32] sum [(i32), (i32)] {
+        entry:
+            t = alloca i32;
+            store (%arg.0), (%t);
+            l = load (%t);
+            x = add (%l), (%arg.1);
+            ret (%x);
+        });
+
+        let main = cilk_ir!(m; define [i32] main [(i32)] {
+        entry:
+            a = call abs [(%arg.0)];
+            b = call sum [(%a), (i32 3)];
+            ret (%b);
+        });
+
+        ir::inliner::Inliner::new(20).run_on_module(&mut m);
+        println!("{}", m.dump(main));
+
+        // The returns of 'abs' are merged by a phi, and the alloca of 'sum' is in the entry
+        let f = m.function_ref(main);
+        assert_eq!(count_opcode(f, opcode::Opcode::Call), 0);
+        let entry = f.basic_block_ref(f.basic_blocks.order[0]);
+        let first = entry.iseq_ref()[0].as_instruction().id;
+        assert_eq!(f.inst_table[first].opcode, opcode::Opcode::Alloca);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let main = jit.find_function_by_name("main").unwrap();
+        let ret = jit.run(main, vec![exec::jit::GenericValue::Int32(-4)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(7));
+        let ret = jit.run(main, vec![exec::jit::GenericValue::Int32(5)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(8));
+    }
+
+    #[test]
+    fn inline_attrs() {
+        let mut m = module::Module::new("cilk");
+
+        cilk_ir!(m; define [i32] fibo [(i32)] {
+        entry:
+            cond = icmp le (%arg.0), (i32 2);
+            br (%cond) l1, l2;
+        l1:
+            ret (i32 1);
+        l2:
+            a1 = sub (%arg.0), (i32 1);
+            r1 = call fibo [(%a1)];
+            a2 = sub (%arg.0), (i32 2);
+            r2 = call fibo [(%a2)];
+            r3 = add (%r1), (%r2);
+            ret (%r3);
+        });
+
+        let one = cilk_ir!(m; define [i32] one [] {
+        entry:
+            ret (i32 1);
+        });
+
+        let twice = cilk_ir!(m; define [i32] twice [(i32)] {
+        entry:
+            x = add (%arg.0), (%arg.0);
+            ret (%x);
+        });
+
+        let main = cilk_ir!(m; define [i32] main [] {
+        entry:
+            r = call fibo [(i32 10)];
+            o = call one [];
+            t = call twice [(%o)];
+            s = add (%r), (%t);
+            ret (%s);
+        });
+
+        m.function_ref_mut(one).attr.no_inline = true;
+        m.function_ref_mut(twice).attr.always_inline = true;
+
+        ir::inliner::Inliner::new(0).run_on_module(&mut m);
+        println!("{}", m.dump(main));
+
+        // Only 'twice' is inlined although every callee exceeds the threshold
+        let f = m.function_ref(main);
+        assert_eq!(count_opcode(f, opcode::Opcode::Call), 2);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let main = jit.find_function_by_name("main").unwrap();
+        assert_eq!(jit.run(main, vec![]), exec::jit::GenericValue::Int32(57));
+    }
+
+    #[test]
+    fn call_graph() {
+        use cilk::analysis::call_graph::{CallGraph, Callee};
+
+        let mut m = module::Module::new("cilk");
+
+        let cilk_println_i32 = m.create_function(
+            "cilk.println.i32",
+            ir::types::Type::Void,
+            vec![ir::types::Type::Int32],
+        );
+
+        let leaf = cilk_ir!(m; define [i32] leaf [(i32)] {
+        entry:
+            x = add (%arg.0), (i32 1);
+            ret (%x);
+        });
+
+        let even = m.create_function("even", types::Type::Int32, vec![types::Type::Int32]);
+        let odd = cilk_ir!(m; define [i32] odd [(i32)] {
+        entry:
+            c = icmp eq (%arg.0), (i32 0);
+            br (%c) zero, nonzero;
+        zero:
+            ret (i32 0);
+        nonzero:
+            x = sub (%arg.0), (i32 1);
+            r = call (->even) [(%x)];
+            ret (%r);
+        });
+        let mut builder = builder::Builder::new(builder::FunctionIdWithModule::new(&mut m, even));
+        cilk_ir!((builder) {
+        entry:
+            c = icmp eq (%arg.0), (i32 0);
+            br (%c) zero, nonzero;
+        zero:
+            ret (i32 1);
+        nonzero:
+            x = sub (%arg.0), (i32 1);
+            r = call odd [(%x)];
+            ret (%r);
+        });
+
+        let main = cilk_ir!(m; define [i32] main [(i32)] {
+        entry:
+            x = call leaf [(%arg.0)];
+            y = call (->even) [(%x)];
+            __ = call (->cilk_println_i32) [(%y)];
+            z = call leaf [(%y)];
+            ret (%z);
+        });
+
+        let graph = CallGraph::new(&m);
+        let callees: Vec<Callee> = graph.callees(main).iter().map(|(_, c)| *c).collect();
+        assert_eq!(
+            callees,
+            vec![
+                Callee::Defined(leaf),
+                Callee::Defined(even),
+                Callee::External(cilk_println_i32),
+                Callee::Defined(leaf)
+            ]
+        );
+        assert_eq!(graph.callers(leaf).len(), 2);
+        assert!(graph.reaches(main, odd));
+        assert!(!graph.reaches(leaf, main));
+
+        // 'even' and 'odd' call each other, and everything is called by 'main'
+        let sccs = graph.sccs_bottom_up();
+        assert_eq!(sccs.len(), 3);
+        let mut recursive = sccs.iter().find(|scc| scc.len() == 2).unwrap().clone();
+        recursive.sort_by_key(|f| f.index());
+        assert_eq!(recursive, vec![even, odd]);
+        assert_eq!(sccs.last().unwrap(), &vec![main]);
+    }
+
+    #[test]
+    fn sroa() {
+        let mut m = module::Module::new("cilk");
+
+        let f = m.create_function("f", types::Type::Int32, vec![types::Type::Int32]);
+
+        let mut builder = builder::Builder::new(builder::FunctionIdWithModule::new(&mut m, f));
+
+        let entry = builder.append_basic_block();
+        builder.set_insert_point(entry);
+
+        let pair_ty = builder
+            .func
+            .module
+            .types
+            .new_array_ty(types::Type::Int32, 2);
+        let struct_ty = builder
+            .func
+            .module
+            .types
+            .new_struct_ty(vec![types::Type::Int32, pair_ty]);
+        let ary_ty = builder
+            .func
+            .module
+            .types
+            .new_array_ty(types::Type::Int32, 4);
+        let var = builder.build_alloca(struct_ty);
+        let ary = builder.build_alloca(ary_ty);
+
+        cilk_ir!((builder) {
+            x = gep (%var), [(i32 0), (i32 0)];
+            store (%arg.0), (%x);
+            y = gep (%var), [(i32 0), (i32 1), (i32 1)];
+            store (i32 5), (%y);
+            p = gep (%var), [(i32 0), (i32 1)];
+            z = gep (%p), [(i32 0), (i32 0)];
+            store (i32 7), (%z);
+            a = gep (%ary), [(i32 0), (%arg.0)];
+            store (i32 10), (%a);
+            lx = load (%x);
+            ly = load (%y);
+            lz = load (%z);
+            la = load (%a);
+            s1 = add (%lx), (%ly);
+            s2 = add (%s1), (%lz);
+            s3 = add (%s2), (%la);
+            ret (%s3);
+        });
+
+        ir::sroa::ScalarReplacementOfAggregates::new().run_on_module(&mut m);
+        println!("{}", m.dump(f));
+
+        // The struct is split and promoted, but the array indexed by a variable stays
+        let func = m.function_ref(f);
+        let allocas: Vec<types::Type> = func
+            .basic_blocks
+            .order
+            .iter()
+            .flat_map(|&bb| func.basic_block_ref(bb).iseq_ref().clone())
+            .map(|v| &func.inst_table[v.as_instruction().id])
+            .filter(|inst| inst.opcode == opcode::Opcode::Alloca)
+            .map(|inst| *inst.operands[0].as_type())
+            .collect();
+        assert_eq!(allocas, vec![ary_ty]);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("f").unwrap();
+        let res = jit.run(func, vec![exec::jit::GenericValue::Int32(1)]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(23));
+    }
+
+    #[test]
+    fn dse() {
+        let mut m = module::Module::new("cilk");
+        let g = m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Common,
+            "g",
+        );
+        let g = value::Value::Global(value::GlobalValue {
+            id: g,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+
+        let pure = cilk_ir!(m; define [i32] pure [(i32)] {
+        entry:
+            x = add (%arg.0), (i32 1);
+            ret (%x);
+        });
+
+        cilk_ir!(m; define [void] set [(i32)] {
+        entry:
+            store (%arg.0), (%g);
+            ret (void);
+        });
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            a = alloca i32;
+            store (i32 1), (%a);
+            store (i32 2), (%g);
+            p = call pure [(%arg.0)];
+            store (%p), (%a);
+            store (i32 3), (%g);
+            c = icmp lt (%arg.0), (i32 0);
+            br (%c) l1, l2;
+        l1:
+            l = load (%a);
+            store (%l), (%g);
+            __ = call set [(i32 4)];
+            store (i32 5), (%g);
+            store (i32 6), (%a);
+            br l3;
+        l2:
+            store (i32 7), (%g);
+            br l3;
+        l3:
+            r = load (%g);
+            ret (%r);
+        });
+
+        m.function_ref_mut(pure).attr.read_none = true;
+
+        ir::dse::DeadStoreElimination::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // Left are 'store %p, %a', 'store %l, %g' read by 'set', 'store 5, %g' and 'store 7, %g'
+        let f = m.function_ref(func);
+        let stores: Vec<value::Value> = f
+            .basic_blocks
+            .order
+            .iter()
+            .flat_map(|&bb| f.basic_block_ref(bb).iseq_ref().clone())
+            .map(|v| &f.inst_table[v.as_instruction().id])
+            .filter(|inst| inst.opcode == opcode::Opcode::Store)
+            .map(|inst| *inst.operands[0].as_value())
+            .collect();
+        assert_eq!(stores.len(), 4);
+        assert_eq!(stores[2], value::Value::new_imm_int32(5));
+        assert_eq!(stores[3], value::Value::new_imm_int32(7));
+    }
+
+    #[test]
+    fn global_dce() {
+        let mut m = module::Module::new("cilk");
+        let g = m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Internal,
+            "g",
+        );
+        let g = value::Value::Global(value::GlobalValue {
+            id: g,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+        let h = m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Internal,
+            "h",
+        );
+        let h = value::Value::Global(value::GlobalValue {
+            id: h,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+
+        // 'e' is unused, but may be used outside the module
+        m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Common,
+            "e",
+        );
+
+        let leaf = cilk_ir!(m; define [i32] leaf [(i32)] {
+        entry:
+            x = add (%arg.0), (i32 1);
+            ret (%x);
+        });
+
+        let helper = cilk_ir!(m; define [i32] helper [(i32)] {
+        entry:
+            x = call leaf [(%arg.0)];
+            ret (%x);
+        });
+
+        let dead = cilk_ir!(m; define [void] dead [] {
+        entry:
+            store (i32 1), (%h);
+            ret (void);
+        });
+
+        cilk_ir!(m; define [i32] get [] {
+        entry:
+            x = load (%g);
+            ret (%x);
+        });
+
+        cilk_ir!(m; define [i32] main [(i32)] {
+        entry:
+            x = call helper [(%arg.0)];
+            ret (%x);
+        });
+
+        for &f in &[leaf, helper, dead] {
+            m.function_ref_mut(f).linkage = global_val::Linkage::Internal;
+        }
+
+        // 'dead' and 'h' are unreachable from the functions visible outside the module
+        ir::global_dce::GlobalDeadCodeElimination::new().run_on_module(&mut m);
+        println!("{:?}", m);
+        assert_eq!(m.functions.len(), 4);
+        assert!(m.find_function("dead").is_none());
+        assert_eq!(m.global_vars.arena.len(), 2);
+
+        // Only 'main', 'helper' and 'leaf' are reachable from 'main'
+        ir::global_dce::GlobalDeadCodeElimination::new()
+            .with_roots(&["main"])
+            .run_on_module(&mut m);
+        println!("{:?}", m);
+        assert_eq!(m.functions.len(), 3);
+        assert!(m.find_function("get").is_none());
+        assert_eq!(m.global_vars.arena.len(), 1);
+        assert_eq!(m.global_vars.arena.iter().next().unwrap().1.name, "e");
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let main = jit.find_function_by_name("main").unwrap();
+        let ret = jit.run(main, vec![exec::jit::GenericValue::Int32(1)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(2));
+    }
+
+    #[test]
+    fn global_const_promotion() {
+        let mut m = module::Module::new("cilk");
+        let g = m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Internal,
+            "g",
+        );
+        let g = value::Value::Global(value::GlobalValue {
+            id: g,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+        let k = m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Internal,
+            "k",
+        );
+        let k = value::Value::Global(value::GlobalValue {
+            id: k,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+
+        // 'e' is never stored to in the module, but may be from outside it
+        let e = m.global_vars.new_global_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Common,
+            "e",
+        );
+        let e = value::Value::Global(value::GlobalValue {
+            id: e,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+
+        cilk_ir!(m; define [void] set [(i32)] {
+        entry:
+            store (%arg.0), (%k);
+            ret (void);
+        });
+
+        let func = cilk_ir!(m; define [i32] func [] {
+        entry:
+            x = load (%g);
+            y = load (%k);
+            w = load (%e);
+            z = add (%x), (%y);
+            r = add (%z), (%w);
+            ret (%r);
+        });
+
+        ir::global_const::GlobalConstantPromotion::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'g' is never stored to, so it's always zero
+        let f = m.function_ref(func);
+        let iseq = f
+            .basic_block_ref(f.basic_blocks.order[0])
+            .iseq_ref()
+            .clone();
+        assert_eq!(iseq.len(), 5);
+        assert_eq!(count_opcode(f, opcode::Opcode::Load), 2);
+        let add = &f.inst_table[iseq[2].as_instruction().id];
+        assert_eq!(
+            add.operands[0],
+            opcode::Operand::Value(value::Value::new_imm_int32(0))
+        );
+
+        ir::global_dce::GlobalDeadCodeElimination::new().run_on_module(&mut m);
+        let names: Vec<&str> = m
+            .global_vars
+            .arena
+            .iter()
+            .map(|(_, g)| g.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["k", "e"]);
+    }
+
+    #[test]
+    fn instcombine() {
+        let mut m = module::Module::new("cilk");
+
+        let f = m.create_function(
+            "f",
+            types::Type::Int32,
+            vec![types::Type::Int32, types::Type::Int32],
+        );
+
+        let mut builder = builder::Builder::new(builder::FunctionIdWithModule::new(&mut m, f));
+
+        let entry = builder.append_basic_block();
+        let small = builder.append_basic_block();
+        let large = builder.append_basic_block();
+        builder.set_insert_point(entry);
+
+        let x = builder.get_param(0).unwrap();
+        let y = builder.get_param(1).unwrap();
+        let a = builder.build_add(x, value::Value::new_imm_int32(0));
+        let b = builder.build_mul(a, value::Value::new_imm_int32(1));
+        let c = builder.build_add(value::Value::new_imm_int32(3), b);
+        let d = builder.build_sub(c, value::Value::new_imm_int32(-4));
+        let e = builder.build_sub(y, y);
+        let g = builder.build_add(d, e);
+        let h = builder.build_mul(g, value::Value::new_imm_int32(8));
+        let i = builder.build_sitofp(h, types::Type::F64);
+        let j = builder.build_fptosi(i, types::Type::Int32);
+        let k = builder.build_icmp(opcode::ICmpKind::Le, j, value::Value::new_imm_int32(100));
+        builder.build_cond_br(k, small, large);
+        builder.set_insert_point(small);
+        builder.build_ret(j);
+        builder.set_insert_point(large);
+        builder.build_ret(value::Value::new_imm_int32(0));
+
+        ir::instcombine::InstCombine::new().run_on_module(&mut m);
+        println!("{}", m.dump(f));
+
+        // add %arg.0, 7; shl, 3; icmp lt, 101; br
+        let func = m.function_ref(f);
+        let insts: Vec<&opcode::Instruction> = func
+            .basic_block_ref(entry)
+            .iseq_ref()
+            .iter()
+            .map(|v| &func.inst_table[v.as_instruction().id])
+            .collect();
+        assert_eq!(insts.len(), 4);
+        assert_eq!(insts[0].opcode, opcode::Opcode::Add);
+        assert_eq!(
+            *insts[0].operands[1].as_value(),
+            value::Value::new_imm_int32(7)
+        );
+        assert_eq!(insts[1].opcode, opcode::Opcode::Shl);
+        assert_eq!(*insts[2].operands[0].as_icmp_kind(), opcode::ICmpKind::Lt);
+        assert_eq!(
+            *insts[2].operands[2].as_value(),
+            value::Value::new_imm_int32(101)
+        );
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("f").unwrap();
+        let args = vec![
+            exec::jit::GenericValue::Int32(1),
+            exec::jit::GenericValue::Int32(5),
+        ];
+        assert_eq!(jit.run(func, args), exec::jit::GenericValue::Int32(64));
+        let args = vec![
+            exec::jit::GenericValue::Int32(10),
+            exec::jit::GenericValue::Int32(5),
+        ];
+        assert_eq!(jit.run(func, args), exec::jit::GenericValue::Int32(0));
+    }
+
+    #[test]
+    fn instcombine_gep() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [] {
+        entry:
+            a = alloca_ ([4; [4; i32]]);
+            p = gep (%a), [(i32 0), (i32 1)];
+            q = gep (%p), [(i32 0), (i32 2)];
+            store (i32 5), (%q);
+            r = gep (%a), [(i32 0), (i32 1), (i32 2)];
+            l = load (%r);
+            ret (%l);
+        });
+
+        ir::instcombine::InstCombine::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // Both accesses use a single gep with the same indices, and the inner one is gone
+        let f = m.function_ref(func);
+        let geps: Vec<Vec<opcode::Operand>> = f
+            .basic_block_ref(f.basic_blocks.order[0])
+            .iseq_ref()
+            .iter()
+            .map(|v| &f.inst_table[v.as_instruction().id])
+            .filter(|inst| inst.opcode == opcode::Opcode::GetElementPtr)
+            .map(|inst| inst.operands.clone())
+            .collect();
+        assert_eq!(geps.len(), 2);
+        assert_eq!(geps[0], geps[1]);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(5));
+    }
+
+    #[test]
+    fn simplify_cfg() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            a = alloca i32;
+            c = icmp lt (%arg.0), (i32 0);
+            br (%c) neg, pos;
+        neg:
+            store (%arg.0), (%a);
+            br fwd;
+        pos:
+            store (%arg.0), (%a);
+            br join;
+        fwd:
+            br join;
+        join:
+            p = phi [ [(i32 1), fwd], [(i32 2), pos] ];
+            t = icmp eq (%p), (i32 1);
+            br (%t) small, large;
+        small:
+            ret (i32 0);
+        large:
+            z = icmp eq (%arg.0), (i32 42);
+            br (%z) zero, other;
+        zero:
+            ret (i32 0);
+        other:
+            l = load (%a);
+            ret (%l);
+        });
+
+        ir::simplify_cfg::SimplifyCFG::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // The store is hoisted into the entry, which jumps straight to 'small' or 'large' since
+        // the phi in 'join' decides the branch. 'zero' is merged into 'small'.
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 4);
+        let entry = f.basic_block_ref(f.basic_blocks.order[0]);
+        assert!(entry
+            .iseq_ref()
+            .iter()
+            .any(|v| f.inst_table[v.as_instruction().id].opcode == opcode::Opcode::Store));
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        for &(arg, ret) in &[(-3, 0), (42, 0), (5, 5)] {
+            let res = jit.run(func, vec![exec::jit::GenericValue::Int32(arg)]);
+            assert_eq!(res, exec::jit::GenericValue::Int32(ret));
+        }
+    }
+
+    #[test]
+    fn simplify_cfg_hoist_icmp() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            c = icmp lt (%arg.0), (i32 0);
+            br (%c) neg, pos;
+        neg:
+            z = icmp eq (%arg.0), (i32 5);
+            x = select (%z), (i32 1), (i32 2);
+            ret (%x);
+        pos:
+            z2 = icmp eq (%arg.0), (i32 5);
+            y = select (%z2), (i32 3), (i32 4);
+            ret (%y);
+        });
+
+        ir::simplify_cfg::SimplifyCFG::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // The icmps are hoisted into the entry, and the selects using them stay behind
+        let f = m.function_ref(func);
+        let entry = f.basic_blocks.order[0];
+        assert_eq!(f.insts_of(entry).len(), 3);
+        assert_eq!(count_opcode(f, opcode::Opcode::Select), 2);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        for &(arg, ret) in &[(-1, 2), (5, 3), (7, 4)] {
+            let res = jit.run(func, vec![exec::jit::GenericValue::Int32(arg)]);
+            assert_eq!(res, exec::jit::GenericValue::Int32(ret));
+        }
+    }
+
+    #[test]
+    fn tail_recursion() {
+        let mut m = module::Module::new("cilk");
+
+        // Far too deep for the stack unless the recursion becomes a loop
+        cilk_ir!(m; define [i32] count [(i32), (i32)] {
+        entry:
+            c = icmp eq (%arg.0), (i32 0);
+            br (%c) done, rec;
+        done:
+            ret (%arg.1);
+        rec:
+            n = sub (%arg.0), (i32 1);
+            a = add (%arg.1), (i32 1);
+            r = call count [(%n), (%a)];
+            ret (%r);
+        });
+
+        cilk_ir!(m; define [i32] fact [(i32)] {
+        entry:
+            c = icmp le (%arg.0), (i32 1);
+            br (%c) l1, l2;
+        l1:
+            ret (i32 1);
+        l2:
+            n = sub (%arg.0), (i32 1);
+            r = call fact [(%n)];
+            x = mul (%arg.0), (%r);
+            ret (%x);
+        });
+
+        cilk_ir!(m; define [i32] fibo [(i32)] {
+        entry:
+            cond = icmp le (%arg.0), (i32 2);
+            br (%cond) l1, l2;
+        l1:
+            ret (i32 1);
+        l2:
+            a1 = sub (%arg.0), (i32 1);
+            r1 = call fibo [(%a1)];
+            a2 = sub (%arg.0), (i32 2);
+            r2 = call fibo [(%a2)];
+            r3 = add (%r1), (%r2);
+            ret (%r3);
+        });
+
+        ir::tail_recursion::TailRecursionElimination::new().run_on_module(&mut m);
+
+        // Only the first call of 'fibo' is left
+        for (name, calls) in &[("count", 0), ("fact", 0), ("fibo", 1)] {
+            let (id, f) = m.functions.iter().find(|(_, f)| &f.name == name).unwrap();
+            println!("{}", m.dump(id));
+            assert_eq!(count_opcode(f, opcode::Opcode::Call), *calls);
+        }
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let count = jit.find_function_by_name("count").unwrap();
+        let args = vec![
+            exec::jit::GenericValue::Int32(10_000_000),
+            exec::jit::GenericValue::Int32(0),
+        ];
+        assert_eq!(
+            jit.run(count, args),
+            exec::jit::GenericValue::Int32(10_000_000)
+        );
+        let fact = jit.find_function_by_name("fact").unwrap();
+        let res = jit.run(fact, vec![exec::jit::GenericValue::Int32(10)]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(3628800));
+        let fibo = jit.find_function_by_name("fibo").unwrap();
+        let res = jit.run(fibo, vec![exec::jit::GenericValue::Int32(20)]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(6765));
+    }
+
+    #[test]
+    fn indvars() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [] {
+        entry:
+            i = alloca i32;
+            j = alloca i32;
+            k = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%j);
+            store (i32 3), (%k);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            lj = load (%j);
+            lk = load (%k);
+            c = icmp lt (%li), (i32 10);
+            br (%c) body, end;
+        body:
+            ls = load (%s);
+            ns = add (%ls), (%lj);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            nj = add (%lj), (i32 1);
+            store (%nj), (%j);
+            nk = add (%lk), (i32 2);
+            store (%nk), (%k);
+            br cond;
+        end:
+            r = load (%s);
+            x = add (%r), (%lk);
+            y = add (%x), (%li);
+            ret (%y);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::indvars::IndVarSimplify::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'i' and 'j' are merged, and 'end' uses 10 and 23 instead of 'li' and 'lk'
+        let f = m.function_ref(func);
+        let cond = f.basic_block_ref(f.basic_blocks.order[1]);
+        let num_phis = cond
+            .iseq_ref()
+            .iter()
+            .filter(|v| f.inst_table[v.as_instruction().id].opcode == opcode::Opcode::Phi)
+            .count();
+        assert_eq!(num_phis, 3);
+        let end = f.basic_block_ref(f.basic_blocks.order[3]);
+        let y = &f.inst_table[end.iseq_ref()[1].as_instruction().id];
+        assert_eq!(
+            y.operands[1],
+            opcode::Operand::Value(value::Value::new_imm_int32(10))
+        );
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(78));
+    }
+
+    #[test]
+    fn lsr() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            a = alloca_ ([16; i32]);
+            i = alloca i32;
+            j = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%j);
+            store (i32 0), (%s);
+            br fill;
+        fill:
+            li = load (%i);
+            c = icmp lt (%li), (i32 8);
+            br (%c) fill_body, init;
+        fill_body:
+            x = mul (%li), (i32 2);
+            p = gep (%a), [(i32 0), (%x)];
+            t = mul (%li), (i32 3);
+            v = add (%t), (%arg.0);
+            store (%v), (%p);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br fill;
+        init:
+            br sum;
+        sum:
+            lj = load (%j);
+            d = icmp lt (%lj), (i32 8);
+            br (%d) sum_body, end;
+        sum_body:
+            y = mul (%lj), (i32 2);
+            q = gep (%a), [(i32 0), (%y)];
+            lq = load (%q);
+            ls = load (%s);
+            ns = add (%ls), (%lq);
+            store (%ns), (%s);
+            nj = add (%lj), (i32 1);
+            store (%nj), (%j);
+            br sum;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::lsr::LoopStrengthReduction::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // Addresses are advanced by 2 elements and 't' by 3 in each iteration
+        let f = m.function_ref(func);
+        for &bb in &f.basic_blocks.order {
+            for val in &*f.basic_block_ref(bb).iseq_ref() {
+                let inst = &f.inst_table[val.as_instruction().id];
+                assert!(!matches!(
+                    inst.opcode,
+                    opcode::Opcode::Mul | opcode::Opcode::Shl
+                ));
+                if inst.opcode == opcode::Opcode::GetElementPtr {
+                    assert!(inst.operands[1..].iter().all(|op| matches!(
+                        op,
+                        opcode::Operand::Value(value::Value::Immediate(_))
+                    )));
+                }
+            }
+        }
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let res = jit.run(func, vec![exec::jit::GenericValue::Int32(1)]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(92));
+    }
+
+    #[test]
+    fn loop_rotate() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            li2 = load (%i);
+            x = add (%r), (%li2);
+            ret (%x);
+        });
+
+        let konst = cilk_ir!(m; define [i32] konst [] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (i32 10);
+            br (%c) body, end;
+        body:
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::loop_rotate::LoopRotate::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+        println!("{}", m.dump(konst));
+
+        // 'entry' guards the loop, and 'cond' becomes the latch branching back to 'body'
+        let f = m.function_ref(func);
+        let (entry, body, cond) = (
+            f.basic_blocks.order[0],
+            f.basic_blocks.order[1],
+            f.basic_blocks.order[2],
+        );
+        let terminator = |bb| {
+            let last = *f.basic_block_ref(bb).iseq_ref().last().unwrap();
+            f.inst_table[last.as_instruction().id].opcode
+        };
+        assert_eq!(terminator(entry), opcode::Opcode::CondBr);
+        assert!(f.basic_block_ref(cond).succ.contains(&body));
+        assert!(f.basic_block_ref(body).pred.contains(&entry));
+
+        // The guard of 'konst' is always true
+        let f = m.function_ref(konst);
+        let entry = f.basic_block_ref(f.basic_blocks.order[0]);
+        let last = *entry.iseq_ref().last().unwrap();
+        assert_eq!(
+            f.inst_table[last.as_instruction().id].opcode,
+            opcode::Opcode::Br
+        );
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let res = jit.run(func, vec![exec::jit::GenericValue::Int32(0)]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(0));
+        let res = jit.run(func, vec![exec::jit::GenericValue::Int32(5)]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(15));
+        let konst = jit.find_function_by_name("konst").unwrap();
+        assert_eq!(jit.run(konst, vec![]), exec::jit::GenericValue::Int32(45));
+    }
+
+    #[test]
+    fn loop_rotate_guard() {
+        let mut m = module::Module::new("cilk");
+
+        // The guard divides by zero, and its copy of 'v' is unused
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            a = alloca i32;
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            v = load volatile (%a);
+            d = div (i32 12), (%li);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            ls = load (%s);
+            ns = add (%ls), (%d);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::loop_rotate::LoopRotate::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // The volatile load is done by the guard and by the latch
+        let f = m.function_ref(func);
+        assert_eq!(count_opcode(f, opcode::Opcode::Load), 2);
+        assert_eq!(count_opcode(f, opcode::Opcode::Div), 2);
+    }
+
     #[test]
-    fn test0_mem2reg() {
+    fn unswitch() {
         let mut m = module::Module::new("cilk");
 
-        let func = cilk_ir!(m; define [i32] func [] {
+        let func = cilk_ir!(m; define [i32] func [(i32), (i32)] {
         entry:
             i = alloca i32;
-            store (i32 3), (%i);
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            flag = icmp eq (%arg.1), (i32 0);
+            br cond;
+        cond:
             li = load (%i);
-            // tmp = add (%li), (i32 2);
-            // store (%tmp), (%i);
-            // li = load (%i);
+            c = icmp lt (%li), (%arg.0);
+            br (%c) body, end;
+        body:
+            br (%flag) add, sub;
+        add:
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            br latch;
+        sub:
+            ls2 = load (%s);
+            ns2 = sub (%ls2), (i32 1);
+            store (%ns2), (%s);
+            br latch;
+        latch:
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::unswitch::LoopUnswitch::new(100).run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'flag' is only tested before entering either loop
+        let f = m.function_ref(func);
+        let flag = f.basic_block_ref(f.basic_blocks.order[0]).iseq_ref()[0];
+        let mut tests = 0;
+        for &bb in &f.basic_blocks.order {
+            let last = *f.basic_block_ref(bb).iseq_ref().last().unwrap();
+            let inst = &f.inst_table[last.as_instruction().id];
+            if inst.opcode == opcode::Opcode::CondBr
+                && inst.operands[0] == opcode::Operand::Value(flag)
+            {
+                assert_eq!(bb, f.basic_blocks.order[0]);
+                tests += 1;
+            }
+        }
+        assert_eq!(tests, 1);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let res = jit.run(
+            func,
+            vec![
+                exec::jit::GenericValue::Int32(5),
+                exec::jit::GenericValue::Int32(0),
+            ],
+        );
+        assert_eq!(res, exec::jit::GenericValue::Int32(10));
+        let res = jit.run(
+            func,
+            vec![
+                exec::jit::GenericValue::Int32(5),
+                exec::jit::GenericValue::Int32(1),
+            ],
+        );
+        assert_eq!(res, exec::jit::GenericValue::Int32(-5));
+    }
+
+    #[test]
+    fn if_conversion() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32), (i32)] {
+        entry:
+            x = alloca i32;
+            c = icmp lt (%arg.0), (%arg.1);
+            br (%c) lt, ge;
+        lt:
+            b2 = mul (%arg.1), (i32 2);
+            store (%b2), (%x);
+            br abs;
+        ge:
+            a1 = add (%arg.0), (i32 1);
+            store (%a1), (%x);
+            br abs;
+        abs:
+            lx = load (%x);
+            n = icmp lt (%lx), (i32 0);
+            br (%n) neg, end;
+        neg:
+            nx = mul (%lx), (i32 -1);
+            store (%nx), (%x);
+            br end;
+        end:
+            r = load (%x);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::if_conversion::IfConversion::new(4).run_on_module(&mut m);
+        ir::branch_folding::BranchFolding::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 1);
+        assert_eq!(count_opcode(f, opcode::Opcode::Select), 2);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        for &(a, b, expected) in &[(1, 3, 6), (4, 2, 5), (-8, 3, 6), (-8, -5, 10), (-3, -9, 2)] {
+            let res = jit.run(
+                func,
+                vec![
+                    exec::jit::GenericValue::Int32(a),
+                    exec::jit::GenericValue::Int32(b),
+                ],
+            );
+            assert_eq!(res, exec::jit::GenericValue::Int32(expected));
+        }
+    }
+
+    #[test]
+    fn icmp_in_other_block() {
+        let mut m = module::Module::new("cilk");
+
+        cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            c = icmp lt (%arg.0), (i32 0);
+            br next;
+        next:
+            x = select (%c), (i32 1), (i32 2);
+            br (%c) neg, pos;
+        neg:
+            ret (%x);
+        pos:
+            y = add (%x), (i32 10);
+            ret (%y);
+        });
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(-1)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(1));
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(1)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(12));
+    }
+
+    #[test]
+    fn licm_select() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br cond;
+        cond:
+            li = load (%i);
+            c = icmp lt (%li), (i32 10);
+            br (%c) body, end;
+        body:
+            neg = icmp lt (%arg.0), (i32 0);
+            x = select (%neg), (%li), (i32 1);
+            ls = load (%s);
+            ns = add (%ls), (%x);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            br cond;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        ir::licm::LoopInvariantCodeMotion::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'neg' is hoisted out of the loop, away from the select using it
+        let f = m.function_ref(func);
+        let select = f
+            .basic_blocks
+            .order
+            .iter()
+            .flat_map(|&bb| f.insts_of(bb))
+            .find(|&id| f.inst_table[id].opcode == opcode::Opcode::Select)
+            .unwrap();
+        let select = &f.inst_table[select];
+        let neg = select.operands[0].as_value().as_instruction().id;
+        assert_ne!(f.inst_table[neg].parent, select.parent);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(-1)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(45));
+        let ret = jit.run(func, vec![exec::jit::GenericValue::Int32(1)]);
+        assert_eq!(ret, exec::jit::GenericValue::Int32(10));
+    }
+
+    #[test]
+    fn volatile_mem2reg() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [] {
+        entry:
+            i = alloca i32;
+            store volatile (i32 3), (%i);
+            li = load volatile (%i);
             ret (%li);
         });
 
@@ -27,6 +2406,15 @@ mod x86_64 {
 
         ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
 
+        // volatile accesses must survive mem2reg
+        let f = m.function_ref(func);
+        let num_volatile = f
+            .inst_table
+            .iter()
+            .filter(|(_, inst)| inst.is_volatile())
+            .count();
+        assert_eq!(num_volatile, 2);
+
         let mut jit = exec::jit::JITExecutor::new(&mut m);
         let func = jit.find_function_by_name("func").unwrap();
         assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(3));
@@ -275,6 +2663,43 @@ mod x86_64 {
         assert_eq!(ret, exec::jit::GenericValue::Int32(55));
     }
 
+    #[test]
+    fn phi_back_edge() {
+        let mut m = module::Module::new("cilk");
+
+        cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            i = alloca i32;
+            s = alloca i32;
+            store (i32 0), (%i);
+            store (i32 0), (%s);
+            br body;
+        body:
+            li = load (%i);
+            ls = load (%s);
+            ns = add (%ls), (%li);
+            store (%ns), (%s);
+            ni = add (%li), (i32 1);
+            store (%ni), (%i);
+            c = icmp lt (%ni), (%arg.0);
+            br (%c) body, end;
+        end:
+            r = load (%s);
+            ret (%r);
+        });
+
+        // The phis in 'body' take 'ni' and 'ns', defined in 'body' itself, from the back edge
+        ir::mem2reg::Mem2Reg::new().run_on_module(&mut m);
+        println!("{:?}", m);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        assert_eq!(
+            jit.run(func, vec![exec::jit::GenericValue::Int32(5)]),
+            exec::jit::GenericValue::Int32(10)
+        );
+    }
+
     #[test]
     fn arr_2d() {
         let mut m = module::Module::new("cilk");
@@ -686,6 +3111,97 @@ main:
         assert_eq!(res, exec::jit::GenericValue::Int32(123));
     }
 
+    #[test]
+    fn named_struct() {
+        let mut m = module::Module::new("cilk");
+
+        let node_ty = m.types.new_named_struct_ty("Node");
+        let ptr_node_ty = m.types.new_pointer_ty(node_ty);
+        m.types
+            .set_struct_body(node_ty, vec![types::Type::Int32, ptr_node_ty]);
+        assert_eq!(m.types.to_string(node_ty), "struct Node");
+        assert_eq!(m.types.get_named_struct_ty("Node"), Some(node_ty));
+
+        let f = m.create_function("f", types::Type::Int32, vec![]);
+
+        let mut builder = builder::Builder::new(builder::FunctionIdWithModule::new(&mut m, f));
+
+        let entry = builder.append_basic_block();
+        builder.set_insert_point(entry);
+
+        let a = builder.build_alloca(node_ty);
+        let b = builder.build_alloca(node_ty);
+
+        cilk_ir!((builder) {
+            a_val = gep (%a), [(i32 0), (i32 0)];
+            store (i32 1), (%a_val);
+            a_next = gep (%a), [(i32 0), (i32 1)];
+            store (%b), (%a_next);
+            b_val = gep (%b), [(i32 0), (i32 0)];
+            store (i32 2), (%b_val);
+            next = load (%a_next);
+            next_val = gep (%next), [(i32 0), (i32 0)];
+            x = load (%next_val);
+            y = load (%a_val);
+            z = add (%x), (%y);
+            ret (%z);
+        });
+
+        println!("{:?}", m);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("f").unwrap();
+        let res = jit.run(func, vec![]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "struct type 'Node' is already defined")]
+    fn named_struct_duplicate() {
+        let m = module::Module::new("cilk");
+        m.types.new_named_struct_ty("Node");
+        m.types.new_named_struct_ty("Node");
+    }
+
+    #[test]
+    fn over_aligned_alloca() {
+        let mut m = module::Module::new("cilk");
+
+        let _ = cilk_ir!(m; define [i32] id [(i32)] {
+            entry:
+                ret (%arg.0);
+        });
+
+        let _ = cilk_ir!(m; define [i32] func [(i32)] {
+            entry:
+                i = alloca i32;
+                a = alloca_ ([8; i32]), align 32;
+                store (%arg.0), (%i);
+                x = gep (%a), [(i32 0), (i32 7)];
+                store (i32 4), (%x);
+                y = call id [(%arg.0)];
+                z = load (%x);
+                w = load (%i);
+                z = add (%z), (%y);
+                z = add (%z), (%w);
+                ret (%z);
+        });
+
+        let machine_module = codegen::x64::standard_conversion_into_machine_module(&mut m);
+        let func = machine_module.find_function_by_name("func").unwrap();
+        let f = machine_module.function_ref(func);
+        let fo = f.frame_objects.as_ref().unwrap();
+        assert_eq!(fo.realign, Some(32));
+        let (info, align) = f.local_mgr.aligns.iter().next().unwrap();
+        assert_eq!(*align, 32);
+        assert_eq!(fo.offset(*info).unwrap() % 32, 0);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        let res = jit.run(func, vec![exec::jit::GenericValue::Int32(3)]);
+        assert_eq!(res, exec::jit::GenericValue::Int32(10));
+    }
+
     #[test]
     fn many_arguments() {
         let mut m = module::Module::new("cilk");
@@ -893,6 +3409,36 @@ main:
         );
     }
 
+    #[test]
+    fn branch_folding_phi() {
+        let mut m = module::Module::new("cilk");
+
+        let func = cilk_ir!(m; define [i32] func [(i32)] {
+        entry:
+            a = add (%arg.0), (i32 1);
+            br l1;
+        l1:
+            p = phi [ [(%a), entry] ];
+            b = add (%p), (i32 1);
+            ret (%b);
+        });
+
+        ir::branch_folding::BranchFolding::new().run_on_module(&mut m);
+        println!("{}", m.dump(func));
+
+        // 'l1' is merged into 'entry', and its phi with a single incoming value goes away
+        let f = m.function_ref(func);
+        assert_eq!(f.basic_blocks.order.len(), 1);
+        assert_eq!(count_opcode(f, opcode::Opcode::Phi), 0);
+
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("func").unwrap();
+        assert_eq!(
+            jit.run(func, vec![exec::jit::GenericValue::Int32(1)]),
+            exec::jit::GenericValue::Int32(3)
+        );
+    }
+
     #[test]
     fn cse0() {
         let mut m = module::Module::new("cilk");
@@ -1154,4 +3700,255 @@ main:
         let func = jit.find_function_by_name("main").unwrap();
         assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::F64(24.6));
     }
+
+    #[test]
+    fn vector_i32() {
+        let mut m = module::Module::new("cilk");
+        cilk_ir!(m; define [i32] main [] {
+            entry:
+                v = alloca_ (<4 x i32>);
+                a = load (%v);
+                a = insertelement (%a), (i32 1), (i32 0);
+                a = insertelement (%a), (i32 2), (i32 1);
+                a = insertelement (%a), (i32 3), (i32 2);
+                a = insertelement (%a), (i32 4), (i32 3);
+                b = add (%a), (%a);
+                c = mul (%b), (%b);
+                s = shufflevector (%c), (%c), [3, 2, 1, 0];
+                d = sub (%s), (%a);
+                store (%d), (%v);
+                l = load (%v);
+                x = extractelement (%l), (i32 0);
+                y = extractelement (%l), (i32 1);
+                r = add (%x), (%y);
+                ret (%r);
+        });
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("main").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(97));
+    }
+
+    #[test]
+    #[should_panic(expected = "vector element index must be a constant within the vector")]
+    fn vector_variable_index() {
+        let mut m = module::Module::new("cilk");
+        cilk_ir!(m; define [i32] main [(i32)] {
+            entry:
+                v = alloca_ (<4 x i32>);
+                a = load (%v);
+                x = extractelement (%a), (%arg.0);
+                ret (%x);
+        });
+    }
+
+    #[test]
+    fn vector_f64() {
+        let mut m = module::Module::new("cilk");
+        cilk_ir!(m; define [f64] main [] {
+            entry:
+                v = alloca_ (<2 x f64>);
+                a = load (%v);
+                a = insertelement (%a), (f64 1.5), (i32 0);
+                a = insertelement (%a), (f64 2.5), (i32 1);
+                b = mul (%a), (%a);
+                c = div (%b), (%a);
+                s = shufflevector (%a), (%c), [1, 2];
+                d = add (%s), (%a);
+                x = extractelement (%d), (i32 0);
+                y = extractelement (%d), (i32 1);
+                r = add (%x), (%y);
+                ret (%r);
+        });
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("main").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::F64(8.0));
+    }
+
+    #[test]
+    fn vector_wide() {
+        let mut m = module::Module::new("cilk");
+        cilk_ir!(m; define [i32] main [] {
+            entry:
+                v = alloca_ (<8 x i32>);
+                a = load (%v);
+                a = insertelement (%a), (i32 1), (i32 0);
+                a = insertelement (%a), (i32 2), (i32 1);
+                a = insertelement (%a), (i32 3), (i32 2);
+                a = insertelement (%a), (i32 4), (i32 3);
+                a = insertelement (%a), (i32 5), (i32 4);
+                a = insertelement (%a), (i32 6), (i32 5);
+                a = insertelement (%a), (i32 7), (i32 6);
+                a = insertelement (%a), (i32 8), (i32 7);
+                s = shufflevector (%a), (%a), [7, 6, 5, 4, 3, 2, 1, 0];
+                b = mul (%a), (%s);
+                c = div (%b), (%a);
+                store (%c), (%v);
+                l = load (%v);
+                x = extractelement (%l), (i32 1);
+                y = extractelement (%l), (i32 6);
+                r = add (%x), (%y);
+                ret (%r);
+        });
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("main").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(9));
+    }
+
+    #[test]
+    fn vector_expanded() {
+        // SSE2 has no instruction for these, so they're done element by element
+        let mut m = module::Module::new("cilk");
+        cilk_ir!(m; define [i32] int [(i32)] {
+            entry:
+                v = alloca_ (<4 x i32>);
+                a = load (%v);
+                a = insertelement (%a), (i32 17), (i32 0);
+                a = insertelement (%a), (i32 -9), (i32 1);
+                a = insertelement (%a), (%arg.0), (i32 2);
+                a = insertelement (%a), (i32 100), (i32 3);
+                b = load (%v);
+                b = insertelement (%b), (i32 5), (i32 0);
+                b = insertelement (%b), (i32 4), (i32 1);
+                b = insertelement (%b), (i32 3), (i32 2);
+                b = insertelement (%b), (%arg.0), (i32 3);
+                c = mul (%a), (%b);
+                d = div (%c), (%b);
+                e = rem (%a), (%b);
+                s = shl (%b), (%e);
+                w = extractelement (%d), (i32 2);
+                x = extractelement (%e), (i32 1);
+                y = extractelement (%s), (i32 3);
+                z = extractelement (%s), (i32 0);
+                r = add (%w), (%x);
+                r = add (%r), (%y);
+                r = add (%r), (%z);
+                ret (%r);
+        });
+        cilk_ir!(m; define [f64] float [] {
+            entry:
+                v = alloca_ (<2 x f64>);
+                a = load (%v);
+                a = insertelement (%a), (f64 7.5), (i32 0);
+                a = insertelement (%a), (f64 -7.5), (i32 1);
+                b = load (%v);
+                b = insertelement (%b), (f64 2.0), (i32 0);
+                b = insertelement (%b), (f64 4.0), (i32 1);
+                c = rem (%a), (%b);
+                x = extractelement (%c), (i32 0);
+                y = extractelement (%c), (i32 1);
+                r = mul (%y), (f64 10.0);
+                r = add (%r), (%x);
+                ret (%r);
+        });
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("int").unwrap();
+        // 7 + (-9 % 4) + (7 << (100 % 7)) + (5 << (17 % 5))
+        assert_eq!(
+            jit.run(func, vec![exec::jit::GenericValue::Int32(7)]),
+            exec::jit::GenericValue::Int32(7 - 1 + 7 * 4 + 5 * 4)
+        );
+        let func = jit.find_function_by_name("float").unwrap();
+        assert_eq!(
+            jit.run(func, vec![]),
+            exec::jit::GenericValue::F64(-35.0 + 1.5)
+        );
+    }
+
+    #[test]
+    fn thread_local_global() {
+        let mut m = module::Module::new("cilk");
+        let g = m.global_vars.new_thread_local_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Common,
+            "counter",
+        );
+        let g = value::Value::Global(value::GlobalValue {
+            id: g,
+            ty: m.types.new_pointer_ty(types::Type::Int32),
+        });
+        cilk_ir!(m; define [i32] main [] {
+            entry:
+                x = load (%g);
+                y = add (%x), (i32 1);
+                store (%y), (%g);
+                ret (%y);
+        });
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("main").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(1));
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(2));
+    }
+
+    #[test]
+    fn thread_local_global_reuse() {
+        // Each executor takes a quarter of the storage for thread-local globals, which is given
+        // back when it's dropped
+        for _ in 0..16 {
+            let mut m = module::Module::new("cilk");
+            let ty = m.types.new_array_ty(types::Type::Int32, 256);
+            let g = m.global_vars.new_thread_local_var_with_name(
+                ty,
+                global_val::Linkage::Common,
+                "counters",
+            );
+            let g = value::Value::Global(value::GlobalValue {
+                id: g,
+                ty: m.types.new_pointer_ty(ty),
+            });
+            cilk_ir!(m; define [i32] main [] {
+                entry:
+                    p = gep (%g), [(i32 0), (i32 255)];
+                    x = load (%p);
+                    y = add (%x), (i32 1);
+                    store (%y), (%p);
+                    ret (%y);
+            });
+            let mut jit = exec::jit::JITExecutor::new(&mut m);
+            let func = jit.find_function_by_name("main").unwrap();
+            assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(1));
+            assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(2));
+        }
+    }
+
+    #[test]
+    fn inline_asm() {
+        let mut m = module::Module::new("cilk");
+        let pause = m.create_inline_asm(
+            types::Type::Void,
+            vec![],
+            inline_asm::InlineAsm::new("pause", "", true)
+                .unwrap()
+                .with_bytes(vec![0xf3, 0x90]),
+        );
+        // eax = ecx * 3
+        let triple = m.create_inline_asm(
+            types::Type::Int32,
+            vec![types::Type::Int32],
+            inline_asm::InlineAsm::new("mov $0, $1\nadd $0, $1\nadd $0, $1", "={eax},{ecx}", false)
+                .unwrap()
+                .with_bytes(vec![0x89, 0xc8, 0x01, 0xc8, 0x01, 0xc8]),
+        );
+        cilk_ir!(m; define [i32] main [] {
+            entry:
+                __ = call (%pause) [];
+                x = call (%triple) [(i32 14)];
+                y = add (%x), (i32 1);
+                ret (%y);
+        });
+        let mut jit = exec::jit::JITExecutor::new(&mut m);
+        let func = jit.find_function_by_name("main").unwrap();
+        assert_eq!(jit.run(func, vec![]), exec::jit::GenericValue::Int32(43));
+    }
+
+    #[test]
+    fn inline_asm_malformed() {
+        use inline_asm::InlineAsm;
+        assert!(InlineAsm::new("nop", "x", false).is_err());
+        assert!(InlineAsm::new("nop", "=i", false).is_err());
+        assert!(InlineAsm::new("nop", "~r", false).is_err());
+        assert!(InlineAsm::new("nop", "=r,={eax}", false).is_err());
+        assert!(InlineAsm::new("mov $0, $", "=r", false).is_err());
+        assert!(InlineAsm::new("mov $0, $1", "=r", false).is_err());
+        assert!(InlineAsm::new("mov $0, $1", "=r,r,~{cc}", false).is_ok());
+    }
 }
@@ -3,7 +3,8 @@ mod x86_64 {
     use cilk::{
         cilk_ir,
         codegen::x64::{asm::print::MachineAsmPrinter, standard_conversion_into_machine_module},
-        ir::{builder, global_val, types, value},
+        ir::builder::FuncRef,
+        ir::{builder, global_val, inline_asm, types, value},
         module::Module,
         *, // for macro
     };
@@ -184,6 +185,138 @@ mod x86_64 {
             &mut m,
         );
     }
+
+    #[test]
+    fn asm_thread_local_var() {
+        let mut m = Module::new("cilk");
+        let ptr_ty = m.types.new_pointer_ty(types::Type::Int32);
+        let counter = m.global_vars.new_thread_local_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::Common,
+            "counter",
+        );
+        let counter = value::Value::Global(value::GlobalValue {
+            id: counter,
+            ty: ptr_ty,
+        });
+        let ext = m.global_vars.new_thread_local_var_with_name(
+            types::Type::Int32,
+            global_val::Linkage::External,
+            "ext",
+        );
+        let ext = value::Value::Global(value::GlobalValue {
+            id: ext,
+            ty: ptr_ty,
+        });
+
+        cilk_ir!(m; define [i32] incr [] {
+            entry:
+                x = load (%counter);
+                y = add (%x), (i32 1);
+                store (%y), (%counter);
+                ret (%y);
+        });
+        cilk_ir!(m; define [i32] get_ext [] {
+            entry:
+                x = load (%ext);
+                ret (%x);
+        });
+
+        println!("{:?}", m);
+
+        compile_and_run(
+            "#include <assert.h>
+        __thread int ext = 5;
+        extern int incr();
+        extern int get_ext();
+        int main() { assert(incr() == 1); assert(incr() == 2); assert(get_ext() == 5); }",
+            &mut m,
+        );
+    }
+
+    #[test]
+    fn asm_inline_asm() {
+        let mut m = Module::new("cilk");
+        // (a + b) * 2 + 3, with edx clobbered
+        let f = m.create_inline_asm(
+            types::Type::Int32,
+            vec![types::Type::Int32, types::Type::Int32, types::Type::Int32],
+            inline_asm::InlineAsm::new(
+                "mov $0, $1\nadd $0, $2\nmov edx, $0\nadd $0, edx\nadd $0, $3",
+                "=r,r,{ecx},i,~{edx},~{cc}",
+                false,
+            )
+            .unwrap(),
+        );
+        cilk_ir!(m; define [i32] func [(i32), (i32)] {
+            entry:
+                x = call (%f) [(%arg.0), (%arg.1), (i32 3)];
+                ret (%x);
+        });
+
+        println!("{:?}", m);
+
+        compile_and_run(
+            "#include <assert.h>
+        extern int func(int, int);
+        int main() { assert(func(4, 5) == 21); }",
+            &mut m,
+        );
+    }
+
+    #[test]
+    fn asm_vector_expanded() {
+        let mut m = Module::new("cilk");
+        cilk_ir!(m; define [i32] test_int [(i32)] {
+            entry:
+                v = alloca_ (<4 x i32>);
+                a = load (%v);
+                a = insertelement (%a), (i32 17), (i32 0);
+                a = insertelement (%a), (i32 -9), (i32 1);
+                a = insertelement (%a), (%arg.0), (i32 2);
+                a = insertelement (%a), (i32 100), (i32 3);
+                b = load (%v);
+                b = insertelement (%b), (i32 5), (i32 0);
+                b = insertelement (%b), (i32 4), (i32 1);
+                b = insertelement (%b), (i32 3), (i32 2);
+                b = insertelement (%b), (%arg.0), (i32 3);
+                c = mul (%a), (%b);
+                e = rem (%a), (%b);
+                s = shl (%b), (%e);
+                x = extractelement (%c), (i32 1);
+                y = extractelement (%s), (i32 2);
+                r = add (%x), (%y);
+                ret (%r);
+        });
+        cilk_ir!(m; define [f64] test_float [] {
+            entry:
+                v = alloca_ (<2 x f64>);
+                a = load (%v);
+                a = insertelement (%a), (f64 7.5), (i32 0);
+                a = insertelement (%a), (f64 -7.5), (i32 1);
+                b = load (%v);
+                b = insertelement (%b), (f64 2.0), (i32 0);
+                b = insertelement (%b), (f64 4.0), (i32 1);
+                c = rem (%a), (%b);
+                x = extractelement (%c), (i32 0);
+                y = extractelement (%c), (i32 1);
+                r = mul (%y), (f64 10.0);
+                r = add (%r), (%x);
+                ret (%r);
+        });
+        compile_and_run(
+            "
+    #include <assert.h>
+    extern int test_int(int);
+    extern double test_float();
+    int main() {
+        assert(test_int(7) == -36 + 6);
+        assert(test_float() == -33.5);
+    }
+            ",
+            &mut m,
+        );
+    }
 }
 
 #[cfg(feature = "riscv64")]